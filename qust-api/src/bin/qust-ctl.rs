@@ -0,0 +1,80 @@
+//! A client of the control server of the live engine.
+//!
+//! qust-ctl [--addr 127.0.0.1:7788] status|pause|resume|cancel_all|flatten|multiplier RATIO
+//!     [--strategy S] [--ticker T]
+//! qust-ctl [--addr 127.0.0.1:7788] reload [--flatten]
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+const USAGE: &str = "usage: qust-ctl [--addr 127.0.0.1:7788] \
+status|pause|resume|cancel_all|flatten|multiplier RATIO [--strategy S] [--ticker T] \
+| reload [--flatten]";
+
+fn main() {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<bool, String> {
+    let mut addr = "127.0.0.1:7788".to_string();
+    let mut params = vec![];
+    let mut positional = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" | "--strategy" | "--ticker" => {
+                let value = args.next().ok_or(USAGE)?;
+                match arg.as_str() {
+                    "--addr" => addr = value,
+                    _ => params.push(format!("{}={}", &arg[2..], value)),
+                }
+            }
+            "--flatten" => params.push("flatten=true".into()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(true);
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let (method, path) = match positional.iter().map(|x| x.as_str()).collect::<Vec<_>>()[..] {
+        ["status"] => ("GET", "/status"),
+        [cmd @ ("pause" | "resume" | "cancel_all" | "flatten" | "reload")] => ("POST", cmd),
+        ["multiplier", ratio] => {
+            params.push(format!("ratio={}", ratio));
+            ("POST", "/multiplier")
+        }
+        _ => return Err(USAGE.into()),
+    };
+    let path = match path.starts_with('/') {
+        true => path.to_string(),
+        false => format!("/{}", path),
+    };
+    let target = match params.is_empty() {
+        true => path,
+        false => format!("{}?{}", path, params.join("&")),
+    };
+    let (code, body) = request(&addr, method, &target).map_err(|e| format!("{}: {}", addr, e))?;
+    println!("{}", body);
+    Ok(code == 200)
+}
+
+fn request(addr: &str, method: &str, target: &str) -> std::io::Result<(u16, String)> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", method, target, addr)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let code = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
+    Ok((code, body.to_string()))
+}
@@ -26,7 +26,11 @@ impl ApiConvert<DataReceive> for DepthMarketDataField {
                 let c = format!("{} {}.{}", self.TradingDay.to_str_0(), self.UpdateTime.to_str_0(), self.UpdateMillisec);
                 dt::parse_from_str(&c, "%Y%m%d %H:%M:%S%.f").expect(&c)
             },
-            c     : self.LastPrice as f32,
+            // a combination quoted but not traded yet has no last price
+            c     : match self.LastPrice < f64::MAX / 2. {
+                true => self.LastPrice as f32,
+                false => ((self.BidPrice1 + self.AskPrice1) / 2.) as f32,
+            },
             v     : self.Volume as f32,
             bid1  : self.BidPrice1 as f32,
             ask1  : self.AskPrice1 as f32,
@@ -79,6 +83,16 @@ impl ApiConvert<CtpOrderAction> for OrderSendWithAcco<'_> {
                 };
                 req.Direction           = dire;
                 req.CombOffsetFlag[0]   = action;
+                // a combination sets the flags of both legs, and closes without today or yesterday
+                if self.contract.to_str_0().as_str().extract_combo().is_some() {
+                    let action = match action as u8 {
+                        THOST_FTDC_OF_Open => action,
+                        _ => THOST_FTDC_OF_Close as i8,
+                    };
+                    req.CombOffsetFlag[0] = action;
+                    req.CombOffsetFlag[1] = action;
+                    req.CombHedgeFlag[1]  = THOST_FTDC_HF_Speculation as i8;
+                }
                 req.VolumeTotalOriginal = num;
                 req.OrderPriceType      = THOST_FTDC_OPT_LimitPrice as i8;
                 req.LimitPrice          = price;
@@ -170,18 +184,44 @@ impl GetInstrumentID for OnRspOrderInsertPacket {
 pub struct CtpQueryRes {
     pub trading_account: RwLock<TradingAccountField>,
     pub instrument_info: RwLock<hm<IstmId, InstrumentField>>,
-    pub contract_data_receive_map: hm<IstmId, DataReceiveOn>, 
-    pub contract_ticker_map: hm<IstmId, &'static str>,
+    pub positions: RwLock<hm<(IstmId, i8, i8), InvestorPositionField>>,
+    pub orders: RwLock<hm<String, OrderReceive>>,
+    pub contract_data_receive_map: RwLock<hm<IstmId, DataReceiveOn>>,
+    pub contract_ticker_map: RwLock<hm<IstmId, &'static str>>,
+    pub md_fanout: hm<IstmId, Vec<DataReceiveOn>>,
 }
 
 impl CtpQueryRes {
+    pub fn send_market_data(&self, data: DepthMarketDataField) {
+        let istm = data.get_instrument_id();
+        if let Some(data_receive_on_vec) = self.md_fanout.get(&istm) {
+            let data_receive = data.api_convert();
+            data_receive_on_vec.iter().for_each(|data_receive_on| {
+                data_receive_on.send(data_receive.clone());
+            });
+        }
+        if self.contract_ticker_map.read().unwrap().contains_key(&istm) {
+            self.send_data_receive(data);
+        }
+    }
+
+    pub fn update_position(&self, data: InvestorPositionField) {
+        self.positions.write().unwrap().insert((data.InstrumentID, data.PosiDirection, data.PositionDate), data);
+    }
+
+    pub fn update_order(&self, data: OrderField) {
+        if let DataReceive::OrderReceive(order_receive) = data.api_convert() {
+            self.orders.write().unwrap().insert(order_receive.id.clone(), order_receive);
+        }
+    }
+
     pub fn send_data_receive<T>(&self, data: T)
     where
         T: GetInstrumentID + ApiConvert<DataReceive>,
     {
         let istm = data.get_instrument_id();
-        let ticker = match self.contract_ticker_map.get(&istm) {
-            Some(ticker) => ticker,
+        let ticker = match self.contract_ticker_map.read().unwrap().get(&istm) {
+            Some(ticker) => *ticker,
             None => {
                 loge!("ctp", "{:?} not found in ticker_contract_map", istm.to_str_v());
                 println!("ctp: {:?} not found in ticker_contract_map", istm.to_str_v());
@@ -190,9 +230,8 @@ impl CtpQueryRes {
         };
         let data_receive = data.api_convert();
         loge!(ticker, "ctp have a  data receive: {:?}", data_receive);
-        if let Some(data_receive_on) = self.contract_data_receive_map.get(&istm) {
-            data_receive_on.push(data_receive);
-            data_receive_on.notify_all();
+        if let Some(data_receive_on) = self.contract_data_receive_map.read().unwrap().get(&istm) {
+            data_receive_on.send(data_receive);
         }
     }
 }
\ No newline at end of file
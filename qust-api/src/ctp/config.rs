@@ -22,6 +22,13 @@ pub struct CtpAccountConfigType<T> {
 pub type CtpAccountStr = CtpAccountConfigType<&'static str>;
 pub type CtpAccountConfig = CtpAccountConfigType<String>;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CtpAccountEntry {
+    pub name: String,
+    pub scale: f32,
+    pub account: CtpAccountConfig,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateDiConfig {
     pub path: String,
@@ -55,14 +62,58 @@ pub struct Config {
    pub ticker_contract_map: Option<TickerContractMap>,
    pub update_di: Option<UpdateDiConfig>,
    pub ctp_account_config: CtpAccountConfig,
+   #[serde(default)]
+   pub ctp_accounts: Vec<CtpAccountEntry>,
    pub data_config: Option<DataConfig>,
    pub money_config: Option<MoneyConfig>,
    pub algo: Option<Box<dyn Algo>>,
    #[serde(default)]
    pub tracing_config: TracingConfig,
+   #[serde(default)]
+   pub schedule_config: ScheduleConfig,
 }
 
 
+impl Config {
+    pub fn from_path(config_path: &str) -> Result<Self> {
+        let to_parsed_string = std::fs::read_to_string(config_path)?;
+        let config = toml::from_str(&to_parsed_string)?;
+        Ok(config)
+    }
+
+    /// Rejects what the live engine cannot run, so a reload leaves the running config as is.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ticker_contract_map) = &self.ticker_contract_map {
+            let mut contract_vec = vec![];
+            for (ticker, contract) in ticker_contract_map.0.iter() {
+                if contract.trim().is_empty() {
+                    anyhow::bail!("ticker maps to an empty contract: {:?}", ticker);
+                }
+                if contract.starts_with("SP") && contract.contains('&') && contract.as_str().extract_combo().is_none() {
+                    anyhow::bail!("ticker maps to a combination it cannot parse: {:?} {}", ticker, contract);
+                }
+                if contract_vec.contains(&contract) {
+                    anyhow::bail!("contract {} is mapped by more than one ticker", contract);
+                }
+                contract_vec.push(contract);
+            }
+        }
+        for entry in self.ctp_accounts.iter() {
+            if entry.scale <= 0. {
+                anyhow::bail!("account {} has a scale not positive: {}", entry.name, entry.scale);
+            }
+        }
+        let schedule_config = &self.schedule_config;
+        if schedule_config.subscribe_lead < 0 || schedule_config.login_lead < schedule_config.subscribe_lead {
+            anyhow::bail!("schedule logs in after it subscribes: {:?}", schedule_config);
+        }
+        if schedule_config.end_lead < 0 || schedule_config.logout_lag < 0 {
+            anyhow::bail!("schedule ends a session out of it: {:?}", schedule_config);
+        }
+        Ok(())
+    }
+}
+
 pub fn get_config() -> Result<Config> {
     use std::env;
     let args = env::args().collect::<Vec<_>>();
@@ -71,9 +122,7 @@ pub fn get_config() -> Result<Config> {
     } else {
         "config.toml"
     };
-    let to_parsed_string = std::fs::read_to_string(config_path)?;
-    let config = toml::from_str(&to_parsed_string)?;
-    Ok(config)
+    Config::from_path(config_path)
 }
 
 pub trait ConfigParse {
@@ -6,8 +6,10 @@ pub(super) mod utiles;
 pub(super) mod time_manager;
 pub mod ctp_wrapper;
 pub mod config;
+pub mod multi_account;
 
 pub mod prelude {
     pub use super::ctp_wrapper::*;
     pub use super::config::*;
+    pub use super::multi_account::*;
 }
\ No newline at end of file
@@ -0,0 +1,179 @@
+use qust::{ prelude::*, std_prelude::* };
+use super::config::CtpAccountEntry;
+use super::ctp_wrapper::CtpApi;
+use crate::gateway::prelude::*;
+
+pub struct AccountRunning {
+    pub name: String,
+    pub running_api: RunningApi<StraApi, CtpApi>,
+    pub is_running: bool,
+    pub last_error: Option<String>,
+}
+
+impl AccountRunning {
+    async fn start(&mut self) {
+        match self.running_api.start().await {
+            Ok(()) => {
+                loge!("ctp", "account {} start running", self.name);
+                self.is_running = true;
+                self.last_error = None;
+            }
+            Err(e) => {
+                loge!(level: Error, "ctp", "account {} start failed: {:?}", self.name, e);
+                if let Err(e) = self.running_api.stop().await {
+                    loge!(level: Error, "ctp", "account {} stop failed: {:?}", self.name, e);
+                }
+                self.renew();
+                self.is_running = false;
+                self.last_error = Some(format!("{e:?}"));
+            }
+        }
+    }
+
+    async fn stop(&mut self) {
+        if let Err(e) = self.running_api.stop().await {
+            loge!(level: Error, "ctp", "account {} stop failed: {:?}", self.name, e);
+            self.last_error = Some(format!("{e:?}"));
+        }
+        self.renew();
+        self.is_running = false;
+    }
+
+    fn renew(&mut self) {
+        self.running_api.service_api = self
+            .running_api
+            .service_api
+            .renew(self.running_api.trade_api.clone());
+    }
+}
+
+/// Runs the same strategies on several accounts, with a `MultiStraApi`. The accounts only
+/// trade; the ticks come from one md connection of their own, logged in with the first
+/// account, which keeps running when an account fails or logs out.
+pub struct MultiAccountApi {
+    pub accounts: Vec<AccountRunning>,
+    pub md: CtpApi,
+    pub log_path: Option<String>,
+}
+
+impl MultiAccountApi {
+    /// `gen_pool` builds the strategies of one account from the algo, which is `algo`
+    /// wrapped in a `ScaledAlgo` by the account's `scale`.
+    pub fn new<F>(
+        entries: Vec<CtpAccountEntry>,
+        ticker_contract_map: hm<Ticker, &'static str>,
+        algo: AlgoBox,
+        gen_pool: F,
+    ) -> Self
+    where
+        F: Fn(AlgoBox) -> LiveStraPool,
+    {
+        let ratios = entries.map(|x| x.scale);
+        let multi_stra_api = MultiStraApi::new(&ratios, ticker_contract_map, algo, gen_pool);
+        let md_account = entries.first().expect("no account to run").account.clone();
+        let md = CtpApi::new_md(md_account, multi_stra_api.md_follower(), "./data/md".into());
+        let accounts = izip!(entries, multi_stra_api.stra_api_vec, multi_stra_api.trade_api_vecs)
+            .map(|(entry, stra_api, trade_api)| {
+                let ctp_api = CtpApi::new_account(entry.account, trade_api.clone(), format!("./data/{}", entry.name), None);
+                AccountRunning {
+                    name: entry.name,
+                    running_api: RunningApi {
+                        stra_api,
+                        service_api: ctp_api,
+                        log_path: None,
+                        trade_api,
+                    },
+                    is_running: false,
+                    last_error: None,
+                }
+            })
+            .collect_vec();
+        Self {
+            accounts,
+            md,
+            log_path: Some("./logs".into()),
+        }
+    }
+
+    pub fn init(&self) {
+        if let Some(log_path) = &self.log_path {
+            let ticker_vec = self
+                .accounts
+                .iter()
+                .flat_map(|x| x.running_api.trade_api.iter().map(|x| x.ticker.to_string()))
+                .unique()
+                .collect_vec();
+            logging_service(log_path.clone(), ticker_vec);
+        }
+    }
+
+    pub async fn start(&mut self) -> bool {
+        if let Err(e) = start_gateway(&self.md, vec![]).await {
+            loge!(level: Error, "ctp", "md start failed: {:?}", e);
+        }
+        for account in self.accounts.iter_mut() {
+            account.start().await;
+        }
+        self.accounts.iter().any(|x| x.is_running)
+    }
+
+    pub async fn stop(&mut self) {
+        for account in self.accounts.iter_mut() {
+            account.stop().await;
+        }
+        if let Err(e) = stop_gateway(&self.md, vec![]).await {
+            loge!(level: Error, "ctp", "md stop failed: {:?}", e);
+        }
+        self.md = self.md.renew(vec![]);
+    }
+
+    /// The control targets are named by the accounts, and stay valid when a failed
+    /// account is renewed, as the `TradeApi`s are kept.
+    pub fn control_server(&self) -> ControlServer {
+        let targets = self
+            .accounts
+            .iter()
+            .map(|x| x.running_api.control_target(&x.name))
+            .collect_vec();
+        ControlServer::new(targets)
+    }
+
+    pub async fn retry_failed(&mut self) {
+        for account in self.accounts.iter_mut().filter(|x| !x.is_running) {
+            loge!("ctp", "account {} retry to start", account.name);
+            account.start().await;
+        }
+    }
+}
+
+/// Runs the md connection and every account through the sessions of the products traded.
+/// Each catches up on its own, so one failing to log in retries without holding up the
+/// others.
+pub async fn run_ctp_multi(multi_account_api: MultiAccountApi, schedule_config: ScheduleConfig) {
+    let mut multi_account_api = multi_account_api;
+    let clock = LocalClock;
+    let tickers = multi_account_api
+        .accounts
+        .iter()
+        .flat_map(|x| x.running_api.trade_api.iter().map(|x| x.ticker))
+        .unique()
+        .collect_vec();
+    let scheduler = SessionScheduler::new(&tickers, schedule_config);
+    let mut md_runner = SessionRunner::new(scheduler.clone());
+    let mut runner_vec = multi_account_api
+        .accounts
+        .iter()
+        .map(|_| SessionRunner::new(scheduler.clone()))
+        .collect_vec();
+    loop {
+        let now = clock.now();
+        let mut wake = scheduler.next_wake(now);
+        wake = wake.min(md_runner.catch_up_trader(&mut multi_account_api.md, now).await);
+        for (account, runner) in multi_account_api.accounts.iter_mut().zip(runner_vec.iter_mut()) {
+            wake = wake.min(runner.catch_up_trader(&mut account.running_api, now).await);
+            account.is_running = runner.state.as_ref().is_some_and(|x| x.phase >= SessionPhase::WarmingUp);
+        }
+        loge!("ctp", "sessions sleep until {}", wake);
+        clock.sleep_until(wake).await;
+    }
+}
@@ -1,6 +1,7 @@
 use ctp_futures::*;
 use super::utiles::*;
 use qust::std_prelude::*;
+use qust::prelude::ComboContract;
 use self::trader_api::CThostFtdcTraderSpiOnRspOrderInsertPacket;
 use qust_ds::prelude::*;
 use encoding::{ DecoderTrap, Encoding, all::GBK };
@@ -171,6 +172,12 @@ impl IntoIstmId for &str {
     }
 }
 
+impl IntoIstmId for &ComboContract {
+    fn into_istm_id(self) -> IstmId {
+        self.to_string().as_str().into_istm_id()
+    }
+}
+
 
 pub trait SeeString {
     fn see_string(&self) -> String;
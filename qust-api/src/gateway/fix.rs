@@ -0,0 +1,963 @@
+use super::{Gateway, GatewayAccount, GatewayEvents, GatewayPosition};
+use qust::prelude::*;
+use qust::std_prelude::*;
+use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::collections::BTreeMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+const SOH: u8 = 0x01;
+const BEGIN_STRING: &str = "FIX.4.4";
+const TIME_FORMAT: &str = "%Y%m%d-%H:%M:%S%.3f";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixConfig {
+    pub address: String,
+    pub sender_comp_id: String,
+    pub target_comp_id: String,
+    pub heart_bt_int: u64,
+    #[serde(default)]
+    pub reset_on_logon: bool,
+    pub account: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/* #region FixMessage */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FixMessage {
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self { fields: vec![(35, msg_type.into())] }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|x| x.0 == tag).map(|x| x.1.as_str())
+    }
+
+    pub fn get_parse<T: std::str::FromStr>(&self, tag: u32) -> Option<T> {
+        self.get(tag)?.parse().ok()
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(35).unwrap_or_default()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let body = self
+            .fields
+            .iter()
+            .filter(|x| !matches!(x.0, 8..=10))
+            .fold(Vec::new(), |mut accu, (tag, value)| {
+                accu.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+                accu.push(SOH);
+                accu
+            });
+        let mut res = format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).into_bytes();
+        res.extend_from_slice(&body);
+        let checksum = res.iter().fold(0u32, |accu, x| accu + *x as u32) % 256;
+        res.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        res
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let fields = data
+            .split(|x| *x == SOH)
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let field = std::str::from_utf8(x)?;
+                let (tag, value) = field
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("bad fix field: {}", field))?;
+                Ok((tag.parse()?, value.to_string()))
+            })
+            .collect::<Result<Vec<(u32, String)>>>()?;
+        Ok(Self { fields })
+    }
+
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Self> {
+        let mut head = Vec::new();
+        for _ in 0..2 {
+            if reader.read_until(SOH, &mut head)? == 0 {
+                return Err(anyhow!("fix stream closed"));
+            }
+        }
+        let body_len = Self::decode(&head)?
+            .get_parse::<usize>(9)
+            .ok_or_else(|| anyhow!("fix message without body length"))?;
+        let mut body = vec![0u8; body_len];
+        reader.read_exact(&mut body)?;
+        let mut tail = Vec::new();
+        reader.read_until(SOH, &mut tail)?;
+        let checksum_calc = head.iter().chain(body.iter()).fold(0u32, |accu, x| accu + *x as u32) % 256;
+        let res = Self::decode(&[head, body, tail].concat())?;
+        match res.get_parse::<u32>(10) {
+            Some(checksum) if checksum == checksum_calc => Ok(res),
+            other => Err(anyhow!("fix checksum wrong: {:?} != {}", other, checksum_calc)),
+        }
+    }
+}
+/* #endregion */
+
+/* #region FixSession */
+/// The MsgSeqNum expected next, and the messages come ahead of a gap, held until the
+/// resend fills it. Logon, Logout and ResendRequest are processed as they come, and held
+/// as none.
+#[derive(Default)]
+struct SeqIn {
+    next: u64,
+    ahead: BTreeMap<u64, Option<FixMessage>>,
+    resend_sent: bool,
+}
+
+fn is_gap_fill(msg: &FixMessage) -> bool {
+    msg.msg_type() == "4" && msg.get(123) == Some("Y")
+}
+
+impl SeqIn {
+    fn new(next: u64) -> Self {
+        Self { next, ..Default::default() }
+    }
+
+    /// Moves past the message at `next`, a gap fill to its NewSeqNo.
+    fn advance(&mut self, msg: &FixMessage) {
+        let next = self.next + 1;
+        self.next = match is_gap_fill(msg) {
+            true => msg.get_parse::<u64>(36).unwrap_or(next).max(next),
+            false => next,
+        };
+    }
+
+    /// Pushes the messages held that are in sequence now.
+    fn drain(&mut self, ready: &mut Vec<FixMessage>) {
+        loop {
+            let next = self.next;
+            self.ahead.retain(|seq, _| *seq >= next);
+            match self.ahead.remove(&next) {
+                Some(Some(msg)) => {
+                    self.advance(&msg);
+                    if !is_gap_fill(&msg) {
+                        ready.push(msg);
+                    }
+                }
+                Some(None) => self.next += 1,
+                None => break,
+            }
+        }
+        if self.ahead.is_empty() {
+            self.resend_sent = false;
+        }
+    }
+}
+
+#[derive(Default)]
+struct FixOrderState {
+    contract: String,
+    order_send: OrderSend,
+    cum_qty: i32,
+}
+
+struct FixSession {
+    config: FixConfig,
+    events: GatewayEvents,
+    stream: Mutex<Option<TcpStream>>,
+    seq_out: AtomicU64,
+    seq_in: Mutex<SeqIn>,
+    logged_on: Mutex<bool>,
+    logon_cv: Condvar,
+    running: AtomicBool,
+    logout_sent: AtomicBool,
+    last_send: Mutex<Instant>,
+    last_receive: Mutex<Instant>,
+    test_req_sent: Mutex<Option<Instant>>,
+    cancel_id: AtomicU64,
+    inquiry_id: AtomicU64,
+    account_reply: Mutex<hm<String, std::result::Result<GatewayAccount, String>>>,
+    account_cv: Condvar,
+    order_state: Mutex<hm<String, FixOrderState>>,
+    orders: RwLock<hm<String, OrderReceive>>,
+    positions: RwLock<hm<String, GatewayPosition>>,
+}
+
+impl FixSession {
+    fn is_logged_on(&self) -> bool {
+        *self.logged_on.lock().unwrap()
+    }
+
+    fn set_logged_on(&self, logged_on: bool) {
+        *self.logged_on.lock().unwrap() = logged_on;
+        self.logon_cv.notify_all();
+    }
+
+    fn wait_logged_on(&self, target: bool, timeout: dura) -> bool {
+        let guard = self.logged_on.lock().unwrap();
+        let (guard, _) = self
+            .logon_cv
+            .wait_timeout_while(guard, timeout, |x| *x != target)
+            .unwrap();
+        *guard == target
+    }
+
+    fn send_with_seq(&self, msg: FixMessage, seq: Option<u64>) -> Result<()> {
+        let mut stream_guard = self.stream.lock().unwrap();
+        let stream = stream_guard.as_mut().ok_or_else(|| anyhow!("fix session not connected"))?;
+        let seq = seq.unwrap_or_else(|| self.seq_out.fetch_add(1, Ordering::SeqCst));
+        let mut fields = vec![
+            msg.fields[0].clone(),
+            (49, self.config.sender_comp_id.clone()),
+            (56, self.config.target_comp_id.clone()),
+            (34, seq.to_string()),
+            (52, chrono::Utc::now().format(TIME_FORMAT).to_string()),
+        ];
+        fields.extend_from_slice(&msg.fields[1..]);
+        let msg = FixMessage { fields };
+        loge!("gateway", "fix send: {:?}", msg);
+        stream.write_all(&msg.encode())?;
+        *self.last_send.lock().unwrap() = Instant::now();
+        Ok(())
+    }
+
+    fn send(&self, msg: FixMessage) -> Result<()> {
+        self.send_with_seq(msg, None)
+    }
+
+    fn start_read(self: Arc<Self>, stream: TcpStream) {
+        let mut reader = BufReader::new(stream);
+        while self.running.load(Ordering::SeqCst) {
+            match FixMessage::read_from(&mut reader) {
+                Ok(msg) => {
+                    *self.last_receive.lock().unwrap() = Instant::now();
+                    *self.test_req_sent.lock().unwrap() = None;
+                    if let Err(e) = self.on_message(msg) {
+                        loge!(level: Error, "gateway", "fix process message error: {:?}", e);
+                    }
+                }
+                Err(e) => {
+                    if self.running.load(Ordering::SeqCst) {
+                        loge!(level: Error, "gateway", "fix read error: {:?}", e);
+                    }
+                    break;
+                }
+            }
+        }
+        self.running.store(false, Ordering::SeqCst);
+        self.set_logged_on(false);
+    }
+
+    /// Drops the connection, the read thread stops with it.
+    fn close(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(stream) = self.stream.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.set_logged_on(false);
+    }
+
+    /// Sends a Heartbeat when nothing is sent for `heart_bt_int`, and a TestRequest when
+    /// nothing is received for twice that. Disconnects when nothing answers the TestRequest
+    /// in another `heart_bt_int`.
+    fn start_heartbeat(self: Arc<Self>) {
+        let heart_bt_int = dura::from_secs(self.config.heart_bt_int.max(1));
+        while self.running.load(Ordering::SeqCst) {
+            sleep(dura::from_millis(200));
+            if !self.is_logged_on() {
+                continue;
+            }
+            if self.last_send.lock().unwrap().elapsed() >= heart_bt_int {
+                let _ = self.send(FixMessage::new("0"));
+            }
+            let test_req_sent = *self.test_req_sent.lock().unwrap();
+            match test_req_sent {
+                Some(sent) if sent.elapsed() >= heart_bt_int => {
+                    loge!(level: Error, "gateway", "fix test request not answered in {:?}, disconnect", heart_bt_int);
+                    self.close();
+                }
+                Some(_) => {}
+                None if self.last_receive.lock().unwrap().elapsed() >= heart_bt_int * 2 => {
+                    *self.test_req_sent.lock().unwrap() = Some(Instant::now());
+                    let test_req_id = chrono::Utc::now().format(TIME_FORMAT).to_string();
+                    let _ = self.send(FixMessage::new("1").with(112, test_req_id));
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// The messages to process now `msg` has come, in sequence. A message ahead of a gap is
+    /// held, and a ResendRequest sent, until the gap is filled, and a possible duplicate of
+    /// a message processed is dropped.
+    fn sequence(&self, msg: FixMessage) -> Result<Vec<FixMessage>> {
+        let seq = msg.get_parse::<u64>(34).ok_or_else(|| anyhow!("fix message without MsgSeqNum"))?;
+        let mut ready = vec![];
+        let resend_begin = {
+            let mut seq_in = self.seq_in.lock().unwrap();
+            if msg.msg_type() == "4" && !is_gap_fill(&msg) {
+                let new_seq = msg.get_parse::<u64>(36).ok_or_else(|| anyhow!("sequence reset without NewSeqNo"))?;
+                loge!("gateway", "fix sequence reset: {} -> {}", seq_in.next, new_seq);
+                seq_in.next = new_seq;
+                seq_in.drain(&mut ready);
+                return Ok(ready);
+            }
+            if seq < seq_in.next || seq_in.ahead.contains_key(&seq) {
+                if msg.get(43) == Some("Y") {
+                    loge!("gateway", "fix drop possible duplicate: {}", seq);
+                    return Ok(ready);
+                }
+                return Err(anyhow!("fix MsgSeqNum too low, expect {} but got {}", seq_in.next, seq));
+            }
+            if seq == seq_in.next {
+                seq_in.advance(&msg);
+                if !is_gap_fill(&msg) {
+                    ready.push(msg);
+                }
+                seq_in.drain(&mut ready);
+                None
+            } else {
+                loge!("gateway", "fix sequence gap: expect {} but got {}", seq_in.next, seq);
+                let resend_begin = (!seq_in.resend_sent).then_some(seq_in.next);
+                seq_in.resend_sent = true;
+                if matches!(msg.msg_type(), "A" | "5" | "2") {
+                    seq_in.ahead.insert(seq, None);
+                    ready.push(msg);
+                } else {
+                    seq_in.ahead.insert(seq, Some(msg));
+                }
+                resend_begin
+            }
+        };
+        if let Some(begin) = resend_begin {
+            self.send(FixMessage::new("2").with(7, begin).with(16, 0))?;
+        }
+        Ok(ready)
+    }
+
+    fn on_message(&self, msg: FixMessage) -> Result<()> {
+        loge!("gateway", "fix receive: {:?}", msg);
+        self.sequence(msg)?
+            .into_iter()
+            .try_for_each(|msg| self.process(msg))
+    }
+
+    fn process(&self, msg: FixMessage) -> Result<()> {
+        match msg.msg_type() {
+            "A" => {
+                loge!("gateway", "fix logon success");
+                self.set_logged_on(true);
+            }
+            "0" => {}
+            "1" => {
+                let test_req_id = msg.get(112).unwrap_or_default().to_string();
+                self.send(FixMessage::new("0").with(112, test_req_id))?;
+            }
+            "2" => {
+                let begin_seq = msg.get_parse::<u64>(7).unwrap_or(1);
+                let new_seq = self.seq_out.load(Ordering::SeqCst);
+                let gap_fill = FixMessage::new("4").with(43, "Y").with(123, "Y").with(36, new_seq);
+                self.send_with_seq(gap_fill, Some(begin_seq))?;
+            }
+            "3" => {
+                loge!(level: Error, "gateway", "fix session reject: {:?}", msg.get(58));
+            }
+            "5" => {
+                if !self.logout_sent.swap(true, Ordering::SeqCst) {
+                    let _ = self.send(FixMessage::new("5"));
+                }
+                loge!("gateway", "fix logout: {:?}", msg.get(58));
+                self.set_logged_on(false);
+            }
+            "8" => self.on_execution_report(&msg),
+            "9" => self.on_cancel_reject(&msg),
+            "W" => self.on_market_data(&msg),
+            "Y" => {
+                loge!(level: Error, "gateway", "fix market data request reject: {:?}", msg.get(58));
+            }
+            "BA" => self.on_collateral_report(&msg),
+            "BG" => self.on_collateral_inquiry_ack(&msg),
+            other => {
+                loge!("gateway", "fix get an unkown msg type: {}", other);
+            }
+        }
+        Ok(())
+    }
+
+    fn order_status(ord_status: Option<&str>, cum_qty: i32) -> OrderStatus {
+        match ord_status.and_then(|x| x.chars().next()) {
+            Some('0') | Some('1') => OrderStatus::PartTradedQueueing(cum_qty),
+            Some('2') => OrderStatus::AllTraded,
+            Some('4') | Some('C') => OrderStatus::Canceled(cum_qty),
+            Some('8') => OrderStatus::InsertError(-1),
+            Some('A') => OrderStatus::NotTouched,
+            Some(other) => OrderStatus::Unknown(other),
+            None => OrderStatus::Unknown(' '),
+        }
+    }
+
+    fn report(&self, id: String, contract: &str, order_status: OrderStatus) {
+        let order_receive = OrderReceive {
+            id: id.clone(),
+            order_status,
+            update_time: chrono::Local::now().naive_local(),
+            order_ref: None,
+            front_id: None,
+            session_id: None,
+            exchange_id: None,
+        };
+        self.orders.write().unwrap().insert(id, order_receive.clone());
+        self.events.send(contract, order_receive.into());
+    }
+
+    fn on_execution_report(&self, msg: &FixMessage) {
+        let Some(id) = msg.get(11).map(|x| x.to_string()) else {
+            loge!(level: Error, "gateway", "fix execution report without ClOrdID");
+            return;
+        };
+        let cum_qty = msg.get_parse::<f64>(14).unwrap_or_default() as i32;
+        let order_status = match Self::order_status(msg.get(39), cum_qty) {
+            OrderStatus::InsertError(_) => OrderStatus::InsertError(msg.get_parse(103).unwrap_or(-1)),
+            other => other,
+        };
+        let contract = {
+            let mut order_state = self.order_state.lock().unwrap();
+            let state = order_state.entry(id.clone()).or_default();
+            self.update_position(state, cum_qty);
+            match msg.get(55) {
+                Some(contract) => contract.to_string(),
+                None => state.contract.clone(),
+            }
+        };
+        self.report(id, &contract, order_status);
+    }
+
+    /// The order stays as it was after its cancel is rejected, as its OrdStatus tells.
+    fn on_cancel_reject(&self, msg: &FixMessage) {
+        let Some(id) = msg.get(41).map(|x| x.to_string()) else {
+            loge!(level: Error, "gateway", "fix cancel reject without OrigClOrdID");
+            return;
+        };
+        loge!(level: Error, "gateway", "fix cancel of {} rejected: {:?} {:?}", id, msg.get(102), msg.get(58));
+        let Some((contract, cum_qty)) = self
+            .order_state
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|x| (x.contract.clone(), x.cum_qty))
+        else {
+            return;
+        };
+        if let Some(ord_status) = msg.get(39) {
+            self.report(id, &contract, Self::order_status(Some(ord_status), cum_qty));
+        }
+    }
+
+    /// Balance from TotalNetValue, available from MarginExcess, and the margin the rest.
+    fn on_collateral_report(&self, msg: &FixMessage) {
+        let Some(id) = msg.get(909) else {
+            return;
+        };
+        let balance = msg.get_parse::<f64>(900).unwrap_or_default();
+        let available = msg.get_parse::<f64>(899).unwrap_or(balance);
+        let account = GatewayAccount { balance, available, margin: balance - available };
+        self.account_reply.lock().unwrap().insert(id.to_string(), Ok(account));
+        self.account_cv.notify_all();
+    }
+
+    fn on_collateral_inquiry_ack(&self, msg: &FixMessage) {
+        let Some(id) = msg.get(909) else {
+            return;
+        };
+        if msg.get(945) == Some("4") {
+            let text = msg.get(58).unwrap_or("rejected").to_string();
+            self.account_reply.lock().unwrap().insert(id.to_string(), Err(text));
+            self.account_cv.notify_all();
+        }
+    }
+
+    fn update_position(&self, state: &mut FixOrderState, cum_qty: i32) {
+        let traded = cum_qty - state.cum_qty;
+        if traded <= 0 {
+            return;
+        }
+        state.cum_qty = cum_qty;
+        let mut positions = self.positions.write().unwrap();
+        let position = positions
+            .entry(state.contract.clone())
+            .or_insert_with(|| GatewayPosition { contract: state.contract.clone(), ..Default::default() });
+        use OrderAction::*;
+        match state.order_send.order_action {
+            LoOpen(..) => position.td_lo += traded,
+            ShOpen(..) => position.td_sh += traded,
+            LoClose(..) => position.td_sh -= traded,
+            ShClose(..) => position.td_lo -= traded,
+            LoCloseYd(..) => position.yd_sh -= traded,
+            ShCloseYd(..) => position.yd_lo -= traded,
+            No => {}
+        }
+    }
+
+    fn on_market_data(&self, msg: &FixMessage) {
+        let Some(contract) = msg.get(55) else {
+            return;
+        };
+        let t = msg
+            .get(52)
+            .and_then(|x| dt::parse_from_str(x, TIME_FORMAT).ok())
+            .map(|x| x.and_utc().with_timezone(&chrono::Local).naive_local())
+            .unwrap_or_else(|| chrono::Local::now().naive_local());
+        let mut tick_data = TickData { t, ..Default::default() };
+        tick_data.v = msg.get_parse(387).unwrap_or_default();
+        let mut entry_type = "";
+        for (tag, value) in msg.fields.iter() {
+            match (*tag, entry_type) {
+                (269, _) => entry_type = value.as_str(),
+                (270, "0") => tick_data.bid1 = value.parse().unwrap_or_default(),
+                (270, "1") => tick_data.ask1 = value.parse().unwrap_or_default(),
+                (270, "2") => tick_data.c = value.parse().unwrap_or_default(),
+                (271, "0") => tick_data.bid1_v = value.parse().unwrap_or_default(),
+                (271, "1") => tick_data.ask1_v = value.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+        self.events.send(contract, tick_data.into());
+    }
+}
+/* #endregion */
+
+/* #region FixGateway */
+#[derive(Clone)]
+pub struct FixGateway {
+    session: Arc<FixSession>,
+}
+
+impl FixGateway {
+    pub fn new(config: FixConfig, trade_api_vec: &[Arc<TradeApi>]) -> Self {
+        let session = FixSession {
+            config,
+            events: GatewayEvents::new(trade_api_vec),
+            stream: Mutex::new(None),
+            seq_out: AtomicU64::new(1),
+            seq_in: Mutex::new(SeqIn::new(1)),
+            logged_on: Mutex::new(false),
+            logon_cv: Condvar::new(),
+            running: AtomicBool::new(false),
+            logout_sent: AtomicBool::new(false),
+            last_send: Mutex::new(Instant::now()),
+            last_receive: Mutex::new(Instant::now()),
+            test_req_sent: Mutex::new(None),
+            cancel_id: AtomicU64::new(0),
+            inquiry_id: AtomicU64::new(0),
+            account_reply: Default::default(),
+            account_cv: Condvar::new(),
+            order_state: Default::default(),
+            orders: Default::default(),
+            positions: Default::default(),
+        };
+        Self { session: Arc::new(session) }
+    }
+
+    pub fn is_logged_on(&self) -> bool {
+        self.session.is_logged_on()
+    }
+
+    fn side(order_action: &OrderAction) -> (&'static str, &'static str) {
+        use OrderAction::*;
+        match order_action {
+            LoOpen(..) => ("1", "O"),
+            ShOpen(..) => ("2", "O"),
+            LoClose(..) | LoCloseYd(..) => ("1", "C"),
+            ShClose(..) | ShCloseYd(..) | No => ("2", "C"),
+        }
+    }
+
+    fn md_request(&self, contract: &str, subscribe: bool) -> FixMessage {
+        FixMessage::new("V")
+            .with(262, contract)
+            .with(263, if subscribe { 1 } else { 2 })
+            .with(264, 1)
+            .with(265, 0)
+            .with(267, 3)
+            .with(269, 0)
+            .with(269, 1)
+            .with(269, 2)
+            .with(146, 1)
+            .with(55, contract)
+    }
+}
+
+impl Gateway for FixGateway {
+    fn connect(&self) -> Result<()> {
+        let session = &self.session;
+        let stream = TcpStream::connect(&session.config.address)?;
+        stream.set_nodelay(true)?;
+        let stream_read = stream.try_clone()?;
+        *session.stream.lock().unwrap() = Some(stream);
+        if session.config.reset_on_logon {
+            session.seq_out.store(1, Ordering::SeqCst);
+        }
+        {
+            let mut seq_in = session.seq_in.lock().unwrap();
+            let next = if session.config.reset_on_logon { 1 } else { seq_in.next };
+            *seq_in = SeqIn::new(next);
+        }
+        *session.last_receive.lock().unwrap() = Instant::now();
+        *session.test_req_sent.lock().unwrap() = None;
+        session.running.store(true, Ordering::SeqCst);
+        session.logout_sent.store(false, Ordering::SeqCst);
+        let session_read = session.clone();
+        thread::spawn(move || session_read.start_read(stream_read));
+        let mut logon = FixMessage::new("A")
+            .with(98, 0)
+            .with(108, session.config.heart_bt_int);
+        if session.config.reset_on_logon {
+            logon = logon.with(141, "Y");
+        }
+        if let Some(username) = &session.config.username {
+            logon = logon.with(553, username);
+        }
+        if let Some(password) = &session.config.password {
+            logon = logon.with(554, password);
+        }
+        session.send(logon)?;
+        if !session.wait_logged_on(true, dura::from_secs(10)) {
+            self.disconnect()?;
+            return Err(anyhow!("fix logon timeout: {}", session.config.address));
+        }
+        let session_heartbeat = session.clone();
+        thread::spawn(move || session_heartbeat.start_heartbeat());
+        Ok(())
+    }
+
+    fn disconnect(&self) -> Result<()> {
+        let session = &self.session;
+        if session.is_logged_on() && !session.logout_sent.swap(true, Ordering::SeqCst) {
+            session.send(FixMessage::new("5"))?;
+            session.wait_logged_on(false, dura::from_secs(5));
+        }
+        session.close();
+        Ok(())
+    }
+
+    fn subscribe(&self, contracts: Vec<String>) -> Result<()> {
+        contracts
+            .iter()
+            .try_for_each(|x| self.session.send(self.md_request(x, true)))
+    }
+
+    fn unsubscribe(&self, contracts: Vec<String>) -> Result<()> {
+        contracts
+            .iter()
+            .try_for_each(|x| self.session.send(self.md_request(x, false)))
+    }
+
+    fn place_order(&self, contract: &str, order_send: &OrderSend) -> Result<()> {
+        let (side, open_close) = Self::side(&order_send.order_action);
+        let (num, price) = match order_send.order_action {
+            OrderAction::No => return Err(anyhow!("cannot place OrderAction::No")),
+            OrderAction::LoOpen(i, p)
+            | OrderAction::LoClose(i, p)
+            | OrderAction::LoCloseYd(i, p)
+            | OrderAction::ShOpen(i, p)
+            | OrderAction::ShClose(i, p)
+            | OrderAction::ShCloseYd(i, p) => (i, p),
+        };
+        self.session.order_state.lock().unwrap().insert(
+            order_send.id.clone(),
+            FixOrderState {
+                contract: contract.to_string(),
+                order_send: order_send.clone(),
+                cum_qty: 0,
+            },
+        );
+        let mut msg = FixMessage::new("D").with(11, &order_send.id);
+        if let Some(account) = &self.session.config.account {
+            msg = msg.with(1, account);
+        }
+        msg = msg
+            .with(55, contract)
+            .with(54, side)
+            .with(60, chrono::Utc::now().format(TIME_FORMAT))
+            .with(38, num)
+            .with(40, 2)
+            .with(44, price)
+            .with(59, 0)
+            .with(77, open_close);
+        self.session.send(msg)
+    }
+
+    fn cancel_order(&self, contract: &str, order_send: &OrderSend) -> Result<()> {
+        let (side, _) = Self::side(&order_send.order_action);
+        let cancel_id = self.session.cancel_id.fetch_add(1, Ordering::SeqCst);
+        let msg = FixMessage::new("F")
+            .with(41, &order_send.id)
+            .with(11, format!("{}-c{}", order_send.id, cancel_id))
+            .with(55, contract)
+            .with(54, side)
+            .with(60, chrono::Utc::now().format(TIME_FORMAT))
+            .with(38, order_send.order_action.num());
+        self.session.send(msg)
+    }
+
+    fn query_positions(&self) -> Result<Vec<GatewayPosition>> {
+        Ok(self.session.positions.read().unwrap().values().cloned().collect())
+    }
+
+    fn query_orders(&self) -> Result<Vec<OrderReceive>> {
+        Ok(self.session.orders.read().unwrap().values().cloned().collect())
+    }
+
+    /// The account from a CollateralInquiry, for the brokers answering it with a
+    /// CollateralReport.
+    fn query_account(&self) -> Result<GatewayAccount> {
+        let session = &self.session;
+        let id = format!("coll-{}", session.inquiry_id.fetch_add(1, Ordering::SeqCst));
+        let mut msg = FixMessage::new("BB").with(909, &id).with(263, 0);
+        if let Some(account) = &session.config.account {
+            msg = msg.with(1, account);
+        }
+        session.send(msg)?;
+        let guard = session.account_reply.lock().unwrap();
+        let (mut guard, _) = session
+            .account_cv
+            .wait_timeout_while(guard, dura::from_secs(5), |x| !x.contains_key(&id))
+            .unwrap();
+        match guard.remove(&id) {
+            Some(Ok(account)) => Ok(account),
+            Some(Err(text)) => Err(anyhow!("fix collateral inquiry rejected: {}", text)),
+            None => Err(anyhow!("fix collateral inquiry timeout")),
+        }
+    }
+
+    fn events(&self) -> &GatewayEvents {
+        &self.session.events
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// The broker side of a session, reading what the gateway sends.
+    struct Acceptor {
+        reader: BufReader<TcpStream>,
+        stream: TcpStream,
+        seq: u64,
+    }
+
+    impl Acceptor {
+        fn send_seq(&mut self, msg: FixMessage, seq: u64) {
+            let mut fields = vec![
+                msg.fields[0].clone(),
+                (49, "BROKER".into()),
+                (56, "CLIENT".into()),
+                (34, seq.to_string()),
+                (52, chrono::Utc::now().format(TIME_FORMAT).to_string()),
+            ];
+            fields.extend_from_slice(&msg.fields[1..]);
+            self.stream.write_all(&FixMessage { fields }.encode()).unwrap();
+        }
+
+        fn send(&mut self, msg: FixMessage) {
+            self.send_seq(msg, self.seq);
+            self.seq += 1;
+        }
+
+        /// The next message of the type, past the heartbeats.
+        fn expect(&mut self, msg_type: &str) -> FixMessage {
+            loop {
+                let msg = FixMessage::read_from(&mut self.reader).unwrap();
+                if msg.msg_type() == "0" && msg_type != "0" {
+                    continue;
+                }
+                assert_eq!(msg.msg_type(), msg_type, "{:?}", msg);
+                return msg;
+            }
+        }
+
+        fn sync(&mut self, id: &str) {
+            self.send(FixMessage::new("1").with(112, id));
+            loop {
+                let msg = self.expect("0");
+                if msg.get(112) == Some(id) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn wait_until(f: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < dura::from_secs(10) {
+            if f() {
+                return true;
+            }
+            sleep(dura::from_millis(20));
+        }
+        false
+    }
+
+    fn gateway_logged_on(heart_bt_int: u64) -> (FixGateway, Acceptor) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = FixConfig {
+            address: listener.local_addr().unwrap().to_string(),
+            sender_comp_id: "CLIENT".into(),
+            target_comp_id: "BROKER".into(),
+            heart_bt_int,
+            reset_on_logon: true,
+            account: Some("acc".into()),
+            username: None,
+            password: None,
+        };
+        let gateway = FixGateway::new(config, &[]);
+        let gateway_connect = gateway.clone();
+        let handle = thread::spawn(move || gateway_connect.connect());
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(dura::from_secs(10))).unwrap();
+        let mut acceptor = Acceptor { reader: BufReader::new(stream.try_clone().unwrap()), stream, seq: 1 };
+        let logon = acceptor.expect("A");
+        assert_eq!(logon.get(34), Some("1"));
+        assert_eq!(logon.get(141), Some("Y"));
+        acceptor.send(FixMessage::new("A").with(98, 0).with(108, heart_bt_int));
+        handle.join().unwrap().unwrap();
+        (gateway, acceptor)
+    }
+
+    fn execution_report(id: &str, ord_status: &str, cum_qty: i32) -> FixMessage {
+        FixMessage::new("8")
+            .with(37, format!("b-{}", id))
+            .with(17, format!("e-{}-{}", id, cum_qty))
+            .with(11, id)
+            .with(55, "cu2501")
+            .with(150, ord_status)
+            .with(39, ord_status)
+            .with(14, cum_qty)
+    }
+
+    fn order_status(gateway: &FixGateway, id: &str) -> Option<OrderStatus> {
+        gateway
+            .query_orders()
+            .unwrap()
+            .into_iter()
+            .find(|x| x.id == id)
+            .map(|x| x.order_status)
+    }
+
+    fn td_lo(gateway: &FixGateway) -> i32 {
+        gateway
+            .query_positions()
+            .unwrap()
+            .iter()
+            .find(|x| x.contract == "cu2501")
+            .map(|x| x.td_lo)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn logon_and_test_request() {
+        let (gateway, mut acceptor) = gateway_logged_on(30);
+        assert!(gateway.is_logged_on());
+        acceptor.send(FixMessage::new("1").with(112, "ping"));
+        assert_eq!(acceptor.expect("0").get(112), Some("ping"));
+        let gateway_disconnect = gateway.clone();
+        let handle = thread::spawn(move || gateway_disconnect.disconnect());
+        acceptor.expect("5");
+        acceptor.send(FixMessage::new("5"));
+        handle.join().unwrap().unwrap();
+        assert!(!gateway.is_logged_on());
+    }
+
+    #[test]
+    fn heartbeat_timeout_disconnects() {
+        let (gateway, mut acceptor) = gateway_logged_on(1);
+        acceptor.expect("0");
+        assert!(acceptor.expect("1").get(112).is_some());
+        assert!(wait_until(|| !gateway.is_logged_on()));
+    }
+
+    #[test]
+    fn order_with_sequence_gap_and_cancel() {
+        let (gateway, mut acceptor) = gateway_logged_on(30);
+        let order = OrderSend { id: "o1".into(), order_action: OrderAction::LoOpen(2, 3500.), ..Default::default() };
+        gateway.place_order("cu2501", &order).unwrap();
+        let new_order = acceptor.expect("D");
+        [(11, "o1"), (1, "acc"), (55, "cu2501"), (54, "1"), (38, "2"), (44, "3500"), (77, "O")]
+            .iter()
+            .for_each(|(tag, value)| assert_eq!(new_order.get(*tag), Some(*value), "tag {}", tag));
+        acceptor.send(execution_report("o1", "0", 0));
+        assert!(wait_until(|| matches!(order_status(&gateway, "o1"), Some(OrderStatus::PartTradedQueueing(0)))));
+
+        // 3 and 4 are lost, the fill at 5 waits for them
+        acceptor.send_seq(execution_report("o1", "2", 2), 5);
+        let resend = acceptor.expect("2");
+        assert_eq!(resend.get(7), Some("3"));
+        assert_eq!(resend.get(16), Some("0"));
+        assert!(matches!(order_status(&gateway, "o1"), Some(OrderStatus::PartTradedQueueing(0))));
+        assert_eq!(td_lo(&gateway), 0);
+        acceptor.send_seq(execution_report("o1", "1", 1).with(43, "Y"), 3);
+        acceptor.send_seq(FixMessage::new("4").with(43, "Y").with(123, "Y").with(36, 5), 4);
+        assert!(wait_until(|| matches!(order_status(&gateway, "o1"), Some(OrderStatus::AllTraded))));
+        assert_eq!(td_lo(&gateway), 2);
+
+        // a duplicate of 3 is dropped
+        acceptor.seq = 6;
+        acceptor.send_seq(execution_report("o1", "1", 1).with(43, "Y"), 3);
+        acceptor.sync("after-dup");
+        assert!(matches!(order_status(&gateway, "o1"), Some(OrderStatus::AllTraded)));
+        assert_eq!(td_lo(&gateway), 2);
+
+        let order = OrderSend { id: "o2".into(), order_action: OrderAction::LoOpen(1, 3490.), ..Default::default() };
+        gateway.place_order("cu2501", &order).unwrap();
+        acceptor.expect("D");
+        acceptor.send(execution_report("o2", "0", 0));
+        gateway.cancel_order("cu2501", &order).unwrap();
+        let cancel = acceptor.expect("F");
+        assert_eq!(cancel.get(41), Some("o2"));
+        let cancel_id = cancel.get(11).unwrap().to_string();
+        acceptor.send(
+            FixMessage::new("9")
+                .with(37, "b-o2")
+                .with(11, &cancel_id)
+                .with(41, "o2")
+                .with(39, "0")
+                .with(434, 1)
+                .with(102, 1)
+                .with(58, "too late to cancel"),
+        );
+        acceptor.sync("after-reject");
+        assert!(matches!(order_status(&gateway, "o2"), Some(OrderStatus::PartTradedQueueing(0))));
+        assert!(order_status(&gateway, &cancel_id).is_none());
+        assert_eq!(td_lo(&gateway), 2);
+        acceptor.send(execution_report("o2", "4", 0));
+        assert!(wait_until(|| matches!(order_status(&gateway, "o2"), Some(OrderStatus::Canceled(0)))));
+        assert_eq!(td_lo(&gateway), 2);
+    }
+
+    #[test]
+    fn query_account_by_collateral_inquiry() {
+        let (gateway, mut acceptor) = gateway_logged_on(30);
+        let gateway_query = gateway.clone();
+        let handle = thread::spawn(move || gateway_query.query_account());
+        let inquiry = acceptor.expect("BB");
+        assert_eq!(inquiry.get(1), Some("acc"));
+        let id = inquiry.get(909).unwrap().to_string();
+        acceptor.send(FixMessage::new("BA").with(909, &id).with(1, "acc").with(900, 100000).with(899, 80000));
+        let account = handle.join().unwrap().unwrap();
+        assert_eq!((account.balance, account.available, account.margin), (100000., 80000., 20000.));
+
+        let gateway_query = gateway.clone();
+        let handle = thread::spawn(move || gateway_query.query_account());
+        let id = acceptor.expect("BB").get(909).unwrap().to_string();
+        acceptor.send(FixMessage::new("BG").with(909, &id).with(945, 4).with(58, "not supported"));
+        assert!(handle.join().unwrap().is_err());
+    }
+}
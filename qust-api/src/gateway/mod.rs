@@ -0,0 +1,188 @@
+use qust::prelude::*;
+use qust::std_prelude::*;
+use anyhow::Result;
+use tokio::sync::broadcast;
+
+pub mod fix;
+
+#[derive(Debug, Clone, Default)]
+pub struct GatewayPosition {
+    pub contract: String,
+    pub yd_lo: i32,
+    pub yd_sh: i32,
+    pub td_lo: i32,
+    pub td_sh: i32,
+}
+
+impl GatewayPosition {
+    pub fn to_hold_local(&self) -> HoldLocal {
+        HoldLocal {
+            yd_sh: self.yd_sh,
+            yd_lo: self.yd_lo,
+            td_sh: self.td_sh,
+            td_lo: self.td_lo,
+            exit_sh: 0,
+            exit_lo: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GatewayAccount {
+    pub balance: f64,
+    pub available: f64,
+    pub margin: f64,
+}
+
+/// Routes the `DataReceive` a gateway produces to the `TradeApi` of its contract. The
+/// routes are shared by the clones of the gateway, so a reload can change them.
+#[derive(Default, Clone)]
+pub struct GatewayEvents {
+    routes: Arc<RwLock<hm<String, (Ticker, DataReceiveOn)>>>,
+}
+
+impl GatewayEvents {
+    pub fn new(trade_api_vec: &[Arc<TradeApi>]) -> Self {
+        let res = Self::default();
+        trade_api_vec.iter().for_each(|trade_api| res.insert(trade_api));
+        res
+    }
+
+    pub fn insert(&self, trade_api: &TradeApi) {
+        self.routes
+            .write()
+            .unwrap()
+            .insert(trade_api.contract.to_string(), (trade_api.ticker, trade_api.data_receive.clone()));
+    }
+
+    pub fn remove(&self, contract: &str) {
+        self.routes.write().unwrap().remove(contract);
+    }
+
+    pub fn contracts(&self) -> Vec<String> {
+        self.routes.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn send(&self, contract: &str, data_receive: DataReceive) {
+        let routes = self.routes.read().unwrap();
+        let Some((ticker, data_receive_on)) = routes.get(contract) else {
+            loge!("gateway", "{} not found in contract_ticker_map", contract);
+            return;
+        };
+        loge!(ticker, "gateway have a data receive: {:?}", data_receive);
+        data_receive_on.send(data_receive);
+    }
+}
+
+pub trait Gateway: Clone + Send + Sync + 'static {
+    fn connect(&self) -> Result<()>;
+    fn disconnect(&self) -> Result<()>;
+    fn subscribe(&self, contracts: Vec<String>) -> Result<()>;
+    fn unsubscribe(&self, contracts: Vec<String>) -> Result<()>;
+    fn place_order(&self, contract: &str, order_send: &OrderSend) -> Result<()>;
+    fn cancel_order(&self, contract: &str, order_send: &OrderSend) -> Result<()>;
+    fn query_positions(&self) -> Result<Vec<GatewayPosition>>;
+    fn query_orders(&self) -> Result<Vec<OrderReceive>>;
+    fn query_account(&self) -> Result<GatewayAccount>;
+    fn events(&self) -> &GatewayEvents;
+    fn add_route(&self, trade_api: &TradeApi) {
+        self.events().insert(trade_api);
+    }
+    fn remove_route(&self, trade_api: &TradeApi) {
+        self.events().remove(trade_api.contract);
+    }
+}
+
+async fn start_spy_on_data_send<G: Gateway>(gateway: G, trade_api: Arc<TradeApi>, mut shutdown: broadcast::Receiver<()>) {
+    let contract = trade_api.contract;
+    let ticker = trade_api.ticker;
+    loge!("spy", "gateway start holder notification: {}", contract);
+    while let Some(order_send) = trade_api.data_send.recv(&mut shutdown).await {
+        loge!(ticker, "gateway get a order_action_price notify: {:?}", order_send);
+        let res = match order_send.is_to_cancel {
+            false => gateway.place_order(contract, &order_send),
+            true => gateway.cancel_order(contract, &order_send),
+        };
+        if let Err(e) = res {
+            loge!(level: Error, ticker, "gateway req a order failed: {:?} -- {:?}", e, order_send);
+        }
+    }
+    loge!("spy", "gateway stop holder notification: {}", contract);
+}
+
+async fn run_blocking<G, T, F>(gateway: &G, f: F) -> Result<T>
+where
+    G: Gateway,
+    T: Send + 'static,
+    F: FnOnce(&G) -> Result<T> + Send + 'static,
+{
+    let gateway = gateway.clone();
+    tokio::task::spawn_blocking(move || f(&gateway)).await?
+}
+
+/// Connecting and logging in block on the broker, so they run on the blocking pool.
+pub async fn connect_gateway<G: Gateway>(gateway: &G) -> Result<()> {
+    run_blocking(gateway, |g| g.connect()).await
+}
+
+pub async fn start_gateway<G: Gateway>(gateway: &G, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+    connect_gateway(gateway).await?;
+    add_gateway(gateway, trade_api).await
+}
+
+/// Routes the tickers and subscribes their contracts on a connected gateway.
+pub async fn add_gateway<G: Gateway>(gateway: &G, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+    let contracts = trade_api.iter().map(|x| x.contract.to_string()).collect::<Vec<_>>();
+    trade_api
+        .into_iter()
+        .for_each(|x| {
+            gateway.add_route(&x);
+            let shutdown = x.shutdown.subscribe();
+            tokio::spawn(start_spy_on_data_send(gateway.clone(), x, shutdown));
+        });
+    run_blocking(gateway, |g| g.subscribe(contracts)).await
+}
+
+/// Stops the tickers and unsubscribes their contracts, the gateway stays connected.
+pub async fn remove_gateway<G: Gateway>(gateway: &G, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+    trade_api.iter().for_each(|x| {
+        x.stop();
+        gateway.remove_route(x);
+    });
+    let contracts = trade_api.iter().map(|x| x.contract.to_string()).collect::<Vec<_>>();
+    run_blocking(gateway, |g| g.unsubscribe(contracts)).await
+}
+
+pub async fn stop_gateway<G: Gateway>(gateway: &G, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+    trade_api.iter().for_each(|x| x.stop());
+    run_blocking(gateway, |g| g.unsubscribe(g.events().contracts())).await?;
+    run_blocking(gateway, |g| g.disconnect()).await
+}
+
+pub struct GatewayApi<G>(pub G);
+
+impl<G: Gateway> ServiceApi for GatewayApi<G> {
+    async fn start(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+        start_gateway(&self.0, trade_api).await
+    }
+
+    async fn stop(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+        stop_gateway(&self.0, trade_api).await
+    }
+
+    async fn add(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+        add_gateway(&self.0, trade_api).await
+    }
+
+    async fn remove(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+        remove_gateway(&self.0, trade_api).await
+    }
+}
+
+pub mod prelude {
+    pub use super::{
+        Gateway, GatewayAccount, GatewayApi, GatewayEvents, GatewayPosition, add_gateway, connect_gateway,
+        remove_gateway, start_gateway, stop_gateway,
+    };
+    pub use super::fix::{FixConfig, FixGateway};
+}
@@ -1,5 +1,7 @@
 pub mod ctp;
+pub mod gateway;
 
 pub mod prelude {
     pub use super::ctp::prelude::*;
-}
\ No newline at end of file
+    pub use super::gateway::prelude::*;
+}
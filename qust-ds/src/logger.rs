@@ -82,6 +82,7 @@ fn setup_logging(p: &str, date: da, ticker_vec: &[String], handle: Option<Handle
     str_vec.push("ctp".into());
     str_vec.push("spy".into());
     str_vec.push("stra".into());
+    str_vec.push("gateway".into());
     let mut guard_vec = Vec::with_capacity(ticker_vec.len());
     let layers = str_vec
         .into_iter()
@@ -0,0 +1,722 @@
+use anyhow::{anyhow, bail, Result};
+use arrow::{
+    array::{
+        ArrayRef, AsArray, BooleanArray, Date32Array, Float32Array, Int32Array,
+        RecordBatch, StringArray, TimestampMicrosecondArray, UInt16Array, UInt32Array,
+    },
+    compute::{cast, concat_batches},
+    datatypes::{DataType, Field, Float32Type, Schema, TimeUnit, TimestampMicrosecondType},
+    error::ArrowError,
+};
+use parquet::{
+    arrow::{
+        arrow_reader::{ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter},
+        ArrowWriter, ProjectionMask,
+    },
+    basic::{Compression, ZstdLevel},
+    file::{metadata::ParquetMetaData, properties::WriterProperties, statistics::Statistics},
+};
+use qust::prelude::*;
+use std::{collections::HashMap, fs::File, path::Path, sync::Arc};
+
+/* #region columns */
+/// The microseconds since the epoch of a time, as the files of the crate store it.
+pub(crate) fn dt_micros(t: &dt) -> i64 {
+    t.and_utc().timestamp_micros()
+}
+
+pub(crate) fn micros_dt(x: i64) -> Result<dt> {
+    chrono::DateTime::from_timestamp_micros(x)
+        .map(|x| x.naive_utc())
+        .ok_or_else(|| anyhow!("timestamp {} out of range", x))
+}
+
+/// The days since the epoch of a date.
+pub(crate) fn da_days(t: &da) -> i64 {
+    (*t - da::from_ymd_opt(1970, 1, 1).unwrap()).num_days()
+}
+
+pub(crate) fn days_da(x: i64) -> Result<da> {
+    da::from_ymd_opt(1970, 1, 1)
+        .unwrap()
+        .checked_add_signed(chrono::Duration::days(x))
+        .ok_or_else(|| anyhow!("date {} out of range", x))
+}
+
+fn f32_col(x: &[f32]) -> ArrayRef {
+    Arc::new(Float32Array::from(x.to_vec()))
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| anyhow!("no column {}", name))
+}
+
+fn col_f32(batch: &RecordBatch, name: &str) -> Result<v32> {
+    column(batch, name)?
+        .as_primitive_opt::<Float32Type>()
+        .map(|x| x.values().to_vec())
+        .ok_or_else(|| anyhow!("column {} is not f32", name))
+}
+
+fn col_dt(batch: &RecordBatch, name: &str) -> Result<vdt> {
+    column(batch, name)?
+        .as_primitive_opt::<TimestampMicrosecondType>()
+        .ok_or_else(|| anyhow!("column {} is not a timestamp", name))?
+        .values()
+        .iter()
+        .map(|x| micros_dt(*x))
+        .collect()
+}
+
+fn col_str(batch: &RecordBatch, name: &str) -> Result<Vec<String>> {
+    column(batch, name)?
+        .as_string_opt::<i32>()
+        .map(|x| x.iter().map(|x| x.unwrap_or_default().to_string()).collect_vec())
+        .ok_or_else(|| anyhow!("column {} is not a string", name))
+}
+
+fn meta<T: for<'de> Deserialize<'de>>(batch: &RecordBatch, key: &str) -> Result<T> {
+    let value = batch
+        .schema_ref()
+        .metadata()
+        .get(key)
+        .ok_or_else(|| anyhow!("no metadata {}", key))?;
+    Ok(serde_json::from_str(value)?)
+}
+
+/// The metadata of `key`, the default if the batch has none, an error if it is not a `T`.
+fn meta_or_default<T: for<'de> Deserialize<'de> + Default>(batch: &RecordBatch, key: &str) -> Result<T> {
+    match batch.schema_ref().metadata().contains_key(key) {
+        true => meta(batch, key),
+        false => Ok(T::default()),
+    }
+}
+
+fn ticker_str(ticker: &Ticker) -> String {
+    serde_json::to_value(ticker).unwrap().as_str().unwrap_or_default().to_string()
+}
+
+fn str_ticker(x: &str) -> Result<Ticker> {
+    Ok(serde_json::from_value(serde_json::Value::String(x.into()))?)
+}
+
+fn with_meta(batch: RecordBatch, meta: HashMap<String, String>) -> Result<RecordBatch> {
+    let schema = batch.schema_ref().as_ref().clone().with_metadata(meta);
+    Ok(batch.with_schema(Arc::new(schema))?)
+}
+
+fn batch_with_meta(fields: Vec<Field>, cols: Vec<ArrayRef>, meta: HashMap<String, String>) -> Result<RecordBatch> {
+    let schema = Schema::new(fields).with_metadata(meta);
+    Ok(RecordBatch::try_new(Arc::new(schema), cols)?)
+}
+
+/// Splits a batch with an `id` column into the runs of each id, without the id and
+/// `skip` columns and without the metadata of the whole batch.
+fn split_id(batch: &RecordBatch, skip: &[&str]) -> Result<Vec<(usize, RecordBatch)>> {
+    let id = column(batch, "id")?
+        .as_primitive_opt::<arrow::datatypes::UInt32Type>()
+        .ok_or_else(|| anyhow!("column id is not u32"))?
+        .values()
+        .to_vec();
+    let keep = batch
+        .schema_ref()
+        .fields()
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.name() != "id" && !skip.contains(&x.name().as_str()))
+        .map(|(i, _)| i)
+        .collect_vec();
+    let batch_keep = batch.project(&keep)?;
+    let schema = Schema::new(batch_keep.schema_ref().fields().clone());
+    let batch_keep = RecordBatch::try_new(Arc::new(schema), batch_keep.columns().to_vec())?;
+    let mut res = vec![];
+    let mut start = 0;
+    for i in 1..=id.len() {
+        if i == id.len() || id[i] != id[start] {
+            res.push((start, batch_keep.slice(start, i - start)));
+            start = i;
+        }
+    }
+    Ok(res)
+}
+
+fn with_id(batch: &RecordBatch, id: u32, extra: Vec<(&str, String)>) -> Result<RecordBatch> {
+    let n = batch.num_rows();
+    let mut fields = vec![Field::new("id", DataType::UInt32, false)];
+    let mut cols: Vec<ArrayRef> = vec![Arc::new(UInt32Array::from(vec![id; n]))];
+    for (name, value) in extra {
+        fields.push(Field::new(name, DataType::Utf8, false));
+        cols.push(Arc::new(StringArray::from(vec![value; n])));
+    }
+    fields.extend(batch.schema_ref().fields().iter().map(|x| x.as_ref().clone()));
+    cols.extend(batch.columns().iter().cloned());
+    batch_with_meta(fields, cols, HashMap::new())
+}
+
+fn concat_or_empty(batches: &[RecordBatch], schema: Schema) -> Result<RecordBatch> {
+    match batches.first() {
+        Some(x) => Ok(concat_batches(&x.schema(), batches)?),
+        None => Ok(RecordBatch::new_empty(Arc::new(schema))),
+    }
+}
+/* #endregion */
+
+/* #region ToArrow */
+/// A typed arrow table of the data, its time column `t`.
+pub trait ToArrow: Sized {
+    fn to_batch(&self) -> Result<RecordBatch>;
+    fn from_batch(batch: &RecordBatch) -> Result<Self>;
+}
+
+impl ToArrow for PriceTick {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let fields = vec![
+            Field::new("t", DataType::Timestamp(TimeUnit::Microsecond, None), false),
+            Field::new("c", DataType::Float32, false),
+            Field::new("v", DataType::Float32, false),
+            Field::new("ct", DataType::Int32, false),
+            Field::new("bid1", DataType::Float32, false),
+            Field::new("ask1", DataType::Float32, false),
+            Field::new("bid1_v", DataType::Float32, false),
+            Field::new("ask1_v", DataType::Float32, false),
+        ];
+        let cols: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMicrosecondArray::from(self.t.map(dt_micros))),
+            f32_col(&self.c),
+            f32_col(&self.v),
+            Arc::new(Int32Array::from(self.ct.clone())),
+            f32_col(&self.bid1),
+            f32_col(&self.ask1),
+            f32_col(&self.bid1_v),
+            f32_col(&self.ask1_v),
+        ];
+        batch_with_meta(fields, cols, HashMap::new())
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        Ok(PriceTick {
+            t: col_dt(batch, "t")?,
+            c: col_f32(batch, "c")?,
+            v: col_f32(batch, "v")?,
+            ct: column(batch, "ct")?
+                .as_primitive_opt::<arrow::datatypes::Int32Type>()
+                .ok_or_else(|| anyhow!("column ct is not i32"))?
+                .values()
+                .to_vec(),
+            bid1: col_f32(batch, "bid1")?,
+            ask1: col_f32(batch, "ask1")?,
+            bid1_v: col_f32(batch, "bid1_v")?,
+            ask1_v: col_f32(batch, "ask1_v")?,
+        })
+    }
+}
+
+fn price_ori_schema() -> Vec<Field> {
+    let t = DataType::Timestamp(TimeUnit::Microsecond, None);
+    vec![
+        Field::new("t", t.clone(), false),
+        Field::new("o", DataType::Float32, false),
+        Field::new("h", DataType::Float32, false),
+        Field::new("l", DataType::Float32, false),
+        Field::new("c", DataType::Float32, false),
+        Field::new("v", DataType::Float32, false),
+        Field::new("ki_open_time", t, false),
+        Field::new("ki_pass_last", DataType::UInt16, false),
+        Field::new("ki_pass_this", DataType::UInt16, false),
+        Field::new("ki_contract", DataType::Int32, false),
+    ]
+}
+
+/// The immutable infos, not of a bar each, go in the metadata.
+impl ToArrow for PriceOri {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let cols: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMicrosecondArray::from(self.t.map(dt_micros))),
+            f32_col(&self.o),
+            f32_col(&self.h),
+            f32_col(&self.l),
+            f32_col(&self.c),
+            f32_col(&self.v),
+            Arc::new(TimestampMicrosecondArray::from(self.ki.map(|x| dt_micros(&x.open_time)))),
+            Arc::new(UInt16Array::from(self.ki.map(|x| x.pass_last))),
+            Arc::new(UInt16Array::from(self.ki.map(|x| x.pass_this))),
+            Arc::new(Int32Array::from(self.ki.map(|x| x.contract))),
+        ];
+        let mut meta = HashMap::new();
+        if !self.immut_info.is_empty() {
+            meta.insert("immut_info".into(), serde_json::to_string(&self.immut_info)?);
+        }
+        batch_with_meta(price_ori_schema(), cols, meta)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        let open_time = col_dt(batch, "ki_open_time")?;
+        let u16_col = |name: &str| -> Result<Vec<u16>> {
+            column(batch, name)?
+                .as_primitive_opt::<arrow::datatypes::UInt16Type>()
+                .map(|x| x.values().to_vec())
+                .ok_or_else(|| anyhow!("column {} is not u16", name))
+        };
+        let (pass_last, pass_this) = (u16_col("ki_pass_last")?, u16_col("ki_pass_this")?);
+        let contract = column(batch, "ki_contract")?
+            .as_primitive_opt::<arrow::datatypes::Int32Type>()
+            .ok_or_else(|| anyhow!("column ki_contract is not i32"))?
+            .values()
+            .to_vec();
+        let ki = izip!(open_time, pass_last, pass_this, contract)
+            .map(|(open_time, pass_last, pass_this, contract)| KlineInfo {
+                open_time,
+                pass_last,
+                pass_this,
+                contract,
+            })
+            .collect_vec();
+        Ok(PriceOri {
+            t: col_dt(batch, "t")?,
+            o: col_f32(batch, "o")?,
+            h: col_f32(batch, "h")?,
+            l: col_f32(batch, "l")?,
+            c: col_f32(batch, "c")?,
+            v: col_f32(batch, "v")?,
+            ki,
+            immut_info: meta_or_default(batch, "immut_info")?,
+        })
+    }
+}
+
+/// The ticker and the interval go in the metadata.
+impl ToArrow for Pcon {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let batch = self.price.to_batch()?;
+        let mut meta = batch.schema_ref().metadata().clone();
+        meta.insert("ticker".into(), serde_json::to_string(&self.ticker)?);
+        meta.insert("inter".into(), serde_json::to_string(&self.inter)?);
+        with_meta(batch, meta)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        let inter: TriBox = meta(batch, "inter")?;
+        let ticker: Ticker = meta(batch, "ticker")?;
+        Ok(PriceOri::from_batch(batch)?.to_pcon(inter, ticker))
+    }
+}
+
+/// The bars of all the `Di`s, each told by its `id`, `ticker` and `inter` columns.
+impl ToArrow for Dil {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let batches = self
+            .dil
+            .iter()
+            .enumerate()
+            .map(|(i, di)| {
+                let extra = vec![
+                    ("ticker", ticker_str(&di.pcon.ticker)),
+                    ("inter", serde_json::to_string(&di.pcon.inter)?),
+                ];
+                with_id(&di.pcon.price.to_batch()?, i as u32, extra)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let immut_info = self.dil.map(|x| x.pcon.price.immut_info.clone());
+        let res = concat_or_empty(&batches, Schema::empty())?;
+        let mut meta = HashMap::new();
+        if immut_info.iter().any(|x| !x.is_empty()) {
+            meta.insert("immut_info".into(), serde_json::to_string(&immut_info)?);
+        }
+        with_meta(res, meta)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        if batch.num_columns() == 0 {
+            return Ok(Dil { dil: vec![] });
+        }
+        let (ticker, inter) = (col_str(batch, "ticker")?, col_str(batch, "inter")?);
+        let immut_info: Vec<Vec<vv32>> = meta_or_default(batch, "immut_info")?;
+        let dil = split_id(batch, &["ticker", "inter"])?
+            .into_iter()
+            .enumerate()
+            .map(|(k, (start, part))| {
+                let mut price = PriceOri::from_batch(&part)?;
+                price.immut_info = immut_info.get(k).cloned().unwrap_or_default();
+                let inter: TriBox = serde_json::from_str(&inter[start])?;
+                Ok(price.to_pcon(inter, str_ticker(&ticker[start])?).to_di())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Dil { dil })
+    }
+}
+
+/// The time types of a pnl.
+pub trait ArrowTime: Sized + Clone {
+    fn data_type() -> DataType;
+    fn to_col(data: &[Self]) -> ArrayRef;
+    fn from_col(data: &ArrayRef) -> Result<Vec<Option<Self>>>;
+}
+
+impl ArrowTime for dt {
+    fn data_type() -> DataType {
+        DataType::Timestamp(TimeUnit::Microsecond, None)
+    }
+    fn to_col(data: &[Self]) -> ArrayRef {
+        Arc::new(TimestampMicrosecondArray::from(data.map(dt_micros)))
+    }
+    fn from_col(data: &ArrayRef) -> Result<Vec<Option<Self>>> {
+        data.as_primitive_opt::<TimestampMicrosecondType>()
+            .ok_or_else(|| anyhow!("column t is not a timestamp"))?
+            .iter()
+            .map(|x| x.map(micros_dt).transpose())
+            .collect()
+    }
+}
+
+impl ArrowTime for da {
+    fn data_type() -> DataType {
+        DataType::Date32
+    }
+    fn to_col(data: &[Self]) -> ArrayRef {
+        Arc::new(Date32Array::from(data.map(|x| da_days(x) as i32)))
+    }
+    fn from_col(data: &ArrayRef) -> Result<Vec<Option<Self>>> {
+        data.as_primitive_opt::<arrow::datatypes::Date32Type>()
+            .ok_or_else(|| anyhow!("column t is not a date"))?
+            .iter()
+            .map(|x| x.map(|x| days_da(x as i64)).transpose())
+            .collect()
+    }
+}
+
+const pnl_cols: [&str; 8] = ["pnl", "profit", "money", "money_trade", "cost", "comm", "slip", "hold"];
+
+/// A row for each time, with the rows past the end of a shorter column null, as the
+/// `hold` of `Di::pnl` has one more value than the times.
+impl<T: ArrowTime> ToArrow for PnlRes<T> {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let n = self.1.iter().map(|x| x.len()).chain([self.0.len()]).max().unwrap_or(0);
+        let mut fields = vec![Field::new("t", T::data_type(), true)];
+        let t = T::to_col(&self.0);
+        let mut cols = vec![arrow::compute::kernels::concat::concat(&[
+            t.as_ref(),
+            arrow::array::new_null_array(&T::data_type(), n - self.0.len()).as_ref(),
+        ])?];
+        for (i, x) in self.1.iter().enumerate() {
+            let name = if self.1.len() == pnl_cols.len() { pnl_cols[i].to_string() } else { format!("v{}", i) };
+            fields.push(Field::new(name, DataType::Float32, true));
+            let col = x.iter().map(|v| Some(*v)).chain(std::iter::repeat(None)).take(n);
+            cols.push(Arc::new(Float32Array::from_iter(col)));
+        }
+        batch_with_meta(fields, cols, HashMap::new())
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        let t = T::from_col(column(batch, "t")?)?.into_iter().map_while(|x| x).collect_vec();
+        let value = batch
+            .schema_ref()
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.name() != "t")
+            .map(|(i, x)| {
+                batch
+                    .column(i)
+                    .as_primitive_opt::<Float32Type>()
+                    .map(|x| x.iter().map_while(|x| x).collect_vec())
+                    .ok_or_else(|| anyhow!("column {} is not f32", x.name()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PnlRes(t, value))
+    }
+}
+
+/// The info, as json, goes in the metadata.
+impl<T: Serialize + for<'de> Deserialize<'de>, N: ArrowTime> ToArrow for InfoPnlRes<T, N> {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let batch = self.1.to_batch()?;
+        let meta = HashMap::from([("info".to_string(), serde_json::to_string(&self.0)?)]);
+        with_meta(batch, meta)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        Ok(InfoPnlRes(meta(batch, "info")?, PnlRes::from_batch(batch)?))
+    }
+}
+
+/// The pnls, each told by its `id` column and its info as json in the `info` column.
+impl<T: Serialize + for<'de> Deserialize<'de>, N: ArrowTime> ToArrow for Vec<InfoPnlRes<T, N>> {
+    fn to_batch(&self) -> Result<RecordBatch> {
+        let batches = self
+            .iter()
+            .enumerate()
+            .map(|(i, x)| with_id(&x.1.to_batch()?, i as u32, vec![("info", serde_json::to_string(&x.0)?)]))
+            .collect::<Result<Vec<_>>>()?;
+        concat_or_empty(&batches, Schema::empty())
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Self> {
+        if batch.num_columns() == 0 {
+            return Ok(vec![]);
+        }
+        let info = col_str(batch, "info")?;
+        split_id(batch, &["info"])?
+            .into_iter()
+            .map(|(start, part)| Ok(InfoPnlRes(serde_json::from_str(&info[start])?, PnlRes::from_batch(&part)?)))
+            .collect()
+    }
+}
+/* #endregion */
+
+/* #region SofParquet */
+/// Bounds holding all the times of the range, for skipping the row groups out of them.
+fn range_bounds(range: &ForCompare<dt>) -> (i64, i64) {
+    match range {
+        ForCompare::After(x) => (dt_micros(x), i64::MAX),
+        ForCompare::Before(x) => (i64::MIN, dt_micros(x)),
+        ForCompare::Between(x) => (dt_micros(&x.start), dt_micros(&x.end)),
+        ForCompare::List(x) => x.iter().map(|x| range_bounds(x)).fold((i64::MAX, i64::MIN), |accu, x| {
+            (accu.0.min(x.0), accu.1.max(x.1))
+        }),
+    }
+}
+
+fn stats_micros(stats: &Statistics) -> Option<(i64, i64)> {
+    match stats {
+        Statistics::Int64(x) => Some((*x.min_opt()?, *x.max_opt()?)),
+        Statistics::Int32(x) => {
+            let day = 86_400_000_000i64;
+            Some((*x.min_opt()? as i64 * day, (*x.max_opt()? as i64 + 1) * day - 1))
+        }
+        _ => None,
+    }
+}
+
+/// The row groups of the file whose `t` statistics may hold times of the range.
+fn row_groups_of(metadata: &ParquetMetaData, t_index: usize, range: &ForCompare<dt>) -> Vec<usize> {
+    let (start, end) = range_bounds(range);
+    metadata
+        .row_groups()
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| match x.column(t_index).statistics().and_then(stats_micros) {
+            Some((min, max)) => max >= start && min < end,
+            None => true,
+        })
+        .map(|(i, _)| i)
+        .collect_vec()
+}
+
+/// Parquet files of the arrow tables, compressed with zstd.
+pub trait SofParquet: ToArrow {
+    fn sof_parquet(&self, name: &str, path: &str) -> Result<()> {
+        let batch = self.to_batch()?;
+        let file = File::create(Path::new(path).join(name))?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_max_row_group_size(1 << 16)
+            .build();
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(props))?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    fn rof_parquet(name: &str, path: &str) -> Result<Self> {
+        let file = File::open(Path::new(path).join(name))?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let schema = reader.schema().clone();
+        let batches = reader.build()?.collect::<Result<Vec<_>, _>>()?;
+        Self::from_batch(&concat_batches(&schema, &batches)?)
+    }
+
+    /// Reads the rows of the times in `range` only, skipping the row groups out of it
+    /// on their statistics and filtering the rest on the `t` column before decoding the
+    /// others.
+    fn rof_parquet_range(name: &str, path: &str, range: ForCompare<dt>) -> Result<Self> {
+        let file = File::open(Path::new(path).join(name))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+        let schema = builder.schema().clone();
+        let Ok(t_index) = schema.index_of("t") else {
+            bail!("no column t in {}", name);
+        };
+        let row_groups = row_groups_of(builder.metadata(), t_index, &range);
+        let mask = ProjectionMask::roots(builder.parquet_schema(), [t_index]);
+        let predicate = ArrowPredicateFn::new(mask, move |batch| {
+            let t = cast(batch.column(0), &DataType::Timestamp(TimeUnit::Microsecond, None))?;
+            let t = t.as_primitive::<TimestampMicrosecondType>();
+            t.iter()
+                .map(|x| x.map(|x| Ok(range.compare_same(&micros_dt(x)?))).transpose())
+                .collect::<Result<BooleanArray>>()
+                .map_err(|e| ArrowError::ExternalError(e.into()))
+        });
+        let reader = builder
+            .with_row_groups(row_groups)
+            .with_row_filter(RowFilter::new(vec![Box::new(predicate)]))
+            .build()?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        Self::from_batch(&concat_batches(&schema, &batches)?)
+    }
+}
+
+impl<T: ToArrow> SofParquet for T {}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn at(secs: i64) -> dt {
+        da::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(9, 0, 0).unwrap() + chrono::Duration::seconds(secs)
+    }
+
+    fn price_tick(n: usize) -> PriceTick {
+        let x = (0..n).map(|i| 3500. + i as f32).collect_vec();
+        PriceTick {
+            t: (0..n).map(|i| at(i as i64)).collect(),
+            c: x.clone(),
+            v: vec![1.; n],
+            ct: (0..n as i32).collect(),
+            bid1: x.map(|x| x - 1.),
+            ask1: x.map(|x| x + 1.),
+            bid1_v: vec![2.; n],
+            ask1_v: vec![3.; n],
+        }
+    }
+
+    fn price_ori(n: usize, immut_info: Vec<vv32>) -> PriceOri {
+        let t = (0..n).map(|i| at(300 * i as i64)).collect_vec();
+        let c = (0..n).map(|i| 3500. + i as f32).collect_vec();
+        let ki = t
+            .iter()
+            .map(|x| KlineInfo { open_time: *x - chrono::Duration::seconds(300), pass_last: 2, pass_this: 3, contract: 4 })
+            .collect_vec();
+        PriceOri { t, o: c.clone(), h: c.map(|x| x + 2.), l: c.map(|x| x - 2.), c, v: vec![1.; n], ki, immut_info }
+    }
+
+    fn tri(inter: InterBox) -> TriBox {
+        Box::new(inter)
+    }
+
+    fn json<T: Serialize>(x: &T) -> String {
+        serde_json::to_string(x).unwrap()
+    }
+
+    fn round_trip<T: ToArrow>(x: &T) -> T {
+        T::from_batch(&x.to_batch().unwrap()).unwrap()
+    }
+
+    /// A parquet of `x` with row groups of `rows` rows.
+    fn write_groups<T: ToArrow>(x: &T, path: &Path, rows: usize) {
+        let batch = x.to_batch().unwrap();
+        let props = WriterProperties::builder().set_max_row_group_size(rows).build();
+        let mut writer = ArrowWriter::try_new(File::create(path).unwrap(), batch.schema(), Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn batches_round_trip() {
+        let x = price_tick(5);
+        assert_eq!(json(&round_trip(&x)), json(&x));
+
+        let pcon = price_ori(4, vec![vec![vec![1., 2.]]]).to_pcon(tri(rl5m.clone()), Ticker::rb);
+        let res = round_trip(&pcon);
+        assert_eq!(json(&res.price), json(&pcon.price));
+        assert_eq!((res.ticker, res.inter.debug_string()), (pcon.ticker, pcon.inter.debug_string()));
+
+        let dil = Dil {
+            dil: vec![
+                price_ori(3, vec![]).to_di(Ticker::rb, tri(rl5m.clone())),
+                price_ori(4, vec![vec![vec![5.]]]).to_di(Ticker::au, tri(rl30mday.clone())),
+            ],
+        };
+        let res = round_trip(&dil);
+        assert_eq!(res.dil.len(), 2);
+        izip!(res.dil.iter(), dil.dil.iter()).for_each(|(x, y)| {
+            assert_eq!(json(&x.pcon.price), json(&y.pcon.price));
+            assert_eq!((x.pcon.ticker, x.pcon.inter.debug_string()), (y.pcon.ticker, y.pcon.inter.debug_string()));
+        });
+        assert!(round_trip(&Dil { dil: vec![] }).dil.is_empty());
+    }
+
+    #[test]
+    fn pnls_round_trip() {
+        let t = (0..3).map(at).collect_vec();
+        let mut value = (0..pnl_cols.len()).map(|i| vec![i as f32; 3]).collect_vec();
+        value[7].push(9.);
+        let pnl = PnlRes(t.clone(), value);
+        let batch = pnl.to_batch().unwrap();
+        assert_eq!(batch.num_rows(), 4);
+        assert_eq!(batch.schema_ref().field(8).name(), "hold");
+        assert_eq!(json(&round_trip(&pnl)), json(&pnl));
+
+        let days = t.map(|x| x.date() + chrono::Duration::days(1));
+        let pnls = vec![
+            InfoPnlRes("a".to_string(), PnlRes(days.clone(), vec![vec![1., 2., 3.]])),
+            InfoPnlRes("b".to_string(), PnlRes(days[..2].to_vec(), vec![vec![4., 5.]])),
+        ];
+        let res = round_trip(&pnls);
+        assert_eq!(res.map(|x| x.0.clone()), vec!["a", "b"]);
+        izip!(res.iter(), pnls.iter()).for_each(|(x, y)| assert_eq!(json(&x.1), json(&y.1)));
+        assert!(round_trip(&Vec::<InfoPnlRes<String, da>>::new()).is_empty());
+    }
+
+    #[test]
+    fn bad_metadata_and_times_are_errors() {
+        let batch = price_ori(2, vec![]).to_batch().unwrap();
+        let meta = HashMap::from([("immut_info".to_string(), "[1".to_string())]);
+        assert!(PriceOri::from_batch(&with_meta(batch.clone(), meta).unwrap()).is_err());
+        let dil = Dil { dil: vec![price_ori(2, vec![]).to_di(Ticker::rb, tri(rl5m.clone()))] }.to_batch().unwrap();
+        let meta = HashMap::from([("immut_info".to_string(), "{}".to_string())]);
+        assert!(Dil::from_batch(&with_meta(dil, meta).unwrap()).is_err());
+
+        let t: ArrayRef = Arc::new(TimestampMicrosecondArray::from(vec![i64::MAX]));
+        let c = f32_col(&[1.]);
+        let fields = vec![
+            Field::new("t", DataType::Timestamp(TimeUnit::Microsecond, None), false),
+            Field::new("c", DataType::Float32, false),
+        ];
+        let batch = batch_with_meta(fields, vec![t, c], HashMap::new()).unwrap();
+        assert!(col_dt(&batch, "t").is_err());
+        assert!(PnlRes::<dt>::from_batch(&batch).is_err());
+    }
+
+    #[test]
+    fn range_reads_the_row_groups_in_it() {
+        let dir = temp_dir("range_reads_the_row_groups_in_it");
+        let path = dir.to_str().unwrap();
+        write_groups(&price_tick(10), &dir.join("a.parquet"), 3);
+        let groups = |range: ForCompare<dt>| {
+            let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(dir.join("a.parquet")).unwrap()).unwrap();
+            assert_eq!(builder.metadata().num_row_groups(), 4);
+            row_groups_of(builder.metadata(), 0, &range)
+        };
+        let secs = |range: ForCompare<dt>| {
+            let res = PriceTick::rof_parquet_range("a.parquet", path, range).unwrap();
+            res.t.map(|x| (*x - at(0)).num_seconds())
+        };
+        let list = || ForCompare::List(vec![Box::new(at(1).before()), Box::new(at(8).after())]);
+        assert_eq!(groups(ForCompare::Between(at(4)..at(7))), vec![1, 2]);
+        assert_eq!(secs(ForCompare::Between(at(4)..at(7))), vec![4, 5, 6]);
+        assert_eq!(groups(at(6).after()), vec![2, 3]);
+        assert_eq!(secs(at(6).after()), vec![6, 7, 8, 9]);
+        assert_eq!(groups(list()), vec![0, 1, 2, 3]);
+        assert_eq!(secs(list()), vec![0, 8, 9]);
+        assert!(groups(at(10).after()).is_empty());
+        assert!(secs(at(10).after()).is_empty());
+        assert!(groups(at(0).before()).is_empty());
+        assert!(secs(at(0).before()).is_empty());
+        assert_eq!(secs(at(0).after()), (0..10).collect_vec());
+    }
+
+    #[test]
+    fn range_of_days_reads_date_stats() {
+        let dir = temp_dir("range_of_days_reads_date_stats");
+        let days = (0..6).map(|i| at(0).date() + chrono::Duration::days(i)).collect_vec();
+        write_groups(&PnlRes(days.clone(), vec![(0..6).map(|x| x as f32).collect()]), &dir.join("a.parquet"), 2);
+        let range = ForCompare::Between(days[2].and_hms_opt(0, 0, 0).unwrap()..days[4].and_hms_opt(0, 0, 0).unwrap());
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(dir.join("a.parquet")).unwrap()).unwrap();
+        assert_eq!(row_groups_of(builder.metadata(), 0, &range), vec![1]);
+        let res = PnlRes::<da>::rof_parquet_range("a.parquet", dir.to_str().unwrap(), range).unwrap();
+        assert_eq!((res.0, res.1), (days[2..4].to_vec(), vec![vec![2., 3.]]));
+    }
+}
@@ -0,0 +1,670 @@
+use anyhow::{anyhow, bail};
+use chrono::{Duration, FixedOffset};
+use csv::StringRecord;
+use qust::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{path::Path, thread};
+
+/* #region Schema */
+/// A column, by its index or by its name in the header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Col {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeKind {
+    #[default]
+    Incremental,
+    /// Summed from the open of the trading day, as the exchanges send it.
+    Cumulative,
+}
+
+/// The time of a row, from one `datetime` column or from `date` and `time` columns, and
+/// the milliseconds in a column of their own. `format` of `epoch_s`, `epoch_ms`, `epoch_us`
+/// or `epoch_ns` reads `datetime` as a timestamp. Times in `timezone` are moved to
+/// `local_timezone`, the time of the exchange.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeSchema {
+    pub datetime: Option<Col>,
+    pub date: Option<Col>,
+    pub time: Option<Col>,
+    pub millis: Option<Col>,
+    #[serde(default = "default_format")]
+    pub format: String,
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    #[serde(default = "default_time_format")]
+    pub time_format: String,
+    pub timezone: Option<String>,
+    #[serde(default = "default_local_timezone")]
+    pub local_timezone: String,
+}
+
+/// The trading day of a row, from a column, or else the next trading day for times from
+/// `night_start` and for times before `morning_start` of the night before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingDaySchema {
+    pub col: Option<Col>,
+    #[serde(default = "default_date_format")]
+    pub format: String,
+    #[serde(default = "default_night_start")]
+    pub night_start: tt,
+    #[serde(default = "default_morning_start")]
+    pub morning_start: tt,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickCols {
+    pub c: Col,
+    pub v: Col,
+    pub bid1: Option<Col>,
+    pub ask1: Option<Col>,
+    pub bid1_v: Option<Col>,
+    pub ask1_v: Option<Col>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KlineCols {
+    pub o: Col,
+    pub h: Col,
+    pub l: Col,
+    pub c: Col,
+    pub v: Col,
+}
+
+/// How to read the files of a vendor, in TOML:
+/// ```toml
+/// volume = "cumulative"
+/// [time]
+/// date = "ActionDay"
+/// time = "UpdateTime"
+/// millis = "UpdateMillisec"
+/// [trading_day]
+/// col = "TradingDay"
+/// [tick]
+/// c = "LastPrice"
+/// v = "Volume"
+/// bid1 = "BidPrice1"
+/// ask1 = "AskPrice1"
+/// ```
+/// Prices are multiplied by `price_scale`. Ticks with no bid or ask take the last price,
+/// and no volume at them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportSchema {
+    #[serde(default = "default_true")]
+    pub has_header: bool,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default = "default_price_scale")]
+    pub price_scale: f32,
+    #[serde(default)]
+    pub volume: VolumeKind,
+    pub time: TimeSchema,
+    #[serde(default)]
+    pub trading_day: TradingDaySchema,
+    pub tick: Option<TickCols>,
+    pub kline: Option<KlineCols>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_price_scale() -> f32 {
+    1.
+}
+
+fn default_format() -> String {
+    "%Y-%m-%d %H:%M:%S%.f".into()
+}
+
+fn default_date_format() -> String {
+    "%Y%m%d".into()
+}
+
+fn default_time_format() -> String {
+    "%H:%M:%S%.f".into()
+}
+
+fn default_local_timezone() -> String {
+    "+08:00".into()
+}
+
+fn default_night_start() -> tt {
+    180000.to_tt()
+}
+
+fn default_morning_start() -> tt {
+    60000.to_tt()
+}
+
+impl Default for TradingDaySchema {
+    fn default() -> Self {
+        Self {
+            col: None,
+            format: default_date_format(),
+            night_start: default_night_start(),
+            morning_start: default_morning_start(),
+        }
+    }
+}
+/* #endregion */
+
+/* #region Report */
+/// A row not imported, or a file not read when `line` is none.
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub path: String,
+    pub line: Option<u64>,
+    pub msg: String,
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path, line, self.msg),
+            None => write!(f, "{}: {}", self.path, self.msg),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportRes<T> {
+    pub data: T,
+    pub errors: Vec<RowError>,
+}
+/* #endregion */
+
+/* #region Reading */
+struct Row<T> {
+    t: dt,
+    trading_day: da,
+    data: T,
+}
+
+/// The schema with the columns found in the header of a file.
+struct FileSchema<'a> {
+    schema: &'a ImportSchema,
+    header: Option<StringRecord>,
+    timezone: Option<(FixedOffset, FixedOffset)>,
+}
+
+impl FileSchema<'_> {
+    fn col(&self, col: &Col) -> anyhow::Result<usize> {
+        match (col, &self.header) {
+            (Col::Index(i), _) => Ok(*i),
+            (Col::Name(name), Some(header)) => header
+                .iter()
+                .position(|x| x.trim() == name)
+                .ok_or_else(|| anyhow!("no column {} in the header", name)),
+            (Col::Name(name), None) => bail!("column {} by name with no header", name),
+        }
+    }
+
+    fn str<'r>(&self, record: &'r StringRecord, col: &Col) -> anyhow::Result<&'r str> {
+        let i = self.col(col)?;
+        record
+            .get(i)
+            .map(|x| x.trim())
+            .ok_or_else(|| anyhow!("no column {} in the row", i))
+    }
+
+    fn f32(&self, record: &StringRecord, col: &Col) -> anyhow::Result<f32> {
+        let s = self.str(record, col)?;
+        s.parse().map_err(|_| anyhow!("failed to parse {:?} as a number", s))
+    }
+
+    fn price(&self, record: &StringRecord, col: &Col) -> anyhow::Result<f32> {
+        Ok(self.f32(record, col)? * self.schema.price_scale)
+    }
+
+    fn t(&self, record: &StringRecord) -> anyhow::Result<dt> {
+        let time = &self.schema.time;
+        let mut t = match (&time.datetime, &time.date, &time.time) {
+            (Some(col), _, _) => {
+                let s = self.str(record, col)?;
+                let epoch = |x: i64| chrono::DateTime::from_timestamp_nanos(x).naive_utc();
+                let out_of_range = || anyhow!("timestamp {:?} out of range", s);
+                let nanos = |k: i64| {
+                    s.parse::<i64>()
+                        .map_err(|_| anyhow!("failed to parse {:?} as a timestamp", s))?
+                        .checked_mul(k)
+                        .ok_or_else(out_of_range)
+                };
+                match time.format.as_str() {
+                    "epoch_s" => {
+                        let x = s.parse::<f64>().map_err(|_| anyhow!("failed to parse {:?} as a timestamp", s))?;
+                        // the seconds apart from their fraction, as an f64 of the nanoseconds
+                        // since 1970 is off by hundreds of them
+                        let secs = x.trunc();
+                        if !(-1e18..1e18).contains(&secs) {
+                            return Err(out_of_range());
+                        }
+                        let x = (secs as i64)
+                            .checked_mul(1_000_000_000)
+                            .and_then(|secs| secs.checked_add((x.fract() * 1e9).round() as i64))
+                            .ok_or_else(out_of_range)?;
+                        epoch(x)
+                    }
+                    "epoch_ms" => epoch(nanos(1_000_000)?),
+                    "epoch_us" => epoch(nanos(1_000)?),
+                    "epoch_ns" => epoch(nanos(1)?),
+                    format => dt::parse_from_str(s, format)
+                        .map_err(|_| anyhow!("failed to parse time {:?} with {}", s, format))?,
+                }
+            }
+            (None, Some(date_col), Some(time_col)) => {
+                let (date_s, time_s) = (self.str(record, date_col)?, self.str(record, time_col)?);
+                let date = da::parse_from_str(date_s, &time.date_format)
+                    .map_err(|_| anyhow!("failed to parse date {:?} with {}", date_s, time.date_format))?;
+                let time = tt::parse_from_str(time_s, &time.time_format)
+                    .map_err(|_| anyhow!("failed to parse time {:?} with {}", time_s, time.time_format))?;
+                date.and_time(time)
+            }
+            _ => bail!("the time needs a datetime column, or date and time columns"),
+        };
+        if let Some(col) = &time.millis {
+            t += Duration::milliseconds(self.f32(record, col)? as i64);
+        }
+        if let Some((from, to)) = self.timezone {
+            t = t - Duration::seconds(from.local_minus_utc() as i64) + Duration::seconds(to.local_minus_utc() as i64);
+        }
+        Ok(t)
+    }
+
+    fn trading_day(&self, record: &StringRecord, t: &dt, calendar: &TradingCalendar) -> anyhow::Result<da> {
+        let schema = &self.schema.trading_day;
+        if let Some(col) = &schema.col {
+            let s = self.str(record, col)?;
+            return da::parse_from_str(s, &schema.format)
+                .map_err(|_| anyhow!("failed to parse trading day {:?} with {}", s, schema.format));
+        }
+        let date = t.date();
+        let res = if t.time() >= schema.night_start {
+            calendar.next_trading_day(date)
+        } else if t.time() < schema.morning_start {
+            calendar.next_trading_day(date.pred_opt().unwrap())
+        } else {
+            date
+        };
+        Ok(res)
+    }
+}
+
+fn parse_offset(s: &str) -> anyhow::Result<FixedOffset> {
+    s.parse().map_err(|_| anyhow!("failed to parse the timezone {:?}, as +08:00", s))
+}
+
+impl ImportSchema {
+    pub fn from_toml(s: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    fn read_file<T>(
+        &self,
+        path: &str,
+        calendar: &TradingCalendar,
+        f: &(impl Fn(&FileSchema, &StringRecord) -> anyhow::Result<T> + Sync),
+        errors: &mut Vec<RowError>,
+    ) -> Vec<Row<T>> {
+        let file_error = |msg: String| RowError { path: path.into(), line: None, msg };
+        let timezone = match &self.time.timezone {
+            Some(from) => match (parse_offset(from), parse_offset(&self.time.local_timezone)) {
+                (Ok(from), Ok(to)) => Some((from, to)),
+                (Err(e), _) | (_, Err(e)) => {
+                    errors.push(file_error(e.to_string()));
+                    return vec![];
+                }
+            },
+            None => None,
+        };
+        let reader = csv::ReaderBuilder::new()
+            .has_headers(self.has_header)
+            .delimiter(self.delimiter as u8)
+            .flexible(true)
+            .from_path(path);
+        let mut reader = match reader {
+            Ok(reader) => reader,
+            Err(e) => {
+                errors.push(file_error(e.to_string()));
+                return vec![];
+            }
+        };
+        let header = if self.has_header {
+            match reader.headers() {
+                Ok(header) => Some(header.clone()),
+                Err(e) => {
+                    errors.push(file_error(e.to_string()));
+                    return vec![];
+                }
+            }
+        } else {
+            None
+        };
+        let file_schema = FileSchema { schema: self, header, timezone };
+        let mut res = vec![];
+        let mut record = StringRecord::new();
+        loop {
+            let row = match reader.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => file_schema.t(&record).and_then(|t| {
+                    Ok(Row {
+                        t,
+                        trading_day: file_schema.trading_day(&record, &t, calendar)?,
+                        data: f(&file_schema, &record)?,
+                    })
+                }),
+                Err(e) if e.is_io_error() => {
+                    errors.push(file_error(e.to_string()));
+                    break;
+                }
+                Err(e) => {
+                    errors.push(RowError { path: path.into(), line: e.position().map(|x| x.line()), msg: e.to_string() });
+                    continue;
+                }
+            };
+            match row {
+                Ok(row) => res.push(row),
+                Err(e) => errors.push(RowError {
+                    path: path.into(),
+                    line: record.position().map(|x| x.line()),
+                    msg: e.to_string(),
+                }),
+            }
+        }
+        res
+    }
+
+    /// Reads the files on as many threads as there are cores, into rows sorted by time
+    /// with the volume of each row.
+    fn read_files<T: Send>(
+        &self,
+        paths: &[&str],
+        calendar: &TradingCalendar,
+        f: impl Fn(&FileSchema, &StringRecord) -> anyhow::Result<T> + Sync,
+        v: impl Fn(&mut T) -> &mut f32,
+    ) -> ImportRes<Vec<Row<T>>> {
+        let n = thread::available_parallelism().map(|x| x.get()).unwrap_or(1).min(paths.len().max(1));
+        let (mut rows, mut errors) = thread::scope(|scope| {
+            let handles = (0..n)
+                .map(|i| {
+                    let f = &f;
+                    scope.spawn(move || {
+                        let mut errors = vec![];
+                        let rows = paths
+                            .iter()
+                            .skip(i)
+                            .step_by(n)
+                            .flat_map(|path| self.read_file(path, calendar, f, &mut errors))
+                            .collect_vec();
+                        (rows, errors)
+                    })
+                })
+                .collect_vec();
+            handles.into_iter().fold((vec![], vec![]), |mut accu, x| {
+                let (rows, errors) = x.join().unwrap();
+                accu.0.extend(rows);
+                accu.1.extend(errors);
+                accu
+            })
+        });
+        rows.sort_by_key(|x| x.t);
+        if let VolumeKind::Cumulative = self.volume {
+            let mut last: Option<(da, f32)> = None;
+            for row in rows.iter_mut() {
+                let cum = *v(&mut row.data);
+                // a count that goes down has been reset, and counts from none again
+                *v(&mut row.data) = match last {
+                    Some((day, last_cum)) if day == row.trading_day && cum >= last_cum => cum - last_cum,
+                    _ => cum,
+                };
+                last = Some((row.trading_day, cum));
+            }
+        }
+        errors.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        ImportRes { data: rows, errors }
+    }
+
+    /// The ticks of the files, of each trading day.
+    pub fn read_ticks(&self, paths: &[&str], calendar: &TradingCalendar) -> anyhow::Result<ImportRes<Vec<(da, PriceTick)>>> {
+        let cols = self.tick.as_ref().ok_or_else(|| anyhow!("no tick columns in the schema"))?;
+        let res = self.read_files(
+            paths,
+            calendar,
+            |x, record| {
+                let c = x.price(record, &cols.c)?;
+                let price_or = |col: &Option<Col>| col.as_ref().map(|col| x.price(record, col)).unwrap_or(Ok(c));
+                let volume_or = |col: &Option<Col>| col.as_ref().map(|col| x.f32(record, col)).unwrap_or(Ok(0.));
+                Ok(TickData {
+                    t: Default::default(),
+                    c,
+                    v: x.f32(record, &cols.v)?,
+                    bid1: price_or(&cols.bid1)?,
+                    ask1: price_or(&cols.ask1)?,
+                    bid1_v: volume_or(&cols.bid1_v)?,
+                    ask1_v: volume_or(&cols.ask1_v)?,
+                    ct: 1,
+                })
+            },
+            |x| &mut x.v,
+        );
+        let data = res.data.into_iter().fold(Vec::<(da, PriceTick)>::new(), |mut accu, row| {
+            if accu.last().map(|x| x.0) != Some(row.trading_day) {
+                accu.push((row.trading_day, PriceTick::with_capacity(25_000)));
+            }
+            let mut tick_data = row.data;
+            tick_data.t = row.t;
+            accu.last_mut().unwrap().1.update(&tick_data);
+            accu
+        });
+        Ok(ImportRes { data, errors: res.errors })
+    }
+
+    /// The klines of the files.
+    pub fn read_klines(&self, paths: &[&str], calendar: &TradingCalendar) -> anyhow::Result<ImportRes<PriceOri>> {
+        let cols = self.kline.as_ref().ok_or_else(|| anyhow!("no kline columns in the schema"))?;
+        let res = self.read_files(
+            paths,
+            calendar,
+            |x, record| {
+                Ok(KlineData {
+                    t: Default::default(),
+                    o: x.price(record, &cols.o)?,
+                    h: x.price(record, &cols.h)?,
+                    l: x.price(record, &cols.l)?,
+                    c: x.price(record, &cols.c)?,
+                    v: x.f32(record, &cols.v)?,
+                    ki: Default::default(),
+                })
+            },
+            |x| &mut x.v,
+        );
+        let mut price_ori = PriceOri::with_capacity(res.data.len());
+        for row in res.data {
+            let mut kline_data = row.data;
+            kline_data.t = row.t;
+            price_ori.update(&kline_data);
+        }
+        Ok(ImportRes { data: price_ori, errors: res.errors })
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn write(dir: &Path, name: &str, text: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn at(s: &str) -> dt {
+        dt::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap()
+    }
+
+    const ctp_schema: &str = r#"
+        volume = "cumulative"
+        [time]
+        date = "ActionDay"
+        time = "UpdateTime"
+        millis = "UpdateMillisec"
+        [trading_day]
+        col = "TradingDay"
+        [tick]
+        c = "LastPrice"
+        v = "Volume"
+    "#;
+
+    #[test]
+    fn cumulative_volume_by_the_trading_day() {
+        let dir = temp_dir("cumulative_volume_by_the_trading_day");
+        let header = "TradingDay,ActionDay,UpdateTime,UpdateMillisec,LastPrice,Volume\n";
+        let a = write(
+            &dir,
+            "a.csv",
+            &(header.to_string()
+                + "20240102,20240102,09:00:00,0,3500,10\n"
+                + "20240102,20240102,09:00:01,0,3502,15\n"
+                + "20240103,20240103,09:00:00,0,3510,20\n"),
+        );
+        let b = write(
+            &dir,
+            "b.csv",
+            &(header.to_string()
+                + "20240102,20240102,09:00:00,500,3501,15\n"
+                + "20240102,20240102,09:00:02,0,3503,4\n"
+                + "20240102,20240102,09:00:03,0,3503,7\n"),
+        );
+        let schema = ImportSchema::from_toml(ctp_schema).unwrap();
+        let res = schema.read_ticks(&[&a, &b], &Default::default()).unwrap();
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        let days = res.data.iter().map(|x| x.0.to_string()).collect_vec();
+        assert_eq!(days, ["2024-01-02", "2024-01-03"]);
+        let (_, day) = &res.data[0];
+        assert_eq!(day.t[1], at("2024-01-02 09:00:00.5"));
+        assert_eq!(day.c, [3500., 3501., 3502., 3503., 3503.]);
+        // the count going from 15 down to 4 starts again
+        assert_eq!(day.v, [10., 5., 0., 4., 3.]);
+        assert_eq!(day.bid1, day.c);
+        assert_eq!(day.ask1_v, [0.; 5]);
+        assert_eq!(res.data[1].1.v, [20.]);
+    }
+
+    const night_schema: &str = r#"
+        has_header = false
+        [time]
+        datetime = 0
+        [tick]
+        c = 1
+        v = 2
+    "#;
+
+    #[test]
+    fn night_ticks_trade_the_next_day() {
+        let dir = temp_dir("night_ticks_trade_the_next_day");
+        let path = write(
+            &dir,
+            "a.csv",
+            "2024-01-05 21:00:00,1,1\n2024-01-06 01:00:00,2,1\n2024-01-08 09:00:00,3,1\n2024-01-08 21:00:00,4,1\n",
+        );
+        let schema = ImportSchema::from_toml(night_schema).unwrap();
+        let trading_days = |calendar: &TradingCalendar| {
+            let res = schema.read_ticks(&[&path], calendar).unwrap();
+            assert!(res.errors.is_empty(), "{:?}", res.errors);
+            res.data.iter().map(|x| (x.0.to_string(), x.1.c.clone())).collect_vec()
+        };
+        // the friday night and the hours past its midnight trade on monday
+        assert_eq!(
+            trading_days(&Default::default()),
+            [("2024-01-08".to_string(), vec![1., 2., 3.]), ("2024-01-09".to_string(), vec![4.])]
+        );
+        let holidays = [da::from_ymd_opt(2024, 1, 9).unwrap()].into_iter().collect();
+        assert_eq!(
+            trading_days(&TradingCalendar { holidays }),
+            [("2024-01-08".to_string(), vec![1., 2., 3.]), ("2024-01-10".to_string(), vec![4.])]
+        );
+    }
+
+    #[test]
+    fn errors_by_the_row() {
+        let dir = temp_dir("errors_by_the_row");
+        let header = "TradingDay,ActionDay,UpdateTime,UpdateMillisec,LastPrice,Volume\n";
+        let path = write(
+            &dir,
+            "a.csv",
+            &(header.to_string()
+                + "20240102,20240102,09:00:00,0,3500,10\n"
+                + "20240102,20240102,09:00:01,0,abc,11\n"
+                + "20240102,20240102,9h,0,3501,12\n"
+                + "20240102,20240102,09:00:03,0,3502\n"
+                + "2024-01-02,20240102,09:00:04,0,3503,13\n"
+                + "20240102,20240102,09:00:05,0,3504,14\n"),
+        );
+        let missing = dir.join("missing.csv").to_str().unwrap().to_string();
+        let schema = ImportSchema::from_toml(ctp_schema).unwrap();
+        let res = schema.read_ticks(&[&path, &missing], &Default::default()).unwrap();
+        assert_eq!(res.data.len(), 1);
+        assert_eq!(res.data[0].1.c, [3500., 3504.]);
+        assert_eq!(res.data[0].1.v, [10., 4.]);
+        let lines = res.errors.iter().filter(|x| x.path == path).map(|x| x.line).collect_vec();
+        assert_eq!(lines, [Some(3), Some(4), Some(5), Some(6)]);
+        let msgs = res.errors.iter().map(|x| x.msg.as_str()).collect_vec();
+        assert!(msgs[0].contains("\"abc\""), "{}", msgs[0]);
+        assert!(msgs[1].contains("\"9h\""), "{}", msgs[1]);
+        assert!(msgs[2].contains("no column 5"), "{}", msgs[2]);
+        assert!(msgs[3].contains("trading day"), "{}", msgs[3]);
+        let missing_error = res.errors.iter().find(|x| x.path == missing).unwrap();
+        assert_eq!(missing_error.line, None);
+    }
+
+    #[test]
+    fn epoch_times() {
+        let dir = temp_dir("epoch_times");
+        let schema_of = |format: &str| {
+            let time = format!("datetime = 0\nformat = \"{}\"\ntimezone = \"+00:00\"", format);
+            ImportSchema::from_toml(&night_schema.replace("datetime = 0", &time)).unwrap()
+        };
+        let cases = [
+            ("epoch_s", "1704157200.25"),
+            ("epoch_ms", "1704157200250"),
+            ("epoch_us", "1704157200250000"),
+            ("epoch_ns", "1704157200250000000"),
+        ];
+        for (format, s) in cases {
+            let path = write(&dir, "a.csv", &format!("{},1,1\n", s));
+            let res = schema_of(format).read_ticks(&[&path], &Default::default()).unwrap();
+            assert!(res.errors.is_empty(), "{:?}", res.errors);
+            // 01:00 UTC is 09:00 at the exchange
+            assert_eq!(res.data[0].1.t, [at("2024-01-02 09:00:00.25")], "{}", format);
+        }
+        let overflows = [
+            ("epoch_s", "1e300"),
+            ("epoch_s", "NaN"),
+            ("epoch_s", "9223372037"),
+            ("epoch_ms", "9223372036854775"),
+            ("epoch_us", "-9223372036854776"),
+        ];
+        for (format, s) in overflows {
+            let path = write(&dir, "a.csv", &format!("{},1,1\n", s));
+            let res = schema_of(format).read_ticks(&[&path], &Default::default()).unwrap();
+            assert!(res.data.is_empty());
+            assert_eq!(res.errors.len(), 1);
+            assert!(res.errors[0].msg.contains("out of range"), "{}: {}", format, res.errors[0]);
+        }
+    }
+}
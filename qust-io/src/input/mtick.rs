@@ -0,0 +1,400 @@
+use crate::input::{
+    columnar::{da_days, days_da, dt_micros, micros_dt},
+    ticks::GenDi,
+};
+use anyhow::{anyhow, bail, Result};
+use memmap2::Mmap;
+use qust::prelude::*;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+const col_names: [&str; 8] = ["t", "c", "v", "ct", "bid1", "ask1", "bid1_v", "ask1_v"];
+const index_name: &str = "index";
+
+/* #region Mtick */
+/// An append-only tick store, a directory of column files for each ticker, `t` in
+/// microseconds, `ct` in i32 and the rest in f32, all in the byte order of the machine,
+/// and an `index` of the date and the end row of each day. The index is written last,
+/// so the rows of a day show once all its columns are. One process appends at a time;
+/// any number read, sharing the pages of the files.
+pub struct Mtick {
+    pub path: PathBuf,
+}
+
+impl Mtick {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    fn ticker_dir(&self, ticker: Ticker) -> PathBuf {
+        self.path.join(ticker.to_string())
+    }
+
+    fn read_index(dir: &Path) -> Result<Vec<(da, usize)>> {
+        let bytes = match std::fs::read(dir.join(index_name)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let records: &[[i64; 2]] =
+            bytemuck::try_cast_slice(&bytes[..bytes.len() / 16 * 16]).map_err(|e| anyhow!("{:?}", e))?;
+        records.iter().map(|x| Ok((days_da(x[0])?, x[1] as usize))).collect()
+    }
+
+    /// The dates stored of the ticker.
+    pub fn dates(&self, ticker: Ticker) -> Result<Vec<da>> {
+        Ok(Self::read_index(&self.ticker_dir(ticker))?.into_iter().map(|x| x.0).collect())
+    }
+
+    /// Appends the ticks of a day after the last day stored.
+    pub fn append(&self, ticker: Ticker, date: da, price: &PriceTick) -> Result<()> {
+        let dir = self.ticker_dir(ticker);
+        std::fs::create_dir_all(&dir)?;
+        let index = Self::read_index(&dir)?;
+        if let Some((last, _)) = index.last() {
+            if date <= *last {
+                bail!("{} {} is not after the last day stored, {}", ticker, date, last);
+            }
+        }
+        let rows = index.last().map(|x| x.1).unwrap_or(0);
+        let n = price.t.len();
+        if [price.c.len(), price.v.len(), price.ct.len(), price.bid1.len(), price.ask1.len(), price.bid1_v.len(), price.ask1_v.len()]
+            .iter()
+            .any(|x| *x != n)
+        {
+            bail!("{} {}: columns of different lengths", ticker, date);
+        }
+        let t = price.t.map(dt_micros);
+        let cols: [&[u8]; 8] = [
+            bytemuck::cast_slice(&t),
+            bytemuck::cast_slice(&price.c),
+            bytemuck::cast_slice(&price.v),
+            bytemuck::cast_slice(&price.ct),
+            bytemuck::cast_slice(&price.bid1),
+            bytemuck::cast_slice(&price.ask1),
+            bytemuck::cast_slice(&price.bid1_v),
+            bytemuck::cast_slice(&price.ask1_v),
+        ];
+        for (name, bytes) in col_names.iter().zip(cols) {
+            let file = OpenOptions::new().create(true).truncate(false).write(true).open(dir.join(name))?;
+            // rows past the index are of an append that did not finish
+            let size = if *name == "t" { 8 } else { 4 };
+            file.set_len((rows * size) as u64)?;
+            file.sync_all()?;
+            let mut file = OpenOptions::new().append(true).open(dir.join(name))?;
+            file.write_all(bytes)?;
+            file.sync_data()?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(dir.join(index_name))?;
+        file.write_all(bytemuck::cast_slice(&[[da_days(&date), (rows + n) as i64]]))?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Appends the days of the ticker in `Rtick` after the last day stored. Returns the
+    /// number of days appended.
+    pub fn import(&self, gen: &GenDi, ticker: Ticker) -> Result<usize> {
+        let last = self.dates(ticker)?.last().cloned();
+        let dates = gen
+            .source()
+            .tick_dates(ticker)?
+            .into_iter()
+            .filter(|x| last.map(|last| *x > last).unwrap_or(true))
+            .collect_vec();
+        for date in dates.iter() {
+            let price = gen
+                .get_tick(ticker, ForCompare::Between(*date..*date + chrono::Duration::days(1)))
+                .ok_or_else(|| anyhow!("no ticks of {} {}", ticker, date))?;
+            self.append(ticker, *date, &price)?;
+        }
+        Ok(dates.len())
+    }
+
+    pub fn open(&self, ticker: Ticker) -> Result<MtickReader> {
+        MtickReader::open(&self.ticker_dir(ticker))
+    }
+}
+
+impl GenDi {
+    /// The `Mtick` store in `<path>/Mtick`.
+    pub fn mtick(&self) -> Mtick {
+        Mtick::new(Path::new(self.0).join("Mtick"))
+    }
+}
+/* #endregion */
+
+/* #region MtickReader */
+/// The columns of a ticker mapped from its files, up to the rows of the index when opened.
+pub struct MtickReader {
+    index: Vec<(da, usize)>,
+    maps: Vec<Mmap>,
+}
+
+/// Ticks borrowed from the mapped files.
+#[derive(Clone, Copy)]
+pub struct TickSlice<'a> {
+    pub t: &'a [i64],
+    pub c: &'a [f32],
+    pub v: &'a [f32],
+    pub ct: &'a [i32],
+    pub bid1: &'a [f32],
+    pub ask1: &'a [f32],
+    pub bid1_v: &'a [f32],
+    pub ask1_v: &'a [f32],
+}
+
+impl MtickReader {
+    fn open(dir: &Path) -> Result<Self> {
+        let index = Mtick::read_index(dir)?;
+        let rows = index.last().map(|x| x.1).unwrap_or(0);
+        if rows == 0 {
+            return Ok(Self { index, maps: vec![] });
+        }
+        let maps = col_names
+            .iter()
+            .map(|name| {
+                let size = if *name == "t" { 8 } else { 4 };
+                let file = File::open(dir.join(name))?;
+                // SAFETY: the store is only appended to and a day's rows are in the files
+                // before the index shows them, so the rows read are not written again.
+                let map = unsafe { Mmap::map(&file)? };
+                if map.len() < rows * size {
+                    bail!("{:?} {} has less than the {} rows of the index", dir, name, rows);
+                }
+                Ok(map)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { index, maps })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.last().map(|x| x.1).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dates(&self) -> Vec<da> {
+        self.index.map(|x| x.0)
+    }
+
+    fn col<T: bytemuck::Pod>(&self, i: usize, rows: Range<usize>) -> &[T] {
+        if self.maps.is_empty() {
+            return &[];
+        }
+        let size = std::mem::size_of::<T>();
+        bytemuck::cast_slice(&self.maps[i][rows.start * size..rows.end * size])
+    }
+
+    /// The ticks of the rows.
+    pub fn rows(&self, rows: Range<usize>) -> TickSlice<'_> {
+        TickSlice {
+            t: self.col(0, rows.clone()),
+            c: self.col(1, rows.clone()),
+            v: self.col(2, rows.clone()),
+            ct: self.col(3, rows.clone()),
+            bid1: self.col(4, rows.clone()),
+            ask1: self.col(5, rows.clone()),
+            bid1_v: self.col(6, rows.clone()),
+            ask1_v: self.col(7, rows),
+        }
+    }
+
+    /// The ticks of a day, found in the index.
+    pub fn day(&self, date: da) -> Option<TickSlice<'_>> {
+        let i = self.index.binary_search_by_key(&date, |x| x.0).ok()?;
+        let start = if i == 0 { 0 } else { self.index[i - 1].1 };
+        Some(self.rows(start..self.index[i].1))
+    }
+
+    fn range_rows(&self, range: &ForCompare<dt>) -> Vec<Range<usize>> {
+        let t: &[i64] = self.col(0, 0..self.len());
+        let at = |x: &dt| t.partition_point(|y| *y < dt_micros(x));
+        let run = match range {
+            ForCompare::After(x) => at(x)..t.len(),
+            ForCompare::Before(x) => 0..at(x),
+            ForCompare::Between(x) => at(&x.start)..at(&x.end).max(at(&x.start)),
+            ForCompare::List(x) => {
+                let mut rows = x.iter().flat_map(|x| self.range_rows(x)).collect_vec();
+                rows.sort_by_key(|x| x.start);
+                return rows.into_iter().fold(vec![], |mut accu: Vec<Range<usize>>, x| {
+                    match accu.last_mut() {
+                        Some(last) if x.start <= last.end => last.end = last.end.max(x.end),
+                        _ => accu.push(x),
+                    }
+                    accu
+                });
+            }
+        };
+        std::iter::once(run).filter(|x| !x.is_empty()).collect()
+    }
+
+    /// The ticks in the range, a slice for each run of rows, found by bisecting the times.
+    pub fn range(&self, range: &ForCompare<dt>) -> Vec<TickSlice<'_>> {
+        self.range_rows(range).into_iter().map(|x| self.rows(x)).collect()
+    }
+}
+
+impl TickSlice<'_> {
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    pub fn t_at(&self, i: usize) -> Result<dt> {
+        micros_dt(self.t[i])
+    }
+
+    pub fn to_price_tick(&self) -> Result<PriceTick> {
+        Ok(PriceTick {
+            t: self.t.iter().map(|x| micros_dt(*x)).collect::<Result<_>>()?,
+            c: self.c.to_vec(),
+            v: self.v.to_vec(),
+            ct: self.ct.to_vec(),
+            bid1: self.bid1.to_vec(),
+            ask1: self.ask1.to_vec(),
+            bid1_v: self.bid1_v.to_vec(),
+            ask1_v: self.ask1_v.to_vec(),
+        })
+    }
+
+    pub fn to_tick_data(&self) -> Result<Vec<TickData>> {
+        (0..self.len())
+            .map(|i| Ok(TickData {
+                t: self.t_at(i)?,
+                c: self.c[i],
+                v: self.v[i],
+                bid1: self.bid1[i],
+                ask1: self.ask1[i],
+                bid1_v: self.bid1_v[i],
+                ask1_v: self.ask1_v[i],
+                ct: self.ct[i],
+            }))
+            .collect()
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn day(d: u32) -> da {
+        da::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    fn at(d: u32, secs: u32) -> dt {
+        day(d).and_hms_opt(9, 0, secs).unwrap()
+    }
+
+    /// `n` ticks of day `d`, a second apart from 09:00:00.
+    fn price_tick(d: u32, n: u32) -> PriceTick {
+        let c = (0..n).map(|i| d as f32 * 100. + i as f32).collect_vec();
+        PriceTick {
+            t: (0..n).map(|i| at(d, i)).collect(),
+            c: c.clone(),
+            v: vec![1.; n as usize],
+            ct: (0..n as i32).collect(),
+            bid1: c.map(|x| x - 1.),
+            ask1: c.map(|x| x + 1.),
+            bid1_v: vec![2.; n as usize],
+            ask1_v: vec![3.; n as usize],
+        }
+    }
+
+    fn json(x: &PriceTick) -> String {
+        serde_json::to_string(x).unwrap()
+    }
+
+    fn cs(slices: &[TickSlice]) -> Vec<Vec<f32>> {
+        slices.iter().map(|x| x.c.to_vec()).collect()
+    }
+
+    #[test]
+    fn appended_days_read_back() {
+        let mtick = Mtick::new(temp_dir("appended_days_read_back"));
+        assert!(mtick.open(Ticker::rb).unwrap().is_empty());
+        mtick.append(Ticker::rb, day(2), &price_tick(2, 3)).unwrap();
+        mtick.append(Ticker::rb, day(3), &price_tick(3, 4)).unwrap();
+        assert_eq!(mtick.dates(Ticker::rb).unwrap(), vec![day(2), day(3)]);
+
+        let reader = mtick.open(Ticker::rb).unwrap();
+        assert_eq!((reader.len(), reader.dates()), (7, vec![day(2), day(3)]));
+        assert_eq!(json(&reader.day(day(3)).unwrap().to_price_tick().unwrap()), json(&price_tick(3, 4)));
+        assert_eq!(reader.day(day(2)).unwrap().to_tick_data().unwrap()[2].t, at(2, 2));
+        assert!(reader.day(day(4)).is_none());
+
+        assert_eq!(cs(&reader.range(&ForCompare::Between(at(2, 1)..at(3, 2)))), vec![vec![201., 202., 300., 301.]]);
+        assert_eq!(cs(&reader.range(&at(3, 3).after())), vec![vec![303.]]);
+        assert!(reader.range(&at(2, 0).before()).is_empty());
+        let list = |x: Vec<ForCompare<dt>>| ForCompare::List(x.into_iter().map(Box::new).collect());
+        let overlapping = list(vec![ForCompare::Between(at(3, 1)..at(3, 3)), ForCompare::Between(at(2, 2)..at(3, 2))]);
+        assert_eq!(cs(&reader.range(&overlapping)), vec![vec![202., 300., 301., 302.]]);
+        let apart = list(vec![at(3, 3).after(), at(2, 1).before()]);
+        assert_eq!(cs(&reader.range(&apart)), vec![vec![200.], vec![303.]]);
+    }
+
+    #[test]
+    fn partial_append_is_cut() {
+        let mtick = Mtick::new(temp_dir("partial_append_is_cut"));
+        mtick.append(Ticker::rb, day(2), &price_tick(2, 3)).unwrap();
+        let dir = mtick.ticker_dir(Ticker::rb);
+        for name in ["t", "c", "v"] {
+            let mut file = OpenOptions::new().append(true).open(dir.join(name)).unwrap();
+            file.write_all(&[7; 24]).unwrap();
+        }
+        let reader = mtick.open(Ticker::rb).unwrap();
+        assert_eq!(reader.len(), 3);
+        assert_eq!(json(&reader.day(day(2)).unwrap().to_price_tick().unwrap()), json(&price_tick(2, 3)));
+
+        mtick.append(Ticker::rb, day(3), &price_tick(3, 2)).unwrap();
+        assert_eq!(std::fs::metadata(dir.join("t")).unwrap().len(), 5 * 8);
+        assert_eq!(std::fs::metadata(dir.join("c")).unwrap().len(), 5 * 4);
+        let reader = mtick.open(Ticker::rb).unwrap();
+        assert_eq!(json(&reader.day(day(3)).unwrap().to_price_tick().unwrap()), json(&price_tick(3, 2)));
+        assert_eq!(reader.day(day(2)).unwrap().c, &[200., 201., 202.]);
+    }
+
+    #[test]
+    fn days_not_after_the_last_are_refused() {
+        let mtick = Mtick::new(temp_dir("days_not_after_the_last_are_refused"));
+        mtick.append(Ticker::rb, day(3), &price_tick(3, 2)).unwrap();
+        assert!(mtick.append(Ticker::rb, day(3), &price_tick(3, 2)).is_err());
+        assert!(mtick.append(Ticker::rb, day(2), &price_tick(2, 2)).is_err());
+        let mut uneven = price_tick(4, 2);
+        uneven.c.pop();
+        assert!(mtick.append(Ticker::rb, day(4), &uneven).is_err());
+        assert_eq!(mtick.dates(Ticker::rb).unwrap(), vec![day(3)]);
+        assert_eq!(mtick.open(Ticker::rb).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn import_appends_the_new_days() {
+        let dir = temp_dir("import_appends_the_new_days");
+        let gen = GenDi(dir.to_str().unwrap().to_string().leak());
+        let tick_dir = dir.join("Rtick").join(Ticker::rb.to_string());
+        std::fs::create_dir_all(&tick_dir).unwrap();
+        let save = |d: u32| price_tick(d, 2).sof(&day(d).to_string(), tick_dir.to_str().unwrap());
+        save(2);
+        save(3);
+        let mtick = gen.mtick();
+        assert_eq!(mtick.import(&gen, Ticker::rb).unwrap(), 2);
+        assert_eq!(mtick.import(&gen, Ticker::rb).unwrap(), 0);
+        save(4);
+        assert_eq!(mtick.import(&gen, Ticker::rb).unwrap(), 1);
+        let reader = mtick.open(Ticker::rb).unwrap();
+        assert_eq!(reader.dates(), vec![day(2), day(3), day(4)]);
+        [2, 3, 4].into_iter().for_each(|d| {
+            assert_eq!(json(&reader.day(day(d)).unwrap().to_price_tick().unwrap()), json(&price_tick(d, 2)));
+        });
+    }
+}
@@ -0,0 +1,363 @@
+use qust::prelude::*;
+use qust::std_prelude::*;
+use super::ticks::GenDi;
+use chrono::{Datelike, Duration, Timelike};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// The trading day a tick belongs to: ticks after 18:00 or before 03:00 are the night
+/// session of the next weekday. Holidays are not taken into account.
+pub fn trading_date(t: &dt) -> da {
+    let session_date = match t.hour() {
+        h if h >= 18 => t.date(),
+        h if h < 3 => t.date() - Duration::days(1),
+        _ => return t.date(),
+    };
+    let mut res = session_date + Duration::days(1);
+    while res.weekday().number_from_monday() > 5 {
+        res += Duration::days(1);
+    }
+    res
+}
+
+fn read_journal(path: &PathBuf) -> io::Result<(Vec<TickData>, u64)> {
+    let bytes = std::fs::read(path)?;
+    let mut cursor = Cursor::new(bytes.as_slice());
+    let mut res = vec![];
+    let mut valid_len = 0;
+    while let Ok(tick_data) = bincode::deserialize_from::<_, TickData>(&mut cursor) {
+        res.push(tick_data);
+        valid_len = cursor.position();
+    }
+    Ok((res, valid_len))
+}
+
+fn merge_tick_data(mut tick_data: Vec<TickData>) -> Vec<TickData> {
+    tick_data.sort_by_key(|x| x.t);
+    tick_data.dedup_by_key(|x| x.t);
+    tick_data
+}
+
+struct TickJournal {
+    date: da,
+    last_t: dt,
+    writer: BufWriter<File>,
+}
+
+/// Ticks of the current trading day are appended to `<path>/Rtick_journal/<ticker>/<date>`
+/// and flushed after every batch. When the trading day rolls, or the recorder stops, the
+/// journal is compacted into `<path>/Rtick/<ticker>/<date>`, the file `GenDi::get_tick` reads.
+pub struct TickStore {
+    pub path: &'static str,
+    ticker_contract_map: hm<Ticker, &'static str>,
+    journal_map: hm<Ticker, Mutex<Option<TickJournal>>>,
+}
+
+impl TickStore {
+    pub fn new(path: &'static str, ticker_contract_map: hm<Ticker, &'static str>) -> Self {
+        let journal_map = ticker_contract_map
+            .keys()
+            .map(|ticker| (*ticker, Mutex::new(None)))
+            .collect();
+        Self { path, ticker_contract_map, journal_map }
+    }
+
+    fn tick_path(&self, ticker: Ticker) -> PathBuf {
+        PathBuf::from(self.path).join("Rtick").join(ticker.to_string())
+    }
+
+    fn journal_path(&self, ticker: Ticker) -> PathBuf {
+        PathBuf::from(self.path).join("Rtick_journal").join(ticker.to_string())
+    }
+
+    fn read_saved(&self, ticker: Ticker, date: da) -> Vec<TickData> {
+        let tick_path = self.tick_path(ticker);
+        match tick_path.join(date.to_string()).is_file() {
+            true => rof::<PriceTick>(&date.to_string(), tick_path.to_str().unwrap()).to_tick_data(),
+            false => vec![],
+        }
+    }
+
+    /// Reopens the journal of the day, dropping a record cut by a crash, so that the ticks
+    /// resent after a reconnect are deduped against what is already on disk.
+    fn open_journal(&self, ticker: Ticker, date: da) -> io::Result<TickJournal> {
+        let journal_dir = self.journal_path(ticker);
+        std::fs::create_dir_all(&journal_dir)?;
+        let journal_file = journal_dir.join(date.to_string());
+        let (journal_data, valid_len) = match journal_file.is_file() {
+            true => read_journal(&journal_file)?,
+            false => (vec![], 0),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&journal_file)?;
+        file.set_len(valid_len)?;
+        let last_t = self
+            .read_saved(ticker, date)
+            .iter()
+            .chain(journal_data.iter())
+            .map(|x| x.t)
+            .max()
+            .unwrap_or_default();
+        loge!(ticker, "recorder open journal {}, {} ticks recorded", date, journal_data.len());
+        Ok(TickJournal { date, last_t, writer: BufWriter::new(file) })
+    }
+
+    fn compact_journal(&self, ticker: Ticker, date: da) -> io::Result<()> {
+        let journal_file = self.journal_path(ticker).join(date.to_string());
+        if !journal_file.is_file() {
+            return Ok(());
+        }
+        let (mut journal_data, _) = read_journal(&journal_file)?;
+        let mut tick_data = self.read_saved(ticker, date);
+        tick_data.append(&mut journal_data);
+        let price_tick = PriceTick::from_tick_data(&merge_tick_data(tick_data));
+        let tick_dir = self.tick_path(ticker);
+        std::fs::create_dir_all(&tick_dir)?;
+        let tmp_name = format!("{}.tmp", date);
+        price_tick.sof(&tmp_name, self.journal_path(ticker).to_str().unwrap());
+        std::fs::rename(self.journal_path(ticker).join(tmp_name), tick_dir.join(date.to_string()))?;
+        std::fs::remove_file(journal_file)?;
+        loge!(ticker, "recorder compact journal {}, {} ticks saved", date, price_tick.t.len());
+        Ok(())
+    }
+
+    fn finish_journal(&self, ticker: Ticker, journal_opt: &mut Option<TickJournal>) -> io::Result<()> {
+        if let Some(mut journal) = journal_opt.take() {
+            journal.writer.flush()?;
+            drop(journal.writer);
+            self.compact_journal(ticker, journal.date)?;
+        }
+        Ok(())
+    }
+
+    /// Compacts the journals left by an earlier run that are not of the current trading day.
+    pub fn recover(&self) -> io::Result<()> {
+        let date_now = trading_date(&chrono::Local::now().naive_local());
+        for ticker in self.ticker_contract_map.keys() {
+            let date_vec = self
+                .journal_path(*ticker)
+                .get_file_vec()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|x| da::parse_from_str(&x, "%Y-%m-%d").ok())
+                .filter(|x| *x != date_now)
+                .collect_vec();
+            for date in date_vec {
+                self.compact_journal(*ticker, date)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, ticker: Ticker, tick_data: &[TickData]) -> io::Result<()> {
+        let Some(journal_mutex) = self.journal_map.get(&ticker) else {
+            return Ok(());
+        };
+        let mut journal_opt = journal_mutex.lock().unwrap();
+        for tick in tick_data.iter() {
+            let date = trading_date(&tick.t);
+            if journal_opt.as_ref().map(|x| x.date) != Some(date) {
+                self.finish_journal(ticker, &mut journal_opt)?;
+                *journal_opt = Some(self.open_journal(ticker, date)?);
+            }
+            let journal = journal_opt.as_mut().unwrap();
+            if tick.t <= journal.last_t {
+                continue;
+            }
+            bincode::serialize_into(&mut journal.writer, tick).map_err(io::Error::other)?;
+            journal.last_t = tick.t;
+        }
+        if let Some(journal) = journal_opt.as_mut() {
+            journal.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self, ticker: Ticker) -> io::Result<()> {
+        match self.journal_map.get(&ticker) {
+            Some(journal_mutex) => self.finish_journal(ticker, &mut journal_mutex.lock().unwrap()),
+            None => Ok(()),
+        }
+    }
+
+    /// The recorded ticks of trading days after `date`, the journal of today included.
+    pub fn get_tick_after(&self, ticker: Ticker, date: da) -> PriceTick {
+        let mut tick_data = GenDi(self.path)
+            .get_tick(ticker, (date + Duration::days(1)).after())
+            .map(|x| x.to_tick_data())
+            .unwrap_or_default();
+        let journal_dir = self.journal_path(ticker);
+        journal_dir
+            .get_file_vec()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| da::parse_from_str(x, "%Y-%m-%d").map(|x| x > date).unwrap_or(false))
+            .for_each(|x| {
+                if let Ok((mut journal_data, _)) = read_journal(&journal_dir.join(x)) {
+                    tick_data.append(&mut journal_data);
+                }
+            });
+        PriceTick::from_tick_data(&merge_tick_data(tick_data))
+    }
+
+    /// Like `GenDi::update_dil`, but the days are trading days, and the ticks of today that
+    /// are still in the journal are used too.
+    pub fn update_dil(&self, dil: &mut Dil) {
+        dil.dil.iter_mut().for_each(|x| {
+            let Some(max_time) = x.pcon.price.t.last() else {
+                return;
+            };
+            let tick_data = self.get_tick_after(x.pcon.ticker, trading_date(max_time));
+            let mut price_data = tick_data.to_price_ori(x.pcon.inter.clone(), x.pcon.ticker);
+            x.pcon.price.cat(&mut price_data);
+        });
+    }
+
+    async fn start_spy_on_data_receive(&self, trade_api: Arc<TradeApi>, mut shutdown: broadcast::Receiver<()>) {
+        let ticker = trade_api.ticker;
+        loge!("spy", "recorder start to receive data: {:?}", ticker);
+        while let Some(data_receive_vec) = trade_api.data_receive.recv_batch(&mut shutdown).await {
+            let tick_data = data_receive_vec
+                .into_iter()
+                .filter_map(|x| match x {
+                    DataReceive::TickData(tick_data) => Some(tick_data),
+                    _ => None,
+                })
+                .collect_vec();
+            if let Err(e) = self.record(ticker, &tick_data) {
+                loge!(level: Error, ticker, "recorder write ticks failed: {:?}", e);
+            }
+        }
+        loge!("spy", "recorder stop to receive data: {:?}", ticker);
+    }
+}
+
+/// A `ServiceApi` that records the ticks it receives. Its `TradeApi`s only receive data,
+/// so they can be fed by any md source, e.g. as the md follower of `CtpApi::new_account`.
+pub struct TickRecorder {
+    pub tick_store: Arc<TickStore>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl TickRecorder {
+    pub fn new(path: &'static str, ticker_contract_map: hm<Ticker, &'static str>) -> Self {
+        Self {
+            tick_store: Arc::new(TickStore::new(path, ticker_contract_map)),
+            handles: Default::default(),
+        }
+    }
+
+    pub fn get_trade_api_vec(&self) -> Vec<Arc<TradeApi>> {
+        self.tick_store
+            .ticker_contract_map
+            .iter()
+            .map(|(ticker, contract)| TradeApi::new(contract, *ticker).pip(Arc::new))
+            .collect_vec()
+    }
+
+    pub fn update_dil(&self, dil: &mut Dil) {
+        self.tick_store.update_dil(dil);
+    }
+}
+
+impl ServiceApi for TickRecorder {
+    async fn start(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> anyhow::Result<()> {
+        self.tick_store.recover()?;
+        let handles = trade_api_vec
+            .into_iter()
+            .map(|trade_api| {
+                let tick_store = Arc::clone(&self.tick_store);
+                let shutdown = trade_api.shutdown.subscribe();
+                tokio::spawn(async move { tick_store.start_spy_on_data_receive(trade_api, shutdown).await })
+            })
+            .collect_vec();
+        self.handles.lock().unwrap().extend(handles);
+        Ok(())
+    }
+
+    async fn stop(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> anyhow::Result<()> {
+        trade_api_vec.iter().for_each(|trade_api| trade_api.stop());
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            let _ = handle.await;
+        }
+        for trade_api in trade_api_vec.iter() {
+            self.tick_store.finish(trade_api.ticker)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn at(date: (i32, u32, u32), hms: (u32, u32, u32)) -> dt {
+        da::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(hms.0, hms.1, hms.2).unwrap()
+    }
+
+    fn tick(secs: u32) -> TickData {
+        TickData { t: at((2024, 1, 2), (9, 0, secs)), c: 3500. + secs as f32, v: 1., ..Default::default() }
+    }
+
+    fn store(path: &'static str) -> TickStore {
+        TickStore::new(path, hm::from([(Ticker::rb, "rb2405")]))
+    }
+
+    fn store_path(name: &str) -> &'static str {
+        temp_dir(name).to_str().unwrap().to_string().leak()
+    }
+
+    fn secs(tick_data: &[TickData]) -> Vec<u32> {
+        tick_data.iter().map(|x| x.t.second()).collect()
+    }
+
+    #[test]
+    fn trading_date_rolls_at_night() {
+        let day = |t| trading_date(&t).to_string();
+        assert_eq!(day(at((2024, 1, 2), (17, 59, 59))), "2024-01-02");
+        assert_eq!(day(at((2024, 1, 2), (18, 0, 0))), "2024-01-03");
+        assert_eq!(day(at((2024, 1, 3), (2, 59, 59))), "2024-01-03");
+        assert_eq!(day(at((2024, 1, 3), (3, 0, 0))), "2024-01-03");
+        assert_eq!(day(at((2024, 1, 5), (21, 0, 0))), "2024-01-08");
+        assert_eq!(day(at((2024, 1, 6), (1, 0, 0))), "2024-01-08");
+    }
+
+    #[test]
+    fn record_skips_ticks_not_newer() {
+        let tick_store = store(store_path("record_skips_ticks_not_newer"));
+        let date = trading_date(&tick(0).t);
+        tick_store.record(Ticker::rb, &[tick(1), tick(2), tick(2), tick(1), tick(3)]).unwrap();
+        tick_store.record(Ticker::rb, &[tick(3), tick(4)]).unwrap();
+        let journal_file = tick_store.journal_path(Ticker::rb).join(date.to_string());
+        let (journal_data, _) = read_journal(&journal_file).unwrap();
+        assert_eq!(secs(&journal_data), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn journal_recovers_from_a_cut_record() {
+        let path = store_path("journal_recovers_from_a_cut_record");
+        let tick_store = store(path);
+        let date = trading_date(&tick(0).t);
+        tick_store.record(Ticker::rb, &[tick(1), tick(2), tick(3)]).unwrap();
+        let journal_file = tick_store.journal_path(Ticker::rb).join(date.to_string());
+        let journal_len = std::fs::metadata(&journal_file).unwrap().len();
+        drop(tick_store);
+        OpenOptions::new().write(true).open(&journal_file).unwrap().set_len(journal_len - 3).unwrap();
+
+        let tick_store = store(path);
+        tick_store.record(Ticker::rb, &[tick(2), tick(3), tick(4)]).unwrap();
+        let (journal_data, _) = read_journal(&journal_file).unwrap();
+        assert_eq!(secs(&journal_data), vec![1, 2, 3, 4]);
+        assert_eq!(journal_data[2].c, 3503.);
+
+        tick_store.finish(Ticker::rb).unwrap();
+        assert!(!journal_file.is_file());
+        assert_eq!(secs(&tick_store.read_saved(Ticker::rb, date)), vec![1, 2, 3, 4]);
+        let price_tick = tick_store.get_tick_after(Ticker::rb, date - Duration::days(1));
+        assert_eq!(price_tick.t.len(), 4);
+    }
+}
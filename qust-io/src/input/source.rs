@@ -0,0 +1,565 @@
+use crate::input::{
+    columnar::{dt_micros, micros_dt, SofParquet},
+    ticks::TickFormat,
+};
+use crate::output::profile::StatsRes;
+use anyhow::{anyhow, Result};
+use qust::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/* #region DataSource */
+/// Where `GenDi` keeps the klines of each inter and the ticks of each day.
+pub trait DataSource: Send + Sync {
+    fn get_pcon(&self, inter: &TriBox, ticker: Ticker) -> Result<Option<Pcon>>;
+    fn save_pcon(&self, pcon: &Pcon) -> Result<()>;
+    /// The tickers with klines of the inter.
+    fn pcon_tickers(&self, inter: &TriBox) -> Result<Vec<Ticker>>;
+    /// The dates of the ticks of the ticker, sorted. An error if there are none.
+    fn tick_dates(&self, ticker: Ticker) -> Result<Vec<da>>;
+    fn get_tick_day(&self, ticker: Ticker, date: da) -> Result<PriceTick>;
+    /// The ticks of each day of the ticker `keep` takes, in order, or `None` if there are
+    /// no ticks of the ticker.
+    fn get_tick_days(&self, ticker: Ticker, keep: &dyn Fn(&da) -> bool) -> Result<Option<Vec<PriceTick>>> {
+        let Ok(dates) = self.tick_dates(ticker) else {
+            return Ok(None);
+        };
+        dates
+            .into_iter()
+            .filter(|x| keep(x))
+            .map(|x| self.get_tick_day(ticker, x))
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+    fn save_tick_day(&self, price: &PriceTick, ticker: Ticker, date: da) -> Result<()>;
+    /// Deletes the ticks of the days in the range of every ticker.
+    fn delete_ticks(&self, range: &ForCompare<dt>) -> Result<()>;
+}
+
+/// An SQLite file for a path ending with `.db` or `.sqlite`, otherwise a directory tree.
+pub fn data_source(path: &str) -> Box<dyn DataSource> {
+    if path.ends_with(".db") || path.ends_with(".sqlite") {
+        Box::new(SqliteSource::new(path))
+    } else {
+        Box::new(DirSource::new(path))
+    }
+}
+/* #endregion */
+
+/* #region DirSource */
+/// Klines in `<path>/<inter>/<ticker>` and ticks in `Rtick/<ticker>/<date>`, with bincode.
+pub struct DirSource {
+    pub path: PathBuf,
+}
+
+impl DirSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    fn tick_dir(&self, ticker: Ticker) -> PathBuf {
+        self.path.join("Rtick").join(ticker.to_string())
+    }
+
+    /// The dates of the tick files of the ticker, sorted, each in the format read for it.
+    pub fn tick_files(&self, ticker: Ticker) -> Option<Vec<(da, TickFormat)>> {
+        let mut res = self
+            .tick_dir(ticker)
+            .get_file_vec()
+            .ok()?
+            .iter()
+            .map(|x| TickFormat::of_file(x))
+            .collect_vec();
+        res.sort_by_key(|x| (x.0, x.1 == TickFormat::Bincode));
+        res.dedup_by_key(|x| x.0);
+        Some(res)
+    }
+}
+
+impl DataSource for DirSource {
+    fn get_pcon(&self, inter: &TriBox, ticker: Ticker) -> Result<Option<Pcon>> {
+        let path = self.path.join(inter.debug_string());
+        if !path.join(ticker.to_string()).exists() {
+            return Ok(None);
+        }
+        Ok(Some(<Pcon as Sof>::rof(&ticker.to_string(), path.to_str().unwrap())))
+    }
+
+    fn save_pcon(&self, pcon: &Pcon) -> Result<()> {
+        let path = self.path.join(pcon.inter.debug_string());
+        if !path.is_dir() {
+            std::fs::create_dir(&path)?;
+        }
+        pcon.sof(&pcon.ticker.to_string(), path.to_str().unwrap());
+        Ok(())
+    }
+
+    fn pcon_tickers(&self, inter: &TriBox) -> Result<Vec<Ticker>> {
+        Ok(self
+            .path
+            .join(inter.debug_string())
+            .get_file_vec()
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.into_ticker().unwrap())
+            .collect_vec())
+    }
+
+    fn tick_dates(&self, ticker: Ticker) -> Result<Vec<da>> {
+        let files = self.tick_files(ticker).ok_or_else(|| anyhow!("no tick files of {}", ticker))?;
+        Ok(files.into_iter().map(|x| x.0).collect_vec())
+    }
+
+    fn get_tick_day(&self, ticker: Ticker, date: da) -> Result<PriceTick> {
+        let path = self.tick_dir(ticker);
+        let p_str = path.to_str().unwrap();
+        if path.join(TickFormat::Parquet.file_name(date)).exists() {
+            PriceTick::rof_parquet(&TickFormat::Parquet.file_name(date), p_str)
+        } else {
+            Ok(rof::<PriceTick>(&TickFormat::Bincode.file_name(date), p_str))
+        }
+    }
+
+    fn save_tick_day(&self, price: &PriceTick, ticker: Ticker, date: da) -> Result<()> {
+        price.sof(&TickFormat::Bincode.file_name(date), self.tick_dir(ticker).to_str().unwrap());
+        Ok(())
+    }
+
+    fn delete_ticks(&self, range: &ForCompare<dt>) -> Result<()> {
+        let p = self.path.join("Rtick");
+        for x in p.get_file_vec()?.iter() {
+            let pp = p.join(x);
+            for a in pp.get_file_vec()?.iter() {
+                let date = TickFormat::of_file(a).0;
+                if range.compare_time(&date) {
+                    pp.join(a).remove();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+/* #endregion */
+
+/* #region SqliteSource */
+/// The metadata of a contract, as in `Ticker::info`.
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub ticker: Ticker,
+    pub tz: f32,
+    pub pv: f32,
+    pub slip: f32,
+    pub comm_fixed: f32,
+    pub comm_pct: f32,
+}
+
+impl From<Ticker> for Instrument {
+    fn from(ticker: Ticker) -> Self {
+        let info = ticker.info();
+        let (comm_fixed, comm_pct) = match info.comm {
+            Comm::F(i) => (i, 0.),
+            Comm::P(i) => (0., i),
+        };
+        Self { ticker, tz: info.tz, pv: info.pv, slip: info.slip, comm_fixed, comm_pct }
+    }
+}
+
+/// Which results `SqliteSource::results` returns, every one of them by default.
+#[derive(Debug, Clone, Default)]
+pub struct ResultFilter {
+    pub run: Option<String>,
+    pub name: Option<String>,
+    /// The least sharpe ratio.
+    pub min_sr: Option<f32>,
+}
+
+/// A backtest result saved with `SqliteSource::save_result`.
+#[derive(Debug, Clone)]
+pub struct ResultRow {
+    pub id: i64,
+    pub run: String,
+    pub name: String,
+    pub created: dt,
+    pub stra: String,
+    pub stats: StatsRes,
+}
+
+const stats_cols: &str = "ret, sr, cratio, profit, comm, slip, to_day, to_sum, hold, std, mdd";
+
+const schema_sql: &str = "
+CREATE TABLE IF NOT EXISTS instrument (
+    ticker TEXT PRIMARY KEY, tz REAL, pv REAL, slip REAL, comm_fixed REAL, comm_pct REAL
+);
+CREATE TABLE IF NOT EXISTS pcon (
+    ticker TEXT, inter TEXT, inter_json TEXT, immut_info BLOB,
+    PRIMARY KEY (ticker, inter)
+);
+CREATE TABLE IF NOT EXISTS kline (
+    ticker TEXT, inter TEXT, t INTEGER, o REAL, h REAL, l REAL, c REAL, v REAL,
+    open_time INTEGER, pass_last INTEGER, pass_this INTEGER, contract INTEGER,
+    PRIMARY KEY (ticker, inter, t)
+);
+CREATE TABLE IF NOT EXISTS tick_day (
+    ticker TEXT, date TEXT, n INTEGER, data BLOB,
+    PRIMARY KEY (ticker, date)
+);
+CREATE TABLE IF NOT EXISTS result (
+    id INTEGER PRIMARY KEY AUTOINCREMENT, run TEXT, name TEXT, created TEXT, stra TEXT,
+    ret REAL, sr REAL, cratio REAL, profit REAL, comm REAL, slip REAL, to_day REAL,
+    to_sum REAL, hold REAL, std REAL, mdd REAL
+);
+CREATE INDEX IF NOT EXISTS result_run ON result (run);
+";
+
+fn parse_ticker(s: String) -> Result<Ticker> {
+    s.as_str().into_ticker().ok_or_else(|| anyhow!("unknown ticker {}", s))
+}
+
+/// One SQLite file, of contracts, klines, the ticks of each day and backtest results.
+/// Klines are rows, to be queried by time, and the ticks of a day one bincode blob. Each
+/// call opens its own connection, in WAL mode, so threads and processes share the file.
+pub struct SqliteSource {
+    pub path: PathBuf,
+    schema_made: AtomicBool,
+}
+
+impl SqliteSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf(), schema_made: AtomicBool::new(false) }
+    }
+
+    /// A connection to the file, the tables made and WAL set by the first one.
+    pub fn conn(&self) -> Result<Connection> {
+        let conn = Connection::open(&self.path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(30))?;
+        if !self.schema_made.load(Ordering::Acquire) {
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.execute_batch(schema_sql)?;
+            self.schema_made.store(true, Ordering::Release);
+        }
+        Ok(conn)
+    }
+
+    pub fn save_instrument(&self, instrument: &Instrument) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO instrument VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                instrument.ticker.to_string(),
+                instrument.tz,
+                instrument.pv,
+                instrument.slip,
+                instrument.comm_fixed,
+                instrument.comm_pct
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn instruments(&self) -> Result<Vec<Instrument>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT * FROM instrument ORDER BY ticker")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
+        })?;
+        rows.map(|x| {
+            let (ticker, tz, pv, slip, comm_fixed, comm_pct) = x?;
+            Ok(Instrument { ticker: parse_ticker(ticker)?, tz, pv, slip, comm_fixed, comm_pct })
+        })
+        .collect()
+    }
+
+    /// The klines of the ticker and inter in the range, without the `immut_info` of the pcon.
+    pub fn get_klines(&self, inter: &TriBox, ticker: Ticker, range: &ForCompare<dt>) -> Result<PriceOri> {
+        self.klines(inter, ticker, Some(range))
+    }
+
+    fn klines(&self, inter: &TriBox, ticker: Ticker, range: Option<&ForCompare<dt>>) -> Result<PriceOri> {
+        let (start, end) = match range {
+            Some(ForCompare::After(x)) => (dt_micros(x), i64::MAX),
+            Some(ForCompare::Before(x)) => (i64::MIN, dt_micros(x)),
+            Some(ForCompare::Between(x)) => (dt_micros(&x.start), dt_micros(&x.end)),
+            Some(ForCompare::List(_)) | None => (i64::MIN, i64::MAX),
+        };
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT t, o, h, l, c, v, open_time, pass_last, pass_this, contract FROM kline
+             WHERE ticker = ?1 AND inter = ?2 AND t >= ?3 AND t < ?4 ORDER BY t",
+        )?;
+        let rows = stmt.query_map(params![ticker.to_string(), inter.debug_string(), start, end], |row| {
+            let kline_data = KlineData {
+                t: Default::default(),
+                o: row.get(1)?,
+                h: row.get(2)?,
+                l: row.get(3)?,
+                c: row.get(4)?,
+                v: row.get(5)?,
+                ki: KlineInfo {
+                    open_time: Default::default(),
+                    pass_last: row.get(7)?,
+                    pass_this: row.get(8)?,
+                    contract: row.get(9)?,
+                },
+            };
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(6)?, kline_data))
+        })?;
+        let mut res = PriceOri::with_capacity(1000);
+        for x in rows {
+            let (t, open_time, mut kline_data) = x?;
+            kline_data.t = micros_dt(t)?;
+            kline_data.ki.open_time = micros_dt(open_time)?;
+            if range.map(|x| x.compare_same(&kline_data.t)).unwrap_or(true) {
+                res.update(&kline_data);
+            }
+        }
+        res.shrink_to_fit();
+        Ok(res)
+    }
+
+    /// Saves the stats and the strategy, as JSON, of a backtest under `run`.
+    pub fn save_result<T: Serialize>(&self, run: &str, name: &str, stra: &T, stats: &StatsRes) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.execute(
+            &format!(
+                "INSERT INTO result (run, name, created, stra, {}) VALUES
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                stats_cols
+            ),
+            params![
+                run,
+                name,
+                chrono::Local::now().naive_local().to_string(),
+                serde_json::to_string(stra)?,
+                stats.ret,
+                stats.sr,
+                stats.cratio,
+                stats.profit,
+                stats.comm,
+                stats.slip,
+                stats.to_day,
+                stats.to_sum,
+                stats.hold,
+                stats.std,
+                stats.mdd
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// The results passing the filter, in the order saved.
+    pub fn results(&self, filter: &ResultFilter) -> Result<Vec<ResultRow>> {
+        let conn = self.conn()?;
+        let sql = format!(
+            "SELECT id, run, name, created, stra, {} FROM result
+             WHERE (?1 IS NULL OR run = ?1) AND (?2 IS NULL OR name = ?2) AND (?3 IS NULL OR sr >= ?3)
+             ORDER BY id",
+            stats_cols,
+        );
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params![filter.run, filter.name, filter.min_sr], |row| {
+            Ok((
+                row.get::<_, String>(3)?,
+                ResultRow {
+                    id: row.get(0)?,
+                    run: row.get(1)?,
+                    name: row.get(2)?,
+                    created: Default::default(),
+                    stra: row.get(4)?,
+                    stats: StatsRes {
+                        ret: row.get(5)?,
+                        sr: row.get(6)?,
+                        cratio: row.get(7)?,
+                        profit: row.get(8)?,
+                        comm: row.get(9)?,
+                        slip: row.get(10)?,
+                        to_day: row.get(11)?,
+                        to_sum: row.get(12)?,
+                        hold: row.get(13)?,
+                        std: row.get(14)?,
+                        mdd: row.get(15)?,
+                    },
+                },
+            ))
+        })?;
+        rows.map(|x| {
+            let (created, mut row) = x?;
+            row.created = dt::parse_from_str(&created, "%Y-%m-%d %H:%M:%S%.f")?;
+            Ok(row)
+        })
+        .collect()
+    }
+}
+
+impl DataSource for SqliteSource {
+    fn get_pcon(&self, inter: &TriBox, ticker: Ticker) -> Result<Option<Pcon>> {
+        let pcon_row = self
+            .conn()?
+            .query_row(
+                "SELECT inter_json, immut_info FROM pcon WHERE ticker = ?1 AND inter = ?2",
+                params![ticker.to_string(), inter.debug_string()],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()?;
+        let Some((inter_json, immut_info)) = pcon_row else {
+            return Ok(None);
+        };
+        let mut price = self.klines(inter, ticker, None)?;
+        price.immut_info = bincode::deserialize(&immut_info)?;
+        Ok(Some(Pcon { ticker, inter: serde_json::from_str(&inter_json)?, price }))
+    }
+
+    fn save_pcon(&self, pcon: &Pcon) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let (ticker, inter) = (pcon.ticker.to_string(), pcon.inter.debug_string());
+        tx.execute("DELETE FROM kline WHERE ticker = ?1 AND inter = ?2", params![ticker, inter])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO pcon VALUES (?1, ?2, ?3, ?4)",
+            params![ticker, inter, serde_json::to_string(&pcon.inter)?, bincode::serialize(&pcon.price.immut_info)?],
+        )?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO kline VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")?;
+            for x in pcon.price.to_kline_data() {
+                stmt.execute(params![
+                    ticker,
+                    inter,
+                    dt_micros(&x.t),
+                    x.o,
+                    x.h,
+                    x.l,
+                    x.c,
+                    x.v,
+                    dt_micros(&x.ki.open_time),
+                    x.ki.pass_last,
+                    x.ki.pass_this,
+                    x.ki.contract
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn pcon_tickers(&self, inter: &TriBox) -> Result<Vec<Ticker>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT ticker FROM pcon WHERE inter = ?1 ORDER BY ticker")?;
+        let rows = stmt.query_map(params![inter.debug_string()], |row| row.get::<_, String>(0))?;
+        rows.map(|x| parse_ticker(x?)).collect()
+    }
+
+    fn tick_dates(&self, ticker: Ticker) -> Result<Vec<da>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT date FROM tick_day WHERE ticker = ?1 ORDER BY date")?;
+        let rows = stmt.query_map(params![ticker.to_string()], |row| row.get::<_, String>(0))?;
+        let res = rows.map(|x| Ok(x?.to_da())).collect::<Result<Vec<da>>>()?;
+        if res.is_empty() {
+            return Err(anyhow!("no ticks of {}", ticker));
+        }
+        Ok(res)
+    }
+
+    fn get_tick_day(&self, ticker: Ticker, date: da) -> Result<PriceTick> {
+        let data: Vec<u8> = self.conn()?.query_row(
+            "SELECT data FROM tick_day WHERE ticker = ?1 AND date = ?2",
+            params![ticker.to_string(), date.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(bincode::deserialize(&data)?)
+    }
+
+    fn get_tick_days(&self, ticker: Ticker, keep: &dyn Fn(&da) -> bool) -> Result<Option<Vec<PriceTick>>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT date, data FROM tick_day WHERE ticker = ?1 ORDER BY date")?;
+        let mut rows = stmt.query(params![ticker.to_string()])?;
+        let (mut res, mut any) = (vec![], false);
+        while let Some(row) = rows.next()? {
+            any = true;
+            if keep(&row.get::<_, String>(0)?.to_da()) {
+                res.push(bincode::deserialize(&row.get::<_, Vec<u8>>(1)?)?);
+            }
+        }
+        Ok(any.then_some(res))
+    }
+
+    fn save_tick_day(&self, price: &PriceTick, ticker: Ticker, date: da) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO tick_day VALUES (?1, ?2, ?3, ?4)",
+            params![ticker.to_string(), date.to_string(), price.t.len() as i64, bincode::serialize(price)?],
+        )?;
+        Ok(())
+    }
+
+    fn delete_ticks(&self, range: &ForCompare<dt>) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let days = {
+            let mut stmt = tx.prepare("SELECT ticker, date FROM tick_day")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        for (ticker, date) in days {
+            if range.compare_time(&date.to_da()) {
+                tx.execute("DELETE FROM tick_day WHERE ticker = ?1 AND date = ?2", params![ticker, date])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn stats(sr: f32) -> StatsRes {
+        let (ret, cratio, profit, comm, slip, to_day, to_sum, hold, std, mdd) = Default::default();
+        StatsRes { ret, sr, cratio, profit, comm, slip, to_day, to_sum, hold, std, mdd }
+    }
+
+    fn price_tick(c: f32) -> PriceTick {
+        PriceTick { t: vec![dt::default()], c: vec![c], ..Default::default() }
+    }
+
+    #[test]
+    fn results_by_the_filter() {
+        let source = SqliteSource::new(temp_dir("results_by_the_filter").join("a.db"));
+        source.save_result("a", "x", &1, &stats(0.5)).unwrap();
+        source.save_result("a", "y", &2, &stats(1.5)).unwrap();
+        source.save_result("b'; DROP TABLE result; --", "x", &3, &stats(2.)).unwrap();
+        let stras = |filter: ResultFilter| {
+            source.results(&filter).unwrap().into_iter().map(|x| x.stra).collect_vec()
+        };
+        assert_eq!(stras(Default::default()), ["1", "2", "3"]);
+        assert_eq!(stras(ResultFilter { run: Some("a".into()), ..Default::default() }), ["1", "2"]);
+        assert_eq!(stras(ResultFilter { name: Some("x".into()), ..Default::default() }), ["1", "3"]);
+        assert_eq!(stras(ResultFilter { min_sr: Some(1.5), ..Default::default() }), ["2", "3"]);
+        let filter = ResultFilter { run: Some("b'; DROP TABLE result; --".into()), min_sr: Some(1.), ..Default::default() };
+        assert_eq!(stras(filter), ["3"]);
+        assert_eq!(SqliteSource::new(&source.path).results(&Default::default()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn tick_days_in_order() {
+        let path = temp_dir("tick_days_in_order");
+        let sources: [Box<dyn DataSource>; 2] =
+            [Box::new(DirSource::new(&path)), Box::new(SqliteSource::new(path.join("a.db")))];
+        let days = [3, 2, 4].map(|x| da::from_ymd_opt(2024, 1, x).unwrap());
+        for source in sources.iter() {
+            assert!(source.get_tick_days(Ticker::rb, &|_| true).unwrap().is_none());
+            std::fs::create_dir_all(path.join("Rtick").join(Ticker::rb.to_string())).unwrap();
+            for (i, date) in days.iter().enumerate() {
+                source.save_tick_day(&price_tick(i as f32), Ticker::rb, *date).unwrap();
+            }
+            let closes = |keep: &dyn Fn(&da) -> bool| {
+                source.get_tick_days(Ticker::rb, keep).unwrap().unwrap().iter().map(|x| x.c[0]).collect_vec()
+            };
+            assert_eq!(closes(&|_| true), [1., 0., 2.]);
+            assert_eq!(closes(&|x| *x > days[1]), [0., 2.]);
+            assert!(closes(&|_| false).is_empty());
+        }
+    }
+}
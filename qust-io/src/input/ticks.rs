@@ -1,3 +1,4 @@
+use crate::input::{columnar::SofParquet, source::{data_source, DataSource, DirSource}};
 use qust::{
     prelude::{ori, Event},
     trade::prelude::*,
@@ -5,10 +6,34 @@ use qust::{
 use chrono::Duration;
 use qust_ds::prelude::*;
 use itertools::Itertools;
-use std::{path::{Path, PathBuf},  thread};
+use std::{path::Path,  thread};
 
 pub struct GenDi(pub &'static str);
 
+/// The format of the tick files of a day, `Rtick/<ticker>/<date>` with bincode, or
+/// `Rtick/<ticker>/<date>.parquet`. `GenDi` reads either, the parquet of a day first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickFormat {
+    Bincode,
+    Parquet,
+}
+
+impl TickFormat {
+    pub(crate) fn of_file(name: &str) -> (da, TickFormat) {
+        match name.strip_suffix(".parquet") {
+            Some(date) => (date.to_da(), TickFormat::Parquet),
+            None => (name.to_da(), TickFormat::Bincode),
+        }
+    }
+
+    pub(crate) fn file_name(&self, date: da) -> String {
+        match self {
+            TickFormat::Bincode => date.to_string(),
+            TickFormat::Parquet => format!("{}.parquet", date),
+        }
+    }
+}
+
 pub fn otimes<T: Clone, N: Clone>(x: &[T], y: &[N]) -> Vec<(T, N)> {
     let cc: Vec<Vec<(T, N)>> = x
         .iter()
@@ -18,27 +43,27 @@ pub fn otimes<T: Clone, N: Clone>(x: &[T], y: &[N]) -> Vec<(T, N)> {
 }
 
 impl GenDi {
+    /// The data source of the path, see `data_source`.
+    pub fn source(&self) -> Box<dyn DataSource> {
+        data_source(self.0)
+    }
+
     pub fn get_tick<T: Fromt<da> + PartialOrd>(
         &self,
         ticker: Ticker,
         range: ForCompare<T>,
     ) -> Option<PriceTick> {
-        let p_str = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
-        let mut file_vec = p_str.get_file_vec().ok()?;
-        file_vec.sort();
-        let price_vec = file_vec
-            .iter()
-            .filter(|x| range.compare_time(&x.to_da()))
-            .collect_vec();
-        let es_len = 25_000 * price_vec.len();
-        let mut res =
-            price_vec
-                .into_iter()
-                .fold(PriceTick::with_capacity(es_len), |mut accu, item| {
-                    let mut price_tick = rof::<PriceTick>(item, &p_str);
-                    accu.cat(&mut price_tick);
-                    accu
-                });
+        let price_tick_vec = self
+            .source()
+            .get_tick_days(ticker, &|x| range.compare_time(x))
+            .unwrap_or_else(|e| panic!("{}: {}", ticker, e))?;
+        let es_len = price_tick_vec.iter().map(|x| x.t.len()).sum();
+        let mut res = price_tick_vec
+            .into_iter()
+            .fold(PriceTick::with_capacity(es_len), |mut accu, mut price_tick| {
+                accu.cat(&mut price_tick);
+                accu
+            });
         res.shrink_to_fit();
         res.into()
     }
@@ -90,19 +115,47 @@ impl GenDi {
     }
 
     pub fn sof(&self, dil: &Dil) {
-        dil.dil.iter().for_each(|di| {
-            let path_str = self.0.to_owned() + "/" + &di.pcon.inter.debug_string();
-            let path_dir = Path::new(&path_str);
-            if !path_dir.is_dir() {
-                std::fs::create_dir(path_dir).unwrap();
-            }
-            di.pcon.sof(&di.pcon.ticker.to_string(), &path_str)
-        });
+        let source = self.source();
+        dil.dil.iter().for_each(|di| source.save_pcon(&di.pcon).unwrap());
     }
 
     pub fn sof_tick_data(&self, price: &PriceTick, ticker: Ticker, date: da) {
+        self.source().save_tick_day(price, ticker, date).unwrap();
+    }
+
+    pub fn sof_tick_data_parquet(&self, price: &PriceTick, ticker: Ticker, date: da) -> anyhow::Result<()> {
         let save_path = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
-        price.sof(&date.to_string(), &save_path);
+        price.sof_parquet(&TickFormat::Parquet.file_name(date), &save_path)
+    }
+
+    /// The dates of the tick files of the ticker, sorted, each in the format read for it.
+    pub fn get_tick_files(&self, ticker: Ticker) -> Option<Vec<(da, TickFormat)>> {
+        DirSource::new(self.0).tick_files(ticker)
+    }
+
+    /// Rewrites the tick files of the ticker not in `format` to it, removing the old
+    /// files if `remove` is set. Returns the number of days converted.
+    pub fn convert_ticks(&self, ticker: Ticker, format: TickFormat, remove: bool) -> anyhow::Result<usize> {
+        let p_str = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
+        let mut n = 0;
+        for (date, format_from) in self.get_tick_files(ticker).unwrap_or_default() {
+            if format_from == format {
+                continue;
+            }
+            let price_tick = match format_from {
+                TickFormat::Bincode => rof::<PriceTick>(&format_from.file_name(date), &p_str),
+                TickFormat::Parquet => PriceTick::rof_parquet(&format_from.file_name(date), &p_str)?,
+            };
+            match format {
+                TickFormat::Bincode => price_tick.sof(&format.file_name(date), &p_str),
+                TickFormat::Parquet => price_tick.sof_parquet(&format.file_name(date), &p_str)?,
+            }
+            if remove {
+                std::fs::remove_file(Path::new(&p_str).join(format_from.file_name(date)))?;
+            }
+            n += 1;
+        }
+        Ok(n)
     }
 
     pub fn update_dil(&self, dil: &mut Dil) {
@@ -122,32 +175,12 @@ impl GenDi {
         dil.sof(name, path);
     }
 
-    pub fn update_from_mongodb(&self) {
-        todo!();
-    }
-
     pub fn get_ticks_saved_date(&self, ticker: Ticker) -> Vec<da> {
-        let mut res = (self.0.to_owned() + "/Rtick/" + &ticker.to_string())
-            .get_file_vec()
-            .unwrap()
-            .iter()
-            .map(|x| x.to_da())
-            .collect_vec();
-        res.sort();
-        res
+        self.source().tick_dates(ticker).unwrap()
     }
 
     pub fn get_pcon(&self, x: &(TriBox, Ticker)) -> Option<Pcon> {
-        let path = format!("{}/{:?}/{}", self.0, x.0, x.1);
-        let path_file = Path::new(&path);
-        if path_file.exists() {
-            Some(<Pcon as Sof>::rof(
-                path_file.file_name().unwrap().to_str().unwrap(),
-                path_file.parent().unwrap().to_str().unwrap(),
-            ))
-        } else {
-            None
-        }
+        self.source().get_pcon(&x.0, x.1).unwrap()
     }
 
     pub fn get<T: ToIdentVec>(&self, x: T) -> Dil {
@@ -209,20 +242,7 @@ impl GenDi {
     }
 
     pub fn delete_ticks(&self, range: ForCompare<dt>) {
-        let p = PathBuf::from(&self.0).join("Rtick");
-        p.get_file_vec().unwrap().iter().for_each(|x| {
-            let pp = p.join(x);
-            pp
-                .get_file_vec()
-                .unwrap()
-                .iter()
-                .for_each(|a| {
-                    let date = a.to_da();
-                    if range.compare_time(&date) {
-                        pp.join(a).remove();
-                    }
-                })
-        });
+        self.source().delete_ticks(&range).unwrap();
     }
 }
 
@@ -233,12 +253,11 @@ pub trait ToIdentVec {
 
 impl ToIdentVec for TriBox {
     fn to_ident_vec(&self, path: &str) -> Vec<(TriBox, Ticker)> {
-        format!("{}/{:?}", path, self)
-            .get_file_vec()
+        data_source(path)
+            .pcon_tickers(self)
             .unwrap_or_default()
-            .iter()
-            // .filter_map(|x| )
-            .map(|x| (self.clone(), x.into_ticker().unwrap()))
+            .into_iter()
+            .map(|x| (self.clone(), x))
             .collect_vec()
     }
 }
@@ -280,4 +299,54 @@ pub trait DiToDi: AsRef<Di> + Sized {
             .to_di()
     }
 }
-impl DiToDi for Di {}
\ No newline at end of file
+impl DiToDi for Di {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn price_tick(date: da, c: f32) -> PriceTick {
+        PriceTick {
+            t: vec![date.and_hms_opt(9, 0, 0).unwrap(), date.and_hms_opt(9, 0, 1).unwrap()],
+            c: vec![c, c + 1.],
+            v: vec![1., 2.],
+            ct: vec![0, 0],
+            bid1: vec![c - 1., c],
+            ask1: vec![c + 1., c + 2.],
+            bid1_v: vec![3., 4.],
+            ask1_v: vec![5., 6.],
+        }
+    }
+
+    fn files(path: &Path) -> Vec<String> {
+        let mut res = path.get_file_vec().unwrap();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn ticks_convert_between_bincode_and_parquet() {
+        let dir = temp_dir("ticks_convert_between_bincode_and_parquet");
+        let gen_di = GenDi(dir.to_str().unwrap().to_string().leak());
+        let tick_dir = dir.join("Rtick").join(Ticker::rb.to_string());
+        std::fs::create_dir_all(&tick_dir).unwrap();
+        let (day1, day2) = (da::from_ymd_opt(2024, 1, 2).unwrap(), da::from_ymd_opt(2024, 1, 3).unwrap());
+        let (price1, price2) = (price_tick(day1, 3500.), price_tick(day2, 3600.));
+        price1.sof(&TickFormat::Bincode.file_name(day1), tick_dir.to_str().unwrap());
+        gen_di.sof_tick_data_parquet(&price2, Ticker::rb, day2).unwrap();
+
+        assert_eq!(gen_di.convert_ticks(Ticker::rb, TickFormat::Parquet, false).unwrap(), 1);
+        assert_eq!(files(&tick_dir), vec!["2024-01-02", "2024-01-02.parquet", "2024-01-03.parquet"]);
+        let parquet = vec![(day1, TickFormat::Parquet), (day2, TickFormat::Parquet)];
+        assert_eq!(gen_di.get_tick_files(Ticker::rb).unwrap(), parquet);
+        assert_eq!(gen_di.convert_ticks(Ticker::rb, TickFormat::Parquet, false).unwrap(), 0);
+
+        assert_eq!(gen_di.convert_ticks(Ticker::rb, TickFormat::Bincode, true).unwrap(), 2);
+        assert_eq!(files(&tick_dir), vec!["2024-01-02", "2024-01-03"]);
+        let json = |x: &PriceTick| serde_json::to_string(x).unwrap();
+        let res = gen_di.get_tick(Ticker::rb, day1.after()).unwrap();
+        let mut expected = price1.clone();
+        expected.cat(&mut price2.clone());
+        assert_eq!(json(&res), json(&expected));
+    }
+}
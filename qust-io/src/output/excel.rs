@@ -2,7 +2,7 @@
 use std::fmt::Display;
 use num_traits::Num;
 use qust_ds::prelude::*;
-use qust::{ prelude::{ PnlRes, PriceOri, PriceTick, PriceArc, Di, Pms, cs2, Ptm }, sig::distra::Aee };
+use qust::{ prelude::{ PnlRes, PriceOri, PriceTick, PriceArc, Di, Pms, cs2, Ptm, FactorEval, FactorEvalStats }, sig::distra::Aee };
 use std::sync::Arc;
 use csv;
 use crate::prelude::StatsRes;
@@ -367,6 +367,47 @@ impl IntoDf for StatsRes {
     }
 }
 
+impl IntoDf for FactorEval {
+    type Index = String;
+    type Value = v32;
+    fn to_df(self) -> Df<Self::Index, Self::Value> {
+        let spread = self.spread();
+        let mut value = vec![];
+        let mut column = vec![];
+        for (i, h) in self.horizons.iter().enumerate() {
+            value.extend([
+                self.ic[i].clone(), spread[i].clone(), self.autocorr[i].clone(), self.turnover[i].clone(),
+            ]);
+            column.extend(["ic", "spread", "autocorr", "turnover"].map(|x| format!("{}_{}", x, h)));
+        }
+        Df { index: self.t.to_index().0, value, column }
+    }
+}
+
+impl IntoDf for FactorEvalStats {
+    type Index = String;
+    type Value = v32;
+    fn to_df(self) -> Df<Self::Index, Self::Value> {
+        let mut index = vec!["ic", "ic_std", "icir", "autocorr", "turnover"].map(|x| String::from(*x));
+        (0..self.quantile_ret.first().map(|x| x.len()).unwrap_or_default())
+            .for_each(|i| index.push(format!("q{}", i)));
+        Df {
+            index,
+            value: self.horizons
+                .iter()
+                .enumerate()
+                .map(|(i, _)| {
+                    [
+                        vec![self.ic[i], self.ic_std[i], self.icir[i], self.autocorr[i], self.turnover[i]],
+                        self.quantile_ret[i].clone(),
+                    ].concat()
+                })
+                .collect(),
+            column: self.horizons.map(|x| x.to_string()),
+        }
+    }
+}
+
 pub trait ConcatDf {
     type Output;
     fn concat_df(self) -> Self::Output;
@@ -414,6 +414,30 @@ impl Aplot<da, f32> for [PnlRes<da>] {
     }
 }
 
+impl Aplot<dt, f32> for FactorEval {
+    fn aplot(&self, col: usize) -> evcxr::SVGWrapper {
+        let stats = self.stats();
+        let cum = |x: &v32| x.map(|x| if x.is_nan() { 0. } else { *x }).cumsum();
+        let spread = self.spread();
+        self.horizons
+            .iter()
+            .enumerate()
+            .flat_map(|(i, h)| {
+                let ic_text = format!("ic......{:.3}\nicir....{:.3}", stats.ic[i], stats.icir[i]);
+                let spread_text = stats.quantile_ret[i]
+                    .iter()
+                    .enumerate()
+                    .map(|(k, x)| format!("q{}......{:.4}", k, x))
+                    .join("\n");
+                let ic: PlotWithText<_, _> = (&self.t, cum(&self.ic[i]), format!("ic {}", h), ic_text).into();
+                let spread: PlotWithText<_, _> = (&self.t, cum(&spread[i]), format!("spread {}", h), spread_text).into();
+                [ic, spread]
+            })
+            .collect_vec()
+            .aplot(col)
+    }
+}
+
 type InfoOutput = (Option<String>, Option<String>);
 pub trait PnlWithInfo {
     type Input;
@@ -0,0 +1,10 @@
+//! Temporary files shared by the tests of the crate.
+use std::path::PathBuf;
+
+/// An empty directory of the test `name`, made again on each call.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("qust-io-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
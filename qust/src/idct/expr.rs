@@ -0,0 +1,272 @@
+use super::ta::*;
+use super::ta2::*;
+use crate::trade::di::Di;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::fmt;
+
+#[ta_derive]
+#[derive(PartialEq, Eq, Copy)]
+pub enum ArithOps {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl ArithOps {
+    pub fn evaluate(self, x: f32, y: f32) -> f32 {
+        match self {
+            ArithOps::Add => x + y,
+            ArithOps::Sub => x - y,
+            ArithOps::Mul => x * y,
+            ArithOps::Div => x / y,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            ArithOps::Add => "+",
+            ArithOps::Sub => "-",
+            ArithOps::Mul => "*",
+            ArithOps::Div => "/",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            ArithOps::Add | ArithOps::Sub => 1,
+            ArithOps::Mul | ArithOps::Div => 2,
+        }
+    }
+}
+
+/// A series of the bars, as written in the text of `sig::lang`.
+#[ta_derive]
+#[derive(PartialEq)]
+pub enum Expr {
+    Num(f32),
+    /// `o`, `h`, `l`, `c`, `v` of the bars, or `tz`, the tick size of the ticker.
+    Var(String),
+    /// An indicator or a series function, with the output picked by the index.
+    Call(String, Vec<Expr>, Option<usize>),
+    Neg(Box<Expr>),
+    Bin(ArithOps, Box<Expr>, Box<Expr>),
+}
+
+pub const expr_vars: [&str; 6] = ["o", "h", "l", "c", "v", "tz"];
+
+/// The functions taking a series first and numbers after.
+pub const expr_series_fns: [&str; 8] = ["ma", "std", "max", "min", "sum", "ema", "lag", "diff"];
+
+/// An indicator of the text, with the number of its outputs and the one taken without
+/// an index.
+pub type ExprTaOut = (Box<dyn Ta>, usize, usize);
+
+/// Builds the indicator called `name` on its numbers. `None` for no such indicator, and
+/// the error is the index of the first period that is not a whole number of bars above 0.
+pub fn expr_ta(name: &str, args: &[f32]) -> Option<Result<ExprTaOut, usize>> {
+    let bad_period = std::cell::Cell::new(None);
+    let n = |i: usize| {
+        if !(args[i] >= 1. && args[i].fract() == 0.) && bad_period.get().is_none() {
+            bad_period.set(Some(i));
+        }
+        args[i] as usize
+    };
+    let res: ExprTaOut = match (name, args.len()) {
+        ("rsi", 1) => (Box::new(Rsi(n(0))), 1, 0),
+        ("tr", 0) => (Box::new(Tr), 1, 0),
+        ("atr", 1) => (Box::new(Atr(n(0))), 1, 0),
+        ("macd", 3) => (Box::new(Macd(n(0), n(1), n(2))), 1, 0),
+        ("kdj_k", 3) => (Box::new(Kta(n(0), n(1), n(2))), 1, 0),
+        ("kdj_d", 3) => (Box::new(Dta(n(0), n(1), n(2))), 1, 0),
+        ("kdj_j", 3) => (Box::new(Jta(n(0), n(1), n(2))), 1, 0),
+        ("eff_ratio", 2) => (Box::new(EffRatio(n(0), n(1))), 1, 0),
+        ("boll", 2) => (Box::new(Boll(n(0), args[1])), 3, 0),
+        ("keltner", 3) => (Box::new(Keltner(n(0), n(1), args[2])), 3, 0),
+        ("donchian", 1) => (Box::new(Donchian(n(0))), 3, 0),
+        ("adx", 1) => (Box::new(Adx(n(0))), 3, 2),
+        ("cci", 1) => (Box::new(Cci(n(0))), 1, 0),
+        ("willr", 1) => (Box::new(WilliamsR(n(0))), 1, 0),
+        ("mfi", 1) => (Box::new(Mfi(n(0))), 1, 0),
+        ("chaikin_vol", 2) => (Box::new(ChaikinVol(n(0), n(1))), 1, 0),
+        ("obv", 0) => (Box::new(Obv), 1, 0),
+        ("vwap", 0) => (Box::new(Vwap), 2, 1),
+        ("kama", 3) => (Box::new(Kama(n(0), n(1), n(2))), 2, 1),
+        ("dema", 1) => (Box::new(Dema(n(0))), 2, 1),
+        ("tema", 1) => (Box::new(Tema(n(0))), 2, 1),
+        ("supertrend", 2) => (Box::new(Supertrend(n(0), args[1])), 2, 1),
+        ("sar", 2) => (Box::new(Sar(args[0], args[1])), 2, 1),
+        ("ichimoku", 3) => (Box::new(Ichimoku(n(0), n(1), n(2))), 4, 0),
+        _ => return None,
+    };
+    match bad_period.get() {
+        Some(i) => Some(Err(i)),
+        None => Some(Ok(res)),
+    }
+}
+
+impl Expr {
+    /// The number of an argument, `None` for a series.
+    pub fn num(&self) -> Option<f32> {
+        match self {
+            Expr::Num(x) => Some(*x),
+            Expr::Neg(x) => x.num().map(|x| -x),
+            _ => None,
+        }
+    }
+
+    fn nums(args: &[Expr]) -> Vec<f32> {
+        args.iter().map(|x| x.num().unwrap()).collect()
+    }
+
+    fn leaves(&self, di: &Di, res: &mut avv32) {
+        match self {
+            Expr::Num(_) => {}
+            Expr::Var(x) => match x.as_str() {
+                "o" => res.push(di.o()),
+                "h" => res.push(di.h()),
+                "l" => res.push(di.l()),
+                "c" => res.push(di.c()),
+                "v" => res.push(di.v()),
+                _ => {}
+            },
+            Expr::Call(name, args, _) if expr_series_fns.contains(&name.as_str()) => {
+                args[0].leaves(di, res);
+            }
+            Expr::Call(name, args, i) => {
+                let (ta, _, i_default) = expr_ta(name, &Self::nums(args)).and_then(Result::ok).unwrap();
+                let ta_res = di.calc::<&Box<dyn Ta>, Box<dyn Ta>, avv32>(&ta);
+                res.push(ta_res[i.unwrap_or(i_default)].clone());
+            }
+            Expr::Neg(x) => x.leaves(di, res),
+            Expr::Bin(_, x, y) => {
+                x.leaves(di, res);
+                y.leaves(di, res);
+            }
+        }
+    }
+
+    fn eval<'a>(&self, leaves: &mut impl Iterator<Item = &'a [f32]>, len: usize, di: &Di) -> v32 {
+        match self {
+            Expr::Num(x) => vec![*x; len],
+            Expr::Var(x) if x == "tz" => vec![di.pcon.ticker.info().tz; len],
+            Expr::Var(_) => leaves.next().unwrap().to_vec(),
+            Expr::Call(name, args, _) if expr_series_fns.contains(&name.as_str()) => {
+                let data = args[0].eval(leaves, len, di);
+                let n = Self::nums(&args[1..])[0] as usize;
+                match name.as_str() {
+                    "ma" => data.roll_mean(n),
+                    "std" => data.roll_std(n),
+                    "max" => data.roll_max(n),
+                    "min" => data.roll_min(n),
+                    "sum" => data.roll_sum(n),
+                    "ema" => data.ema(n),
+                    "lag" => data.lag((n, f32::NAN)),
+                    _ => izip!(data.iter(), data.lag((n, f32::NAN)).iter())
+                        .map(|(x, y)| x - y)
+                        .collect(),
+                }
+            }
+            Expr::Call(..) => leaves.next().unwrap().to_vec(),
+            Expr::Neg(x) => x.eval(leaves, len, di).into_iter().map(|x| -x).collect(),
+            Expr::Bin(f, x, y) => {
+                let x = x.eval(leaves, len, di);
+                let y = y.eval(leaves, len, di);
+                izip!(x.iter(), y.iter()).map(|(x, y)| f.evaluate(*x, *y)).collect()
+            }
+        }
+    }
+
+    fn fmt_prec(&self, f: &mut fmt::Formatter<'_>, prec: u8) -> fmt::Result {
+        match self {
+            Expr::Num(x) => write!(f, "{}", x),
+            Expr::Var(x) => write!(f, "{}", x),
+            Expr::Call(name, args, i) => {
+                write!(f, "{}({})", name, args.iter().map(|x| x.to_string()).join(", "))?;
+                match i {
+                    Some(i) => write!(f, "[{}]", i),
+                    None => Ok(()),
+                }
+            }
+            Expr::Neg(x) => {
+                write!(f, "-")?;
+                x.fmt_prec(f, 3)
+            }
+            Expr::Bin(ops, x, y) => {
+                let prec_now = ops.precedence();
+                if prec_now < prec {
+                    write!(f, "(")?;
+                }
+                x.fmt_prec(f, prec_now)?;
+                write!(f, " {} ", ops.symbol())?;
+                y.fmt_prec(f, prec_now + 1)?;
+                if prec_now < prec {
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_prec(f, 0)
+    }
+}
+
+/// The series of the `Expr`s, one output each.
+#[ta_derive]
+pub struct ExprTa(pub Vec<Expr>);
+
+#[typetag::serde]
+impl Ta for ExprTa {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        let mut res = vec![];
+        self.0.iter().for_each(|x| x.leaves(di, &mut res));
+        res
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, di: &Di) -> vv32 {
+        let len = di.c().len();
+        let mut leaves = da.into_iter();
+        self.0.iter().map(|x| x.eval(&mut leaves, len, di)).collect()
+    }
+    fn exprs(&self) -> Option<Vec<Expr>> {
+        Some(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ono, ori, Ticker};
+    use crate::test_util::di_ohlc;
+
+    #[test]
+    fn exprs_on_the_bars() {
+        let di = di_ohlc(Ticker::rb, &[1., 2., 3., 4.], &[3., 4., 5., 6.], &[1., 1., 2., 3.], &[2., 3., 5., 4.]);
+        let var = |x: &str| Expr::Var(x.into());
+        let call = |name: &str, args: Vec<Expr>| Expr::Call(name.into(), args, None);
+        let bin = |ops, x, y| Expr::Bin(ops, Box::new(x), Box::new(y));
+        let exprs = vec![
+            bin(ArithOps::Mul, bin(ArithOps::Sub, var("c"), var("o")), Expr::Num(2.)),
+            bin(ArithOps::Add, Expr::Neg(var("c").into()), bin(ArithOps::Div, var("h"), var("l"))),
+            call("diff", vec![var("c"), Expr::Num(1.)]),
+            call("lag", vec![var("c"), Expr::Num(2.)]),
+            bin(ArithOps::Sub, call("tr", vec![]), var("tz")),
+        ];
+        let tr = di.calc(ori + ono + Tr)[0].clone();
+        let expected = [
+            vec![2., 2., 4., 0.],
+            vec![1., 1., -2.5, -2.],
+            vec![f32::NAN, 1., 2., -1.],
+            vec![f32::NAN, f32::NAN, 2., 3.],
+            tr.iter().map(|x| x - Ticker::rb.info().tz).collect(),
+        ];
+        let res = di.calc(ori + ono + ExprTa(exprs));
+        izip!(res.iter(), expected.iter()).for_each(|(x, y)| {
+            izip!(x.iter(), y.iter()).for_each(|(a, b)| assert!(a == b || a.is_nan() && b.is_nan(), "{:?} != {:?}", x, y));
+        });
+    }
+}
@@ -89,4 +89,49 @@ macro_rules! loge {
                 .build()
         )
     }
-}
\ No newline at end of file
+}
+
+/// Records a live metric into `live::metrics::METRICS`. Without the `metrics` feature it
+/// expands to nothing, but `time:` still evaluates its expression. `since:` observes the
+/// time past a start, given as an `Option<Instant>`, when there is one.
+#[cfg(feature = "metrics")]
+#[macro_export]
+macro_rules! metric {
+    (counter: $name: expr, $($k: ident = $v: expr),* ; $value: expr) => {
+        $crate::live::metrics::METRICS.counter_add($name, vec![$((stringify!($k), $v.to_string())),*], $value as f64)
+    };
+    (gauge: $name: expr, $($k: ident = $v: expr),* ; $value: expr) => {
+        $crate::live::metrics::METRICS.gauge_set($name, vec![$((stringify!($k), $v.to_string())),*], $value as f64)
+    };
+    (time: $name: expr, $($k: ident = $v: expr),* ; $e: expr) => {{
+        let time_start = std::time::Instant::now();
+        let res = $e;
+        let elapsed = time_start.elapsed().as_secs_f64();
+        $crate::live::metrics::METRICS.observe($name, vec![$((stringify!($k), $v.to_string())),*], elapsed);
+        res
+    }};
+    (since: $name: expr, $($k: ident = $v: expr),* ; $start: expr) => {
+        if let Some(start) = $start {
+            let elapsed = std::time::Instant::elapsed(&start).as_secs_f64();
+            $crate::live::metrics::METRICS.observe($name, vec![$((stringify!($k), $v.to_string())),*], elapsed);
+        }
+    };
+    (sent: $ticker: expr, $order_send: expr) => {
+        $crate::live::metrics::METRICS.order_sent($ticker, $order_send)
+    };
+    (filled: $ticker: expr, $order_action: expr, $volume: expr) => {
+        $crate::live::metrics::METRICS.order_filled($ticker, $order_action, $volume)
+    };
+    (mark: $ticker: expr, $price: expr) => {
+        $crate::live::metrics::METRICS.mark($ticker, $price)
+    };
+}
+
+#[cfg(not(feature = "metrics"))]
+#[macro_export]
+macro_rules! metric {
+    (time: $name: expr, $($k: ident = $v: expr),* ; $e: expr) => {
+        $e
+    };
+    ($($args: tt)*) => {{}};
+}
@@ -0,0 +1,534 @@
+use crate::idct::prelude::*;
+use crate::sig::{cond::CondLoop, posi::*};
+use crate::trade::di::Di;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum ModelError {
+    #[error("reading the model: {0}")]
+    Read(String),
+    #[error("model format: {0}")]
+    Format(String),
+    #[error("not supported: {0}")]
+    Unsupported(String),
+    #[error("features {found:?} are not in the order the model was trained on, {expected:?}")]
+    FeatureOrder { expected: Vec<String>, found: Vec<String> },
+}
+
+pub type ModelResult<T> = Result<T, ModelError>;
+
+fn format_err<T>(x: &str) -> ModelResult<T> {
+    Err(ModelError::Format(x.to_string()))
+}
+
+fn get<'a>(value: &'a Value, key: &str) -> ModelResult<&'a Value> {
+    value.get(key).ok_or_else(|| ModelError::Format(format!("no `{}`", key)))
+}
+
+fn get_f32(value: &Value) -> ModelResult<f32> {
+    match value {
+        Value::Number(x) => Ok(x.as_f64().unwrap_or(f64::NAN) as f32),
+        // xgboost writes numbers as strings, and the base score as `[5E-1]` since 2.0
+        Value::String(x) => x
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse()
+            .map_err(|_| ModelError::Format(format!("{} is not a number", x))),
+        _ => format_err(&format!("{} is not a number", value)),
+    }
+}
+
+fn get_vec<T>(value: &Value, f: impl Fn(&Value) -> ModelResult<T>) -> ModelResult<Vec<T>> {
+    value
+        .as_array()
+        .ok_or_else(|| ModelError::Format(format!("{} is not an array", value)))?
+        .iter()
+        .map(f)
+        .collect()
+}
+
+fn get_string(value: &Value) -> ModelResult<String> {
+    value
+        .as_str()
+        .map(|x| x.to_string())
+        .ok_or_else(|| ModelError::Format(format!("{} is not a string", value)))
+}
+
+/// A tree of xgboost, its nodes in arrays.
+struct XgbTree {
+    left: Vec<i64>,
+    right: Vec<i64>,
+    feature: Vec<usize>,
+    cond: v32,
+    default_left: Vec<bool>,
+}
+
+impl XgbTree {
+    /// The tree from the node `i`. The children of a node come after it, so a tree read
+    /// from a broken file cannot loop.
+    fn node(&self, i: usize) -> ModelResult<TreeNode> {
+        if i >= self.left.len() {
+            return format_err(&format!("node {} out of the tree", i));
+        }
+        if self.left[i] < 0 {
+            return Ok(TreeNode::Leaf(self.cond[i]));
+        }
+        if let Some(child) = [self.left[i], self.right[i]].into_iter().find(|x| *x <= i as i64) {
+            return format_err(&format!("node {} has a child {} not after it", i, child));
+        }
+        Ok(TreeNode::Split {
+            feature: self.feature[i],
+            threshold: self.cond[i],
+            inclusive: false,
+            missing: MissingGo::Nan,
+            default_left: self.default_left[i],
+            left: Box::new(self.node(self.left[i] as usize)?),
+            right: Box::new(self.node(self.right[i] as usize)?),
+        })
+    }
+}
+
+/* #region Model */
+#[ta_derive]
+#[derive(Copy, PartialEq, Eq)]
+pub enum ModelLink {
+    Identity,
+    /// The sigmoid of the raw score, for binary classifiers.
+    Logistic,
+}
+
+impl ModelLink {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            ModelLink::Identity => x,
+            ModelLink::Logistic => 1. / (1. + (-x).exp()),
+        }
+    }
+}
+
+#[ta_derive]
+#[derive(Copy, PartialEq, Eq)]
+pub enum MissingGo {
+    /// Missing values taken as zero.
+    AsZero,
+    /// Missing values go the default way.
+    Nan,
+    /// Zeros and missing values go the default way.
+    Zero,
+}
+
+#[ta_derive]
+pub enum TreeNode {
+    Leaf(f32),
+    Split {
+        feature: usize,
+        threshold: f32,
+        /// Left on `x <= threshold` as lightgbm splits, otherwise on `x < threshold` as
+        /// xgboost splits.
+        inclusive: bool,
+        missing: MissingGo,
+        default_left: bool,
+        left: Box<TreeNode>,
+        right: Box<TreeNode>,
+    },
+}
+
+impl TreeNode {
+    fn predict(&self, x: &[f32]) -> f32 {
+        let mut node = self;
+        loop {
+            match node {
+                TreeNode::Leaf(v) => return *v,
+                TreeNode::Split { feature, threshold, inclusive, missing, default_left, left, right } => {
+                    let v = match (missing, x[*feature]) {
+                        (MissingGo::AsZero, v) if v.is_nan() => 0.,
+                        (_, v) => v,
+                    };
+                    let is_missing = match missing {
+                        MissingGo::AsZero => false,
+                        MissingGo::Nan => v.is_nan(),
+                        MissingGo::Zero => v.is_nan() || v == 0.,
+                    };
+                    let go_left = if is_missing {
+                        *default_left
+                    } else if *inclusive {
+                        v <= *threshold
+                    } else {
+                        v < *threshold
+                    };
+                    node = if go_left { left } else { right };
+                }
+            }
+        }
+    }
+
+    fn max_feature(&self) -> Option<usize> {
+        match self {
+            TreeNode::Leaf(_) => None,
+            TreeNode::Split { feature, left, right, .. } => {
+                [Some(*feature), left.max_feature(), right.max_feature()].into_iter().flatten().max()
+            }
+        }
+    }
+}
+
+#[ta_derive]
+pub enum Model {
+    Linear {
+        features: Vec<String>,
+        coef: v32,
+        intercept: f32,
+        link: ModelLink,
+    },
+    Trees {
+        features: Vec<String>,
+        trees: Vec<TreeNode>,
+        base_score: f32,
+        link: ModelLink,
+    },
+}
+
+impl Model {
+    /// A linear or logistic model as
+    /// `{"features": ["a", "b"], "coef": [0.1, -0.2], "intercept": 0.0, "link": "logistic"}`,
+    /// the `link` left out for a linear one.
+    pub fn linear_json(text: &str) -> ModelResult<Self> {
+        let value: Value = serde_json::from_str(text).map_err(|e| ModelError::Read(e.to_string()))?;
+        let features = get_vec(get(&value, "features")?, get_string)?;
+        let coef = get_vec(get(&value, "coef")?, get_f32)?;
+        if coef.len() != features.len() {
+            return format_err(&format!("{} coefs for {} features", coef.len(), features.len()));
+        }
+        let link = match value.get("link").and_then(|x| x.as_str()) {
+            None | Some("identity") => ModelLink::Identity,
+            Some("logistic") => ModelLink::Logistic,
+            Some(x) => return Err(ModelError::Unsupported(format!("link {}", x))),
+        };
+        let intercept = value.get("intercept").map(get_f32).transpose()?.unwrap_or(0.);
+        Ok(Model::Linear { features, coef, intercept, link })
+    }
+
+    /// The json of `Booster.dump_model()` of lightgbm, for a regression or a binary model.
+    pub fn lightgbm_json(text: &str) -> ModelResult<Self> {
+        let value: Value = serde_json::from_str(text).map_err(|e| ModelError::Read(e.to_string()))?;
+        let features = get_vec(get(&value, "feature_names")?, get_string)?;
+        if value.get("num_class").and_then(|x| x.as_u64()).unwrap_or(1) > 1 {
+            return Err(ModelError::Unsupported("multiclass models".into()));
+        }
+        let objective = value.get("objective").and_then(|x| x.as_str()).unwrap_or("regression");
+        let link = if objective.starts_with("binary") || objective.starts_with("cross_entropy") {
+            ModelLink::Logistic
+        } else {
+            ModelLink::Identity
+        };
+        fn node(value: &Value) -> ModelResult<TreeNode> {
+            if let Some(leaf) = value.get("leaf_value") {
+                return Ok(TreeNode::Leaf(get_f32(leaf)?));
+            }
+            let decision_type = value.get("decision_type").and_then(|x| x.as_str()).unwrap_or("<=");
+            if decision_type != "<=" {
+                return Err(ModelError::Unsupported(format!("split {}", decision_type)));
+            }
+            let missing = match value.get("missing_type").and_then(|x| x.as_str()).unwrap_or("None") {
+                "NaN" => MissingGo::Nan,
+                "Zero" => MissingGo::Zero,
+                _ => MissingGo::AsZero,
+            };
+            Ok(TreeNode::Split {
+                feature: get(value, "split_feature")?.as_u64().ok_or(ModelError::Format("split_feature".into()))? as usize,
+                threshold: get_f32(get(value, "threshold")?)?,
+                inclusive: true,
+                missing,
+                default_left: value.get("default_left").and_then(|x| x.as_bool()).unwrap_or(true),
+                left: Box::new(node(get(value, "left_child")?)?),
+                right: Box::new(node(get(value, "right_child")?)?),
+            })
+        }
+        let trees = get_vec(get(&value, "tree_info")?, |x| node(get(x, "tree_structure")?))?;
+        Self::trees(features, trees, 0., link)
+    }
+
+    /// The json of `Booster.save_model("model.json")` of xgboost, for a regression or a
+    /// binary logistic model.
+    pub fn xgboost_json(text: &str) -> ModelResult<Self> {
+        let value: Value = serde_json::from_str(text).map_err(|e| ModelError::Read(e.to_string()))?;
+        let learner = get(&value, "learner")?;
+        let features = get_vec(get(learner, "feature_names")?, get_string)?;
+        let objective = get_string(get(get(learner, "objective")?, "name")?)?;
+        let base_score = get_f32(get(get(learner, "learner_model_param")?, "base_score")?)?;
+        let (link, base_score) = match objective.as_str() {
+            "binary:logistic" | "reg:logistic" => (ModelLink::Logistic, (base_score / (1. - base_score)).ln()),
+            "reg:squarederror" | "reg:linear" | "reg:absoluteerror" | "reg:pseudohubererror" => {
+                (ModelLink::Identity, base_score)
+            }
+            x if x.starts_with("rank:") => (ModelLink::Identity, base_score),
+            x => return Err(ModelError::Unsupported(format!("objective {}", x))),
+        };
+        let booster = get(get(learner, "gradient_booster")?, "model")?;
+        let trees = get_vec(get(booster, "trees")?, |tree| {
+            let tree = XgbTree {
+                left: get_vec(get(tree, "left_children")?, |x| Ok(x.as_i64().unwrap_or(-1)))?,
+                right: get_vec(get(tree, "right_children")?, |x| Ok(x.as_i64().unwrap_or(-1)))?,
+                feature: get_vec(get(tree, "split_indices")?, |x| Ok(x.as_u64().unwrap_or(0) as usize))?,
+                cond: get_vec(get(tree, "split_conditions")?, get_f32)?,
+                default_left: get_vec(get(tree, "default_left")?, |x| {
+                    Ok(x.as_bool().unwrap_or_else(|| x.as_u64() == Some(1)))
+                })?,
+            };
+            let n = tree.left.len();
+            if [tree.right.len(), tree.feature.len(), tree.cond.len(), tree.default_left.len()].iter().any(|x| *x != n) {
+                return format_err("tree arrays of different lengths");
+            }
+            tree.node(0)
+        })?;
+        Self::trees(features, trees, base_score, link)
+    }
+
+    fn trees(features: Vec<String>, trees: Vec<TreeNode>, base_score: f32, link: ModelLink) -> ModelResult<Self> {
+        if let Some(i) = trees.iter().filter_map(|x| x.max_feature()).max().filter(|i| *i >= features.len()) {
+            return format_err(&format!("a split on feature {} of {}", i, features.len()));
+        }
+        Ok(Model::Trees { features, trees, base_score, link })
+    }
+
+    /// Reads a model file of `linear_json`, `lightgbm_json` or `xgboost_json`, telling
+    /// them by their keys.
+    pub fn load(path: impl AsRef<Path>) -> ModelResult<Self> {
+        let text = std::fs::read_to_string(path.as_ref()).map_err(|e| ModelError::Read(e.to_string()))?;
+        let value: Value = serde_json::from_str(&text).map_err(|e| ModelError::Read(e.to_string()))?;
+        if value.get("tree_info").is_some() {
+            Self::lightgbm_json(&text)
+        } else if value.get("learner").is_some() {
+            Self::xgboost_json(&text)
+        } else if value.get("coef").is_some() {
+            Self::linear_json(&text)
+        } else {
+            format_err("neither a linear, a lightgbm nor an xgboost model")
+        }
+    }
+
+    pub fn features(&self) -> &[String] {
+        match self {
+            Model::Linear { features, .. } | Model::Trees { features, .. } => features,
+        }
+    }
+
+    /// The prediction on the features in the order of `features`.
+    pub fn predict(&self, x: &[f32]) -> f32 {
+        match self {
+            Model::Linear { coef, intercept, link, .. } => {
+                link.apply(intercept + izip!(coef.iter(), x.iter()).map(|(c, v)| c * v).sum::<f32>())
+            }
+            Model::Trees { trees, base_score, link, .. } => {
+                link.apply(base_score + trees.iter().map(|t| t.predict(x)).sum::<f32>())
+            }
+        }
+    }
+
+    /// Binds each feature to the first output of a `Pms`, in the order the model was
+    /// trained on.
+    pub fn bind(self, features: Vec<(&str, Pms)>) -> ModelResult<ModelTa> {
+        let found = features.iter().map(|x| x.0.to_string()).collect_vec();
+        if found != self.features() {
+            return Err(ModelError::FeatureOrder { expected: self.features().to_vec(), found });
+        }
+        let features = features.into_iter().map(|x| x.1).collect();
+        Ok(ModelTa { model: self, features })
+    }
+}
+/* #endregion */
+
+/* #region ModelTa */
+/// The prediction of `model` at each bar, on `features` bound by `Model::bind`. A `Ta`
+/// as `ori + ono + model_ta` for a `BandCond`, an `Iocond` or a `Ktn`, a `CondLoop`, and a
+/// `Money` with `ModelMoney`.
+#[ta_derive]
+pub struct ModelTa {
+    pub model: Model,
+    pub features: Vec<Pms>,
+}
+
+#[typetag::serde]
+impl Ta for ModelTa {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        self.features.map(|x| di.calc(x)[0].clone())
+    }
+
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let n = da.first().map(|x| x.len()).unwrap_or(0);
+        let mut row = vec![0f32; da.len()];
+        let res = (0..n)
+            .map(|i| {
+                row.iter_mut().zip(da.iter()).for_each(|(x, y)| *x = y[i]);
+                self.model.predict(&row)
+            })
+            .collect_vec();
+        vec![res]
+    }
+}
+
+impl CondLoop for ModelTa {
+    fn cond<'a>(&self, di: &'a Di) -> Box<dyn FnMut(usize) -> f32 + 'a> {
+        let res = di.calc(self.clone())[0].clone();
+        Box::new(move |i| res[i])
+    }
+}
+
+/// The money times the prediction of the model at the bar, none for a prediction not
+/// above `threshold`. A model of `ModelLink::Logistic` predicts a probability, always
+/// above zero, so it takes a threshold as 0.5 to ever be out.
+#[ta_derive]
+pub struct ModelMoney {
+    pub model: ModelTa,
+    pub money: Box<dyn Money>,
+    pub threshold: f32,
+}
+
+#[typetag::serde]
+impl Money for ModelMoney {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let f = self.money.register(di);
+        let pred = di.calc(self.model.clone())[0].clone();
+        Box::new(move |x, i| match pred[i] {
+            p if p > self.threshold => &f(x, i) * p,
+            _ => NormHold::No,
+        })
+    }
+    fn get_init_weight(&self) -> f32 {
+        self.money.get_init_weight()
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(ModelMoney { money: self.money.change_weight(weight), ..self.clone() })
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ono, ori, KlineType, Ticker, M1};
+    use crate::test_util::di_c;
+
+    /// A dump of lightgbm of two trees, the second a leaf.
+    const lightgbm_dump: &str = r#"{
+        "name": "tree", "version": "v4", "num_class": 1, "num_tree_per_iteration": 1,
+        "max_feature_idx": 1, "objective": "regression", "feature_names": ["a", "b"],
+        "tree_info": [
+            {"tree_index": 0, "num_leaves": 3, "shrinkage": 1, "tree_structure": {
+                "split_index": 0, "split_feature": 0, "threshold": 0.5, "decision_type": "<=",
+                "default_left": true, "missing_type": "None",
+                "left_child": {"leaf_index": 0, "leaf_value": 1.0},
+                "right_child": {
+                    "split_index": 1, "split_feature": 1, "threshold": 2.0, "decision_type": "<=",
+                    "default_left": false, "missing_type": "NaN",
+                    "left_child": {"leaf_index": 1, "leaf_value": 2.0},
+                    "right_child": {"leaf_index": 2, "leaf_value": 3.0}
+                }
+            }},
+            {"tree_index": 1, "num_leaves": 1, "shrinkage": 1, "tree_structure": {"leaf_value": 0.25}}
+        ]
+    }"#;
+
+    /// A model of xgboost of one tree, `a < 0.5` then `b < 2`, with the base score, the
+    /// objective and the left children given.
+    fn xgboost_dump(base_score: &str, objective: &str, left_children: &str) -> String {
+        format!(
+            r#"{{"learner": {{
+                "attributes": {{}}, "feature_names": ["a", "b"], "feature_types": ["float", "float"],
+                "gradient_booster": {{"name": "gbtree", "model": {{
+                    "gbtree_model_param": {{"num_trees": "1"}},
+                    "trees": [{{
+                        "id": 0,
+                        "left_children": {},
+                        "right_children": [2, 4, -1, -1, -1],
+                        "split_conditions": [0.5, 2.0, 0.3, -0.1, 0.2],
+                        "split_indices": [0, 1, 0, 0, 0],
+                        "default_left": [0, 1, 0, 0, 0]
+                    }}]
+                }}}},
+                "learner_model_param": {{"base_score": "{}", "num_feature": "2"}},
+                "objective": {{"name": "{}"}}
+            }}}}"#,
+            left_children, base_score, objective
+        )
+    }
+
+    fn assert_predict(model: &Model, cases: &[([f32; 2], f32)]) {
+        for (x, y) in cases {
+            let p = model.predict(x);
+            assert!((p - y).abs() < 1e-6, "{:?}: {} != {}", x, p, y);
+        }
+    }
+
+    fn sigmoid(x: f32) -> f32 {
+        1. / (1. + (-x).exp())
+    }
+
+    #[test]
+    fn lightgbm_predicts() {
+        let model = Model::lightgbm_json(lightgbm_dump).unwrap();
+        assert_eq!(model.features(), ["a", "b"]);
+        let cases = [
+            ([0.5, 9.], 1.25),
+            // no missing type takes NaN as 0
+            ([f32::NAN, 9.], 1.25),
+            ([1., 2.], 2.25),
+            ([1., 2.5], 3.25),
+            ([1., f32::NAN], 3.25),
+        ];
+        assert_predict(&model, &cases);
+        let model = Model::lightgbm_json(&lightgbm_dump.replace("regression", "binary sigmoid:1")).unwrap();
+        assert_predict(&model, &cases.map(|(x, y)| (x, sigmoid(y))));
+    }
+
+    #[test]
+    fn lightgbm_refuses() {
+        let dump = lightgbm_dump.replace("\"num_class\": 1", "\"num_class\": 3");
+        assert!(matches!(Model::lightgbm_json(&dump), Err(ModelError::Unsupported(_))));
+        let dump = lightgbm_dump.replace("\"split_feature\": 1", "\"split_feature\": 2");
+        assert!(matches!(Model::lightgbm_json(&dump), Err(ModelError::Format(_))));
+    }
+
+    #[test]
+    fn xgboost_predicts() {
+        let model = Model::xgboost_json(&xgboost_dump("5E-1", "reg:squarederror", "[1, 3, -1, -1, -1]")).unwrap();
+        let cases = [
+            ([0., 1.], 0.4),
+            ([0., 2.], 0.7),
+            ([0.5, 0.], 0.8),
+            ([f32::NAN, 0.], 0.8),
+            ([0., f32::NAN], 0.4),
+        ];
+        assert_predict(&model, &cases);
+        let model = Model::xgboost_json(&xgboost_dump("[2.5E-1]", "binary:logistic", "[1, 3, -1, -1, -1]")).unwrap();
+        let logit = (0.25f32 / 0.75).ln();
+        assert_predict(&model, &cases.map(|(x, y)| (x, sigmoid(logit + y - 0.5))));
+    }
+
+    #[test]
+    fn xgboost_refuses_a_broken_tree() {
+        for left_children in ["[1, 0, -1, -1, -1]", "[1, 1, -1, -1, -1]", "[1, 5, -1, -1, -1]", "[1, 3, -1, -1]"] {
+            let dump = xgboost_dump("5E-1", "reg:squarederror", left_children);
+            assert!(matches!(Model::xgboost_json(&dump), Err(ModelError::Format(_))), "{}", left_children);
+        }
+        let dump = xgboost_dump("5E-1", "multi:softprob", "[1, 3, -1, -1, -1]");
+        assert!(matches!(Model::xgboost_json(&dump), Err(ModelError::Unsupported(_))));
+    }
+
+    #[test]
+    fn model_money_over_the_threshold() {
+        let di = di_c(Ticker::rb, &[99., 100., 101., 102.]);
+        let text = r#"{"features": ["c"], "coef": [1.0], "intercept": -100.0, "link": "logistic"}"#;
+        let model = Model::linear_json(text).unwrap().bind(vec![("c", ori + ono + KlineType::Close)]).unwrap();
+        let money = |threshold| ModelMoney { model: model.clone(), money: Box::new(M1(2.)), threshold };
+        let holds = |money: &ModelMoney| {
+            let f = money.register(&di);
+            (0..4).map(|i| f(&NormHold::Lo(1.), i)).collect_vec()
+        };
+        let lo = |x: f32| NormHold::Lo(2. * sigmoid(x));
+        assert_eq!(holds(&money(0.5)), [NormHold::No, NormHold::No, lo(1.), lo(2.)]);
+        assert_eq!(holds(&money(0.)), [lo(-1.), lo(0.), lo(1.), lo(2.)]);
+    }
+}
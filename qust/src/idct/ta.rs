@@ -1,8 +1,11 @@
 use std::sync::Arc;
 
-use super::pms::GetPmsFromTa;
+use super::dcon::VertBack;
+use super::pms::{GetPmsFromTa, PmsType};
 use super::prelude::Convert;
 use crate::idct::fore::ForeTaCalc;
+use crate::idct::expr::Expr;
+use crate::idct::ta_state::*;
 use crate::idct::part::Part::*;
 use crate::prelude::{find_day_index_night_flat, KlineState, PriBox};
 use crate::trade::di::Di;
@@ -30,6 +33,15 @@ pub trait Ta {
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32;
     fn end(&self, _di: &Di) {}
+    /// The state taking the bars one at a time as `calc_da` does on the whole history,
+    /// `None` for a `Ta` that is recalculated when the `Di` grows.
+    fn ta_state(&self) -> Option<TaStateBox> {
+        None
+    }
+    /// The series expressions of the outputs, for printing them back as text.
+    fn exprs(&self) -> Option<Vec<Expr>> {
+        None
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, AsRef)]
@@ -172,6 +184,17 @@ impl Ta for Rsi {
         vec![res]
         // vec![ret_s, ret_l, res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut lag = LagState::new(1, Some(f32::NAN));
+        let mut ema_l = EmaState::new(self.0);
+        let mut ema_s = EmaState::new(self.0);
+        Some(Box::new(move |bar: &[f32]| {
+            let ret = bar[0] - lag.next(bar[0]);
+            let x = ema_l.next(if ret > 0f32 { ret } else { 0f32 });
+            let y = ema_s.next(if ret < 0f32 { -ret } else { 0f32 });
+            vec![(100f32 * x) / (x + y)]
+        }))
+    }
 }
 /* #endregion */
 
@@ -197,6 +220,14 @@ impl Ta for Tr {
             .collect();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut lag = LagState::new(1, None);
+        Some(Box::new(move |bar: &[f32]| {
+            let (h, l, c) = (bar[0], bar[1], bar[2]);
+            let l_1 = lag.next(c);
+            vec![(h - c).abs().max((l - l_1).abs()).max((h - l).abs())]
+        }))
+    }
 }
 /* #endregion */
 
@@ -213,6 +244,10 @@ impl Ta for Atr {
         let res = da[0].ema(self.0);
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut ema = EmaState::new(self.0);
+        Some(Box::new(move |bar: &[f32]| vec![ema.next(bar[0])]))
+    }
 }
 
 #[ta_derive]
@@ -226,6 +261,20 @@ impl Ta for RollTa<KlineType> {
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll(self.1, self.2.clone())
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        roll_state(self.1, &self.2)
+    }
+}
+
+fn roll_state(f: RollFunc, ops: &RollOps) -> Option<TaStateBox> {
+    let roll_state = RollState::new(f, ops)?;
+    let mut roll_state_vec: Vec<RollState> = vec![];
+    Some(Box::new(move |bar: &[f32]| {
+        roll_state_vec.resize(bar.len(), roll_state.clone());
+        izip!(roll_state_vec.iter_mut(), bar.iter())
+            .map(|(x, y)| x.next(*y))
+            .collect()
+    }))
 }
 
 impl AsRef<Box<dyn Ta>> for Box<dyn Ta> {
@@ -242,6 +291,9 @@ impl Ta for RollTa<Box<dyn Ta>> {
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll(self.1, self.2.clone())
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        roll_state(self.1, &self.2)
+    }
 }
 
 #[ta_derive]
@@ -255,6 +307,9 @@ impl Ta for Max {
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll_max(self.1)
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        roll_state(RollFunc::Max, &RollOps::N(self.1))
+    }
 }
 
 #[ta_derive]
@@ -268,6 +323,9 @@ impl Ta for Min {
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll_min(self.1)
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        roll_state(RollFunc::Min, &RollOps::N(self.1))
+    }
 }
 
 /* #endregion */
@@ -373,6 +431,44 @@ impl Ta for ShiftInter {
 }
 
 
+/// `ta` on the bars of `dcon`, like `Event` of a longer interval, put back on the bars
+/// of the `Di`. Each value shows from the bar finishing its longer bar and is held until
+/// the next one finishes, so it takes no bar that is still merging, in a backtest or
+/// as the `Di` grows live. Taken under a `Convert` keeping the bars, like `ori`.
+#[ta_derive]
+pub struct DconTa {
+    pub dcon: Convert,
+    pub ta: Box<dyn Ta>,
+}
+
+impl DconTa {
+    pub fn new<T: Ta + Clone>(dcon: Convert, ta: T) -> Self {
+        Self { dcon, ta: Box::new(ta) }
+    }
+}
+
+#[typetag::serde]
+impl Ta for DconTa {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        let pms = PmsType { dcon: self.dcon.clone(), part: ono, fore: self.ta.clone() };
+        let res = di.calc(&pms);
+        // `vert_back` of a `Convert` ends in the arms taking `res` as it is, so it is never `None`
+        self.dcon
+            .vert_back(di, res.iter().map(|x| &x[..]).collect())
+            .expect("vert_back of a Convert")
+            .into_iter()
+            .map(|mut x| {
+                x.ffill();
+                Arc::new(x)
+            })
+            .collect()
+    }
+
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        da.into_iter().map(|x| x.to_vec()).collect()
+    }
+}
+
 #[ta_derive]
 pub struct DayKlineWrapper(pub KlineType);
 
@@ -404,6 +500,11 @@ impl Ta for Diff {
             .collect_vec();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut ema_fast = EmaState::new(self.0);
+        let mut ema_slow = EmaState::new(self.1);
+        Some(Box::new(move |bar: &[f32]| vec![ema_fast.next(bar[0]) - ema_slow.next(bar[0])]))
+    }
 }
 
 #[ta_derive]
@@ -420,6 +521,10 @@ impl Ta for Macd {
             .collect();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut ema = EmaState::new(self.2);
+        Some(Box::new(move |bar: &[f32]| vec![bar[0] - ema.next(bar[0])]))
+    }
 }
 
 /* #endregion */
@@ -450,6 +555,16 @@ impl Ta for Kta {
         let rsv = izip!(rsvnum, rsvdom).map(|(x, y)| 100. * x / y);
         vec![rsv.collect_vec().ema(self.1)]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut roll_max = RollState::new(RollFunc::Max, &RollOps::N(self.0))?;
+        let mut roll_min = RollState::new(RollFunc::Min, &RollOps::N(self.0))?;
+        let mut ema = EmaState::new(self.1);
+        Some(Box::new(move |bar: &[f32]| {
+            let rsvnum = bar[2] - roll_min.next(bar[1]);
+            let rsvdom = roll_max.next(bar[0]) - rsvnum;
+            vec![ema.next(100. * rsvnum / rsvdom)]
+        }))
+    }
 }
 
 #[typetag::serde]
@@ -460,6 +575,10 @@ impl Ta for Dta {
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         vec![da[0].ema(self.2)]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut ema = EmaState::new(self.2);
+        Some(Box::new(move |bar: &[f32]| vec![ema.next(bar[0])]))
+    }
 }
 
 #[typetag::serde]
@@ -477,6 +596,9 @@ impl Ta for Jta {
             .collect_vec();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        Some(Box::new(|bar: &[f32]| vec![3. * bar[1] - 2. * bar[0]]))
+    }
 }
 /* #endregion */
 
@@ -501,6 +623,15 @@ impl Ta for EffRatio {
             .collect_vec();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut lag = LagState::new(self.0, None);
+        let mut roll_sum = RollState::new(RollFunc::Sum, &RollOps::N(self.1))?;
+        Some(Box::new(move |bar: &[f32]| {
+            let diff = bar[0] - lag.next(bar[0]);
+            let vol = roll_sum.next(diff.abs());
+            vec![if vol == 0. { 0. } else { 100. * diff / vol }]
+        }))
+    }
 }
 /* #endregion */
 
@@ -519,6 +650,10 @@ impl Ta for Spread {
         .collect_vec();
         vec![res]
     }
+    fn ta_state(&self) -> Option<TaStateBox> {
+        let mut roll_mean = RollState::new(RollFunc::Mean, &RollOps::N(self.0))?;
+        Some(Box::new(move |bar: &[f32]| vec![(bar[0] / roll_mean.next(bar[0])) - 1.]))
+    }
 }
 /* #endregion */
 
@@ -542,3 +677,95 @@ impl Ta for Rankma {
     }
 }
 /* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idct::expr::ExprTa;
+    use crate::live::prelude::{BtKline, CondType3, StreamApiType};
+    use crate::prelude::{ori, rl30mday, rl5m, CommSlip, Interval, HoldLocal, PriceTick, TickData, Ticker, ToNum, TriBox};
+    use crate::sig::prelude::{BandCond, BandState, Dire, Ptm, M1};
+    use std::sync::RwLock;
+
+    /// Two ticks a bar of `rl5m` from 09:10, at its open and at its close, both at `c`.
+    fn ticks(c: &[f32]) -> Vec<TickData> {
+        let date = da::from_ymd_opt(2024, 1, 2).unwrap();
+        izip!(rl5m.intervals(), c)
+            .flat_map(|(interval, c)| {
+                let Interval::Time(start, end) = interval else { unreachable!() };
+                [start, end].map(|x| TickData { t: date.and_time(x), c: *c, v: 1., ..Default::default() })
+            })
+            .collect()
+    }
+
+    fn di(tick_data: &[TickData]) -> Di {
+        PriceTick::from_tick_data(tick_data).to_di(Box::new(rl5m.clone()) as TriBox, Ticker::rb)
+    }
+
+    /// Closes rising and falling by turns, over the 25 bars of the morning.
+    fn closes() -> v32 {
+        (0..25).map(|i| 3500. + [0., 3., 5., 2., -4., -1., 6.][i % 7] + i as f32).collect()
+    }
+
+    /// The change of the close of the last finished 30m bar.
+    fn higher() -> DconTa {
+        let diff = Expr::Call("diff".into(), vec![Expr::Var("c".into()), Expr::Num(1.)], None);
+        DconTa::new(Convert::Event(Box::new(rl30mday.clone())), ExprTa(vec![diff]))
+    }
+
+    #[test]
+    fn dcon_ta_shows_finished_bars_only() {
+        let c = closes();
+        let di = di(&ticks(&c));
+        assert_eq!(di.size(), c.len());
+        let res = di.calc(ori + ono + higher())[0].clone();
+        let finished = di.calc(Convert::Event(Box::new(rl30mday.clone()))).finished.clone().unwrap();
+        let finished_at = (0..c.len()).filter(|i| matches!(finished[*i], KlineState::Finished)).collect_vec();
+        assert!(finished_at.len() > 2, "{:?}", finished_at);
+        let mut expected = vec![f32::NAN; c.len()];
+        finished_at.windows(2).for_each(|x| {
+            (x[1]..c.len()).for_each(|i| expected[i] = c[x[1]] - c[x[0]]);
+        });
+        izip!(res.iter(), expected.iter()).enumerate().for_each(|(i, (x, y))| {
+            assert!(x == y || x.is_nan() && y.is_nan(), "bar {}: {} != {}", i, x, y);
+        });
+        // the bars seen so far give the same values as all of them
+        (1..c.len()).for_each(|n| {
+            let res_n = self::di(&ticks(&c[..n])).calc(ori + ono + higher())[0].clone();
+            izip!(res_n.iter(), res.iter()).for_each(|(x, y)| assert!(x == y || x.is_nan() && y.is_nan(), "{} bars", n));
+        });
+    }
+
+    #[test]
+    fn dcon_ta_live_as_in_the_backtest() {
+        let c = closes();
+        let tick_data = ticks(&c);
+        let pms = ori + ono + higher();
+        let ptm = Ptm::Ptm3(
+            Box::new(M1(1.)),
+            Dire::Lo,
+            Box::new(BandCond(Dire::Lo, BandState::Lieing, pms.clone())),
+            Box::new(BandCond(Dire::Sh, BandState::Lieing, pms)),
+        );
+        let di_bt = di(&tick_data);
+        let pnl_res = ptm.bt_kline((&di_bt, CommSlip(0., 0.)));
+        let pv = Ticker::rb.info().pv;
+        let hold_bt = izip!(pnl_res.1[2].iter(), c.iter()).map(|(x, c)| x / (c * pv)).collect_vec();
+        assert!(hold_bt.contains(&1.) && hold_bt.contains(&0.), "{:?}", hold_bt);
+
+        let start = 6;
+        let di_live = RwLock::new(di(&tick_data[..start * 2]));
+        let mut ptm_fn = ptm.cond_type3(&di_live);
+        let hold = HoldLocal::default();
+        let hold_live = tick_data[start * 2..]
+            .iter()
+            .map(|tick_data| {
+                let stream_api = StreamApiType { tick_data, hold: &hold, target_ratio: 1., order_working: false };
+                ptm_fn(&stream_api).to_num()
+            })
+            .skip(1)
+            .step_by(2)
+            .collect_vec();
+        assert_eq!(hold_live, hold_bt[start..]);
+    }
+}
@@ -0,0 +1,842 @@
+use super::ta::Ta;
+use crate::idct::part::find_day_index_night_flat;
+use crate::trade::di::Di;
+use qust_ds::prelude::*;
+use qust_derive::*;
+
+/*
+Bands are `[down, c, up]` and lines on the price `[c, line]`, so a `BandCond` takes the
+price out of the bands and a `CrossCond` the price across the line. The windows are
+partial on the first bars, as `RollOps::N` is.
+*/
+
+fn hlcv(di: &Di) -> avv32 {
+    vec![di.h(), di.l(), di.c(), di.v()]
+}
+
+/// The true range, on the close of the bar before.
+fn true_range(h: &[f32], l: &[f32], c: &[f32]) -> v32 {
+    let c_lag = c.lag(1f32);
+    izip!(h.iter(), l.iter(), c_lag.iter())
+        .map(|(h, l, c_lag)| (h - l).max((h - c_lag).abs()).max((l - c_lag).abs()))
+        .collect()
+}
+
+/// Wilder's smoothing, the `ema` of a span of `2n - 1`.
+fn wilder(data: &[f32], n: usize) -> v32 {
+    data.ema(2 * n - 1)
+}
+
+fn typical_price(h: &[f32], l: &[f32], c: &[f32]) -> v32 {
+    izip!(h.iter(), l.iter(), c.iter())
+        .map(|(h, l, c)| (h + l + c) / 3.)
+        .collect()
+}
+
+fn mid_range(h: &[f32], l: &[f32], n: usize) -> v32 {
+    izip!(h.roll_max(n), l.roll_min(n))
+        .map(|(h, l)| (h + l) / 2.)
+        .collect()
+}
+
+/* #region Bands */
+/// Bollinger bands of `n` bars, `k` population standard deviations off the mean.
+#[ta_derive]
+pub struct Boll(pub usize, pub f32);
+
+#[typetag::serde]
+impl Ta for Boll {
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let c = da[0];
+        let (down, up) = izip!(c.roll_mean(self.0), c.roll_std(self.0))
+            .map(|(mid, std)| (mid - self.1 * std, mid + self.1 * std))
+            .unzip();
+        vec![down, c.to_vec(), up]
+    }
+}
+
+/// Keltner channel, `k` `ema`s of `atr_n` bars of the true range off the `ema` of `n` bars.
+#[ta_derive]
+pub struct Keltner(pub usize, pub usize, pub f32);
+
+#[typetag::serde]
+impl Ta for Keltner {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let atr = true_range(da[0], da[1], da[2]).ema(self.1);
+        let (down, up) = izip!(da[2].ema(self.0), atr.iter())
+            .map(|(mid, atr)| (mid - self.2 * atr, mid + self.2 * atr))
+            .unzip();
+        vec![down, da[2].to_vec(), up]
+    }
+}
+
+/// Donchian channel of the `n` bars before, so the close can break out of it.
+#[ta_derive]
+pub struct Donchian(pub usize);
+
+#[typetag::serde]
+impl Ta for Donchian {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let up = da[0].roll_max(self.0).lag((1usize, f32::NAN));
+        let down = da[1].roll_min(self.0).lag((1usize, f32::NAN));
+        vec![down, da[2].to_vec(), up]
+    }
+}
+/* #endregion */
+
+/* #region Adx */
+/// `[+DI, -DI, ADX]` of Wilder's directional movement, a `CrossCond` takes the DI cross.
+#[ta_derive]
+pub struct Adx(pub usize);
+
+#[typetag::serde]
+impl Ta for Adx {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let (h, l, c) = (da[0], da[1], da[2]);
+        let (h_lag, l_lag) = (h.lag(1f32), l.lag(1f32));
+        let (dm_lo, dm_sh): (v32, v32) = izip!(h.iter(), l.iter(), h_lag.iter(), l_lag.iter())
+            .map(|(h, l, h_lag, l_lag)| {
+                let (up, down) = (h - h_lag, l_lag - l);
+                (
+                    if up > down && up > 0. { up } else { 0. },
+                    if down > up && down > 0. { down } else { 0. },
+                )
+            })
+            .unzip();
+        let tr = wilder(&true_range(h, l, c), self.0);
+        let di_of = |dm: &[f32]| {
+            izip!(wilder(dm, self.0), tr.iter())
+                .map(|(dm, tr)| if *tr == 0. { 0. } else { 100. * dm / tr })
+                .collect_vec()
+        };
+        let (di_lo, di_sh) = (di_of(&dm_lo), di_of(&dm_sh));
+        let dx = izip!(di_lo.iter(), di_sh.iter())
+            .map(|(x, y)| if x + y == 0. { 0. } else { 100. * (x - y).abs() / (x + y) })
+            .collect_vec();
+        let adx = wilder(&dx, self.0);
+        vec![di_lo, di_sh, adx]
+    }
+}
+/* #endregion */
+
+/* #region Oscillators */
+/// Commodity channel index on the typical price.
+#[ta_derive]
+pub struct Cci(pub usize);
+
+#[typetag::serde]
+impl Ta for Cci {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let tp = typical_price(da[0], da[1], da[2]);
+        let res = tp
+            .rolling(self.0)
+            .map(|x| {
+                let mean = x.mean();
+                let md = x.iter().map(|y| (y - mean).abs()).sum::<f32>() / x.len() as f32;
+                let last = x[x.len() - 1];
+                if md == 0. { 0. } else { (last - mean) / (0.015 * md) }
+            })
+            .collect_vec();
+        vec![res]
+    }
+}
+
+/// Williams %R, from -100 at the low of `n` bars to 0 at the high.
+#[ta_derive]
+pub struct WilliamsR(pub usize);
+
+#[typetag::serde]
+impl Ta for WilliamsR {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let res = izip!(da[0].roll_max(self.0), da[1].roll_min(self.0), da[2].iter())
+            .map(|(h, l, c)| if h == l { -50. } else { -100. * (h - c) / (h - l) })
+            .collect_vec();
+        vec![res]
+    }
+}
+
+/// Money flow index, a `Rsi` of the money flow on the typical price.
+#[ta_derive]
+pub struct Mfi(pub usize);
+
+#[typetag::serde]
+impl Ta for Mfi {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        hlcv(di)
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let tp = typical_price(da[0], da[1], da[2]);
+        let tp_lag = tp.lag(1f32);
+        let (mf_lo, mf_sh): (v32, v32) = izip!(tp.iter(), tp_lag.iter(), da[3].iter())
+            .map(|(tp, tp_lag, v)| match tp.partial_cmp(tp_lag) {
+                Some(std::cmp::Ordering::Greater) => (tp * v, 0.),
+                Some(std::cmp::Ordering::Less) => (0., tp * v),
+                _ => (0., 0.),
+            })
+            .unzip();
+        let res = izip!(mf_lo.roll_sum(self.0), mf_sh.roll_sum(self.0))
+            .map(|(lo, sh)| match (lo, sh) {
+                (_, 0.) if lo == 0. => 50.,
+                (_, 0.) => 100.,
+                _ => 100. - 100. / (1. + lo / sh),
+            })
+            .collect_vec();
+        vec![res]
+    }
+}
+
+/// Chaikin volatility, the change in percent of the `ema` of the range over `m` bars.
+#[ta_derive]
+pub struct ChaikinVol(pub usize, pub usize);
+
+#[typetag::serde]
+impl Ta for ChaikinVol {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let range_ema = izip!(da[0].iter(), da[1].iter())
+            .map(|(h, l)| h - l)
+            .collect_vec()
+            .ema(self.0);
+        let res = izip!(range_ema.iter(), range_ema.lag(self.1 as f32).iter())
+            .map(|(x, y)| if *y == 0. { 0. } else { 100. * (x - y) / y })
+            .collect_vec();
+        vec![res]
+    }
+}
+/* #endregion */
+
+/* #region Volume */
+/// On balance volume, from 0 at the first bar.
+#[ta_derive]
+pub struct Obv;
+
+#[typetag::serde]
+impl Ta for Obv {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.c(), di.v()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let res = izip!(da[0].iter(), da[0].lag(1f32).iter(), da[1].iter())
+            .map(|(c, c_lag, v)| match c.partial_cmp(c_lag) {
+                Some(std::cmp::Ordering::Greater) => *v,
+                Some(std::cmp::Ordering::Less) => -v,
+                _ => 0.,
+            })
+            .collect_vec()
+            .cumsum();
+        vec![res]
+    }
+}
+
+/// Volume weighted typical price, started again at each trading day with the night.
+#[ta_derive]
+pub struct Vwap;
+
+#[typetag::serde]
+impl Ta for Vwap {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        hlcv(di)
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, di: &Di) -> vv32 {
+        let tp = typical_price(da[0], da[1], da[2]);
+        let day_vec = find_day_index_night_flat(di.t());
+        let mut res = Vec::with_capacity(tp.len());
+        let (mut pv_sum, mut v_sum) = (0f32, 0f32);
+        for (i, (tp, v)) in izip!(tp.iter(), da[3].iter()).enumerate() {
+            if i > 0 && day_vec[i] != day_vec[i - 1] {
+                (pv_sum, v_sum) = (0., 0.);
+            }
+            pv_sum += tp * v;
+            v_sum += v;
+            res.push(if v_sum == 0. { *tp } else { pv_sum / v_sum });
+        }
+        vec![da[2].to_vec(), res]
+    }
+}
+/* #endregion */
+
+/* #region Moving averages */
+/// Kaufman's adaptive moving average of `n` bars, between the `ema`s of `fast` and `slow`.
+#[ta_derive]
+pub struct Kama(pub usize, pub usize, pub usize);
+
+#[typetag::serde]
+impl Ta for Kama {
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let c = da[0];
+        let change = izip!(c.iter(), c.lag(self.0 as f32).iter())
+            .map(|(x, y)| (x - y).abs())
+            .collect_vec();
+        let vol = izip!(c.iter(), c.lag(1f32).iter())
+            .map(|(x, y)| (x - y).abs())
+            .collect_vec()
+            .roll_sum(self.0);
+        let (fast, slow) = (2. / (self.1 as f32 + 1.), 2. / (self.2 as f32 + 1.));
+        let mut res = Vec::with_capacity(c.len());
+        let mut kama = c.first().copied().unwrap_or_default();
+        for (c, change, vol) in izip!(c.iter(), change.iter(), vol.iter()) {
+            let er = if *vol == 0. { 0. } else { change / vol };
+            let sc = (er * (fast - slow) + slow).powi(2);
+            kama += sc * (c - kama);
+            res.push(kama);
+        }
+        vec![c.to_vec(), res]
+    }
+}
+
+/// Double exponential moving average.
+#[ta_derive]
+pub struct Dema(pub usize);
+
+#[typetag::serde]
+impl Ta for Dema {
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let e1 = da[0].ema(self.0);
+        let e2 = e1.ema(self.0);
+        let res = izip!(e1.iter(), e2.iter())
+            .map(|(x, y)| 2. * x - y)
+            .collect_vec();
+        vec![da[0].to_vec(), res]
+    }
+}
+
+/// Triple exponential moving average.
+#[ta_derive]
+pub struct Tema(pub usize);
+
+#[typetag::serde]
+impl Ta for Tema {
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let e1 = da[0].ema(self.0);
+        let e2 = e1.ema(self.0);
+        let e3 = e2.ema(self.0);
+        let res = izip!(e1.iter(), e2.iter(), e3.iter())
+            .map(|(x, y, z)| 3. * x - 3. * y + z)
+            .collect_vec();
+        vec![da[0].to_vec(), res]
+    }
+}
+/* #endregion */
+
+/* #region Trailing lines */
+/// Supertrend of `k` Wilder true ranges of `n` bars off the middle of the bar.
+#[ta_derive]
+pub struct Supertrend(pub usize, pub f32);
+
+#[typetag::serde]
+impl Ta for Supertrend {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let (h, l, c) = (da[0], da[1], da[2]);
+        let atr = wilder(&true_range(h, l, c), self.0);
+        let mut res = Vec::with_capacity(c.len());
+        let (mut up, mut down, mut is_lo) = (f32::NAN, f32::NAN, true);
+        for i in 0..c.len() {
+            let mid = (h[i] + l[i]) / 2.;
+            let (up_basic, down_basic) = (mid + self.1 * atr[i], mid - self.1 * atr[i]);
+            let c_last = if i == 0 { c[0] } else { c[i - 1] };
+            up = if up.is_nan() || up_basic < up || c_last > up { up_basic } else { up };
+            down = if down.is_nan() || down_basic > down || c_last < down { down_basic } else { down };
+            if is_lo && c[i] < down {
+                is_lo = false;
+            } else if !is_lo && c[i] > up {
+                is_lo = true;
+            }
+            res.push(if is_lo { down } else { up });
+        }
+        vec![c.to_vec(), res]
+    }
+}
+
+/// Parabolic SAR, the acceleration growing by `step` on each new extreme up to `max`.
+#[ta_derive]
+pub struct Sar(pub f32, pub f32);
+
+#[typetag::serde]
+impl Ta for Sar {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l(), di.c()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let (h, l) = (da[0], da[1]);
+        let mut res = Vec::with_capacity(h.len());
+        if h.is_empty() {
+            return vec![vec![], vec![]];
+        }
+        let (mut is_lo, mut af, mut ep, mut sar) = (true, self.0, h[0], l[0]);
+        res.push(sar);
+        for i in 1..h.len() {
+            sar += af * (ep - sar);
+            if is_lo {
+                sar = sar.min(l[i - 1]).min(l[i.saturating_sub(2)]);
+                if l[i] < sar {
+                    (is_lo, sar, ep, af) = (false, ep, l[i], self.0);
+                } else if h[i] > ep {
+                    (ep, af) = (h[i], (af + self.0).min(self.1));
+                }
+            } else {
+                sar = sar.max(h[i - 1]).max(h[i.saturating_sub(2)]);
+                if h[i] > sar {
+                    (is_lo, sar, ep, af) = (true, ep, h[i], self.0);
+                } else if l[i] < ep {
+                    (ep, af) = (l[i], (af + self.0).min(self.1));
+                }
+            }
+            res.push(sar);
+        }
+        vec![da[2].to_vec(), res]
+    }
+}
+
+/// `[tenkan, kijun, senkou a, senkou b]` of Ichimoku, the spans shifted `kijun` bars on,
+/// a `CrossCond` takes the tenkan kijun cross.
+#[ta_derive]
+pub struct Ichimoku(pub usize, pub usize, pub usize);
+
+#[typetag::serde]
+impl Ta for Ichimoku {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        vec![di.h(), di.l()]
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        let tenkan = mid_range(da[0], da[1], self.0);
+        let kijun = mid_range(da[0], da[1], self.1);
+        let span_a = izip!(tenkan.iter(), kijun.iter())
+            .map(|(x, y)| (x + y) / 2.)
+            .collect_vec()
+            .lag((self.1, f32::NAN));
+        let span_b = mid_range(da[0], da[1], self.2).lag((self.1, f32::NAN));
+        vec![tenkan, kijun, span_a, span_b]
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::test_util::{days, di_bars};
+
+    /*
+    The values are of the definitions of TA-Lib and pandas-ta, worked out in f64 from the
+    bars below, with pandas' `ewm(span, adjust=False)` for the `ema` and Wilder's smoothing,
+    the close before the first bar at its own close and the windows partial on the first
+    bars. Where the libraries seed otherwise, only the values of their first bars differ.
+    */
+    const H: [f32; 20] = [
+        10.5, 11.2, 11., 11.8, 12.4, 12.1, 12.9, 13.3, 12.8, 12.2, 11.9, 12.6, 13.1, 13.8, 14.2,
+        13.9, 13.4, 12.7, 12.9, 13.6,
+    ];
+    const L: [f32; 20] = [
+        9.8, 10.4, 10.3, 10.9, 11.6, 11.4, 12., 12.5, 12., 11.5, 11.1, 11.8, 12.3, 13., 13.5,
+        13.1, 12.6, 11.9, 12.1, 12.8,
+    ];
+    const C: [f32; 20] = [
+        10.2, 11., 10.5, 11.6, 12.2, 11.7, 12.8, 12.9, 12.2, 11.6, 11.8, 12.5, 13., 13.6, 13.7,
+        13.3, 12.8, 12.1, 12.7, 13.4,
+    ];
+    const V: [f32; 20] = [
+        100., 120., 90., 150., 160., 110., 170., 130., 140., 120., 100., 150., 160., 180., 140.,
+        130., 150., 170., 110., 160.,
+    ];
+
+    fn di() -> Di {
+        di_bars(Ticker::rb, days(20), [&C, &H, &L, &C, &V])
+    }
+
+    fn assert_near(res: &[f32], expect: &[f32]) {
+        assert_eq!(res.len(), expect.len());
+        izip!(res.iter(), expect.iter())
+            .enumerate()
+            .for_each(|(i, (x, y))| {
+                assert!(
+                    (x - y).abs() <= 1e-3 * y.abs().max(1.) || x.is_nan() && y.is_nan(),
+                    "bar {}: {} != {}\n{:?}",
+                    i,
+                    x,
+                    y,
+                    res
+                );
+            });
+    }
+
+    fn calc<T: Ta + Clone>(ta: T) -> avv32 {
+        di().calc(ori + ono + ta)
+    }
+
+    #[test]
+    fn boll() {
+        let res = calc(Boll(5, 2.));
+        assert_near(
+            &res[0],
+            &[
+                10.2, 9.8, 9.9067, 9.7632, 9.6467, 10.2202, 10.2389, 11.1622, 11.4782,
+                11.1622, 11.22, 11.2619, 11.2208, 11.0141, 11.5035, 12.3491, 12.5941, 11.9202,
+                11.8289, 11.9253,
+            ],
+        );
+        assert_near(&res[1], &C);
+        assert_near(
+            &res[2],
+            &[
+                10.2, 11.4, 11.2266, 11.8868, 12.5533, 12.5798, 13.2811, 13.3178, 13.2418,
+                13.3178, 13.3, 13.1381, 13.2192, 13.9859, 14.3365, 14.0909, 13.9659, 14.2798,
+                14.0111, 13.7947,
+            ],
+        );
+    }
+
+    #[test]
+    fn keltner() {
+        let res = calc(Keltner(5, 3, 1.5));
+        assert_near(
+            &res[0],
+            &[
+                9.15, 9.17, 9.362, 9.2639, 10.0872, 10.2953, 10.4315, 11.0503, 10.9673,
+                10.8795, 10.754, 10.9679, 11.2992, 11.739, 12.1432, 12.0992, 11.8906, 11.3914,
+                11.4605, 11.6649,
+            ],
+        );
+        assert_near(
+            &res[2],
+            &[
+                11.25, 11.87, 11.662, 12.6305, 12.8094, 12.8027, 13.6673, 13.7289, 13.6602,
+                13.1771, 13.1199, 13.3565, 13.6954, 14.1378, 14.3428, 14.4324, 14.2684, 13.984,
+                13.9247, 14.2863,
+            ],
+        );
+    }
+
+    #[test]
+    fn donchian() {
+        let res = calc(Donchian(4));
+        assert_near(
+            &res[0],
+            &[
+                f32::NAN,
+                9.8,
+                9.8,
+                9.8,
+                9.8,
+                10.3,
+                10.3,
+                10.9,
+                11.4,
+                11.4,
+                11.5,
+                11.1,
+                11.1,
+                11.1,
+                11.1,
+                11.8,
+                12.3,
+                12.6,
+                11.9,
+                11.9,
+            ],
+        );
+        assert_near(
+            &res[2],
+            &[
+                f32::NAN,
+                10.5,
+                11.2,
+                11.2,
+                11.8,
+                12.4,
+                12.4,
+                12.9,
+                13.3,
+                13.3,
+                13.3,
+                13.3,
+                12.8,
+                13.1,
+                13.8,
+                14.2,
+                14.2,
+                14.2,
+                14.2,
+                13.9,
+            ],
+        );
+    }
+
+    #[test]
+    fn adx() {
+        let res = calc(Adx(5));
+        assert_near(
+            &res[0],
+            &[
+                0., 20.2899, 16.092, 31.1195, 40.219, 31.753, 41.8341, 43.4541, 33.7649,
+                27.6088, 21.7752, 35.816, 41.5659, 51.531, 52.6319, 40.8549, 31.7271, 23.9783,
+                24.2013, 37.0547,
+            ],
+        );
+        assert_near(
+            &res[1],
+            &[
+                0., 0., 2.9557, 1.9783, 1.5681, 6.5004, 4.6235, 3.7062, 15.2673, 25.5068,
+                30.6821, 24.1275, 18.9285, 14.822, 11.9142, 20.4364, 29.8342, 41.5436, 32.4782,
+                24.6864,
+            ],
+        );
+        assert_near(
+            &res[2],
+            &[
+                0., 22.2222, 32.6096, 44.9287, 55.499, 57.8356, 62.7823, 67.5601, 60.9302,
+                48.2696, 41.3162, 36.468, 36.6797, 40.8228, 45.7695, 43.0016, 34.129, 32.5022,
+                28.5246, 26.6375,
+            ],
+        );
+    }
+
+    #[test]
+    fn cci() {
+        assert_near(
+            &calc(Cci(5))[0],
+            &[
+                0., 66.6667, 14.7059, 115.942, 119.8157, 54.0293, 111.3903, 106.7416, 2.6455,
+                -80.61, -95.9596, 20.1342, 111.8881, 120.5357, 99.473, 37.3406, -70.1058,
+                -132.7684, -57.041, 65.0685,
+            ],
+        );
+    }
+
+    #[test]
+    fn williams_r() {
+        assert_near(
+            &calc(WilliamsR(5))[0],
+            &[
+                -42.8571, -14.2857, -50., -10., -7.6923, -33.3333, -3.8462, -16.6667,
+                -57.8947, -89.4737, -68.1818, -36.3636, -5., -7.4074, -16.129, -37.5,
+                -73.6842, -91.3043, -65.2174, -25.,
+            ],
+        );
+    }
+
+    #[test]
+    fn mfi() {
+        assert_near(
+            &calc(Mfi(5))[0],
+            &[
+                50., 100., 57.7502, 75.9879, 83.8406, 68.7995, 72.0349, 85.249, 65.5608,
+                46.2633, 47.0085, 45.0345, 47.5239, 71.0654, 87.6714, 82.5285, 63.4667, 43.035,
+                36.5001, 37.8061,
+            ],
+        );
+    }
+
+    #[test]
+    fn chaikin_vol() {
+        assert_near(
+            &calc(ChaikinVol(5, 3))[0],
+            &[
+                0., 5.7143, 3.4286, 13.4857, 7.6541, 4.6939, 2.5668, 1.5357, 6.2456, -6.3324,
+                -3.8272, -2.3065, 3.7809, 2.2256, -3.7638, -2.2432, -1.3405, 4.4383, 2.604,
+                1.5419,
+            ],
+        );
+    }
+
+    #[test]
+    fn obv() {
+        assert_near(
+            &calc(Obv)[0],
+            &[
+                0., 120., 30., 180., 340., 230., 400., 530.,
+                390., 270., 370., 520., 680., 860., 1000., 870.,
+                720., 550., 660., 820.,
+            ],
+        );
+    }
+
+    #[test]
+    fn kama() {
+        assert_near(
+            &calc(Kama(5, 2, 30))[1],
+            &[
+                10.2, 10.5556, 10.5533, 10.7342, 11.0524, 11.1198, 11.3253, 11.7027, 11.7198,
+                11.7157, 11.7163, 11.7323, 11.7419, 12.0227, 12.7682, 12.8793, 12.8775, 12.8075,
+                12.7978, 12.8075,
+            ],
+        );
+    }
+
+    #[test]
+    fn dema_tema() {
+        assert_near(
+            &calc(Dema(5))[1],
+            &[
+                10.2, 10.712, 10.6224, 11.2746, 11.9454, 11.9076, 12.5648, 12.903, 12.5763,
+                12.0145, 11.8739, 12.2595, 12.7568, 13.3587, 13.678, 13.5405, 13.1325, 12.4844,
+                12.5736, 13.0739,
+            ],
+        );
+        assert_near(
+            &calc(Tema(5))[1],
+            &[
+                10.2, 10.8272, 10.6426, 11.4168, 12.1326, 11.9369, 12.6765, 12.9688, 12.4652,
+                11.782, 11.7049, 12.2543, 12.8509, 13.5117, 13.7786, 13.5047, 12.978, 12.238,
+                12.4763, 13.146,
+            ],
+        );
+    }
+
+    #[test]
+    fn supertrend() {
+        assert_near(
+            &calc(Supertrend(3, 2.))[1],
+            &[
+                8.75, 9.16, 9.16, 9.3836, 10.1802, 10.1802, 10.4509, 11.0605, 11.0605,
+                11.0605, 11.0605, 11.0605, 11.0883, 11.793, 12.3258, 12.3258, 12.3258, 13.9636,
+                13.9636, 13.9636,
+            ],
+        );
+    }
+
+    #[test]
+    fn sar() {
+        assert_near(
+            &calc(Sar(0.02, 0.2))[1],
+            &[
+                9.8, 9.8, 9.8, 9.856, 9.9726, 10.1668, 10.3455, 10.6009, 10.9248,
+                11.2098, 13.3, 13.256, 13.2129, 11.1, 11.154, 11.2758, 11.3928, 11.5051,
+                11.6129, 11.7164,
+            ],
+        );
+    }
+
+    #[test]
+    fn ichimoku() {
+        let res = calc(Ichimoku(3, 5, 8));
+        assert_near(
+            &res[0],
+            &[
+                10.15, 10.5, 10.5, 11.05, 11.35, 11.65, 12.15, 12.35, 12.65,
+                12.4, 11.95, 11.85, 12.1, 12.8, 13.25, 13.6, 13.4, 12.9,
+                12.65, 12.75,
+            ],
+        );
+        assert_near(
+            &res[1],
+            &[
+                10.15, 10.5, 10.5, 10.8, 11.1, 11.35, 11.6, 12.1, 12.35,
+                12.35, 12.2, 12.2, 12.1, 12.45, 12.65, 13., 13.25, 13.05,
+                13.05, 12.9,
+            ],
+        );
+        assert_near(
+            &res[2],
+            &[
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                10.15,
+                10.5,
+                10.5,
+                10.925,
+                11.225,
+                11.5,
+                11.875,
+                12.225,
+                12.5,
+                12.375,
+                12.075,
+                12.025,
+                12.1,
+                12.625,
+                12.95,
+            ],
+        );
+        assert_near(
+            &res[3],
+            &[
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                f32::NAN,
+                10.15,
+                10.5,
+                10.5,
+                10.8,
+                11.1,
+                11.1,
+                11.35,
+                11.55,
+                11.8,
+                11.8,
+                12.1,
+                12.2,
+                12.2,
+                12.45,
+                12.65,
+            ],
+        );
+    }
+
+    #[test]
+    fn vwap_resets_with_the_night() {
+        let t = [
+            "2024-01-02 21:00:00",
+            "2024-01-02 23:00:00",
+            "2024-01-03 00:30:00",
+            "2024-01-03 09:00:00",
+            "2024-01-03 14:00:00",
+            "2024-01-03 21:00:00",
+            "2024-01-04 10:00:00",
+            "2024-01-04 14:30:00",
+            "2024-01-05 09:00:00",
+        ]
+        .map(|x| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap())
+        .to_vec();
+        let tp = [10., 11., 12., 13., 14., 20., 22., 21., 30.];
+        let v = [1., 2., 1., 0., 4., 3., 1., 2., 5.];
+        let di = di_bars(Ticker::rb, t, [&tp, &tp, &tp, &tp, &v]);
+        let res = di.calc(ori + ono + Vwap);
+        let day = |tp: &[f32], v: &[f32]| {
+            (1..=tp.len())
+                .map(|i| {
+                    izip!(&tp[..i], &v[..i]).map(|(x, y)| x * y).sum::<f32>()
+                        / v[..i].iter().sum::<f32>()
+                })
+                .collect_vec()
+        };
+        let expect = [
+            day(&tp[..5], &v[..5]),
+            day(&tp[5..8], &v[5..8]),
+            day(&tp[8..], &v[8..]),
+        ]
+        .concat();
+        assert_near(&res[0], &tp);
+        assert_near(&res[1], &expect);
+        let di = di_bars(
+            Ticker::rb,
+            days(3),
+            [&tp[..3], &tp[..3], &tp[..3], &tp[..3], &[0.; 3]],
+        );
+        assert_near(&di.calc(ori + ono + Vwap)[1], &tp[..3]);
+    }
+}
@@ -0,0 +1,263 @@
+use qust_ds::prelude::*;
+use qust_ds::roll::{AggFunc2, RollFunc, RollOps};
+use std::collections::VecDeque;
+
+/// The incremental counterpart of `Ta::calc_da`, taking the bars one at a time.
+pub trait TaState: Send + Sync {
+    /// Takes the inputs of a bar, one for each series of `calc_di`, and returns its
+    /// values, one for each series of `calc_da`.
+    fn next(&mut self, bar: &[f32]) -> v32;
+
+    /// Takes the history bar by bar, returning what `calc_da` does on it.
+    fn init(&mut self, da: Vec<&[f32]>) -> vv32 {
+        let mut res: vv32 = vec![];
+        let mut bar = vec![0.; da.len()];
+        for i in 0..da.first().map(|x| x.len()).unwrap_or_default() {
+            bar.iter_mut().zip(da.iter()).for_each(|(x, y)| *x = y[i]);
+            let values = self.next(&bar);
+            if res.is_empty() {
+                res = vec![vec![]; values.len()];
+            }
+            res.iter_mut().zip(values).for_each(|(x, y)| x.push(y));
+        }
+        res
+    }
+}
+
+pub type TaStateBox = Box<dyn TaState>;
+
+impl<F> TaState for F
+where
+    F: FnMut(&[f32]) -> v32 + Send + Sync,
+{
+    fn next(&mut self, bar: &[f32]) -> v32 {
+        self(bar)
+    }
+}
+
+/// `ema` of a series.
+#[derive(Debug, Clone)]
+pub struct EmaState {
+    mul: f32,
+    last: Option<f32>,
+}
+
+impl EmaState {
+    pub fn new(i: usize) -> Self {
+        Self { mul: 2f32 / i as f32, last: None }
+    }
+
+    pub fn next(&mut self, x: f32) -> f32 {
+        let res = match self.last {
+            Some(last) => self.mul * x + (1.0 - self.mul) * last,
+            None => x,
+        };
+        self.last = Some(res);
+        res
+    }
+}
+
+/// `lag` of a series, the first bars filled by `fill`, or by the first value without.
+#[derive(Debug, Clone)]
+pub struct LagState {
+    n: usize,
+    fill: Option<f32>,
+    window: VecDeque<f32>,
+}
+
+impl LagState {
+    pub fn new(n: usize, fill: Option<f32>) -> Self {
+        Self { n, fill, window: VecDeque::with_capacity(n + 1) }
+    }
+
+    pub fn next(&mut self, x: f32) -> f32 {
+        let fill = *self.fill.get_or_insert(x);
+        self.window.push_back(x);
+        match self.window.len() > self.n {
+            true => self.window.pop_front().unwrap(),
+            false => fill,
+        }
+    }
+}
+
+/// `RollOps::roll` of a series, over the same window in the same order, so the values
+/// are the same to the bit.
+#[derive(Debug, Clone)]
+pub struct RollState {
+    f: RollFunc,
+    n: usize,
+    init_miss: bool,
+    i: usize,
+    window: VecDeque<f32>,
+}
+
+impl RollState {
+    /// `None` for `RollOps::Vary`, which has a window of its own for every bar.
+    pub fn new(f: RollFunc, ops: &RollOps) -> Option<Self> {
+        let (n, init_miss) = match ops {
+            RollOps::N(n) => (*n, false),
+            RollOps::InitMiss(n) => (*n, true),
+            RollOps::Vary(_) => return None,
+        };
+        Some(Self { f, n, init_miss, i: 0, window: VecDeque::with_capacity(n + 1) })
+    }
+
+    pub fn next(&mut self, x: f32) -> f32 {
+        self.window.push_back(x);
+        if self.window.len() > self.n.max(1) {
+            self.window.pop_front();
+        }
+        let res = self.window.make_contiguous().agg(self.f);
+        self.i += 1;
+        match self.init_miss && self.i < self.n {
+            true => f32::NAN,
+            false => res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::test_util::{di_c, di_ohlc};
+
+    fn series(n: usize, seed: usize) -> v32 {
+        (0..n)
+            .scan(100f32, |x, i| {
+                *x += ((i * 7 + seed) % 11) as f32 - 5.;
+                Some(*x)
+            })
+            .collect()
+    }
+
+    fn assert_same(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        izip!(a.iter(), b.iter()).enumerate().for_each(|(i, (x, y))| {
+            assert!(x.to_bits() == y.to_bits() || x.is_nan() && y.is_nan(), "bar {}: {} != {}", i, x, y);
+        });
+    }
+
+    /// `init` on the first bars and `next` on the rest give what `calc_da` does.
+    fn assert_state_same<T: Ta>(ta: T, da: Vec<v32>) {
+        let di = di_c(Ticker::rb, &da[0]);
+        let da = da.iter().map(|x| &x[..]).collect_vec();
+        let batch = ta.calc_da(da.clone(), &di);
+        let mut ta_state = ta.ta_state().unwrap();
+        let k = da[0].len() / 3;
+        let mut res = ta_state.init(da.iter().map(|x| &x[..k]).collect());
+        for i in k..da[0].len() {
+            let bar = da.iter().map(|x| x[i]).collect_vec();
+            izip!(res.iter_mut(), ta_state.next(&bar)).for_each(|(x, y)| x.push(y));
+        }
+        assert_eq!(res.len(), batch.len());
+        izip!(res.iter(), batch.iter()).for_each(|(x, y)| assert_same(x, y));
+    }
+
+    fn hlc() -> Vec<v32> {
+        let c = series(60, 3);
+        let h = izip!(c.iter(), series(60, 5).iter()).map(|(x, y)| x + (y % 3.).abs()).collect_vec();
+        let l = izip!(c.iter(), series(60, 8).iter()).map(|(x, y)| x - (y % 4.).abs()).collect_vec();
+        vec![h, l, c]
+    }
+
+    #[test]
+    fn ema_state_seeds_with_first_value() {
+        let x = series(30, 1);
+        let mut ema = EmaState::new(10);
+        assert_eq!(ema.next(x[0]), x[0]);
+        let mut ema = EmaState::new(10);
+        assert_same(&x.iter().map(|y| ema.next(*y)).collect_vec(), &x.ema(10));
+    }
+
+    #[test]
+    fn lag_state_fills_first_bars() {
+        let x = series(10, 2);
+        let mut lag = LagState::new(2, None);
+        let res = x.iter().map(|y| lag.next(*y)).collect_vec();
+        assert_eq!(&res[..2], &[x[0], x[0]]);
+        assert_eq!(&res[2..], &x[..8]);
+        let mut lag = LagState::new(1, Some(f32::NAN));
+        assert!(lag.next(x[0]).is_nan());
+        assert_eq!(lag.next(x[1]), x[0]);
+    }
+
+    #[test]
+    fn rsi_state() {
+        assert_state_same(Rsi(14), vec![series(60, 1)]);
+    }
+
+    #[test]
+    fn atr_state() {
+        assert_state_same(Tr, hlc());
+        assert_state_same(Atr(14), vec![series(60, 4).map(|x| (x % 5.).abs())]);
+    }
+
+    #[test]
+    fn macd_state() {
+        assert_state_same(Diff(12, 26), vec![series(60, 2)]);
+        assert_state_same(Macd(12, 26, 9), vec![series(60, 6)]);
+    }
+
+    #[test]
+    fn kdj_state() {
+        assert_state_same(Kta(9, 3, 3), hlc());
+        assert_state_same(Dta(9, 3, 3), vec![series(60, 7)]);
+        assert_state_same(Jta(9, 3, 3), vec![series(60, 7), series(60, 9)]);
+    }
+
+    #[test]
+    fn roll_ta_state() {
+        use RollFunc::*;
+        for f in [Sum, Mean, Min, Max, Var, Std, Momentum, Skewness] {
+            for ops in [RollOps::N(5), RollOps::InitMiss(5), RollOps::N(1)] {
+                assert_state_same(RollTa(KlineType::Close, f, ops), vec![series(40, 1)]);
+            }
+        }
+        assert!(RollTa(KlineType::Close, Sum, RollOps::Vary(Box::new(vec![1; 40]))).ta_state().is_none());
+    }
+
+    #[test]
+    fn max_min_state() {
+        assert_state_same(Max(KlineType::High, 10), vec![series(60, 2)]);
+        assert_state_same(Min(KlineType::Low, 10), vec![series(60, 2)]);
+    }
+
+    #[test]
+    fn eff_ratio_state() {
+        assert_state_same(EffRatio(5, 10), vec![series(60, 3)]);
+        assert_state_same(EffRatio(5, 10), vec![vec![100.; 30]]);
+    }
+
+    #[test]
+    fn spread_state() {
+        assert_state_same(Spread(10), vec![series(60, 5)]);
+    }
+
+    #[test]
+    fn pms_extends_on_new_bars() {
+        let [h, l, c]: [v32; 3] = hlc().try_into().unwrap();
+        let pms_vec: Vec<Pms> = vec![ori + ono + Rsi(14), ori + ono + Atr(14), ori + ono + Jta(9, 3, 3)];
+        let mut di = di_ohlc(Ticker::rb, &c[..40], &h[..40], &l[..40], &c[..40]);
+        pms_vec.iter().for_each(|x| {
+            di.calc(x);
+        });
+        let di_all = di_ohlc(Ticker::rb, &c, &h, &l, &c);
+        for i in 40..c.len() {
+            let price = &mut di.pcon.price;
+            price.t.push(di_all.pcon.price.t[i]);
+            price.o.push(c[i]);
+            price.h.push(h[i]);
+            price.l.push(l[i]);
+            price.c.push(c[i]);
+            price.v.push(1.);
+            price.ki.push(di_all.pcon.price.ki[i].clone());
+            di.clear2();
+            for pms in pms_vec.iter() {
+                let res = di.calc(pms);
+                assert!(di.data_save.save_ta_state.read().unwrap().contains_key(&pms.to_box()));
+                assert_same(&res[0], &di_all.calc(pms)[0][..=i]);
+            }
+        }
+    }
+}
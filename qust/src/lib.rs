@@ -14,20 +14,28 @@ pub mod trade {
 pub mod idct {
     pub mod calc;
     pub mod dcon;
+    pub mod expr;
     pub mod fore;
     pub mod macros;
+    pub mod model;
     pub mod part;
     pub mod pms;
     pub mod ta;
+    pub mod ta2;
+    pub mod ta_state;
 
     pub mod prelude {
         pub use super::{
             calc::*,
             dcon::{Convert::*, *},
+            expr::*,
             fore::*,
+            model::*,
             part::*,
             pms::*,
             ta::{Max as maxta, Min as minta, *},
+            ta2::*,
+            ta_state::*,
         };
     }
 }
@@ -36,7 +44,11 @@ pub mod sig {
     pub mod bt;
     pub mod cond;
     pub mod distra;
+    pub mod exit;
+    pub mod factor;
+    pub mod lang;
     pub mod livesig;
+    pub mod money;
     pub mod pnl;
     pub mod posi;
 
@@ -45,7 +57,11 @@ pub mod sig {
             bt::*,
             cond::*,
             distra::*,
+            exit::*,
+            factor::*,
+            lang::*,
             livesig::*,
+            money::*,
             pnl::*,
             posi::{Dire::*, *},
         };
@@ -54,6 +70,14 @@ pub mod sig {
     }
 }
 
+pub mod port {
+    pub mod weight;
+
+    pub mod prelude {
+        pub use super::weight::*;
+    }
+}
+
 pub mod live {
     pub mod order_types;
     pub mod bt_kline;
@@ -62,9 +86,14 @@ pub mod live {
     pub mod live_ops;
     pub mod match_ops;
     pub mod algo;
-    pub mod thread_manger;
     pub mod cond2;
     pub mod live_run;
+    pub mod control;
+    pub mod reload;
+    pub mod schedule;
+    pub mod spread;
+    #[cfg(feature = "metrics")]
+    pub mod metrics;
 
     pub mod prelude {
         pub use super::{
@@ -77,7 +106,13 @@ pub mod live {
             algo::*,
             cond2::*,
             live_run::*,
+            control::*,
+            reload::*,
+            schedule::*,
+            spread::*,
         };
+        #[cfg(feature = "metrics")]
+        pub use super::metrics::*;
     }
 }
 
@@ -100,7 +135,9 @@ pub mod prelude {
         sig::prelude::*, 
         trade::prelude::*, 
         loge,
+        metric,
         live::prelude::*,
+        port::prelude::*,
     };
     pub use qust_ds::prelude::*;
     pub use serde::{Deserialize, Serialize};
@@ -150,4 +187,7 @@ pub mod prelude {
 }
 
 #[macro_use]
-extern crate lazy_static;
\ No newline at end of file
+extern crate lazy_static;
+
+#[cfg(test)]
+pub(crate) mod test_util;
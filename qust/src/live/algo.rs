@@ -6,7 +6,7 @@ use qust_derive::*;
 use dyn_clone::{clone_trait_object, DynClone};
 use serde::{Deserialize, Serialize};
 
-use super::prelude::{HoldLocal, LiveTarget, OrderAction, RetFnAlgo };
+use super::prelude::{HoldLocal, LiveTarget, OrderAction, RetFnAlgo, StreamAlgo, StreamApiType};
 use crate::sig::prelude::ToNum;
 
 #[clone_trait]
@@ -17,41 +17,143 @@ pub trait Algo {
 #[ta_derive]
 pub struct TargetSimple;
 
+pub(crate) fn target_simple_action(target: i32, hold_local: &HoldLocal, tick_data: &TickData) -> OrderAction {
+    use OrderAction::*;
+    let gap = target - hold_local.sum();
+    match (gap, target, hold_local.yd_sh, hold_local.yd_lo, hold_local.td_sh, hold_local.td_lo) {
+        (0, ..) => No,
+        (_, 0.., 1.., ..) => LoCloseYd(hold_local.yd_sh, tick_data.bid1),
+        (_, 0.., 0, _, 1.., _) => LoClose(hold_local.td_sh, tick_data.bid1),
+        (0.., 0.., 0, _, 0, _) => LoOpen(gap, tick_data.bid1),
+        (..=-1, 0.., 0, 1.., 0, 0..) => {
+            if hold_local.yd_lo >= -gap {
+                ShCloseYd(-gap, tick_data.ask1)
+            } else {
+                ShCloseYd(hold_local.yd_lo, tick_data.ask1)
+            }
+        }
+        (..=-1, 0.., 0, 0, 0, 1..) => ShClose(-gap, tick_data.ask1),
+        (_, ..=-1, _, 1.., ..) => ShCloseYd(hold_local.yd_lo, tick_data.ask1),
+        (_, ..=-1, _, 0, _, 1..) => ShClose(hold_local.td_lo, tick_data.ask1),
+        (..=-1, ..=-1, _, 0, _, 0) => ShOpen(-gap, tick_data.ask1),
+        (0.., ..=-1, 1.., 0, 0.., 0) => {
+            if hold_local.yd_sh >= gap {
+                LoCloseYd(gap, tick_data.bid1)
+            } else {
+                LoCloseYd(hold_local.yd_sh, tick_data.bid1)
+            }
+        }
+        (0.., ..=-1, 0, 0, 1.., 0) => LoClose(gap, tick_data.bid1),
+        _ => panic!("something action wrong"),
+    }
+}
+
 #[typetag::serde]
 impl Algo for TargetSimple {
     fn algo(&self, _ticker: Ticker) -> RetFnAlgo {
         Box::new(move |stream_algo| {
-            use OrderAction::*;
+            let target = stream_algo.live_target.to_num() as i32;
+            target_simple_action(target, stream_algo.stream_api.hold, stream_algo.stream_api.tick_data)
+        })
+    }
+}
+
+/// Slices the `TargetSimple` action into clips, the next clip is released only once the
+/// `OrderPool` has no working order, i.e. the previous clip is filled or canceled.
+/// `book_ratio` caps a clip at the volume of the side of the book it takes.
+#[ta_derive]
+pub struct IcebergClip {
+    pub max_clip: i32,
+    pub book_ratio: Option<f32>,
+    pub rand_ratio: f32,
+}
+
+impl IcebergClip {
+    pub fn new(max_clip: i32) -> Self {
+        Self {
+            max_clip,
+            book_ratio: None,
+            rand_ratio: 0.,
+        }
+    }
+
+    pub fn with_book_ratio(mut self, book_ratio: f32) -> Self {
+        self.book_ratio = Some(book_ratio);
+        self
+    }
+
+    pub fn with_rand_ratio(mut self, rand_ratio: f32) -> Self {
+        self.rand_ratio = rand_ratio.clamp(0., 1.);
+        self
+    }
+
+    fn clip_size(&self, order_action: &OrderAction, tick_data: &TickData) -> i32 {
+        let mut clip = self.max_clip as f32;
+        if let Some(ratio) = self.book_ratio {
+            let book_v = if order_action.is_lo() { tick_data.ask1_v } else { tick_data.bid1_v };
+            clip = clip.min(book_v * ratio);
+        }
+        if self.rand_ratio > 0. {
+            clip *= 1. - rand::random::<f32>() * self.rand_ratio;
+        }
+        (clip.round() as i32).max(1)
+    }
+}
+
+#[typetag::serde]
+impl Algo for IcebergClip {
+    fn algo(&self, ticker: Ticker) -> RetFnAlgo {
+        let algo = self.clone();
+        let mut clip_last: Option<OrderAction> = None;
+        Box::new(move |stream_algo| {
             let target = stream_algo.live_target.to_num() as i32;
             let hold_local = stream_algo.stream_api.hold;
             let tick_data = stream_algo.stream_api.tick_data;
-            let gap = target - hold_local.sum();
-            match (gap, target, hold_local.yd_sh, hold_local.yd_lo, hold_local.td_sh, hold_local.td_lo) {
-                (0, ..) => No,
-                (_, 0.., 1.., ..) => LoCloseYd(hold_local.yd_sh, tick_data.bid1),
-                (_, 0.., 0, _, 1.., _) => LoClose(hold_local.td_sh, tick_data.bid1),
-                (0.., 0.., 0, _, 0, _) => LoOpen(gap, tick_data.bid1),
-                (..=-1, 0.., 0, 1.., 0, 0..) => {
-                    if hold_local.yd_lo >= -gap {
-                        ShCloseYd(-gap, tick_data.ask1)
-                    } else {
-                        ShCloseYd(hold_local.yd_lo, tick_data.ask1)
-                    }
-                }
-                (..=-1, 0.., 0, 0, 0, 1..) => ShClose(-gap, tick_data.ask1),
-                (_, ..=-1, _, 1.., ..) => ShCloseYd(hold_local.yd_lo, tick_data.ask1),
-                (_, ..=-1, _, 0, _, 1..) => ShClose(hold_local.td_lo, tick_data.ask1),
-                (..=-1, ..=-1, _, 0, _, 0) => ShOpen(-gap, tick_data.ask1),
-                (0.., ..=-1, 1.., 0, 0.., 0) => {
-                    if hold_local.yd_sh >= gap {
-                        LoCloseYd(gap, tick_data.bid1)
-                    } else {
-                        LoCloseYd(hold_local.yd_sh, tick_data.bid1)
-                    }
+            let order_action = target_simple_action(target, hold_local, tick_data);
+            if let OrderAction::No = order_action {
+                clip_last = None;
+                return order_action;
+            }
+            if let Some(clip) = &clip_last {
+                if stream_algo.stream_api.order_working
+                    && std::mem::discriminant(clip) == std::mem::discriminant(&order_action)
+                {
+                    return clip.clone();
                 }
-                (0.., ..=-1, 0, 0, 1.., 0) => LoClose(gap, tick_data.bid1),
-                _ => panic!("something action wrong"),
             }
+            let clip_num = algo.clip_size(&order_action, tick_data).min(order_action.num());
+            let clip = order_action.with_num(clip_num);
+            loge!(ticker, "iceberg release a clip: {:?} of {:?}", clip, order_action);
+            clip_last = Some(clip.clone());
+            clip
+        })
+    }
+}
+
+/// Scales the `LiveTarget` before handing it to the inner algo, e.g. to size one
+/// strategy for accounts with different capital.
+#[ta_derive]
+pub struct ScaledAlgo {
+    pub algo: AlgoBox,
+    pub ratio: f32,
+}
+
+#[typetag::serde]
+impl Algo for ScaledAlgo {
+    fn algo(&self, ticker: Ticker) -> RetFnAlgo {
+        let mut algo_fn = self.algo.algo(ticker);
+        let ratio = self.ratio;
+        Box::new(move |stream_algo| {
+            let stream_algo_scaled = StreamAlgo {
+                stream_api: StreamApiType {
+                    tick_data: stream_algo.stream_api.tick_data,
+                    hold: stream_algo.stream_api.hold,
+                    target_ratio: stream_algo.stream_api.target_ratio,
+                    order_working: stream_algo.stream_api.order_working,
+                },
+                live_target: stream_algo.live_target.scale(ratio),
+            };
+            algo_fn(&stream_algo_scaled)
         })
     }
 }
@@ -94,3 +196,94 @@ impl Algo for AlgoTargetAndPrice {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::{OrderPool, OrderReceive, OrderStatus};
+
+    fn tick(bid1_v: f32, ask1_v: f32) -> TickData {
+        TickData { c: 10., bid1: 10., ask1: 11., bid1_v, ask1_v, ..Default::default() }
+    }
+
+    /// Runs the algo on `target` with the hold and the working order of `order_pool`, and
+    /// takes its action into the pool.
+    fn step(algo_fn: &mut RetFnAlgo, order_pool: &mut OrderPool, target: f32, tick_data: &TickData) -> OrderAction {
+        let stream_api = StreamApiType {
+            tick_data,
+            hold: &order_pool.hold,
+            target_ratio: 1.,
+            order_working: order_pool.is_working(),
+        };
+        let live_target = if target >= 0. { LiveTarget::Lo(target) } else { LiveTarget::Sh(-target) };
+        let order_action = algo_fn(&StreamAlgo { stream_api, live_target });
+        order_pool.process_order_action(order_action.clone()).unwrap();
+        order_action
+    }
+
+    fn order_pool() -> OrderPool {
+        OrderPool { ticker: Ticker::rb, hold: Default::default(), pool: Default::default(), combo: None }
+    }
+
+    fn update_working(order_pool: &mut OrderPool, order_status: OrderStatus) {
+        let id = order_pool.pool.keys().next().unwrap().clone();
+        order_pool.update_order(OrderReceive { id, order_status, ..Default::default() }).unwrap();
+    }
+
+    #[test]
+    fn iceberg_waits_for_the_clip_to_finish() {
+        let mut algo_fn = IcebergClip::new(3).algo(Ticker::rb);
+        let mut order_pool = order_pool();
+        let tick_data = tick(100., 100.);
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 7., &tick_data), OrderAction::LoOpen(3, 10.));
+        update_working(&mut order_pool, OrderStatus::Inserted);
+        update_working(&mut order_pool, OrderStatus::PartTradedQueueing(2));
+        let tick_data = TickData { bid1: 10.5, ..tick_data };
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 7., &tick_data), OrderAction::LoOpen(3, 10.));
+        assert_eq!(order_pool.pool.len(), 1);
+        update_working(&mut order_pool, OrderStatus::AllTraded);
+        assert_eq!(order_pool.hold.sum(), 3);
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 7., &tick_data), OrderAction::LoOpen(3, 10.5));
+        update_working(&mut order_pool, OrderStatus::Canceled(1));
+        assert_eq!(order_pool.hold.sum(), 4);
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 7., &tick_data), OrderAction::LoOpen(3, 10.5));
+        update_working(&mut order_pool, OrderStatus::AllTraded);
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 7., &tick_data), OrderAction::No);
+        assert!(!order_pool.is_working());
+    }
+
+    #[test]
+    fn iceberg_turns_at_once() {
+        let mut algo_fn = IcebergClip::new(2).algo(Ticker::rb);
+        let mut order_pool = order_pool();
+        let tick_data = tick(100., 100.);
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 5., &tick_data), OrderAction::LoOpen(2, 10.));
+        update_working(&mut order_pool, OrderStatus::PartTradedQueueing(1));
+        assert_eq!(step(&mut algo_fn, &mut order_pool, -5., &tick_data), OrderAction::ShOpen(2, 11.));
+        assert!(order_pool.pool.values().next().unwrap().is_to_cancel);
+    }
+
+    #[test]
+    fn iceberg_clip_takes_the_other_side_of_the_book() {
+        let algo = IcebergClip::new(10).with_book_ratio(0.5);
+        let mut algo_fn = algo.algo(Ticker::rb);
+        let mut order_pool = order_pool();
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 20., &tick(100., 4.)), OrderAction::LoOpen(2, 10.));
+        let mut algo_fn = algo.algo(Ticker::rb);
+        let mut order_pool = self::order_pool();
+        assert_eq!(step(&mut algo_fn, &mut order_pool, -20., &tick(6., 100.)), OrderAction::ShOpen(3, 11.));
+        let mut algo_fn = algo.algo(Ticker::rb);
+        let mut order_pool = self::order_pool();
+        assert_eq!(step(&mut algo_fn, &mut order_pool, 20., &tick(100., 0.)), OrderAction::LoOpen(1, 10.));
+    }
+
+    #[test]
+    fn iceberg_rand_ratio_shrinks_the_clip() {
+        let algo = IcebergClip::new(10).with_rand_ratio(0.5);
+        for _ in 0..50 {
+            let mut algo_fn = algo.algo(Ticker::rb);
+            let n = step(&mut algo_fn, &mut order_pool(), 20., &tick(100., 100.)).num();
+            assert!((5..=10).contains(&n), "{}", n);
+        }
+    }
+}
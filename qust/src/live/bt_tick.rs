@@ -90,10 +90,8 @@ impl ApiType for WithAlgoBox<Box<dyn CondType7>> {
         let mut algo_fn = self.algo.algo(aler);
         Box::new(move |stream_api| {
             let norm_hold = ops_fn(stream_api.tick_data);
-            let stream_algo = StreamAlgo {
-                stream_api,
-                live_target: norm_hold,
-            };
+            let live_target = norm_hold.scale(stream_api.target_ratio);
+            let stream_algo = StreamAlgo { stream_api, live_target };
             algo_fn(&stream_algo)
         })
     }
@@ -118,6 +116,8 @@ where
             let stream_api = StreamApiType {
                 tick_data,
                 hold: &hold,
+                target_ratio: 1.,
+                order_working: false,
             };
             last_order_action = ops_fn(stream_api);
             res
@@ -168,9 +168,29 @@ impl CondTypeA for WithDiKline<Stral, RwLock<Di>> {
                     state: is_finished 
                 },
             };
-            ptm_fn(&stream_cond_type0)
+            metric!(
+                time: "qust_cond_type1_seconds", ticker = pcon_ident.ticker, inter = format!("{:?}", pcon_ident.inter);
+                ptm_fn(&stream_cond_type0)
+            )
         })
     }
+
+    fn stra_key(&self) -> Option<String> {
+        serde_json::to_string(&self.data).ok()
+    }
+
+    fn take_di(&mut self) -> Vec<Di> {
+        vec![self.di.read().unwrap().clone()]
+    }
+
+    fn put_di(&mut self, di_vec: Vec<Di>) {
+        let di_self = self.di.get_mut().unwrap();
+        let pcon_ident = di_self.pcon.ident();
+        if let Some(di) = di_vec.into_iter().find(|x| x.pcon.ident() == pcon_ident) {
+            loge!(pcon_ident.ticker, "{:?} take the di warmed up", pcon_ident.inter);
+            *di_self = di;
+        }
+    }
 }
 
 
@@ -192,6 +212,23 @@ impl CondTypeA for WithTicker<Vec<WithDiKline<Stral, RwLock<Di>>>> {
             live_target
         })
     }
+
+    fn stra_key(&self) -> Option<String> {
+        self.data.iter().map(|x| x.stra_key()).collect::<Option<Vec<_>>>().map(|x| x.join(";"))
+    }
+
+    fn take_di(&mut self) -> Vec<Di> {
+        self.data.iter_mut().flat_map(|x| x.take_di()).collect()
+    }
+
+    fn put_di(&mut self, mut di_vec: Vec<Di>) {
+        self.data.iter_mut().for_each(|x| {
+            let pcon_ident = x.di.get_mut().unwrap().pcon.ident();
+            if let Some(i) = di_vec.iter().position(|di| di.pcon.ident() == pcon_ident) {
+                x.put_di(vec![di_vec.swap_remove(i)]);
+            }
+        });
+    }
 }
 
 impl<'a, T> CondTypeA for WithDiKline<T, &'a Di>
@@ -232,7 +269,7 @@ where
                 return last_live_target.clone();
             }
             let i = kline_range.i - 1;
-            let stream_api = StreamApiType { tick_data, hold };
+            let stream_api = StreamApiType { tick_data, hold, ..*stream_api };
             let di_kline = DiKline { di, i };
             let di_kline_state = DiKlineState { di_kline, state: finished };
             let stream_cond_type1 = StreamCondType1 { stream_api: stream_api.clone(), di_kline_state };
@@ -261,11 +298,24 @@ where
         let mut ops_fn = self.data.cond_type_a();
         let mut algo_fn = self.algo.algo(self.data.get_ticker());
         Box::new(move |stream_api| {
-            let live_target = ops_fn(&stream_api);
+            let live_target = ops_fn(&stream_api).scale(stream_api.target_ratio);
             let stream_algo = StreamAlgo { stream_api, live_target };
             algo_fn(&stream_algo)
         })
     }
+
+    fn stra_key(&self) -> Option<String> {
+        let algo_key = serde_json::to_string(&self.algo).ok()?;
+        self.data.stra_key().map(|x| format!("{}|{}", x, algo_key))
+    }
+
+    fn take_di(&mut self) -> Vec<Di> {
+        self.data.take_di()
+    }
+
+    fn put_di(&mut self, di_vec: Vec<Di>) {
+        self.data.put_di(di_vec)
+    }
 }
 
 impl<'a> ApiType for WithDiKline<Box<dyn CondType4>, &'a Di> {
@@ -318,43 +368,63 @@ impl BtTick for DiStral<'_> {
     type Input<'a> = (AlgoBox, BtMatchBox, &'a hm<Ticker, Vec<TickData>>);
     type Output = Vec<InfoPnlRes<Ticker, dt>>;
     fn bt_tick(&self, input: Self::Input<'_>) -> Self::Output {
-        thread::scope(|scope| {
-            let mut handles = vec![];
-            for (di, index_vec) in self.dil.dil.iter().zip(self.index_vec.iter()) {
-                let stra_vec = index_vec.iter().map(|&i| self.stral.0[i].clone()).collect_vec();
-                if stra_vec.is_empty() {
+        bt_tick_distral(self, input, |x| x)
+    }
+}
+
+impl BtTick for ExitTick<DiStral<'_>> {
+    type Input<'a> = (AlgoBox, BtMatchBox, &'a hm<Ticker, Vec<TickData>>);
+    type Output = Vec<InfoPnlRes<Ticker, dt>>;
+    fn bt_tick(&self, input: Self::Input<'_>) -> Self::Output {
+        bt_tick_distral(&self.data, input, |data| ExitTick { data, exits: self.exits.clone() })
+    }
+}
+
+fn bt_tick_distral<F, T>(
+    distral: &DiStral<'_>,
+    input: (AlgoBox, BtMatchBox, &hm<Ticker, Vec<TickData>>),
+    f: F,
+) -> Vec<InfoPnlRes<Ticker, dt>>
+where
+    F: Fn(BtWrapper<Stral>) -> T + Sync,
+    T: CondType1 + Send + Sync,
+{
+    thread::scope(|scope| {
+        let mut handles = vec![];
+        for (di, index_vec) in distral.dil.dil.iter().zip(distral.index_vec.iter()) {
+            let stra_vec = index_vec.iter().map(|&i| distral.stral.0[i].clone()).collect_vec();
+            if stra_vec.is_empty() {
+                continue;
+            }
+            let stra_ops = f(BtWrapper(Stral(stra_vec)));
+            let algo_ops = input.0.clone();
+            let match_ops = input.1.clone();
+            let ticker = di.pcon.ticker;
+            let tick = match input.2.get(&ticker) {
+                Some(tick) => tick,
+                None => {
+                    println!("tick data not contains: {:?}", ticker);
                     continue;
                 }
-                let stra_ops = BtWrapper(Stral(stra_vec));
-                let algo_ops = input.0.clone();
-                let match_ops = input.1.clone();
-                let ticker = di.pcon.ticker;
-                let tick = match input.2.get(&ticker) {
-                    Some(tick) => tick,
-                    None => {
-                        println!("tick data not contains: {:?}", ticker);
-                        continue;
-                    }
-                };
-                let handle = scope.spawn(move || {
-                    let with_di_kline = WithDiKline { data: stra_ops, di };
-                    let with_algo_box = WithAlgoBox { data: with_di_kline, algo: algo_ops };
-                    let with_match_box = WithMatchBox { data: with_algo_box, match_box: match_ops };
-                    let trade_info_vec = with_match_box.bt_tick(tick);
-                    let pnl_res_dt = TickerTradeInfo {
-                        ticker,
-                        trade_info_vec,
-                    }.into_pnl_res();
-                    InfoPnlRes(ticker, pnl_res_dt)
-                });
-                handles.push(handle);
-            } 
-            handles
-                .into_iter()
-                .map(|x| x.join().unwrap())
-                .collect_vec()
-        })
-    }
+            };
+            let handle = scope.spawn(move || {
+                let with_di_kline = WithDiKline { data: stra_ops, di };
+                let with_algo_box = WithAlgoBox { data: with_di_kline, algo: algo_ops };
+                let with_match_box = WithMatchBox { data: with_algo_box, match_box: match_ops };
+                let trade_info_vec = with_match_box.bt_tick(tick);
+                let pnl_res_dt = TickerTradeInfo {
+                    ticker,
+                    trade_info_vec,
+                }.into_pnl_res();
+                InfoPnlRes(ticker, pnl_res_dt)
+            });
+            handles.push(handle);
+        } 
+        handles
+            .into_iter()
+            .map(|x| x.join().unwrap())
+            .collect_vec()
+    })
 }
 
 
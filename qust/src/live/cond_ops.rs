@@ -1,6 +1,6 @@
 #![allow(unused_imports)]
 use std::sync::RwLock;
-use crate::loge;
+use crate::{loge, metric};
 use crate::prelude::{Di, DiStral, GetCdt, KlineData, NormHold, OnlyOne, OrderError, PconIdent, Stra, Stral, TickData, Ticker};
 use qust_ds::prelude::*;
 use qust_derive::*;
@@ -48,6 +48,11 @@ pub struct DiKlineState<'a> {
 pub struct StreamApiType<'a> {
     pub tick_data: &'a TickData,
     pub hold: &'a HoldLocal,
+    /// Scales the `LiveTarget` on its way to the algo, the multiplier of the control plane.
+    pub target_ratio: f32,
+    /// An order of the ticker is in the `OrderPool`, neither filled nor canceled yet. Never
+    /// in a backtest, where an order lives for a tick.
+    pub order_working: bool,
 }
 
 pub struct StreamCondType1<'a> {
@@ -249,7 +254,10 @@ where
                     state: is_finished 
                 },
             };
-            ptm_fn(&stream_cond_type1)
+            metric!(
+                time: "qust_cond_type1_seconds", ticker = pcon_ident.ticker, inter = format!("{:?}", pcon_ident.inter);
+                ptm_fn(&stream_cond_type1)
+            )
         })
     }
 
@@ -263,6 +271,17 @@ pub trait ApiType: Send + Sync {
     {
         Box::new(self.clone())
     }
+    /// The parameters of the strategy, a reload keeps the running strategy when they are
+    /// unchanged. `None` is never taken as unchanged.
+    fn stra_key(&self) -> Option<String> {
+        None
+    }
+    /// Hands out the warmed-up `Di`s when the strategy is replaced by a reload.
+    fn take_di(&mut self) -> Vec<Di> {
+        vec![]
+    }
+    /// Takes the `Di`s of the strategy it replaces, in place of its own of the same pcon.
+    fn put_di(&mut self, _di_vec: Vec<Di>) {}
 }
 pub type ApiTypeBox = Box<dyn ApiType>;
 
@@ -325,6 +344,13 @@ pub trait CondTypeA {
         crate::prelude::aler
     }
     fn cond_type_a(&self) -> RetFnCondType3; 
+    fn stra_key(&self) -> Option<String> {
+        None
+    }
+    fn take_di(&mut self) -> Vec<Di> {
+        vec![]
+    }
+    fn put_di(&mut self, _di_vec: Vec<Di>) {}
 }
 
 pub trait ToLiveStraPool<T> {
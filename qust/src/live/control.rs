@@ -0,0 +1,537 @@
+use crate::loge;
+use crate::std_prelude::*;
+use qust_ds::prelude::hm;
+use super::prelude::{HoldLocal, OrderAction, ReloadOptions, ReloadRequest, RunningApi, StraApi, UpdateDi};
+use super::live_ops::DataReceive;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    CancelAll,
+    Flatten,
+    Multiplier(f32),
+    /// Swaps in the strategy staged by a reload.
+    Reload,
+    /// Cancels the pending orders and ends the strategy task once the pool is empty,
+    /// after closing the hold if `flatten`.
+    Retire { flatten: bool },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TickerMode {
+    #[default]
+    Running,
+    /// The strategy is still fed, but its orders are not sent.
+    Paused,
+    /// Closes the hold through the order pool, then turns to `Paused`.
+    Flatten,
+    /// The ticker is removed by a reload, and stops once drained.
+    Retiring { flatten: bool },
+}
+
+/// The state the control plane changes on a ticker. The multiplier scales the `LiveTarget`
+/// of the strategy on its way to the algo, which works it out against the real hold, so the
+/// ticker ends up holding `multiplier` times the target. A strategy sending orders without
+/// an algo is not scaled.
+#[derive(Debug, Clone)]
+pub struct TickerControl {
+    pub mode: TickerMode,
+    pub multiplier: f32,
+    /// The order action the strategy sent last, when running.
+    pub last_action: OrderAction,
+}
+
+impl Default for TickerControl {
+    fn default() -> Self {
+        Self {
+            mode: TickerMode::default(),
+            multiplier: 1.,
+            last_action: OrderAction::default(),
+        }
+    }
+}
+
+impl TickerControl {
+    /// Returns true if the pending orders are to be canceled.
+    pub fn apply(&mut self, control_command: ControlCommand) -> bool {
+        match control_command {
+            ControlCommand::Pause => self.mode = TickerMode::Paused,
+            ControlCommand::Resume => self.mode = TickerMode::Running,
+            ControlCommand::CancelAll => return true,
+            ControlCommand::Flatten => {
+                self.mode = TickerMode::Flatten;
+                return true;
+            }
+            ControlCommand::Multiplier(ratio) if ratio > 0. => self.multiplier = ratio,
+            ControlCommand::Multiplier(ratio) => {
+                loge!(level: Warn, "stra", "control ignore a multiplier not positive: {}", ratio);
+            }
+            ControlCommand::Reload => {}
+            ControlCommand::Retire { flatten } => {
+                self.mode = TickerMode::Retiring { flatten };
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingOrder {
+    pub id: String,
+    pub order_action: OrderAction,
+    pub order_status: String,
+    pub is_to_cancel: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TickerStatus {
+    pub strategy: String,
+    pub ticker: String,
+    pub contract: String,
+    pub mode: TickerMode,
+    pub multiplier: f32,
+    pub last_action: OrderAction,
+    pub hold: HoldLocal,
+    pub pending_orders: Vec<PendingOrder>,
+}
+
+/// The tickers of a strategy, as they are after any reload.
+pub struct ControlTarget {
+    pub name: String,
+    pub update_di: Arc<UpdateDi>,
+}
+
+impl StraApi {
+    pub fn control_target(&self, name: &str) -> ControlTarget {
+        ControlTarget {
+            name: name.into(),
+            update_di: Arc::clone(&self.update_di),
+        }
+    }
+
+    /// Queues the command to every live ticker, returns how many got it.
+    pub fn command_all(&self, control_command: ControlCommand) -> usize {
+        let ticker_live_vec = self.update_di.ticker_live_vec();
+        for ticker_live in ticker_live_vec.iter() {
+            let trade_api = &ticker_live.trade_api;
+            loge!(trade_api.ticker, "stra send a command: {:?}", control_command);
+            trade_api.data_receive.send(DataReceive::Control(control_command.clone()));
+        }
+        ticker_live_vec.len()
+    }
+}
+
+impl<N> RunningApi<StraApi, N> {
+    pub fn control_target(&self, name: &str) -> ControlTarget {
+        self.stra_api.control_target(name)
+    }
+}
+
+/// Serves the control plane over plain HTTP on a loopback address:
+///
+/// `GET /status`, and `POST /pause`, `/resume`, `/cancel_all`, `/flatten`,
+/// `/multiplier?ratio=0.5`, each taking optional `strategy=` and `ticker=` filters.
+/// `POST /reload?flatten=true` is there when the server is built `with_reload`.
+///
+/// Commands are queued to the tickers like any other `DataReceive`, so they are carried
+/// out by the strategy task through its `OrderPool`.
+pub struct ControlServer {
+    pub targets: Vec<ControlTarget>,
+    pub reload: Option<mpsc::Sender<ReloadRequest>>,
+}
+
+enum Route {
+    Reply(u16, String),
+    Reload(ReloadOptions),
+}
+
+impl ControlServer {
+    pub fn new(targets: Vec<ControlTarget>) -> Self {
+        Self { targets, reload: None }
+    }
+
+    /// Forwards `POST /reload` to the owner of the `RunningApi`.
+    pub fn with_reload(mut self, reload: mpsc::Sender<ReloadRequest>) -> Self {
+        self.reload = Some(reload);
+        self
+    }
+
+    pub fn status(&self) -> Vec<TickerStatus> {
+        let mut res = vec![];
+        for target in self.targets.iter() {
+            for ticker_live in target.update_di.ticker_live_vec() {
+                let order_pool = ticker_live.order_pool.lock().unwrap();
+                let control = ticker_live.control.lock().unwrap();
+                let pending_orders = order_pool
+                    .pool
+                    .values()
+                    .map(|x| PendingOrder {
+                        id: x.id.clone(),
+                        order_action: x.order_action.clone(),
+                        order_status: format!("{:?}", x.order_status),
+                        is_to_cancel: x.is_to_cancel,
+                    })
+                    .collect();
+                res.push(TickerStatus {
+                    strategy: target.name.clone(),
+                    ticker: ticker_live.trade_api.ticker.to_string(),
+                    contract: ticker_live.trade_api.contract.to_string(),
+                    mode: control.mode,
+                    multiplier: control.multiplier,
+                    last_action: control.last_action.clone(),
+                    hold: order_pool.hold.clone(),
+                    pending_orders,
+                });
+            }
+        }
+        res.sort_by(|x, y| (&x.strategy, &x.ticker).cmp(&(&y.strategy, &y.ticker)));
+        res
+    }
+
+    /// Sends the command to the matched tickers, returns how many there are.
+    pub fn command(&self, strategy: Option<&str>, ticker: Option<&str>, control_command: ControlCommand) -> usize {
+        let mut n = 0;
+        for target in self.targets.iter().filter(|x| strategy.is_none_or(|s| s == x.name)) {
+            for ticker_live in target.update_di.ticker_live_vec() {
+                let trade_api = &ticker_live.trade_api;
+                let is_matched = ticker.is_none_or(|t| t == trade_api.ticker.to_string() || t == trade_api.contract);
+                if is_matched {
+                    loge!(trade_api.ticker, "control send a command: {:?}", control_command);
+                    trade_api.data_receive.send(DataReceive::Control(control_command.clone()));
+                    n += 1;
+                }
+            }
+        }
+        n
+    }
+
+    pub async fn serve(self, addr: &str) -> anyhow::Result<()> {
+        let socket_addr: SocketAddr = addr.parse()?;
+        if !socket_addr.ip().is_loopback() {
+            anyhow::bail!("control server only listens on a loopback address: {}", addr);
+        }
+        let listener = TcpListener::bind(socket_addr).await?;
+        loge!("stra", "control server listen on {}", addr);
+        let server = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(stream).await {
+                    loge!(level: Error, "stra", "control server handle a request failed: {:?}", e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, stream: TcpStream) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        let mut header_line = String::new();
+        while reader.read_line(&mut header_line).await? > 2 {
+            header_line.clear();
+        }
+        let (code, body) = match self.route(&request_line) {
+            Route::Reply(code, body) => (code, body),
+            Route::Reload(options) => self.reload(options).await,
+        };
+        let reason = match code {
+            200 => "OK",
+            404 => "Not Found",
+            _ => "Bad Request",
+        };
+        let response = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            code,
+            reason,
+            body.len(),
+            body,
+        );
+        let mut stream = reader.into_inner();
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    async fn reload(&self, options: ReloadOptions) -> (u16, String) {
+        let Some(reload) = self.reload.as_ref() else {
+            return (404, error_body("reload is not served"));
+        };
+        let (respond, respond_receive) = oneshot::channel();
+        if reload.send(ReloadRequest { options, respond }).await.is_err() {
+            return (400, error_body("reload is not served any more"));
+        }
+        match respond_receive.await {
+            Ok(Ok(reload_plan)) => (200, serde_json::json!({ "ok": true, "plan": reload_plan }).to_string()),
+            Ok(Err(e)) => (400, error_body(&e)),
+            Err(_) => (400, error_body("reload dropped")),
+        }
+    }
+
+    fn route(&self, request_line: &str) -> Route {
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method, target),
+            _ => return Route::Reply(400, error_body("bad request line")),
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let params: hm<&str, &str> = query
+            .split('&')
+            .filter_map(|x| x.split_once('='))
+            .collect();
+        let strategy = params.get("strategy").copied();
+        let ticker = params.get("ticker").copied();
+        let control_command = match (method, path) {
+            ("GET", "/status") => {
+                return match serde_json::to_string(&self.status()) {
+                    Ok(body) => Route::Reply(200, body),
+                    Err(e) => Route::Reply(400, error_body(&e.to_string())),
+                };
+            }
+            ("POST", "/reload") => {
+                let options = ReloadOptions {
+                    flatten: params.get("flatten").is_some_and(|x| *x == "true"),
+                    ..Default::default()
+                };
+                return Route::Reload(options);
+            }
+            ("POST", "/pause") => ControlCommand::Pause,
+            ("POST", "/resume") => ControlCommand::Resume,
+            ("POST", "/cancel_all") => ControlCommand::CancelAll,
+            ("POST", "/flatten") => ControlCommand::Flatten,
+            ("POST", "/multiplier") => match params.get("ratio").and_then(|x| x.parse::<f32>().ok()) {
+                Some(ratio) if ratio > 0. => ControlCommand::Multiplier(ratio),
+                _ => return Route::Reply(400, error_body("multiplier needs a positive ratio")),
+            },
+            _ => return Route::Reply(404, error_body(&format!("no route for {} {}", method, path))),
+        };
+        match self.command(strategy, ticker, control_command) {
+            0 => Route::Reply(404, error_body("no ticker matched")),
+            n => Route::Reply(200, format!("{{\"ok\":true,\"tickers\":{}}}", n)),
+        }
+    }
+}
+
+fn error_body(msg: &str) -> String {
+    serde_json::json!({ "ok": false, "error": msg }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::{CondType7, ReloadPlan, TargetSimple, WithAlgoBox};
+    use crate::prelude::Ticker;
+    use crate::test_util::{first_tick, sent, stra_api, tick, wait_mode, Stra, Target};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::sync::broadcast;
+
+    fn server() -> (StraApi, ControlServer) {
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(Stra)), (Ticker::hc, Box::new(Stra))]);
+        let server = ControlServer::new(vec![stra_api.control_target("a")]);
+        (stra_api, server)
+    }
+
+    fn reply(server: &ControlServer, request_line: &str) -> (u16, String) {
+        match server.route(request_line) {
+            Route::Reply(code, body) => (code, body),
+            Route::Reload(_) => panic!("{} routed to a reload", request_line),
+        }
+    }
+
+    /// The commands queued to the ticker.
+    async fn commands(stra_api: &StraApi, ticker: Ticker) -> Vec<String> {
+        let ticker_live = stra_api.update_di.ticker_live(&ticker).unwrap();
+        let (_shutdown_send, mut shutdown) = broadcast::channel(1);
+        let batch = ticker_live.trade_api.data_receive.recv_batch(&mut shutdown);
+        match tokio::time::timeout(Duration::from_millis(10), batch).await {
+            Ok(Some(batch)) => batch.into_iter().map(|x| format!("{:?}", x)).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The order sent is filled, and the ticker left flat.
+    fn fill_flat(stra_api: &StraApi, ticker: Ticker) {
+        let ticker_live = stra_api.update_di.ticker_live(&ticker).unwrap();
+        let mut order_pool = ticker_live.order_pool.lock().unwrap();
+        order_pool.hold = Default::default();
+        order_pool.pool.clear();
+    }
+
+    #[test]
+    fn commands_change_the_control() {
+        let mut control = TickerControl::default();
+        assert!(!control.apply(ControlCommand::Pause));
+        assert_eq!(control.mode, TickerMode::Paused);
+        assert!(!control.apply(ControlCommand::Resume));
+        assert_eq!(control.mode, TickerMode::Running);
+        assert!(control.apply(ControlCommand::CancelAll));
+        assert_eq!(control.mode, TickerMode::Running);
+        assert!(control.apply(ControlCommand::Flatten));
+        assert_eq!(control.mode, TickerMode::Flatten);
+        assert!(!control.apply(ControlCommand::Multiplier(0.5)));
+        assert!(!control.apply(ControlCommand::Multiplier(-1.)));
+        assert!(!control.apply(ControlCommand::Multiplier(0.)));
+        assert_eq!(control.multiplier, 0.5);
+        assert!(control.apply(ControlCommand::Retire { flatten: true }));
+        assert_eq!(control.mode, TickerMode::Retiring { flatten: true });
+    }
+
+    #[tokio::test]
+    async fn routes_queue_the_commands() {
+        let (stra_api, server) = server();
+        assert_eq!(reply(&server, "POST /pause HTTP/1.1"), (200, "{\"ok\":true,\"tickers\":2}".into()));
+        assert_eq!(commands(&stra_api, Ticker::rb).await, vec!["Control(Pause)"]);
+        assert_eq!(commands(&stra_api, Ticker::hc).await, vec!["Control(Pause)"]);
+
+        assert_eq!(reply(&server, "POST /resume?ticker=rb HTTP/1.1").0, 200);
+        assert_eq!(reply(&server, "POST /cancel_all?ticker=rb2501 HTTP/1.1").0, 200);
+        assert_eq!(reply(&server, "POST /flatten?strategy=a&ticker=rb HTTP/1.1").0, 200);
+        assert_eq!(reply(&server, "POST /multiplier?ratio=0.5&ticker=rb HTTP/1.1").0, 200);
+        let rb = vec!["Control(Resume)", "Control(CancelAll)", "Control(Flatten)", "Control(Multiplier(0.5))"];
+        assert_eq!(commands(&stra_api, Ticker::rb).await, rb);
+        assert!(commands(&stra_api, Ticker::hc).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bad_routes_queue_nothing() {
+        let (stra_api, server) = server();
+        assert_eq!(reply(&server, "POST /flatten?strategy=b HTTP/1.1").0, 404);
+        assert_eq!(reply(&server, "POST /pause?ticker=au HTTP/1.1").0, 404);
+        assert_eq!(reply(&server, "POST /multiplier?ratio=-1 HTTP/1.1").0, 400);
+        assert_eq!(reply(&server, "POST /multiplier HTTP/1.1").0, 400);
+        let (code, body) = reply(&server, "POST /halt HTTP/1.1");
+        assert_eq!((code, body.contains("no route for POST /halt")), (404, true));
+        assert_eq!(reply(&server, "GET /pause HTTP/1.1").0, 404);
+        assert_eq!(reply(&server, "").0, 400);
+        assert!(commands(&stra_api, Ticker::rb).await.is_empty());
+        assert!(commands(&stra_api, Ticker::hc).await.is_empty());
+
+        let (code, body) = reply(&server, "GET /status HTTP/1.1");
+        let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((code, status[0]["ticker"].as_str(), status[1]["contract"].as_str()), (200, Some("hc"), Some("rb2501")));
+    }
+
+    #[tokio::test]
+    async fn reload_goes_to_its_owner() {
+        let (_stra_api, server) = server();
+        assert!(matches!(server.route("POST /reload?flatten=true HTTP/1.1"), Route::Reload(ReloadOptions { flatten: true, .. })));
+        assert!(matches!(server.route("POST /reload HTTP/1.1"), Route::Reload(ReloadOptions { flatten: false, .. })));
+        assert_eq!(server.reload(Default::default()).await.0, 404);
+
+        let (reload_send, mut reload_receive) = mpsc::channel(1);
+        let server = server.with_reload(reload_send);
+        tokio::spawn(async move {
+            let request: ReloadRequest = reload_receive.recv().await.unwrap();
+            let plan = ReloadPlan { added: vec![Ticker::au], removed: vec![], changed: vec![], unchanged: vec![] };
+            request.respond.send(Ok(plan)).unwrap();
+            let request = reload_receive.recv().await.unwrap();
+            request.respond.send(Err("bad config".into())).unwrap();
+        });
+        let (code, body) = server.reload(Default::default()).await;
+        assert_eq!((code, body.contains("\"added\":[\"au\"]")), (200, true));
+        let (code, body) = server.reload(Default::default()).await;
+        assert_eq!((code, body.contains("bad config")), (400, true));
+        assert_eq!(server.reload(Default::default()).await.0, 400);
+    }
+
+    #[tokio::test]
+    async fn requests_over_http() {
+        let (stra_api, server) = server();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.handle(stream).await.unwrap();
+        });
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"POST /pause?ticker=hc HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"ok\":true,\"tickers\":1}"));
+        assert_eq!(commands(&stra_api, Ticker::hc).await, vec!["Control(Pause)"]);
+    }
+
+    #[tokio::test]
+    async fn flatten_pauses_once_flat() {
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(Stra))]);
+        stra_api.update_di.ticker_live(&Ticker::rb).unwrap().order_pool.lock().unwrap().hold.yd_lo = 2;
+        stra_api.start_spy_on_data_receive(stra_api.get_trade_api_vec1()).unwrap();
+        first_tick(&stra_api, Ticker::rb).await;
+        assert_eq!(stra_api.command_all(ControlCommand::Flatten), 1);
+        let ticker_live = stra_api.update_di.ticker_live(&Ticker::rb).unwrap();
+        ticker_live.trade_api.data_receive.send(tick());
+        assert_eq!(sent(&stra_api, Ticker::rb).await, OrderAction::ShCloseYd(2, 3501.));
+        assert_eq!(ticker_live.control.lock().unwrap().mode, TickerMode::Flatten);
+
+        fill_flat(&stra_api, Ticker::rb);
+        ticker_live.trade_api.data_receive.send(tick());
+        wait_mode(&stra_api, Ticker::rb, TickerMode::Paused).await;
+        ticker_live.trade_api.stop();
+        tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retiring_ends_the_ticker_once_drained() {
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(Stra)), (Ticker::hc, Box::new(Stra))]);
+        for ticker in [Ticker::rb, Ticker::hc] {
+            stra_api.update_di.ticker_live(&ticker).unwrap().order_pool.lock().unwrap().hold.yd_lo = 2;
+        }
+        stra_api.start_spy_on_data_receive(stra_api.get_trade_api_vec1()).unwrap();
+        let (rb, hc) = (stra_api.update_di.ticker_live(&Ticker::rb).unwrap(), stra_api.update_di.ticker_live(&Ticker::hc).unwrap());
+        first_tick(&stra_api, Ticker::rb).await;
+        first_tick(&stra_api, Ticker::hc).await;
+        let server = ControlServer::new(vec![stra_api.control_target("a")]);
+        server.command(None, Some("rb"), ControlCommand::Retire { flatten: true });
+        server.command(None, Some("hc"), ControlCommand::Retire { flatten: false });
+
+        hc.trade_api.data_receive.send(tick());
+        tokio::time::timeout(Duration::from_secs(1), hc.wait_retired()).await.unwrap();
+        assert_eq!(hc.order_pool.lock().unwrap().hold.yd_lo, 2);
+
+        rb.trade_api.data_receive.send(tick());
+        assert_eq!(sent(&stra_api, Ticker::rb).await, OrderAction::ShCloseYd(2, 3501.));
+        assert!(stra_api.is_running());
+        fill_flat(&stra_api, Ticker::rb);
+        rb.trade_api.data_receive.send(tick());
+        tokio::time::timeout(Duration::from_secs(1), rb.wait_retired()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flatten_waits_for_a_tick() {
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(Stra))]);
+        let ticker_live = stra_api.update_di.ticker_live(&Ticker::rb).unwrap();
+        ticker_live.order_pool.lock().unwrap().hold.yd_lo = 2;
+        stra_api.start_spy_on_data_receive(stra_api.get_trade_api_vec1()).unwrap();
+        stra_api.command_all(ControlCommand::Flatten);
+        wait_mode(&stra_api, Ticker::rb, TickerMode::Flatten).await;
+        assert!(ticker_live.order_pool.lock().unwrap().pool.is_empty());
+        ticker_live.trade_api.data_receive.send(tick());
+        assert_eq!(sent(&stra_api, Ticker::rb).await, OrderAction::ShCloseYd(2, 3501.));
+        ticker_live.trade_api.stop();
+        tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn multiplier_scales_the_algo_target() {
+        let stra = WithAlgoBox { data: Box::new(Target(4.)) as Box<dyn CondType7>, algo: Box::new(TargetSimple) };
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(stra))]);
+        stra_api.start_spy_on_data_receive(stra_api.get_trade_api_vec1()).unwrap();
+        assert_eq!(stra_api.command_all(ControlCommand::Multiplier(0.5)), 1);
+        let ticker_live = stra_api.update_di.ticker_live(&Ticker::rb).unwrap();
+        ticker_live.trade_api.data_receive.send(tick());
+        assert_eq!(sent(&stra_api, Ticker::rb).await, OrderAction::LoOpen(2, 3500.));
+        assert_eq!(ticker_live.control.lock().unwrap().last_action, OrderAction::LoOpen(2, 3500.));
+        ticker_live.trade_api.stop();
+        tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+    }
+}
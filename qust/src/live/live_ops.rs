@@ -1,70 +1,162 @@
 use crate::prelude::StreamApiType;
-use crate::{ loge, std_prelude::*, trade::prelude::* };
+use crate::{ loge, metric, std_prelude::*, trade::prelude::* };
 use qust_ds::prelude::*;
 use serde::de::DeserializeOwned;
-use std::sync::MutexGuard;
-use super::prelude::LiveStraPool;
+use super::prelude::{ApiType, ApiTypeBox, LiveStraPool, ControlCommand, TickerControl, TickerMode};
+use super::algo::target_simple_action;
 use super::order_types::*;
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{broadcast, mpsc::{self, error::TrySendError}, watch, Mutex as AsyncMutex, Notify};
 
-#[derive(Default, Debug)]
-pub struct NotifyData<T> {
-    pub data: Mutex<T>,
-    pub started: Mutex<bool>,
-    cv: Condvar,
+/// A bounded channel shared through `TradeApi`. Any holder can send, the one task that
+/// consumes the data takes the receiver for as long as it waits on it.
+#[derive(Debug)]
+pub struct LiveChannel<T> {
+    sender: mpsc::Sender<T>,
+    receiver: AsyncMutex<mpsc::Receiver<T>>,
 }
 
-impl<T> NotifyData<T> {
-    pub fn notify_all(&self) {
-        self.cv.notify_all();
+impl<T> LiveChannel<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self { sender, receiver: AsyncMutex::new(receiver) }
     }
 
-    pub fn wait_or_exit(&self, info: &str) -> (MutexGuard<T>, bool) {
-        let guard = self.cv.wait(self.data.lock().unwrap()).unwrap();
-        let is_started = *self.started.lock().unwrap();
-        if !is_started {
-            loge!("spy", "{info}");
-        }
-        (guard, is_started)
+    pub async fn send(&self, data: T) {
+        // the receiver lives as long as the channel, so the send cannot fail
+        let _ = self.sender.send(data).await;
     }
 
-    pub fn start(&self) {
-        *self.started.lock().unwrap() = true;
+    pub fn try_send(&self, data: T) -> Result<(), TrySendError<T>> {
+        self.sender.try_send(data)
     }
 
-    pub fn stop(&self) {
-        *self.started.lock().unwrap() = false;
-        self.notify_all();
+    /// Waits for the next data, or `None` once `shutdown` fires.
+    pub async fn recv(&self, shutdown: &mut broadcast::Receiver<()>) -> Option<T> {
+        let mut receiver = self.receiver.lock().await;
+        tokio::select! {
+            biased;
+            _ = shutdown.recv() => None,
+            data = receiver.recv() => data,
+        }
     }
-    
-    pub fn set(&self, data: T) {
-        *self.data.lock().unwrap() = data;
+
+    /// Waits for data, and returns all that is queued at that moment.
+    pub async fn recv_many(&self, shutdown: &mut broadcast::Receiver<()>) -> Option<Vec<T>> {
+        let mut receiver = self.receiver.lock().await;
+        let mut res = Vec::with_capacity(receiver.len().max(1));
+        let limit = receiver.max_capacity();
+        tokio::select! {
+            biased;
+            _ = shutdown.recv() => None,
+            n = receiver.recv_many(&mut res, limit) => (n > 0).then_some(res),
+        }
     }
 }
 
-impl<T> NotifyData<VecDeque<T>> {
-    fn wait_or_exit_vec(&self, info: &str) -> (MutexGuard<VecDeque<T>>, bool) {
-        let mut guard = self.data.lock().unwrap();
-        while guard.is_empty() {
-            guard = self.cv.wait(guard).unwrap();
+/// What to do with ticks that find the channel of a lagging strategy full.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LagPolicy {
+    /// Drop them.
+    DropNew,
+    /// Keep only the latest of them, delivered once the strategy catches up.
+    #[default]
+    KeepLatest,
+}
+
+/// The `DataReceive` channel of a ticker. Ticks are bounded by the channel and the
+/// `LagPolicy`, order returns are never dropped: when the channel is full they wait in an
+/// overflow queue, which is handed out after the channel so the order is kept.
+#[derive(Debug)]
+pub struct DataReceiveChannel {
+    channel: LiveChannel<DataReceive>,
+    overflow: Mutex<VecDeque<DataReceive>>,
+    pub lag_policy: LagPolicy,
+    dropped: AtomicUsize,
+    #[cfg(feature = "metrics")]
+    tick_at: Mutex<Option<Instant>>,
+}
+
+impl DataReceiveChannel {
+    pub fn new(capacity: usize, lag_policy: LagPolicy) -> Self {
+        Self {
+            channel: LiveChannel::new(capacity),
+            overflow: Default::default(),
+            lag_policy,
+            dropped: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            tick_at: Default::default(),
         }
-        let is_started = *self.started.lock().unwrap();
-        if !is_started {
-            loge!("spy", "{info}");
+    }
+
+    pub fn send(&self, data: DataReceive) {
+        #[cfg(feature = "metrics")]
+        if let DataReceive::TickData(_) = data {
+            *self.tick_at.lock().unwrap() = Some(Instant::now());
+        }
+        let mut overflow = self.overflow.lock().unwrap();
+        let data = match overflow.is_empty() {
+            true => match self.channel.try_send(data) {
+                Ok(()) => return,
+                Err(TrySendError::Full(data) | TrySendError::Closed(data)) => data,
+            },
+            false => data,
+        };
+        match (data, self.lag_policy) {
+            (DataReceive::TickData(_), LagPolicy::DropNew) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            (data @ DataReceive::TickData(_), LagPolicy::KeepLatest) => {
+                let len_before = overflow.len();
+                overflow.retain(|x| !matches!(x, DataReceive::TickData(_)));
+                self.dropped.fetch_add(len_before - overflow.len(), Ordering::Relaxed);
+                overflow.push_back(data);
+            }
+            (data, _) => overflow.push_back(data),
         }
-        (guard, is_started)
     }
-    
-    pub fn push(&self, data: T) {
-        self.data.lock().unwrap().push_back(data);
+
+    /// Waits for data, and returns all that is queued at that moment, or `None` once
+    /// `shutdown` fires.
+    pub async fn recv_batch(&self, shutdown: &mut broadcast::Receiver<()>) -> Option<VecDeque<DataReceive>> {
+        let mut res: VecDeque<DataReceive> = self.channel.recv_many(shutdown).await?.into();
+        res.append(&mut self.overflow.lock().unwrap());
+        Some(res)
+    }
+
+    /// The number of ticks dropped since the last call.
+    pub fn take_dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// When the latest tick was sent, if any was since the last call.
+    #[cfg(feature = "metrics")]
+    pub fn take_tick_at(&self) -> Option<Instant> {
+        self.tick_at.lock().unwrap().take()
     }
 }
 
+impl Default for DataReceiveChannel {
+    fn default() -> Self {
+        Self::new(DATA_RECEIVE_CAPACITY, LagPolicy::default())
+    }
+}
+
+impl Default for LiveChannel<OrderSend> {
+    fn default() -> Self {
+        Self::new(DATA_SEND_CAPACITY)
+    }
+}
+
+pub const DATA_RECEIVE_CAPACITY: usize = 256;
+pub const DATA_SEND_CAPACITY: usize = 16;
 
 #[derive(Clone, Debug)]
 pub enum DataReceive {
     TickData(TickData),
     OrderReceive(OrderReceive),
+    Control(ControlCommand),
 }
 
 impl From<TickData> for DataReceive {
@@ -78,8 +170,8 @@ impl From<OrderReceive> for DataReceive {
     }
 }
 
-pub type DataSendOn = Arc<NotifyData<OrderSend>>;
-pub type DataReceiveOn = Arc<NotifyData<VecDeque<DataReceive>>>;
+pub type DataSendOn = Arc<LiveChannel<OrderSend>>;
+pub type DataReceiveOn = Arc<DataReceiveChannel>;
 
 #[derive(Debug)]
 pub struct TradeApi {
@@ -87,136 +179,347 @@ pub struct TradeApi {
     pub ticker: Ticker,
     pub data_send: DataSendOn,
     pub data_receive: DataReceiveOn,
+    pub shutdown: broadcast::Sender<()>,
 }
 
-#[derive(Default)]
-pub struct UpdateDi {
-    pub live_api: LiveStraPool,
-    ticker_contract_map: hm<Ticker, &'static str>,
-    ticker_order_pool_map: hm<Ticker, Arc<Mutex<OrderPool>>>,
-    pub ticker_record: hm<Ticker, Mutex<Vec<TickData>>>,
+impl TradeApi {
+    pub fn new(contract: &'static str, ticker: Ticker) -> Self {
+        Self {
+            contract,
+            ticker,
+            data_send: Default::default(),
+            data_receive: Default::default(),
+            shutdown: broadcast::channel(1).0,
+        }
+    }
+
+    /// Ends every task that subscribed to `shutdown` on this ticker.
+    pub fn stop(&self) {
+        let _ = self.shutdown.send(());
+    }
 }
 
-impl UpdateDi {
-    pub fn new(live_api: LiveStraPool, ticker_contract_map: hm<Ticker, &'static str>) -> Self {
-        let mut res = Self {
-            live_api,
-            ..Default::default()
+/// The state of a live ticker, kept across a reload of its strategy.
+pub struct TickerLive {
+    pub trade_api: Arc<TradeApi>,
+    pub order_pool: Mutex<OrderPool>,
+    pub record: Mutex<Vec<TickData>>,
+    pub control: Mutex<TickerControl>,
+    stra_key: Mutex<Option<String>>,
+    stra: Mutex<Option<ApiTypeBox>>,
+    stra_next: Mutex<Option<ApiTypeBox>>,
+    retired: Notify,
+}
+
+enum SpyEnd {
+    Shutdown,
+    Reload,
+    Retired,
+}
+
+impl TickerLive {
+    pub fn new(contract: &'static str, ticker: Ticker, stra: ApiTypeBox) -> Self {
+        let order_pool = OrderPool {
+            ticker,
+            hold: Default::default(),
+            pool: Default::default(),
+            combo: contract.extract_combo().map(ComboHold::new),
         };
-        res.merge_ticker_contract_map(ticker_contract_map);
-        res
+        Self {
+            trade_api: TradeApi::new(contract, ticker).pip(Arc::new),
+            order_pool: Mutex::new(order_pool),
+            record: Default::default(),
+            control: Default::default(),
+            stra_key: Mutex::new(stra.stra_key()),
+            stra: Mutex::new(Some(stra)),
+            stra_next: Default::default(),
+            retired: Notify::new(),
+        }
     }
 
-    pub fn get_ticker_string_vec(&self) -> Vec<String> {
-        self
-            .ticker_contract_map
-            .keys()
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
-    }
-    
-    pub fn merge_ticker_contract_map(&mut self, ticker_contract_map: hm<Ticker, &'static str>) {
-        self.ticker_contract_map.clear();
-        let (ticker_contract_map, ticker_order_pool_map, ticker_record) = self
-            .live_api
-            .data
-            .iter()
-            .fold((hm::new(), hm::new(), hm::new()), |mut accu, live_api_ticker| {
-                let ticker = live_api_ticker.ticker;
-                match ticker_contract_map.get(&ticker) {
-                    Some(&contract) => {
-                        let order_pool = OrderPool {
-                            ticker,
-                            hold: Default::default(),
-                            pool: Default::default(),
-                        };
-                        let order_pool = Arc::new(Mutex::new(order_pool));
-                        // accu.0.insert(contract, ticker);
-                        accu.0.insert(ticker, contract);
-                        accu.1.insert(ticker, order_pool);
-                        accu.2.insert(ticker, Default::default());
-                    }
-                    None => {
-                        loge!("stra", "ticker cannot find mapping contract");
-                    }
-                }
-                accu
-            });
-        self.ticker_contract_map = ticker_contract_map;
-        self.ticker_order_pool_map = ticker_order_pool_map;
-        self.ticker_record = ticker_record;
-    }
-
-    fn start_spy_on_data_receive(&self, trade_api: Arc<TradeApi>) -> Option<()> {
-        trade_api.data_receive.start();
-        let order_pool_arc = self.ticker_order_pool_map.get(&trade_api.ticker)?.clone();
-        let mut order_pool = order_pool_arc.lock().unwrap();
-        let live_api_ticker = self.live_api.data.iter().find(|x| x.ticker == trade_api.ticker)?;
-        let mut live_api_ops = live_api_ticker.data.api_type();
-        let mut last_tick_data = TickData::default();
+    pub fn stra_key(&self) -> Option<String> {
+        self.stra_key.lock().unwrap().clone()
+    }
+
+    /// The strategy replaces the running one at its next batch, taking over its `Di`s.
+    /// The `OrderPool` and the control state are kept.
+    pub fn stage_stra(&self, stra: ApiTypeBox) {
+        loge!(self.trade_api.ticker, "stra staged to replace the running one");
+        *self.stra_key.lock().unwrap() = stra.stra_key();
+        *self.stra_next.lock().unwrap() = Some(stra);
+        self.trade_api.data_receive.send(DataReceive::Control(ControlCommand::Reload));
+    }
+
+    /// Waits until the strategy task ends after a `ControlCommand::Retire`.
+    pub async fn wait_retired(&self) {
+        self.retired.notified().await
+    }
+
+    fn take_stra(&self) -> Option<ApiTypeBox> {
+        let mut stra = self.stra.lock().unwrap().take()?;
+        self.apply_stra_next(&mut stra);
+        Some(stra)
+    }
+
+    fn apply_stra_next(&self, stra: &mut ApiTypeBox) {
+        let stra_next = self.stra_next.lock().unwrap().take();
+        if let Some(mut stra_next) = stra_next {
+            stra_next.put_di(stra.take_di());
+            *stra = stra_next;
+            loge!(self.trade_api.ticker, "stra replaced by reload");
+        }
+    }
+
+    async fn start_spy_on_data_receive(&self, mut shutdown: broadcast::Receiver<()>, update_di: &UpdateDi) {
+        let trade_api = &self.trade_api;
+        let Some(mut stra) = self.take_stra() else {
+            loge!(level: Warn, trade_api.ticker, "stra is already running");
+            return;
+        };
+        let mut last_tick_data = None;
         loge!("spy", "stra start to send data: {:?}", trade_api.ticker);
         loop {
-            let (mut guard, is_started) = trade_api
-                .data_receive
-                .wait_or_exit_vec(&format!("stra stop to receive data: {:?}", trade_api.ticker));
-            if !is_started {
-                break;
+            match self.spy(stra.as_ref(), &mut last_tick_data, &mut shutdown, update_di).await {
+                SpyEnd::Reload => self.apply_stra_next(&mut stra),
+                SpyEnd::Shutdown => break,
+                SpyEnd::Retired => {
+                    loge!(trade_api.ticker, "stra retired");
+                    self.retired.notify_one();
+                    break;
+                }
             }
-            let mut data_receive_vec = VecDeque::default();
-            data_receive_vec.append(&mut guard);
-            drop(guard);
+        }
+        *self.stra.lock().unwrap() = Some(stra);
+        loge!("spy", "stra stop to receive data: {:?}", trade_api.ticker);
+    }
+
+    /// The exchange keeps the hold of a combination by leg, so the pool of the ticker
+    /// trading a leg takes its fills as well.
+    fn hand_leg_fill(&self, update_di: &UpdateDi, leg_fill: LegFill) {
+        let ticker = self.trade_api.ticker;
+        let Some(leg_live) = update_di.ticker_live_by_contract(&leg_fill.contract) else {
+            loge!(ticker, "no ticker trades the leg, the combination keeps its fill: {:?}", leg_fill);
+            return;
+        };
+        let mut order_pool = leg_live.order_pool.lock().unwrap();
+        match order_pool.hold.fill(&leg_fill.order_action, leg_fill.n) {
+            true => loge!(leg_live.trade_api.ticker, "order pool take a leg fill of {}: {:?}", ticker, leg_fill),
+            false => loge!(level: Error, leg_live.trade_api.ticker, "order pool cannot take a leg fill: {:?}", leg_fill),
+        }
+    }
+
+    async fn spy(
+        &self,
+        stra: &dyn ApiType,
+        last_tick_data: &mut Option<TickData>,
+        shutdown: &mut broadcast::Receiver<()>,
+        update_di: &UpdateDi,
+    ) -> SpyEnd {
+        let trade_api = &self.trade_api;
+        let mut live_api_ops = stra.api_type();
+        while let Some(mut data_receive_vec) = trade_api.data_receive.recv_batch(shutdown).await {
             loge!(trade_api.ticker, "data receive: cumlative len: {}", data_receive_vec.len());
-            while let Some(data_receive) = data_receive_vec.pop_front() {
-                match data_receive {
-                    DataReceive::TickData(tick_data) => {
-                        loge!(trade_api.ticker, "data recive ---------- tick data --------------");
-                        self.ticker_record[&trade_api.ticker].lock().unwrap().push(tick_data.clone());
-                        last_tick_data = tick_data;
-                        let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &order_pool.hold };
-                        live_api_ops(stream_api);
-                        loge!(trade_api.ticker, "data recive ++++++++++ tick data ++++++++++++++");
-                    }
-                    DataReceive::OrderReceive(data_receive) => {
-                        loge!(trade_api.ticker, "data recive ---------- data receive --------------");
-                        if let Err(e) = order_pool.update_order(data_receive) {
-                            loge!(trade_api.ticker, "update err {:?}", e);
+            metric!(gauge: "qust_data_receive_depth", ticker = trade_api.ticker; data_receive_vec.len());
+            #[cfg(feature = "metrics")]
+            let tick_at = trade_api.data_receive.take_tick_at();
+            let dropped = trade_api.data_receive.take_dropped();
+            if dropped > 0 {
+                loge!(level: Warn, trade_api.ticker, "stra lags, {} ticks dropped", dropped);
+                metric!(counter: "qust_ticks_dropped_total", ticker = trade_api.ticker; dropped);
+            }
+            let mut is_reload = false;
+            let mut is_retired = false;
+            let (order_cancel_vec, order_res, leg_fills) = {
+                let mut order_pool = self.order_pool.lock().unwrap();
+                let mut control = self.control.lock().unwrap();
+                let mut order_cancel_vec = vec![];
+                while let Some(data_receive) = data_receive_vec.pop_front() {
+                    match data_receive {
+                        DataReceive::TickData(tick_data) => {
+                            loge!(trade_api.ticker, "data recive ---------- tick data --------------");
+                            self.record.lock().unwrap().push(tick_data.clone());
+                            let last_tick_data = last_tick_data.insert(tick_data);
+                            metric!(mark: trade_api.ticker, last_tick_data.c);
+                            let stream_api = StreamApiType {
+                                tick_data: last_tick_data,
+                                hold: &order_pool.hold,
+                                target_ratio: control.multiplier,
+                                order_working: order_pool.is_working(),
+                            };
+                            metric!(time: "qust_stra_eval_seconds", ticker = trade_api.ticker; live_api_ops(stream_api));
+                            loge!(trade_api.ticker, "data recive ++++++++++ tick data ++++++++++++++");
                         }
-                        loge!(trade_api.ticker, "data recive ++++++++++ data receive ++++++++++++++");
-                    } 
-                }
-                if data_receive_vec.is_empty() {
-                    loge!(trade_api.ticker, "data receive ----------: {:?}", &order_pool.hold);
-                    let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &order_pool.hold };
-                    let order_action = live_api_ops(stream_api);
-                    loge!(trade_api.ticker, "stra calced a order_action: {:?}", order_action);
-                    match order_pool.process_order_action(order_action) {
-                        Ok(Some(order_input)) => {
-                            loge!(trade_api.ticker, "data receive +++++++ stra send a order to ctp: {:?}", order_input);
-                            trade_api.data_send.set(order_input);
-                            trade_api.data_send.notify_all();
+                        DataReceive::OrderReceive(data_receive) => {
+                            loge!(trade_api.ticker, "data recive ---------- data receive --------------");
+                            if let Err(e) = order_pool.update_order(data_receive) {
+                                loge!(trade_api.ticker, "update err {:?}", e);
+                            }
+                            loge!(trade_api.ticker, "data recive ++++++++++ data receive ++++++++++++++");
                         }
-                        Ok(None) => {
-                            loge!(trade_api.ticker, "data receive +++++++ stra order pool calc a none order send");
+                        DataReceive::Control(ControlCommand::Reload) => {
+                            loge!(trade_api.ticker, "data receive a reload");
+                            is_reload = true;
                         }
-                        Err(e) => {
-                            loge!(trade_api.ticker, "data receive +++++++ order output error: {:?}", e);
+                        DataReceive::Control(control_command) => {
+                            loge!(trade_api.ticker, "data receive a control command: {:?}", control_command);
+                            if control.apply(control_command) {
+                                order_cancel_vec.extend(order_pool.cancel_all());
+                            }
                         }
                     }
                 }
+                loge!(trade_api.ticker, "data receive ----------: {:?}", &order_pool.hold);
+                metric!(gauge: "qust_position", ticker = trade_api.ticker; order_pool.hold.sum());
+                // a command before the first tick has no price to close at
+                let order_action = match (control.mode, last_tick_data.as_ref()) {
+                    (TickerMode::Paused, _) => None,
+                    (TickerMode::Retiring { flatten: false }, _) => {
+                        is_retired = order_pool.pool.is_empty();
+                        Some(OrderAction::No)
+                    }
+                    (_, None) => {
+                        loge!(trade_api.ticker, "stra waits for a tick to act");
+                        None
+                    }
+                    (TickerMode::Running, Some(last_tick_data)) => {
+                        let stream_api = StreamApiType {
+                            tick_data: last_tick_data,
+                            hold: &order_pool.hold,
+                            target_ratio: control.multiplier,
+                            order_working: order_pool.is_working(),
+                        };
+                        let order_action = metric!(time: "qust_stra_eval_seconds", ticker = trade_api.ticker; live_api_ops(stream_api));
+                        control.last_action = order_action.clone();
+                        Some(order_action)
+                    }
+                    (TickerMode::Flatten, Some(last_tick_data)) => {
+                        let order_action = target_simple_action(0, &order_pool.hold, last_tick_data);
+                        if let OrderAction::No = order_action {
+                            if order_pool.pool.is_empty() {
+                                loge!(trade_api.ticker, "stra flattened, pause it");
+                                control.mode = TickerMode::Paused;
+                            }
+                        }
+                        Some(order_action)
+                    }
+                    (TickerMode::Retiring { .. }, Some(last_tick_data)) => {
+                        let order_action = target_simple_action(0, &order_pool.hold, last_tick_data);
+                        is_retired = matches!(order_action, OrderAction::No) && order_pool.pool.is_empty();
+                        Some(order_action)
+                    }
+                };
+                loge!(trade_api.ticker, "stra calced a order_action: {:?}", order_action);
+                let order_res = order_action.map(|x| order_pool.process_order_action(x));
+                (order_cancel_vec, order_res, order_pool.take_leg_fills())
+            };
+            for leg_fill in leg_fills {
+                self.hand_leg_fill(update_di, leg_fill);
+            }
+            for order_cancel in order_cancel_vec {
+                loge!(trade_api.ticker, "data receive +++++++ control cancel a order: {:?}", order_cancel);
+                metric!(sent: trade_api.ticker, &order_cancel);
+                trade_api.data_send.send(order_cancel).await;
+            }
+            match order_res {
+                Some(Ok(Some(order_input))) => {
+                    loge!(trade_api.ticker, "data receive +++++++ stra send a order to ctp: {:?}", order_input);
+                    metric!(sent: trade_api.ticker, &order_input);
+                    metric!(since: "qust_tick_to_order_seconds", ticker = trade_api.ticker; tick_at.filter(|_| !order_input.is_to_cancel));
+                    trade_api.data_send.send(order_input).await;
+                }
+                Some(Ok(None)) => {
+                    loge!(trade_api.ticker, "data receive +++++++ stra order pool calc a none order send");
+                }
+                Some(Err(e)) => {
+                    loge!(trade_api.ticker, "data receive +++++++ order output error: {:?}", e);
+                }
+                None => {
+                    loge!(trade_api.ticker, "data receive +++++++ stra paused");
+                }
+            }
+            if is_retired {
+                return SpyEnd::Retired;
+            }
+            if is_reload {
+                return SpyEnd::Reload;
             }
         }
-        Some(())
+        SpyEnd::Shutdown
+    }
+}
+
+/// The live tickers, which a reload adds to and removes from while the strategies run.
+#[derive(Default)]
+pub struct UpdateDi {
+    tickers: RwLock<hm<Ticker, Arc<TickerLive>>>,
+}
+
+impl UpdateDi {
+    pub fn new(live_api: LiveStraPool, ticker_contract_map: hm<Ticker, &'static str>) -> Self {
+        let res = Self::default();
+        for live_api_ticker in live_api.data.into_iter() {
+            let ticker = live_api_ticker.ticker;
+            match ticker_contract_map.get(&ticker) {
+                Some(&contract) => {
+                    res.insert_ticker(TickerLive::new(contract, ticker, live_api_ticker.data).pip(Arc::new));
+                }
+                None => {
+                    loge!("stra", "ticker cannot find mapping contract");
+                }
+            }
+        }
+        res
+    }
+
+    pub fn get_ticker_string_vec(&self) -> Vec<String> {
+        self.ticker_live_vec()
+            .iter()
+            .map(|x| x.trade_api.ticker.to_string())
+            .collect_vec()
+    }
+
+    pub fn ticker_live(&self, ticker: &Ticker) -> Option<Arc<TickerLive>> {
+        self.tickers.read().unwrap().get(ticker).cloned()
+    }
+
+    /// The live tickers, in the order of their names.
+    pub fn ticker_live_vec(&self) -> Vec<Arc<TickerLive>> {
+        self.tickers
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .sorted_by_key(|x| x.trade_api.ticker.to_string())
+            .collect_vec()
+    }
+
+    pub fn ticker_live_by_contract(&self, contract: &str) -> Option<Arc<TickerLive>> {
+        self.tickers
+            .read()
+            .unwrap()
+            .values()
+            .find(|x| x.trade_api.contract == contract)
+            .cloned()
+    }
+
+    pub fn insert_ticker(&self, ticker_live: Arc<TickerLive>) {
+        self.tickers.write().unwrap().insert(ticker_live.trade_api.ticker, ticker_live);
+    }
+
+    pub fn remove_ticker(&self, ticker: &Ticker) -> Option<Arc<TickerLive>> {
+        self.tickers.write().unwrap().remove(ticker)
     }
 }
 
 pub struct StraApi {
     pub update_di: Arc<UpdateDi>,
+    /// The number of ticker threads alive, none when they have all ended.
+    alive: Arc<watch::Sender<Option<usize>>>,
 }
 
 impl StraApi {
     pub fn new(live_api: LiveStraPool, ticker_contract_map: hm<Ticker, &'static str>) -> Self {
-        let update_di = UpdateDi::new(live_api, ticker_contract_map).pip(Arc::new);
-        StraApi { update_di }
+        UpdateDi::new(live_api, ticker_contract_map).into()
     }
 
     pub fn load_from_update_di_path<T>(p: impl AsRef<Path>) -> Self
@@ -227,34 +530,225 @@ impl StraApi {
         let file_name = p_path.file_name().unwrap();
         let dir_name = p_path.parent().unwrap();
         let stra_api = T::rof(file_name.to_str().unwrap(), dir_name.as_os_str().to_str().unwrap());
-        let update_di = UpdateDi::new(stra_api.into(), Default::default());
-        Self { update_di: Arc::new(update_di) }
+        UpdateDi::new(stra_api.into(), Default::default()).into()
     }
 
     pub fn get_trade_api_vec1(&self) -> Vec<Arc<TradeApi>> {
         self.update_di
-            .ticker_contract_map
+            .ticker_live_vec()
+            .into_iter()
+            .map(|x| Arc::clone(&x.trade_api))
+            .collect_vec()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.alive.borrow().is_some()
+    }
+
+    fn threads_ended(alive: &watch::Sender<Option<usize>>, ended: usize) {
+        alive.send_modify(|x| {
+            if let Some(n) = x {
+                *n = n.saturating_sub(ended);
+                if *n == 0 {
+                    *x = None;
+                }
+            }
+        });
+    }
+
+    /// The closure of `ApiType::api_type` is not `Send` and keeps the state of the
+    /// strategy across the awaits on the channels, so the task of a ticker cannot move
+    /// between the workers of a shared runtime; making it `Send` would bind every
+    /// strategy. Each ticker is then the local task of a current thread runtime on a
+    /// thread of its own, rather than of a pool of `LocalSet` threads, where a slow
+    /// evaluation of one ticker would hold up the ticks of the others on its thread.
+    fn spawn_thread(
+        alive: Arc<watch::Sender<Option<usize>>>,
+        update_di: Arc<UpdateDi>,
+        ticker_live: Arc<TickerLive>,
+    ) -> std::io::Result<()> {
+        let shutdown = ticker_live.trade_api.shutdown.subscribe();
+        let name = format!("stra-{}", ticker_live.trade_api.ticker);
+        let res = thread::Builder::new().name(name).spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            let local_set = tokio::task::LocalSet::new();
+            local_set.block_on(&rt, ticker_live.start_spy_on_data_receive(shutdown, &update_di));
+            Self::threads_ended(&alive, 1);
+        });
+        res.map(|_| ())
+    }
+
+    /// Starts the strategy of a ticker while the others are running, returns false if
+    /// they are not.
+    pub fn spawn_ticker(&self, ticker_live: Arc<TickerLive>) -> bool {
+        let is_running = self.alive.send_if_modified(|x| match x {
+            Some(n) => {
+                *n += 1;
+                true
+            }
+            None => false,
+        });
+        if !is_running {
+            return false;
+        }
+        if let Err(e) = Self::spawn_thread(Arc::clone(&self.alive), Arc::clone(&self.update_di), ticker_live) {
+            loge!(level: Error, "stra", "cannot spawn a stra thread: {:?}", e);
+            Self::threads_ended(&self.alive, 1);
+            return false;
+        }
+        true
+    }
+
+    /// Starts a thread for each ticker. It runs until the ticker gets its shutdown;
+    /// tickers added by a reload start theirs through `spawn_ticker`.
+    pub fn start_spy_on_data_receive(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> std::io::Result<()> {
+        let ticker_live_vec = trade_api_vec
             .iter()
-            .map(|(ticker, contract)| {
-                TradeApi {
-                    contract,
-                    ticker: *ticker,
-                    data_send: Default::default(),
-                    data_receive: Default::default(),
+            .filter_map(|trade_api| {
+                let res = self.update_di.ticker_live(&trade_api.ticker);
+                if res.is_none() {
+                    loge!(level: Warn, "stra", "no stra for the ticker: {:?}", trade_api.ticker);
                 }
-                .pip(Arc::new)
+                res
             })
-            .collect_vec()
+            .collect_vec();
+        if ticker_live_vec.is_empty() {
+            return Ok(());
+        }
+        self.alive.send_replace(Some(ticker_live_vec.len()));
+        let n = ticker_live_vec.len();
+        for (i, ticker_live) in ticker_live_vec.into_iter().enumerate() {
+            if let Err(e) = Self::spawn_thread(Arc::clone(&self.alive), Arc::clone(&self.update_di), ticker_live) {
+                Self::threads_ended(&self.alive, n - i);
+                return Err(e);
+            }
+        }
+        Ok(())
     }
 
-    pub fn start_spy_on_data_send(&self, trade_api: Arc<TradeApi>) {
-        trade_api.data_send.start();
+    /// Waits for the ticker threads to finish the batch at hand after the shutdown.
+    pub async fn wait_finished(&self) {
+        let mut alive = self.alive.subscribe();
+        let _ = alive.wait_for(|x| x.is_none()).await;
     }
+}
 
-    pub fn start_spy_on_data_receive(&self, trade_api: Arc<TradeApi>) {
-        let update_di = Arc::clone(&self.update_di);
-        thread::spawn(move || {
-            update_di.start_spy_on_data_receive(trade_api);
-        });
+impl From<UpdateDi> for StraApi {
+    fn from(value: UpdateDi) -> Self {
+        StraApi {
+            update_di: Arc::new(value),
+            alive: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::{RetFnApi, WithTicker};
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Stra;
+
+    impl ApiType for Stra {
+        fn api_type(&self) -> RetFnApi<'_> {
+            Box::new(|_| OrderAction::No)
+        }
+    }
+
+    fn tick(c: f32) -> DataReceive {
+        TickData { c, ..Default::default() }.into()
+    }
+
+    /// The close of the ticks and -1 for anything else.
+    fn batch_of(batch: VecDeque<DataReceive>) -> Vec<f32> {
+        batch
+            .into_iter()
+            .map(|x| match x {
+                DataReceive::TickData(x) => x.c,
+                _ => -1.,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn full_channel_holds_the_sender() {
+        let channel = LiveChannel::new(2);
+        let (_shutdown_send, mut shutdown) = broadcast::channel(1);
+        channel.send(1).await;
+        channel.send(2).await;
+        assert!(matches!(channel.try_send(3), Err(TrySendError::Full(3))));
+        let send = channel.send(3);
+        tokio::pin!(send);
+        assert!(tokio::time::timeout(Duration::from_millis(20), &mut send).await.is_err());
+        assert_eq!(channel.recv(&mut shutdown).await, Some(1));
+        assert!(tokio::time::timeout(Duration::from_secs(1), send).await.is_ok());
+        assert_eq!(channel.recv_many(&mut shutdown).await, Some(vec![2, 3]));
+    }
+
+    #[tokio::test]
+    async fn shutdown_ends_the_receive() {
+        let channel = LiveChannel::new(2);
+        let (shutdown_send, mut shutdown) = broadcast::channel(1);
+        channel.send(1).await;
+        shutdown_send.send(()).unwrap();
+        assert_eq!(channel.recv(&mut shutdown).await, None);
+    }
+
+    #[tokio::test]
+    async fn lagging_ticks_by_the_policy() {
+        let (_shutdown_send, mut shutdown) = broadcast::channel(1);
+        let batch = |lag_policy| {
+            let channel = DataReceiveChannel::new(2, lag_policy);
+            [tick(1.), tick(2.), DataReceive::Control(ControlCommand::Pause), tick(3.), tick(4.)]
+                .into_iter()
+                .for_each(|x| channel.send(x));
+            channel
+        };
+        let channel = batch(LagPolicy::DropNew);
+        assert_eq!(channel.take_dropped(), 2);
+        assert_eq!(batch_of(channel.recv_batch(&mut shutdown).await.unwrap()), vec![1., 2., -1.]);
+        channel.send(tick(5.));
+        assert_eq!(batch_of(channel.recv_batch(&mut shutdown).await.unwrap()), vec![5.]);
+
+        let channel = batch(LagPolicy::KeepLatest);
+        assert_eq!(channel.take_dropped(), 1);
+        assert_eq!(batch_of(channel.recv_batch(&mut shutdown).await.unwrap()), vec![1., 2., -1., 4.]);
+        assert_eq!(channel.take_dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn stop_ends_the_ticker_threads() {
+        let ticker_contract_map = hm::from([(Ticker::rb, "rb2501"), (Ticker::hc, "hc2501")]);
+        let data = [Ticker::rb, Ticker::hc]
+            .into_iter()
+            .map(|ticker| WithTicker { ticker, data: Box::new(Stra) as ApiTypeBox })
+            .collect();
+        let stra_api = StraApi::new(LiveStraPool { data }, ticker_contract_map);
+        assert!(!stra_api.is_running());
+        stra_api.start_spy_on_data_receive(stra_api.get_trade_api_vec1()).unwrap();
+        assert!(stra_api.is_running());
+        let (rb, hc) = (stra_api.update_di.ticker_live(&Ticker::rb).unwrap(), stra_api.update_di.ticker_live(&Ticker::hc).unwrap());
+        rb.trade_api.data_receive.send(tick(1.));
+        hc.trade_api.data_receive.send(tick(2.));
+        for _ in 0..200 {
+            if !rb.record.lock().unwrap().is_empty() && !hc.record.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!((rb.record.lock().unwrap().len(), hc.record.lock().unwrap().len()), (1, 1));
+
+        rb.trade_api.stop();
+        let mut alive = stra_api.alive.subscribe();
+        tokio::time::timeout(Duration::from_secs(1), alive.wait_for(|x| *x == Some(1))).await.unwrap().unwrap();
+        assert!(stra_api.is_running());
+        assert!(rb.stra.lock().unwrap().is_some());
+        hc.trade_api.stop();
+        tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+        assert!(!stra_api.is_running());
+        assert!(hc.stra.lock().unwrap().is_some());
+        assert!(!stra_api.spawn_ticker(rb));
     }
 }
@@ -1,29 +1,50 @@
+use std::future::Future;
 use std::sync::Arc;
-use super::prelude::{StraApi, TradeApi};
+use super::prelude::{
+    Algo, AlgoBox, ControlCommand, LiveStraPool, ScaledAlgo, SessionEnd, SessionEvent, SessionTrader, StraApi, TradeApi,
+};
+use crate::prelude::Ticker;
 use anyhow::Result;
-use qust_ds::prelude::logging_service;
+use qust_ds::prelude::{hm, logging_service};
 
 
 pub trait ServiceApi {
-    fn start(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()>;
-    fn stop(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()>;
+    fn start(&self, trade_api: Vec<Arc<TradeApi>>) -> impl Future<Output = Result<()>> + Send;
+    fn stop(&self, trade_api: Vec<Arc<TradeApi>>) -> impl Future<Output = Result<()>> + Send;
+    /// Starts the tickers a reload adds while the service is running.
+    fn add(&self, trade_api: Vec<Arc<TradeApi>>) -> impl Future<Output = Result<()>> + Send {
+        let tickers = trade_api.iter().map(|x| x.ticker).collect::<Vec<_>>();
+        async move { anyhow::bail!("the service cannot add tickers while running: {:?}", tickers) }
+    }
+    /// Stops the tickers a reload removes while the service is running.
+    fn remove(&self, trade_api: Vec<Arc<TradeApi>>) -> impl Future<Output = Result<()>> + Send {
+        trade_api.iter().for_each(|x| x.stop());
+        async { Ok(()) }
+    }
 }
 
 
 impl ServiceApi for StraApi {
-    fn start(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> Result<()> {
-        trade_api_vec.iter().for_each(|x| {
-            self.start_spy_on_data_send(Arc::clone(x));
-            self.start_spy_on_data_receive(Arc::clone(x));
-        });
+    async fn start(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> Result<()> {
+        self.start_spy_on_data_receive(trade_api_vec)?;
         Ok(())
     }
 
-    fn stop(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> Result<()> {
-        trade_api_vec.iter().for_each(|trade_api| {
-            trade_api.data_send.stop();
-            trade_api.data_receive.stop();
-        });
+    async fn stop(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> Result<()> {
+        trade_api_vec.iter().for_each(|trade_api| trade_api.stop());
+        self.wait_finished().await;
+        Ok(())
+    }
+
+    async fn add(&self, trade_api_vec: Vec<Arc<TradeApi>>) -> Result<()> {
+        for trade_api in trade_api_vec.iter() {
+            let Some(ticker_live) = self.update_di.ticker_live(&trade_api.ticker) else {
+                anyhow::bail!("no stra for the ticker: {:?}", trade_api.ticker);
+            };
+            if !self.spawn_ticker(ticker_live) {
+                anyhow::bail!("stra is not running");
+            }
+        }
         Ok(())
     }
 }
@@ -48,15 +69,200 @@ where
         Ok(())
     }
 
-    pub fn start(&self) -> Result<()> {
-        self.stra_api.start(self.trade_api.clone())?;
-        self.service_api.start(self.trade_api.clone())?;
+    pub async fn start(&self) -> Result<()> {
+        self.stra_api.start(self.trade_api.clone()).await?;
+        self.service_api.start(self.trade_api.clone()).await?;
         Ok(())
     }
 
-    pub fn stop(&self) -> Result<()> {
-        self.stra_api.stop(self.trade_api.clone())?;
-        self.service_api.stop(self.trade_api.clone())?;
+    pub async fn stop(&self) -> Result<()> {
+        self.stra_api.stop(self.trade_api.clone()).await?;
+        self.service_api.stop(self.trade_api.clone()).await?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// A service a trader logs in to before each session and out of after it.
+pub trait SessionService: ServiceApi + Sized {
+    /// Logs in without subscribing, confirming the settlement at the first session of a
+    /// trading day.
+    fn login(&self, confirm_settlement: bool) -> impl Future<Output = Result<()>>;
+    /// A service to log in with at the next session, as one stopped cannot start again.
+    fn renew(&self, trade_api: Vec<Arc<TradeApi>>) -> Self;
+}
+
+/// The strategies start paused at the subscription, so they warm up on the ticks before
+/// the open.
+impl<N: SessionService> SessionTrader for RunningApi<StraApi, N> {
+    async fn on_session_event(&mut self, event: SessionEvent) -> Result<()> {
+        match event {
+            SessionEvent::Login { confirm_settlement } => self.service_api.login(confirm_settlement).await,
+            SessionEvent::Subscribe => {
+                self.stra_api.start(self.trade_api.clone()).await?;
+                self.stra_api.command_all(ControlCommand::Pause);
+                self.service_api.add(self.trade_api.clone()).await
+            }
+            SessionEvent::Open => {
+                self.stra_api.command_all(ControlCommand::Resume);
+                Ok(())
+            }
+            SessionEvent::End(SessionEnd::Flatten) => {
+                self.stra_api.command_all(ControlCommand::Flatten);
+                Ok(())
+            }
+            SessionEvent::End(SessionEnd::Hold) => Ok(()),
+            SessionEvent::Logout => {
+                let res = self.stop().await;
+                self.service_api = self.service_api.renew(self.trade_api.clone());
+                res
+            }
+        }
+    }
+}
+
+/// The same strategies on several accounts. Each account has its own `StraApi`, so the
+/// order pools and holds are kept per (account, ticker), and its algo scaled by the
+/// account's ratio.
+pub struct MultiStraApi {
+    pub stra_api_vec: Vec<StraApi>,
+    pub trade_api_vecs: Vec<Vec<Arc<TradeApi>>>,
+}
+
+impl MultiStraApi {
+    /// `gen_pool` builds the strategies of one account from the algo, which is `algo`
+    /// wrapped in a `ScaledAlgo` by the account's ratio.
+    pub fn new<F>(ratios: &[f32], ticker_contract_map: hm<Ticker, &'static str>, algo: AlgoBox, gen_pool: F) -> Self
+    where
+        F: Fn(AlgoBox) -> LiveStraPool,
+    {
+        let stra_api_vec = ratios
+            .iter()
+            .map(|ratio| {
+                let algo_scaled = ScaledAlgo { algo: algo.clone(), ratio: *ratio }.algo_box();
+                StraApi::new(gen_pool(algo_scaled), ticker_contract_map.clone())
+            })
+            .collect::<Vec<_>>();
+        let trade_api_vecs = stra_api_vec.iter().map(|x| x.get_trade_api_vec1()).collect();
+        Self { stra_api_vec, trade_api_vecs }
+    }
+
+    /// The tickers of every account, for one md connection to feed the ticks to them all.
+    pub fn md_follower(&self) -> Vec<Arc<TradeApi>> {
+        self.trade_api_vecs.iter().flatten().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::{CondType7, OrderAction, TargetSimple, TickerMode, WithAlgoBox, WithTicker};
+    use crate::prelude::{TickData, Ticker};
+    use crate::test_util::{sent, stra_api, tick, ticker_contract_map, wait_mode, Target};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Takes down what is asked of it, in a log shared by the services renewed from it.
+    #[derive(Clone, Default)]
+    struct Service {
+        id: usize,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Service {
+        fn call(&self, call: &str, trade_api: &[Arc<TradeApi>]) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("{} {} {}", self.id, call, trade_api.len()));
+            Ok(())
+        }
+    }
+
+    impl ServiceApi for Service {
+        async fn start(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+            self.call("start", &trade_api)
+        }
+
+        async fn stop(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+            self.call("stop", &trade_api)
+        }
+
+        async fn add(&self, trade_api: Vec<Arc<TradeApi>>) -> Result<()> {
+            self.call("add", &trade_api)
+        }
+    }
+
+    impl SessionService for Service {
+        async fn login(&self, confirm_settlement: bool) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("{} login {}", self.id, confirm_settlement));
+            Ok(())
+        }
+
+        fn renew(&self, _trade_api: Vec<Arc<TradeApi>>) -> Self {
+            Self { id: self.id + 1, calls: self.calls.clone() }
+        }
+    }
+
+    #[tokio::test]
+    async fn account_trades_from_the_open_to_the_logout() {
+        let stra = WithAlgoBox { data: Box::new(Target(2.)) as Box<dyn CondType7>, algo: Box::new(TargetSimple) };
+        let stra_api = stra_api(vec![(Ticker::rb, Box::new(stra))]);
+        let trade_api = stra_api.get_trade_api_vec1();
+        let service = Service::default();
+        let mut running_api = RunningApi { stra_api, service_api: service.clone(), log_path: None, trade_api };
+        let rb = running_api.stra_api.update_di.ticker_live(&Ticker::rb).unwrap();
+
+        running_api.on_session_event(SessionEvent::Login { confirm_settlement: true }).await.unwrap();
+        assert!(!running_api.stra_api.is_running());
+        running_api.on_session_event(SessionEvent::Subscribe).await.unwrap();
+        assert!(running_api.stra_api.is_running());
+        wait_mode(&running_api.stra_api, Ticker::rb, TickerMode::Paused).await;
+        rb.trade_api.data_receive.send(TickData { c: 3400., bid1: 3400., ask1: 3401., ..Default::default() }.into());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(rb.record.lock().unwrap().len(), 1);
+        assert!(rb.order_pool.lock().unwrap().pool.is_empty());
+
+        running_api.on_session_event(SessionEvent::Open).await.unwrap();
+        assert_eq!(sent(&running_api.stra_api, Ticker::rb).await, OrderAction::LoOpen(2, 3400.));
+        {
+            let mut order_pool = rb.order_pool.lock().unwrap();
+            order_pool.hold.td_lo = 2;
+            order_pool.pool.clear();
+        }
+
+        running_api.on_session_event(SessionEvent::End(SessionEnd::Hold)).await.unwrap();
+        running_api.on_session_event(SessionEvent::End(SessionEnd::Flatten)).await.unwrap();
+        assert_eq!(sent(&running_api.stra_api, Ticker::rb).await, OrderAction::ShClose(2, 3401.));
+        assert_eq!(rb.control.lock().unwrap().mode, TickerMode::Flatten);
+
+        running_api.on_session_event(SessionEvent::Logout).await.unwrap();
+        assert!(!running_api.stra_api.is_running());
+        assert_eq!(running_api.service_api.id, 1);
+        assert_eq!(*service.calls.lock().unwrap(), vec!["0 login true", "0 add 1", "0 stop 1"]);
+    }
+
+    #[tokio::test]
+    async fn accounts_scale_the_algo_and_share_the_md() {
+        let gen_pool = |algo: AlgoBox| {
+            let stra = WithAlgoBox { data: Box::new(Target(4.)) as Box<dyn CondType7>, algo };
+            LiveStraPool { data: vec![WithTicker { ticker: Ticker::rb, data: Box::new(stra) }] }
+        };
+        let multi_stra_api = MultiStraApi::new(&[1., 0.5], ticker_contract_map(), Box::new(TargetSimple), gen_pool);
+        let md_follower = multi_stra_api.md_follower();
+        assert_eq!(md_follower.len(), 2);
+        assert!(!Arc::ptr_eq(&md_follower[0], &md_follower[1]));
+        for (stra_api, trade_api) in multi_stra_api.stra_api_vec.iter().zip(multi_stra_api.trade_api_vecs.iter()) {
+            assert!(md_follower.iter().any(|x| Arc::ptr_eq(x, &trade_api[0])));
+            stra_api.start_spy_on_data_receive(trade_api.clone()).unwrap();
+        }
+
+        md_follower
+            .iter()
+            .filter(|x| x.contract == "rb2501")
+            .for_each(|x| x.data_receive.send(tick()));
+        let [account_a, account_b] = &multi_stra_api.stra_api_vec[..] else { unreachable!() };
+        assert_eq!(sent(account_a, Ticker::rb).await, OrderAction::LoOpen(4, 3500.));
+        assert_eq!(sent(account_b, Ticker::rb).await, OrderAction::LoOpen(2, 3500.));
+        md_follower.iter().for_each(|x| x.stop());
+        for stra_api in multi_stra_api.stra_api_vec.iter() {
+            tokio::time::timeout(Duration::from_secs(1), stra_api.wait_finished()).await.unwrap();
+        }
+    }
+}
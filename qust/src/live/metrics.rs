@@ -0,0 +1,382 @@
+use crate::loge;
+use crate::prelude::Ticker;
+use super::order_types::{OrderAction, OrderSend};
+use once_cell::sync::Lazy;
+use qust_ds::prelude::hm;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The registry the `metric!` macro records into.
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// The metrics recorded by the live engine, with their type and help text.
+pub const METRIC_INFO: &[(&str, MetricKind, &str)] = &[
+    ("qust_tick_to_order_seconds", MetricKind::Histogram, "Time from a tick entering data_receive to the order it triggers being sent."),
+    ("qust_data_receive_depth", MetricKind::Gauge, "Number of DataReceive queued when the strategy took its last batch."),
+    ("qust_ticks_dropped_total", MetricKind::Counter, "Ticks dropped by the LagPolicy of a lagging strategy."),
+    ("qust_orders_sent_total", MetricKind::Counter, "Orders sent to the gateway."),
+    ("qust_cancels_sent_total", MetricKind::Counter, "Cancel requests sent to the gateway."),
+    ("qust_orders_cancelled_total", MetricKind::Counter, "Orders reported canceled."),
+    ("qust_orders_rejected_total", MetricKind::Counter, "Orders reported with an insert error."),
+    ("qust_volume_sent_total", MetricKind::Counter, "Volume of the orders sent."),
+    ("qust_volume_filled_total", MetricKind::Counter, "Volume filled."),
+    ("qust_fill_ratio", MetricKind::Gauge, "Volume filled over volume sent."),
+    ("qust_position", MetricKind::Gauge, "Net position of the order pool."),
+    ("qust_pnl", MetricKind::Gauge, "Pnl in price points of the fills since start, marked to the last tick."),
+    ("qust_stra_eval_seconds", MetricKind::Histogram, "Time of a strategy evaluation on a batch of data."),
+    ("qust_cond_type1_seconds", MetricKind::Histogram, "Time of a CondType1 call."),
+    ("qust_ctp_connected", MetricKind::Gauge, "1 if the CTP front is connected."),
+    ("qust_ctp_reconnect_total", MetricKind::Counter, "Reconnections to the CTP front."),
+];
+
+const BUCKETS: [f64; 10] = [0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 1.];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Clone)]
+enum Series {
+    Value(f64),
+    Histogram { counts: [u64; BUCKETS.len()], sum: f64, count: u64 },
+}
+
+#[derive(Debug, Default)]
+struct PnlState {
+    cash: f64,
+    position: f64,
+    last_price: f64,
+    volume_sent: f64,
+    volume_filled: f64,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    data: Mutex<BTreeMap<&'static str, BTreeMap<Labels, Series>>>,
+    pnl: Mutex<hm<Ticker, PnlState>>,
+}
+
+impl Metrics {
+    fn update(&self, name: &'static str, labels: Labels, f: impl FnOnce(&mut Series)) {
+        let mut data = self.data.lock().unwrap();
+        let series = data
+            .entry(name)
+            .or_default()
+            .entry(labels)
+            .or_insert_with(|| match kind_of(name) {
+                MetricKind::Histogram => Series::Histogram { counts: Default::default(), sum: 0., count: 0 },
+                _ => Series::Value(0.),
+            });
+        f(series)
+    }
+
+    pub fn counter_add(&self, name: &'static str, labels: Labels, v: f64) {
+        self.update(name, labels, |series| {
+            if let Series::Value(x) = series {
+                *x += v;
+            }
+        });
+    }
+
+    pub fn gauge_set(&self, name: &'static str, labels: Labels, v: f64) {
+        self.update(name, labels, |series| {
+            if let Series::Value(x) = series {
+                *x = v;
+            }
+        });
+    }
+
+    pub fn observe(&self, name: &'static str, labels: Labels, v: f64) {
+        self.update(name, labels, |series| {
+            if let Series::Histogram { counts, sum, count } = series {
+                BUCKETS
+                    .iter()
+                    .zip(counts.iter_mut())
+                    .filter(|(bucket, _)| v <= **bucket)
+                    .for_each(|(_, n)| *n += 1);
+                *sum += v;
+                *count += 1;
+            }
+        });
+    }
+
+    pub fn order_sent(&self, ticker: Ticker, order_send: &OrderSend) {
+        let labels = vec![("ticker", ticker.to_string())];
+        if order_send.is_to_cancel {
+            self.counter_add("qust_cancels_sent_total", labels, 1.);
+            return;
+        }
+        let volume = order_send.order_action.num() as f64;
+        self.counter_add("qust_orders_sent_total", labels.clone(), 1.);
+        self.counter_add("qust_volume_sent_total", labels, volume);
+        self.pnl.lock().unwrap().entry(ticker).or_default().volume_sent += volume;
+    }
+
+    /// Fills are taken at the order price, as `OrderReceive` does not carry the trade price.
+    pub fn order_filled(&self, ticker: Ticker, order_action: &OrderAction, volume: i32) {
+        use OrderAction::*;
+        let (sign, price) = match order_action {
+            LoOpen(_, p) | LoClose(_, p) | LoCloseYd(_, p) => (1., *p as f64),
+            ShOpen(_, p) | ShClose(_, p) | ShCloseYd(_, p) => (-1., *p as f64),
+            No => return,
+        };
+        let labels = vec![("ticker", ticker.to_string())];
+        let mut pnl = self.pnl.lock().unwrap();
+        let state = pnl.entry(ticker).or_default();
+        state.cash -= sign * volume as f64 * price;
+        state.position += sign * volume as f64;
+        state.volume_filled += volume as f64;
+        if state.last_price == 0. {
+            state.last_price = price;
+        }
+        let (pnl_value, fill_ratio) = (state.pnl(), state.fill_ratio());
+        drop(pnl);
+        self.counter_add("qust_volume_filled_total", labels.clone(), volume as f64);
+        self.gauge_set("qust_fill_ratio", labels.clone(), fill_ratio);
+        self.gauge_set("qust_pnl", labels, pnl_value);
+    }
+
+    pub fn mark(&self, ticker: Ticker, price: f32) {
+        if price <= 0. {
+            return;
+        }
+        let mut pnl = self.pnl.lock().unwrap();
+        let state = pnl.entry(ticker).or_default();
+        state.last_price = price as f64;
+        let pnl_value = state.pnl();
+        drop(pnl);
+        self.gauge_set("qust_pnl", vec![("ticker", ticker.to_string())], pnl_value);
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let data = self.data.lock().unwrap();
+        let mut res = String::new();
+        for (name, series_map) in data.iter() {
+            let kind = kind_of(name);
+            if let Some((_, _, help)) = METRIC_INFO.iter().find(|x| x.0 == *name) {
+                let _ = writeln!(res, "# HELP {} {}", name, help);
+            }
+            let _ = writeln!(res, "# TYPE {} {}", name, kind.as_str());
+            for (labels, series) in series_map.iter() {
+                match series {
+                    Series::Value(v) => {
+                        let _ = writeln!(res, "{}{} {}", name, render_labels(labels, None), v);
+                    }
+                    Series::Histogram { counts, sum, count } => {
+                        for (bucket, n) in BUCKETS.iter().zip(counts.iter()) {
+                            let le = bucket.to_string();
+                            let _ = writeln!(res, "{}_bucket{} {}", name, render_labels(labels, Some(&le)), n);
+                        }
+                        let _ = writeln!(res, "{}_bucket{} {}", name, render_labels(labels, Some("+Inf")), count);
+                        let _ = writeln!(res, "{}_sum{} {}", name, render_labels(labels, None), sum);
+                        let _ = writeln!(res, "{}_count{} {}", name, render_labels(labels, None), count);
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+impl PnlState {
+    fn pnl(&self) -> f64 {
+        self.cash + self.position * self.last_price
+    }
+
+    fn fill_ratio(&self) -> f64 {
+        match self.volume_sent > 0. {
+            true => self.volume_filled / self.volume_sent,
+            false => 0.,
+        }
+    }
+}
+
+fn kind_of(name: &str) -> MetricKind {
+    METRIC_INFO
+        .iter()
+        .find(|x| x.0 == name)
+        .map(|x| x.1)
+        .unwrap_or(MetricKind::Gauge)
+}
+
+fn render_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut parts = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{}\"", le));
+    }
+    match parts.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", parts.join(",")),
+    }
+}
+
+/// Serves `GET /metrics` on a loopback address for a Prometheus scraper.
+pub async fn serve_metrics(addr: &str) -> anyhow::Result<()> {
+    let socket_addr: SocketAddr = addr.parse()?;
+    if !socket_addr.ip().is_loopback() {
+        anyhow::bail!("metrics server only listens on a loopback address: {}", addr);
+    }
+    let listener = TcpListener::bind(socket_addr).await?;
+    loge!("stra", "metrics server listen on {}", addr);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream).await {
+                loge!(level: Error, "stra", "metrics server handle a request failed: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: TcpStream) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut header_line = String::new();
+    while reader.read_line(&mut header_line).await? > 2 {
+        header_line.clear();
+    }
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", METRICS.render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::OrderPool;
+
+    fn order_send(order_action: OrderAction) -> OrderSend {
+        let mut order_pool = OrderPool { ticker: Ticker::rb, hold: Default::default(), pool: Default::default(), combo: None };
+        order_pool.create_order(order_action)
+    }
+
+    fn value(metrics: &Metrics, name: &str) -> f64 {
+        match metrics.data.lock().unwrap()[name][&vec![("ticker", "rb".to_string())]] {
+            Series::Value(x) => x,
+            _ => panic!("{} is a histogram", name),
+        }
+    }
+
+    #[test]
+    fn render_in_the_text_format() {
+        let metrics = Metrics::default();
+        metrics.counter_add("qust_orders_sent_total", vec![("ticker", "rb".into())], 2.);
+        metrics.counter_add("qust_orders_sent_total", vec![("ticker", "rb".into())], 1.);
+        metrics.gauge_set("qust_position", vec![("ticker", "a\"b\\c\nd".into())], -3.);
+        metrics.gauge_set("my_gauge", vec![], 1.5);
+        let lines = metrics.render().lines().map(String::from).collect::<Vec<_>>();
+        let expected = [
+            "# TYPE my_gauge gauge",
+            "my_gauge 1.5",
+            "# HELP qust_orders_sent_total Orders sent to the gateway.",
+            "# TYPE qust_orders_sent_total counter",
+            "qust_orders_sent_total{ticker=\"rb\"} 3",
+            "# HELP qust_position Net position of the order pool.",
+            "# TYPE qust_position gauge",
+            "qust_position{ticker=\"a\\\"b\\\\c\\nd\"} -3",
+        ];
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn histogram_buckets_add_up() {
+        let metrics = Metrics::default();
+        let labels = || vec![("ticker", "rb".to_string())];
+        [0.00002, 0.002, 0.002, 5.].into_iter().for_each(|x| metrics.observe("qust_stra_eval_seconds", labels(), x));
+        metrics.counter_add("qust_stra_eval_seconds", labels(), 1.);
+        let text = metrics.render();
+        let bucket = |le: &str| {
+            let prefix = format!("qust_stra_eval_seconds_bucket{{ticker=\"rb\",le=\"{}\"}} ", le);
+            text.lines().find_map(|x| x.strip_prefix(prefix.as_str())).unwrap().to_string()
+        };
+        let counts = BUCKETS.iter().map(|x| bucket(&x.to_string())).collect::<Vec<_>>();
+        assert_eq!(counts, ["0", "1", "1", "1", "1", "3", "3", "3", "3", "3"]);
+        assert_eq!(bucket("+Inf"), "4");
+        assert!(text.contains("\nqust_stra_eval_seconds_count{ticker=\"rb\"} 4\n"));
+        assert!(text.contains(&format!("\nqust_stra_eval_seconds_sum{{ticker=\"rb\"}} {}\n", 0.00002 + 0.002 + 0.002 + 5.)));
+        assert!(text.contains("# TYPE qust_stra_eval_seconds histogram\n"));
+    }
+
+    #[test]
+    fn since_observes_a_start_only() {
+        let start = Some(std::time::Instant::now());
+        crate::metric!(since: "qust_tick_to_order_seconds", ticker = "since_test"; start);
+        crate::metric!(since: "qust_tick_to_order_seconds", ticker = "since_test"; None::<std::time::Instant>);
+        let data = METRICS.data.lock().unwrap();
+        match data["qust_tick_to_order_seconds"][&vec![("ticker", "since_test".to_string())]] {
+            Series::Histogram { count, .. } => assert_eq!(count, 1),
+            _ => panic!("qust_tick_to_order_seconds is not a histogram"),
+        }
+    }
+
+    #[test]
+    fn pnl_of_the_fills_marked_to_the_last_tick() {
+        let metrics = Metrics::default();
+        metrics.mark(Ticker::rb, 100.);
+        assert_eq!(value(&metrics, "qust_pnl"), 0.);
+        metrics.order_sent(Ticker::rb, &order_send(OrderAction::LoOpen(2, 100.)));
+        metrics.order_filled(Ticker::rb, &OrderAction::LoOpen(2, 100.), 2);
+        assert_eq!((value(&metrics, "qust_pnl"), value(&metrics, "qust_fill_ratio")), (0., 1.));
+        metrics.mark(Ticker::rb, 110.);
+        assert_eq!(value(&metrics, "qust_pnl"), 20.);
+        metrics.mark(Ticker::rb, 0.);
+        assert_eq!(value(&metrics, "qust_pnl"), 20.);
+
+        metrics.order_sent(Ticker::rb, &order_send(OrderAction::ShClose(2, 120.)));
+        metrics.order_filled(Ticker::rb, &OrderAction::ShClose(2, 120.), 1);
+        assert_eq!((value(&metrics, "qust_pnl"), value(&metrics, "qust_fill_ratio")), (30., 0.75));
+        metrics.order_filled(Ticker::rb, &OrderAction::ShCloseYd(3, 90.), 3);
+        metrics.mark(Ticker::rb, 80.);
+        assert_eq!(value(&metrics, "qust_pnl"), -80. + 270. - 2. * 80.);
+        assert_eq!(value(&metrics, "qust_volume_filled_total"), 6.);
+
+        let mut cancel = order_send(OrderAction::LoOpen(1, 100.));
+        cancel.is_to_cancel = true;
+        metrics.order_sent(Ticker::rb, &cancel);
+        assert_eq!(value(&metrics, "qust_cancels_sent_total"), 1.);
+        assert_eq!((value(&metrics, "qust_orders_sent_total"), value(&metrics, "qust_volume_sent_total")), (2., 4.));
+    }
+
+    #[test]
+    fn first_fill_marks_the_pnl() {
+        let metrics = Metrics::default();
+        metrics.order_filled(Ticker::rb, &OrderAction::ShOpen(1, 50.), 1);
+        assert_eq!(value(&metrics, "qust_pnl"), 0.);
+        metrics.order_filled(Ticker::rb, &OrderAction::No, 1);
+        assert_eq!(value(&metrics, "qust_volume_filled_total"), 1.);
+    }
+}
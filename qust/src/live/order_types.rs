@@ -2,8 +2,8 @@
 use serde::{ Serialize, Deserialize };
 use qust_ds::prelude::*;
 use qust_derive::*;
-use crate::loge;
-use crate::prelude::{PconIdent, Ticker};
+use crate::{loge, metric};
+use crate::prelude::{ComboContract, PconIdent, Ticker};
 use crate::sig::prelude::{NormHold, ToNum};
 use once_cell::sync::Lazy;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -40,6 +40,54 @@ pub enum OrderAction {
     No,
 }
 
+impl OrderAction {
+    pub fn num(&self) -> i32 {
+        use OrderAction::*;
+        match self {
+            LoOpen(i, _) | LoClose(i, _) | LoCloseYd(i, _) | ShOpen(i, _) | ShClose(i, _) | ShCloseYd(i, _) => *i,
+            No => 0,
+        }
+    }
+
+    pub fn with_num(&self, n: i32) -> Self {
+        use OrderAction::*;
+        match self {
+            LoOpen(_, p) => LoOpen(n, *p),
+            LoClose(_, p) => LoClose(n, *p),
+            LoCloseYd(_, p) => LoCloseYd(n, *p),
+            ShOpen(_, p) => ShOpen(n, *p),
+            ShClose(_, p) => ShClose(n, *p),
+            ShCloseYd(_, p) => ShCloseYd(n, *p),
+            No => No,
+        }
+    }
+
+    pub fn is_lo(&self) -> bool {
+        matches!(self, OrderAction::LoOpen(..) | OrderAction::LoClose(..) | OrderAction::LoCloseYd(..))
+    }
+
+    pub fn scale(&self, ratio: f32) -> Self {
+        match (self.num() as f32 * ratio).round() as i32 {
+            0 => OrderAction::No,
+            n => self.with_num(n),
+        }
+    }
+
+    /// The same offset on the other side.
+    pub fn opposite(&self) -> Self {
+        use OrderAction::*;
+        match self {
+            LoOpen(i, p) => ShOpen(*i, *p),
+            LoClose(i, p) => ShClose(*i, *p),
+            LoCloseYd(i, p) => ShCloseYd(*i, *p),
+            ShOpen(i, p) => LoOpen(*i, *p),
+            ShClose(i, p) => LoClose(*i, *p),
+            ShCloseYd(i, p) => LoCloseYd(*i, *p),
+            No => No,
+        }
+    }
+}
+
 
 impl From<NormHold> for LiveTarget {
     fn from(value: NormHold) -> Self {
@@ -51,7 +99,7 @@ impl From<NormHold> for LiveTarget {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub enum LiveTarget {
     #[default]
     No,
@@ -89,6 +137,15 @@ impl LiveTarget {
             _ => panic!("cannot add: {:?} {:?}", self, other),
         }
     }
+
+    pub fn scale(&self, ratio: f32) -> Self {
+        match self {
+            Self::No => Self::No,
+            Self::Lo(i) => Self::Lo((i * ratio).round()),
+            Self::Sh(i) => Self::Sh((i * ratio).round()),
+            Self::OrderAction(order_action) => Self::OrderAction(order_action.scale(ratio)),
+        }
+    }
 }
 
 impl ToNum for LiveTarget {
@@ -103,7 +160,7 @@ impl ToNum for LiveTarget {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct HoldLocal {
     pub yd_sh: i32,
     pub yd_lo: i32,
@@ -121,9 +178,60 @@ impl HoldLocal {
     pub fn sum_pending(&self) -> i32 {
         self.sum() + self.exit_lo - self.exit_sh
     }
+
+    /// Takes `n` filled of the order, false for an order it cannot take.
+    pub fn fill(&mut self, order_action: &OrderAction, n: i32) -> bool {
+        match order_action {
+            OrderAction::LoOpen(..) => self.td_lo += n,
+            OrderAction::ShOpen(..) => self.td_sh += n,
+            OrderAction::LoClose(..) => self.td_sh -= n,
+            OrderAction::ShClose(..) => self.td_lo -= n,
+            _ => return false,
+        }
+        true
+    }
 }
 
 
+/// A fill of a leg of a combination, for the pool of the ticker trading the leg.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegFill {
+    pub contract: String,
+    pub order_action: OrderAction,
+    pub n: i32,
+}
+
+/// The holds of the legs of a combination, which the exchange keeps by leg.
+#[derive(Debug, Clone)]
+pub struct ComboHold {
+    pub combo: ComboContract,
+    pub holds: Vec<HoldLocal>,
+    /// The fills of the legs not handed to the pools of the legs yet.
+    pub leg_fills: Vec<LegFill>,
+}
+
+impl ComboHold {
+    pub fn new(combo: ComboContract) -> Self {
+        let holds = vec![HoldLocal::default(); combo.legs.len()];
+        Self { combo, holds, leg_fills: vec![] }
+    }
+
+    /// Splits `n` filled of a combination order into its legs, every leg taking its part
+    /// even when another cannot.
+    pub fn fill(&mut self, order_action: &OrderAction, n: i32) -> bool {
+        let mut res = true;
+        for (leg, hold) in izip!(self.combo.legs.iter(), self.holds.iter_mut()) {
+            let leg_action = match leg.ratio > 0 {
+                true => order_action.clone(),
+                false => order_action.opposite(),
+            };
+            let leg_n = n * leg.ratio.abs();
+            res &= hold.fill(&leg_action, leg_n);
+            self.leg_fills.push(LegFill { contract: leg.contract.clone(), order_action: leg_action, n: leg_n });
+        }
+        res
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub enum OrderStatus {
@@ -169,6 +277,8 @@ pub struct OrderPool {
     pub ticker: Ticker,
     pub hold: HoldLocal,
     pub pool: hm<String, OrderSend>,
+    /// For a combination contract, `hold` is in combinations, and this in lots of the legs.
+    pub combo: Option<ComboHold>,
 }
 
 impl OrderPool {
@@ -192,6 +302,11 @@ impl OrderPool {
         new_order
     }
 
+    /// An order is in the pool, neither filled nor canceled yet.
+    pub fn is_working(&self) -> bool {
+        !self.pool.is_empty()
+    }
+
     pub fn cancel_order(&mut self, order_ref: &str) -> OrderResult<Option<OrderSend>> {
         let order = self.pool
             .get_mut(order_ref)
@@ -209,6 +324,22 @@ impl OrderPool {
         }
     }
 
+    /// The fills of the legs of a combination since last taken.
+    pub fn take_leg_fills(&mut self) -> Vec<LegFill> {
+        self.combo
+            .as_mut()
+            .map(|x| std::mem::take(&mut x.leg_fills))
+            .unwrap_or_default()
+    }
+
+    pub fn cancel_all(&mut self) -> Vec<OrderSend> {
+        let order_id_vec = self.pool.keys().cloned().collect_vec();
+        order_id_vec
+            .iter()
+            .filter_map(|order_id| self.cancel_order(order_id).ok().flatten())
+            .collect()
+    }
+
     fn delete_order(&mut self, order_ref: &str) -> OrderResult<OrderSend> {
         self.pool
             .remove(order_ref)
@@ -220,22 +351,16 @@ impl OrderPool {
             .pool
             .get(order_ref)
             .ok_or(OrderError::OrderNotFound(order_ref.to_string()))?;
-        match &order_action.order_action {
-            OrderAction::LoOpen(i, _) => {
-                self.hold.td_lo += c.unwrap_or(*i);
-            }
-            OrderAction::ShOpen(i, _) => {
-                self.hold.td_sh += c.unwrap_or(*i);
-            }
-            OrderAction::LoClose(i, _) => {
-                self.hold.td_sh -= c.unwrap_or(*i);
-            }
-            OrderAction::ShClose(i, _) => {
-                self.hold.td_lo -= c.unwrap_or(*i);
-            }
-            other => {
-                return Err(OrderError::Logic(format!("order action on what? {:?} {:?}", other, line!())));
+        let n = c.unwrap_or(order_action.order_action.num());
+        metric!(filled: self.ticker, &order_action.order_action, n);
+        if !self.hold.fill(&order_action.order_action, n) {
+            return Err(OrderError::Logic(format!("order action on what? {:?} {:?}", order_action.order_action, line!())));
+        }
+        if let Some(combo_hold) = self.combo.as_mut() {
+            if !combo_hold.fill(&order_action.order_action, n) {
+                loge!(level: Error, self.ticker, "order pool combo legs cannot take: {:?}", order_action.order_action);
             }
+            loge!(self.ticker, "order pool combo legs hold: {:?}", combo_hold.holds);
         }
         self.delete_order(order_ref)?;
         Ok(true)
@@ -261,10 +386,12 @@ impl OrderPool {
             }
             OrderStatus::Canceled(i) => {
                 loge!(self.ticker, "order pool order update canceled");
+                metric!(counter: "qust_orders_cancelled_total", ticker = self.ticker; 1);
                 self.finished_order_update(&order.id, Some(i))?
             }
             OrderStatus::InsertError(_i) => {
                 loge!(self.ticker, "order pool order update insert error");
+                metric!(counter: "qust_orders_rejected_total", ticker = self.ticker; 1);
                 self.delete_order(&order.id)?;
                 false
             }
@@ -348,4 +475,62 @@ enum CancelRes {
     NotHave,
     CancelAll,
     DoNothing,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{ExtractCombo, ExtractTicker};
+
+    fn combo_pool() -> OrderPool {
+        OrderPool {
+            ticker: Ticker::rb,
+            hold: Default::default(),
+            pool: Default::default(),
+            combo: "SP rb2410&rb2501".extract_combo().map(ComboHold::new),
+        }
+    }
+
+    #[test]
+    fn combo_fill_splits_into_legs() {
+        let mut pool = combo_pool();
+        let order = pool.create_order(OrderAction::LoOpen(2, 10.));
+        pool.update_order(OrderReceive { id: order.id, order_status: OrderStatus::AllTraded, ..Default::default() })
+            .unwrap();
+        assert_eq!(pool.hold.td_lo, 2);
+        let combo = pool.combo.as_ref().unwrap();
+        assert_eq!((combo.holds[0].td_lo, combo.holds[1].td_sh), (2, 2));
+        let leg_fills = pool.take_leg_fills();
+        assert_eq!(
+            leg_fills,
+            vec![
+                LegFill { contract: "rb2410".into(), order_action: OrderAction::LoOpen(2, 10.), n: 2 },
+                LegFill { contract: "rb2501".into(), order_action: OrderAction::ShOpen(2, 10.), n: 2 },
+            ]
+        );
+        assert!(pool.take_leg_fills().is_empty());
+    }
+
+    #[test]
+    fn combo_fill_partly_canceled_then_closed() {
+        let mut pool = combo_pool();
+        let order = pool.create_order(OrderAction::ShOpen(3, 10.));
+        pool.update_order(OrderReceive { id: order.id, order_status: OrderStatus::Canceled(1), ..Default::default() })
+            .unwrap();
+        let order = pool.create_order(OrderAction::LoClose(1, 10.));
+        pool.update_order(OrderReceive { id: order.id, order_status: OrderStatus::AllTraded, ..Default::default() })
+            .unwrap();
+        let combo = pool.combo.as_ref().unwrap();
+        assert_eq!(pool.hold.sum(), 0);
+        assert_eq!((combo.holds[0].sum(), combo.holds[1].sum()), (0, 0));
+        assert_eq!(pool.take_leg_fills().len(), 4);
+    }
+
+    #[test]
+    fn combo_has_no_ticker() {
+        assert!("SP rb2410&rb2501".extract_ticker().is_none());
+        assert_eq!("rb2410".extract_ticker(), Some((Ticker::rb, 2410)));
+        let combo = "SP rb2410&rb2501".extract_combo().unwrap();
+        assert_eq!(combo.legs.iter().map(|x| x.ticker).collect::<Vec<_>>(), vec![Ticker::rb, Ticker::rb]);
+    }
+}
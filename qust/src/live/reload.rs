@@ -0,0 +1,291 @@
+use crate::loge;
+use crate::std_prelude::*;
+use crate::trade::prelude::Ticker;
+use qust_ds::prelude::hm;
+use super::prelude::{ControlCommand, DataReceive, LiveStraPool, RunningApi, ServiceApi, StraApi, TickerLive, UpdateDi};
+use anyhow::Result;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Clone)]
+pub struct ReloadOptions {
+    /// Closes the hold of the removed tickers before they stop.
+    pub flatten: bool,
+    /// How long the removed tickers are given to drain, they are stopped anyway after it.
+    pub drain_timeout: dura,
+}
+
+impl Default for ReloadOptions {
+    fn default() -> Self {
+        Self {
+            flatten: false,
+            drain_timeout: dura::from_secs(30),
+        }
+    }
+}
+
+/// What a reload does to the running tickers. A ticker whose contract changes is
+/// removed and added again, so the hold of the old contract is closed first; without
+/// `flatten` the plan is refused while there is a hold or a working order.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReloadPlan {
+    pub added: Vec<Ticker>,
+    pub removed: Vec<Ticker>,
+    pub changed: Vec<Ticker>,
+    pub unchanged: Vec<Ticker>,
+}
+
+impl ReloadPlan {
+    /// Diffs the new strategies against the running ones, rejecting a config the live
+    /// engine cannot take before anything changes.
+    pub fn new(
+        update_di: &UpdateDi,
+        live_api: &LiveStraPool,
+        ticker_contract_map: &hm<Ticker, &'static str>,
+        options: &ReloadOptions,
+    ) -> Result<Self> {
+        let mut contract_ticker_map: hm<&str, Ticker> = hm::new();
+        for live_api_ticker in live_api.data.iter() {
+            let ticker = live_api_ticker.ticker;
+            let Some(&contract) = ticker_contract_map.get(&ticker) else {
+                anyhow::bail!("ticker cannot find mapping contract: {:?}", ticker);
+            };
+            if contract.is_empty() {
+                anyhow::bail!("ticker maps to an empty contract: {:?}", ticker);
+            }
+            if let Some(ticker_other) = contract_ticker_map.insert(contract, ticker) {
+                match ticker_other == ticker {
+                    true => anyhow::bail!("ticker has more than one stra: {:?}", ticker),
+                    false => anyhow::bail!("contract {} is mapped by both {:?} and {:?}", contract, ticker_other, ticker),
+                }
+            }
+        }
+        let mut res = Self::default();
+        for live_api_ticker in live_api.data.iter() {
+            let ticker = live_api_ticker.ticker;
+            match update_di.ticker_live(&ticker) {
+                Some(ticker_live) if ticker_live.trade_api.contract == ticker_contract_map[&ticker] => {
+                    let stra_key = live_api_ticker.data.stra_key();
+                    match stra_key.is_some() && stra_key == ticker_live.stra_key() {
+                        true => res.unchanged.push(ticker),
+                        false => res.changed.push(ticker),
+                    }
+                }
+                Some(ticker_live) => {
+                    let order_pool = ticker_live.order_pool.lock().unwrap();
+                    let hold = &order_pool.hold;
+                    let is_holding = [hold.yd_lo, hold.td_lo, hold.yd_sh, hold.td_sh].iter().any(|x| *x != 0);
+                    if !options.flatten && (is_holding || order_pool.is_working()) {
+                        anyhow::bail!(
+                            "contract of {:?} changes from {} to {} with a hold of {:?}, reload with flatten or close it first",
+                            ticker,
+                            ticker_live.trade_api.contract,
+                            ticker_contract_map[&ticker],
+                            hold,
+                        );
+                    }
+                    res.removed.push(ticker);
+                    res.added.push(ticker);
+                }
+                None => res.added.push(ticker),
+            }
+        }
+        for ticker_live in update_di.ticker_live_vec() {
+            let ticker = ticker_live.trade_api.ticker;
+            if !contract_ticker_map.values().any(|x| *x == ticker) {
+                res.removed.push(ticker);
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// A reload asked through the control plane, answered by whoever owns the `RunningApi`.
+pub struct ReloadRequest {
+    pub options: ReloadOptions,
+    pub respond: oneshot::Sender<Result<ReloadPlan, String>>,
+}
+
+pub fn reload_channel() -> (mpsc::Sender<ReloadRequest>, mpsc::Receiver<ReloadRequest>) {
+    mpsc::channel(1)
+}
+
+impl<N: ServiceApi> RunningApi<StraApi, N> {
+    /// Moves the running strategies to `live_api`. New tickers are started, removed ones
+    /// drain their pending orders and stop, and changed ones swap the strategy in place,
+    /// keeping their `OrderPool` and the `Di`s warmed up. Unchanged tickers are untouched.
+    pub async fn reload(
+        &mut self,
+        live_api: LiveStraPool,
+        ticker_contract_map: hm<Ticker, &'static str>,
+        options: &ReloadOptions,
+    ) -> Result<ReloadPlan> {
+        let update_di = Arc::clone(&self.stra_api.update_di);
+        let plan = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, options)?;
+        loge!("stra", "reload plan: {:?}", plan);
+        let is_running = self.stra_api.is_running();
+        let mut stra_map = live_api
+            .data
+            .into_iter()
+            .map(|x| (x.ticker, x.data))
+            .collect::<hm<_, _>>();
+
+        let ticker_live_added = plan
+            .added
+            .iter()
+            .map(|ticker| {
+                let stra = stra_map.remove(ticker).unwrap();
+                Arc::new(TickerLive::new(ticker_contract_map[ticker], *ticker, stra))
+            })
+            .collect::<Vec<_>>();
+        let ticker_live_removed = plan
+            .removed
+            .iter()
+            .filter_map(|ticker| update_di.ticker_live(ticker))
+            .collect::<Vec<_>>();
+        let trade_api_added = ticker_live_added.iter().map(|x| Arc::clone(&x.trade_api)).collect::<Vec<_>>();
+        if is_running && !trade_api_added.is_empty() {
+            if let Err(e) = self.service_api.add(trade_api_added.clone()).await {
+                loge!(level: Error, "stra", "reload failed to add the tickers: {:?}", e);
+                let _ = self.service_api.remove(trade_api_added).await;
+                return Err(e);
+            }
+        }
+
+        for ticker in plan.changed.iter() {
+            let stra = stra_map.remove(ticker).unwrap();
+            update_di.ticker_live(ticker).unwrap().stage_stra(stra);
+        }
+
+        if is_running {
+            for ticker_live in ticker_live_removed.iter() {
+                let retire = ControlCommand::Retire { flatten: options.flatten };
+                ticker_live.trade_api.data_receive.send(DataReceive::Control(retire));
+            }
+            let deadline = tokio::time::Instant::now() + options.drain_timeout;
+            for ticker_live in ticker_live_removed.iter() {
+                if tokio::time::timeout_at(deadline, ticker_live.wait_retired()).await.is_err() {
+                    loge!(level: Warn, ticker_live.trade_api.ticker, "stra not drained before the timeout, stop it anyway");
+                }
+            }
+            let trade_api_removed = ticker_live_removed.iter().map(|x| Arc::clone(&x.trade_api)).collect::<Vec<_>>();
+            trade_api_removed.iter().for_each(|x| x.stop());
+            if let Err(e) = self.service_api.remove(trade_api_removed).await {
+                loge!(level: Error, "stra", "reload failed to remove the tickers: {:?}", e);
+            }
+        }
+        for ticker_live in ticker_live_removed.iter() {
+            update_di.remove_ticker(&ticker_live.trade_api.ticker);
+        }
+
+        for ticker_live in ticker_live_added.into_iter() {
+            update_di.insert_ticker(Arc::clone(&ticker_live));
+            if is_running && !self.stra_api.spawn_ticker(ticker_live) {
+                loge!(level: Error, "stra", "reload cannot spawn a ticker, stra thread ended");
+            }
+        }
+        self.trade_api = self.stra_api.get_trade_api_vec1();
+        loge!("stra", "reload finished");
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live::prelude::{RetFnApi, WithTicker};
+    use crate::prelude::{ApiType, OrderAction, TickData};
+
+    #[derive(Clone)]
+    struct Stra(Option<&'static str>);
+
+    impl ApiType for Stra {
+        fn api_type(&self) -> RetFnApi<'_> {
+            Box::new(|_| OrderAction::No)
+        }
+        fn stra_key(&self) -> Option<String> {
+            self.0.map(String::from)
+        }
+    }
+
+    fn live_api(stras: &[(Ticker, Option<&'static str>)]) -> LiveStraPool {
+        let data = stras
+            .iter()
+            .map(|(ticker, key)| WithTicker { ticker: *ticker, data: Box::new(Stra(*key)) as _ })
+            .collect();
+        LiveStraPool { data }
+    }
+
+    fn running() -> (UpdateDi, hm<Ticker, &'static str>) {
+        let ticker_contract_map = hm::from([(Ticker::rb, "rb2501"), (Ticker::hc, "hc2501"), (Ticker::i, "i2501")]);
+        let live_api = live_api(&[(Ticker::rb, Some("a")), (Ticker::hc, Some("a")), (Ticker::i, Some("a"))]);
+        (UpdateDi::new(live_api, ticker_contract_map.clone()), ticker_contract_map)
+    }
+
+    #[test]
+    fn plan_adds_and_removes() {
+        let (update_di, mut ticker_contract_map) = running();
+        ticker_contract_map.remove(&Ticker::i);
+        ticker_contract_map.insert(Ticker::j, "j2501");
+        let live_api = live_api(&[(Ticker::rb, Some("a")), (Ticker::hc, Some("a")), (Ticker::j, Some("a"))]);
+        let plan = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).unwrap();
+        assert_eq!(plan.added, vec![Ticker::j]);
+        assert_eq!(plan.removed, vec![Ticker::i]);
+        assert!(plan.changed.is_empty());
+        assert_eq!(plan.unchanged, vec![Ticker::rb, Ticker::hc]);
+    }
+
+    #[test]
+    fn plan_changes_on_the_params() {
+        let (update_di, ticker_contract_map) = running();
+        let live_api = live_api(&[(Ticker::rb, Some("b")), (Ticker::hc, None), (Ticker::i, Some("a"))]);
+        let plan = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).unwrap();
+        assert!(plan.added.is_empty() && plan.removed.is_empty());
+        assert_eq!(plan.changed, vec![Ticker::rb, Ticker::hc]);
+        assert_eq!(plan.unchanged, vec![Ticker::i]);
+    }
+
+    #[test]
+    fn plan_restarts_on_a_contract_change() {
+        let (update_di, mut ticker_contract_map) = running();
+        ticker_contract_map.insert(Ticker::rb, "rb2505");
+        let live_api = live_api(&[(Ticker::rb, Some("a")), (Ticker::hc, Some("a")), (Ticker::i, Some("a"))]);
+        let plan = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).unwrap();
+        assert_eq!(plan.added, vec![Ticker::rb]);
+        assert_eq!(plan.removed, vec![Ticker::rb]);
+        assert_eq!(plan.unchanged, vec![Ticker::hc, Ticker::i]);
+    }
+
+    #[test]
+    fn plan_refuses_a_contract_change_with_a_hold() {
+        let (update_di, mut ticker_contract_map) = running();
+        ticker_contract_map.insert(Ticker::rb, "rb2505");
+        let live_api = live_api(&[(Ticker::rb, Some("a")), (Ticker::hc, Some("a")), (Ticker::i, Some("a"))]);
+        let ticker_live = update_di.ticker_live(&Ticker::rb).unwrap();
+        ticker_live.order_pool.lock().unwrap().hold.yd_sh = 2;
+        let res = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default());
+        assert!(res.unwrap_err().to_string().contains("rb2505"));
+        let options = ReloadOptions { flatten: true, ..Default::default() };
+        let plan = ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &options).unwrap();
+        assert_eq!(plan.removed, vec![Ticker::rb]);
+
+        ticker_live.order_pool.lock().unwrap().hold.yd_sh = 0;
+        assert!(ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).is_ok());
+        let tick_data = TickData { c: 3500., bid1: 3500., ask1: 3501., ..Default::default() };
+        ticker_live
+            .order_pool
+            .lock()
+            .unwrap()
+            .process_order_action(OrderAction::LoOpen(1, tick_data.bid1))
+            .unwrap();
+        assert!(ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn plan_refuses_a_contract_of_two_tickers() {
+        let (update_di, mut ticker_contract_map) = running();
+        ticker_contract_map.insert(Ticker::hc, "rb2501");
+        let live_api = live_api(&[(Ticker::rb, Some("a")), (Ticker::hc, Some("a"))]);
+        assert!(ReloadPlan::new(&update_di, &live_api, &ticker_contract_map, &Default::default()).is_err());
+    }
+}
@@ -0,0 +1,676 @@
+use crate::loge;
+use crate::std_prelude::*;
+use crate::trade::prelude::{Ticker, TradingPeriod};
+use chrono::{Datelike, Duration, Weekday};
+use qust_ds::prelude::{da, dt, tt, ToTt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::future::Future;
+
+/// Weekends and the exchange holidays are not trading days.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradingCalendar {
+    pub holidays: BTreeSet<da>,
+}
+
+impl TradingCalendar {
+    pub fn is_trading_day(&self, date: da) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    pub fn next_trading_day(&self, date: da) -> da {
+        let mut res = date.succ_opt().unwrap();
+        while !self.is_trading_day(res) {
+            res = res.succ_opt().unwrap();
+        }
+        res
+    }
+
+    pub fn prev_trading_day(&self, date: da) -> da {
+        let mut res = date.pred_opt().unwrap();
+        while !self.is_trading_day(res) {
+            res = res.pred_opt().unwrap();
+        }
+        res
+    }
+
+    /// The night session of `date` belongs to the next trading day, and is not held before
+    /// a break longer than a weekend.
+    pub fn has_night(&self, date: da) -> bool {
+        self.is_trading_day(date) && (self.next_trading_day(date) - date).num_days() <= 3
+    }
+}
+
+/// The close of the night session of a ticker, past midnight for the metals and crude.
+pub fn night_close(ticker: Ticker) -> Option<tt> {
+    use Ticker::*;
+    match TradingPeriod::from(ticker) {
+        TradingPeriod::Light => None,
+        TradingPeriod::LightNight => Some(230000.to_tt()),
+        TradingPeriod::LightNightMorn => match ticker {
+            au | ag | sc => Some(23000.to_tt()),
+            _ => Some(10000.to_tt()),
+        },
+    }
+}
+
+/// The open and the close of the day session of a ticker, the same for all the products
+/// of `Ticker`.
+pub fn day_session(_ticker: Ticker) -> (tt, tt) {
+    (90000.to_tt(), 150000.to_tt())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionEnd {
+    /// Keeps the hold into the next session.
+    #[default]
+    Hold,
+    /// Closes the hold through the order pool before the session closes.
+    Flatten,
+}
+
+/// The lead and lag times are in seconds around the open and the close of a session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub login_lead: i64,
+    pub subscribe_lead: i64,
+    pub end_lead: i64,
+    pub logout_lag: i64,
+    pub day_end: SessionEnd,
+    pub night_end: SessionEnd,
+    pub calendar: TradingCalendar,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            login_lead: 1200,
+            subscribe_lead: 300,
+            end_lead: 60,
+            logout_lag: 300,
+            day_end: SessionEnd::Hold,
+            night_end: SessionEnd::Hold,
+            calendar: TradingCalendar::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionKind {
+    Day,
+    Night,
+}
+
+/// A day session runs through its breaks, so a trader stays logged in over them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub kind: SessionKind,
+    pub trading_day: da,
+    pub open: dt,
+    pub close: dt,
+    /// The first session of its trading day confirms the settlement at login.
+    pub confirm_settlement: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SessionPhase {
+    LoggedIn,
+    /// Subscribed with the strategies paused, so they warm up on the pre-open ticks.
+    WarmingUp,
+    Open,
+    Ending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionEvent {
+    Login { confirm_settlement: bool },
+    Subscribe,
+    Open,
+    End(SessionEnd),
+    Logout,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionState {
+    pub session: Session,
+    pub phase: SessionPhase,
+}
+
+/// The sessions of a trader from the products it trades. The day session runs from the
+/// earliest open of them to the latest close, and the night session until the latest close.
+#[derive(Debug, Clone)]
+pub struct SessionScheduler {
+    pub config: ScheduleConfig,
+    pub day: (tt, tt),
+    pub night_len: Option<Duration>,
+}
+
+impl SessionScheduler {
+    pub fn new(tickers: &[Ticker], config: ScheduleConfig) -> Self {
+        let mut res = Self { config, day: (90000.to_tt(), 150000.to_tt()), night_len: None };
+        res.update_tickers(tickers);
+        res
+    }
+
+    pub fn update_tickers(&mut self, tickers: &[Ticker]) {
+        let day_vec = tickers.iter().map(|x| day_session(*x)).collect::<Vec<_>>();
+        if let (Some(open), Some(close)) = (day_vec.iter().map(|x| x.0).min(), day_vec.iter().map(|x| x.1).max()) {
+            self.day = (open, close);
+        }
+        let night_open = 210000.to_tt();
+        self.night_len = tickers
+            .iter()
+            .filter_map(|x| night_close(*x))
+            .map(|x| {
+                let len = x - night_open;
+                if len < Duration::zero() { len + Duration::days(1) } else { len }
+            })
+            .max();
+    }
+
+    /// The sessions opening from `start` to `end`, in the order of time.
+    pub fn sessions(&self, start: da, end: da) -> Vec<Session> {
+        let calendar = &self.config.calendar;
+        let mut res = vec![];
+        let mut date = start;
+        while date <= end {
+            if calendar.is_trading_day(date) {
+                let is_after_night = self.night_len.is_some() && calendar.has_night(calendar.prev_trading_day(date));
+                res.push(Session {
+                    kind: SessionKind::Day,
+                    trading_day: date,
+                    open: date.and_time(self.day.0),
+                    close: date.and_time(self.day.1),
+                    confirm_settlement: !is_after_night,
+                });
+            }
+            if let Some(night_len) = self.night_len.filter(|_| calendar.has_night(date)) {
+                let open = date.and_time(210000.to_tt());
+                res.push(Session {
+                    kind: SessionKind::Night,
+                    trading_day: calendar.next_trading_day(date),
+                    open,
+                    close: open + night_len,
+                    confirm_settlement: true,
+                });
+            }
+            date = date.succ_opt().unwrap();
+        }
+        res
+    }
+
+    /// When the phases of a session start, and when it is logged out.
+    pub fn timeline(&self, session: &Session) -> [(dt, Option<SessionPhase>); 5] {
+        let config = &self.config;
+        [
+            (session.open - Duration::seconds(config.login_lead), Some(SessionPhase::LoggedIn)),
+            (session.open - Duration::seconds(config.subscribe_lead), Some(SessionPhase::WarmingUp)),
+            (session.open, Some(SessionPhase::Open)),
+            (session.close - Duration::seconds(config.end_lead), Some(SessionPhase::Ending)),
+            (session.close + Duration::seconds(config.logout_lag), None),
+        ]
+    }
+
+    pub fn session_end(&self, session: &Session) -> SessionEnd {
+        match session.kind {
+            SessionKind::Day => self.config.day_end,
+            SessionKind::Night => self.config.night_end,
+        }
+    }
+
+    /// Where a trader should be at `now`, `None` when logged out.
+    pub fn state_at(&self, now: dt) -> Option<SessionState> {
+        let date = now.date();
+        self.sessions(date.pred_opt().unwrap(), date.succ_opt().unwrap())
+            .into_iter()
+            .find_map(|session| {
+                let phase = self
+                    .timeline(&session)
+                    .into_iter()
+                    .take_while(|(t, _)| *t <= now)
+                    .last()?
+                    .1?;
+                Some(SessionState { session, phase })
+            })
+    }
+
+    /// The next instant after `now` a phase changes, a year on at most.
+    pub fn next_wake(&self, now: dt) -> dt {
+        let mut date = now.date().pred_opt().unwrap();
+        for _ in 0..366 {
+            let wake = self
+                .sessions(date, date)
+                .iter()
+                .flat_map(|x| self.timeline(x))
+                .map(|x| x.0)
+                .find(|x| *x > now);
+            if let Some(wake) = wake {
+                return wake;
+            }
+            date = date.succ_opt().unwrap();
+        }
+        now + Duration::days(1)
+    }
+
+    /// The events met from `start` to `end`, by a trader logged out at `start`.
+    pub fn events(&self, start: dt, end: dt) -> Vec<(dt, SessionEvent)> {
+        let mut runner = SessionRunner::new(self.clone());
+        let mut t = start;
+        let mut res = vec![];
+        while t < end {
+            res.extend(runner.catch_up(t).into_iter().map(|x| (t, x)));
+            t = self.next_wake(t);
+        }
+        res
+    }
+}
+
+/// Keeps where a trader is in its sessions, and tells the events that take it to where
+/// it should be. A trader started late catches up at once, skipping the warm-up.
+#[derive(Debug, Clone)]
+pub struct SessionRunner {
+    pub scheduler: SessionScheduler,
+    pub state: Option<SessionState>,
+}
+
+impl SessionRunner {
+    pub fn new(scheduler: SessionScheduler) -> Self {
+        Self { scheduler, state: None }
+    }
+
+    /// The events due at `now`, the state they lead to is taken as reached.
+    pub fn catch_up(&mut self, now: dt) -> Vec<SessionEvent> {
+        let target = self.scheduler.state_at(now);
+        let mut res = vec![];
+        let phase_from = match (&self.state, &target) {
+            (Some(state), Some(target)) if state.session == target.session && state.phase <= target.phase => {
+                Some(state.phase)
+            }
+            (Some(_), _) => {
+                res.push(SessionEvent::Logout);
+                None
+            }
+            (None, _) => None,
+        };
+        if let Some(target) = &target {
+            let phase_vec = [SessionPhase::LoggedIn, SessionPhase::WarmingUp, SessionPhase::Open, SessionPhase::Ending];
+            for phase in phase_vec.into_iter().filter(|x| phase_from.is_none_or(|y| *x > y) && *x <= target.phase) {
+                let event = match phase {
+                    SessionPhase::LoggedIn => SessionEvent::Login {
+                        confirm_settlement: target.session.confirm_settlement,
+                    },
+                    SessionPhase::WarmingUp => SessionEvent::Subscribe,
+                    SessionPhase::Open => SessionEvent::Open,
+                    SessionPhase::Ending => SessionEvent::End(self.scheduler.session_end(&target.session)),
+                };
+                res.push(event);
+            }
+        }
+        self.state = target;
+        res
+    }
+
+    /// Takes the trader as logged out, it catches up again at the next call.
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    pub fn next_wake(&self, now: dt) -> dt {
+        self.scheduler.next_wake(now)
+    }
+
+    /// Takes the trader to where it should be at `now`, and tells when to wake it next. A
+    /// trader failing an event is logged out, and caught up again after `RETRY_SECS`.
+    pub async fn catch_up_trader<T: SessionTrader>(&mut self, trader: &mut T, now: dt) -> dt {
+        for event in self.catch_up(now) {
+            loge!("session", "session event: {:?}", event);
+            if let Err(e) = trader.on_session_event(event).await {
+                loge!(level: Error, "session", "session event {:?} failed: {:?}", event, e);
+                if event != SessionEvent::Logout {
+                    let _ = trader.on_session_event(SessionEvent::Logout).await;
+                }
+                self.reset();
+                return self.next_wake(now).min(now + Duration::seconds(RETRY_SECS));
+            }
+        }
+        self.next_wake(now)
+    }
+}
+
+/// How long a trader failing an event waits before it catches up again.
+pub const RETRY_SECS: i64 = 60;
+
+/// A trader driven through its sessions by a `SessionRunner`.
+pub trait SessionTrader {
+    fn on_session_event(&mut self, event: SessionEvent) -> impl Future<Output = anyhow::Result<()>>;
+}
+
+pub trait Clock {
+    fn now(&self) -> dt;
+    fn sleep_until(&self, t: dt) -> impl Future<Output = ()> + Send;
+}
+
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn now(&self) -> dt {
+        chrono::Local::now().naive_local()
+    }
+
+    fn sleep_until(&self, t: dt) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep((t - self.now()).to_std().unwrap_or_default())
+    }
+}
+
+/// Jumps to the instant slept until, so a schedule runs through its days at once offline.
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    now: Arc<Mutex<dt>>,
+}
+
+impl SimClock {
+    pub fn new(now: dt) -> Self {
+        Self { now: Arc::new(Mutex::new(now)) }
+    }
+
+    pub fn set(&self, t: dt) {
+        *self.now.lock().unwrap() = t;
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> dt {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, t: dt) -> impl Future<Output = ()> + Send {
+        let now = Arc::clone(&self.now);
+        async move {
+            {
+                let mut now = now.lock().unwrap();
+                *now = t.max(*now);
+            }
+            tokio::task::yield_now().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(x: &str) -> dt {
+        dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn d(x: &str) -> da {
+        da::parse_from_str(x, "%Y-%m-%d").unwrap()
+    }
+
+    /// The National Day holiday of 2024, from Tuesday 1 to Monday 7 October.
+    fn config() -> ScheduleConfig {
+        let holidays = (1..=7).map(|i| da::from_ymd_opt(2024, 10, i).unwrap()).collect();
+        ScheduleConfig { calendar: TradingCalendar { holidays }, ..Default::default() }
+    }
+
+    /// Sleeps on the clock from wake to wake, taking the events of the runner on the way.
+    async fn run(runner: &mut SessionRunner, clock: &SimClock, end: dt) -> Vec<(dt, SessionEvent)> {
+        let mut res = vec![];
+        while clock.now() < end {
+            let now = clock.now();
+            res.extend(runner.catch_up(now).into_iter().map(|x| (now, x)));
+            clock.sleep_until(runner.next_wake(now)).await;
+            assert!(clock.now() > now);
+        }
+        res
+    }
+
+    fn run_sim(tickers: &[Ticker], config: ScheduleConfig, start: dt, end: dt) -> Vec<(dt, SessionEvent)> {
+        let scheduler = SessionScheduler::new(tickers, config);
+        let mut runner = SessionRunner::new(scheduler.clone());
+        let clock = SimClock::new(start);
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let res = rt.block_on(run(&mut runner, &clock, end));
+        assert_eq!(res, scheduler.events(start, end));
+        res
+    }
+
+    fn session_events(
+        open: &str,
+        close: &str,
+        confirm_settlement: bool,
+        session_end: SessionEnd,
+    ) -> Vec<(dt, SessionEvent)> {
+        let (open, close) = (t(open), t(close));
+        vec![
+            (open - Duration::minutes(20), SessionEvent::Login { confirm_settlement }),
+            (open - Duration::minutes(5), SessionEvent::Subscribe),
+            (open, SessionEvent::Open),
+            (close - Duration::minutes(1), SessionEvent::End(session_end)),
+            (close + Duration::minutes(5), SessionEvent::Logout),
+        ]
+    }
+
+    /// Takes down the events at the time of the clock, and fails those in `fail` once each.
+    struct Trader {
+        clock: SimClock,
+        events: Vec<(dt, SessionEvent)>,
+        fail: Vec<SessionEvent>,
+    }
+
+    impl SessionTrader for Trader {
+        async fn on_session_event(&mut self, event: SessionEvent) -> anyhow::Result<()> {
+            self.events.push((self.clock.now(), event));
+            match self.fail.iter().position(|x| *x == event) {
+                Some(i) => {
+                    self.fail.remove(i);
+                    anyhow::bail!("{:?} failed", event)
+                }
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn run_trader(fail: Vec<SessionEvent>, start: dt, end: dt) -> Vec<(dt, SessionEvent)> {
+        let clock = SimClock::new(start);
+        let mut trader = Trader { clock: clock.clone(), events: vec![], fail };
+        let mut runner = SessionRunner::new(SessionScheduler::new(&[Ticker::rb], config()));
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async {
+            while clock.now() < end {
+                let wake = runner.catch_up_trader(&mut trader, clock.now()).await;
+                clock.sleep_until(wake).await;
+            }
+        });
+        trader.events
+    }
+
+    #[test]
+    fn calendar_skips_weekends_and_holidays() {
+        let calendar = config().calendar;
+        assert!(!calendar.is_trading_day(d("2024-09-28")));
+        assert!(!calendar.is_trading_day(d("2024-10-03")));
+        assert_eq!(calendar.next_trading_day(d("2024-09-27")), d("2024-09-30"));
+        assert_eq!(calendar.next_trading_day(d("2024-09-30")), d("2024-10-08"));
+        assert_eq!(calendar.prev_trading_day(d("2024-10-08")), d("2024-09-30"));
+        assert!(calendar.has_night(d("2024-09-27")));
+        assert!(!calendar.has_night(d("2024-09-30")));
+        assert!(!calendar.has_night(d("2024-09-29")));
+    }
+
+    #[test]
+    fn night_closes_past_midnight() {
+        use Ticker::*;
+        let close = |tickers: &[Ticker]| {
+            let scheduler = SessionScheduler::new(tickers, config());
+            scheduler
+                .sessions(d("2024-09-27"), d("2024-09-27"))
+                .into_iter()
+                .find(|x| x.kind == SessionKind::Night)
+                .map(|x| (x.trading_day, x.close))
+        };
+        assert_eq!(close(&[rb]), Some((d("2024-09-30"), t("2024-09-27 23:00:00"))));
+        assert_eq!(close(&[rb, cu]), Some((d("2024-09-30"), t("2024-09-28 01:00:00"))));
+        assert_eq!(close(&[cu, au]), Some((d("2024-09-30"), t("2024-09-28 02:30:00"))));
+        assert_eq!(close(&[ag]), Some((d("2024-09-30"), t("2024-09-28 02:30:00"))));
+        assert_eq!(close(&[sc]), Some((d("2024-09-30"), t("2024-09-28 02:30:00"))));
+        assert_eq!(close(&[AP, SF]), None);
+    }
+
+    #[test]
+    fn day_session_of_the_products() {
+        use Ticker::*;
+        let day = |tickers: &[Ticker]| {
+            let session = SessionScheduler::new(tickers, config()).sessions(d("2024-09-27"), d("2024-09-27"))[0].clone();
+            (session.open.time(), session.close.time())
+        };
+        assert_eq!(day(&[rb]), (90000.to_tt(), 150000.to_tt()));
+        assert_eq!(day(&[AP, cu, au]), (90000.to_tt(), 150000.to_tt()));
+        assert_eq!(day(&[]), (90000.to_tt(), 150000.to_tt()));
+    }
+
+    #[test]
+    fn sim_clock_through_weekend_and_holiday() {
+        let events = run_sim(
+            &[Ticker::rb],
+            ScheduleConfig { night_end: SessionEnd::Flatten, ..config() },
+            t("2024-09-27 06:00:00"),
+            t("2024-10-09 06:00:00"),
+        );
+        let expect = [
+            session_events("2024-09-27 09:00:00", "2024-09-27 15:00:00", false, SessionEnd::Hold),
+            session_events("2024-09-27 21:00:00", "2024-09-27 23:00:00", true, SessionEnd::Flatten),
+            session_events("2024-09-30 09:00:00", "2024-09-30 15:00:00", false, SessionEnd::Hold),
+            session_events("2024-10-08 09:00:00", "2024-10-08 15:00:00", true, SessionEnd::Hold),
+            session_events("2024-10-08 21:00:00", "2024-10-08 23:00:00", true, SessionEnd::Flatten),
+        ]
+        .concat();
+        assert_eq!(events, expect);
+    }
+
+    #[test]
+    fn sim_clock_through_night_past_midnight() {
+        let events = run_sim(
+            &[Ticker::au, Ticker::cu],
+            config(),
+            t("2024-09-27 16:00:00"),
+            t("2024-09-30 16:00:00"),
+        );
+        let expect = [
+            session_events("2024-09-27 21:00:00", "2024-09-28 02:30:00", true, SessionEnd::Hold),
+            session_events("2024-09-30 09:00:00", "2024-09-30 15:00:00", false, SessionEnd::Hold),
+        ]
+        .concat();
+        assert_eq!(events, expect);
+        let events = run_sim(&[Ticker::cu], config(), t("2024-09-26 20:00:00"), t("2024-09-27 08:00:00"));
+        assert_eq!(events, session_events("2024-09-26 21:00:00", "2024-09-27 01:00:00", true, SessionEnd::Hold));
+    }
+
+    #[test]
+    fn settlement_confirmed_by_first_session_of_day() {
+        let scheduler = SessionScheduler::new(&[Ticker::rb], config());
+        let confirm = scheduler
+            .sessions(d("2024-09-26"), d("2024-10-08"))
+            .into_iter()
+            .map(|x| (x.kind, x.trading_day, x.confirm_settlement))
+            .collect::<Vec<_>>();
+        use SessionKind::*;
+        assert_eq!(
+            confirm,
+            vec![
+                (Day, d("2024-09-26"), false),
+                (Night, d("2024-09-27"), true),
+                (Day, d("2024-09-27"), false),
+                (Night, d("2024-09-30"), true),
+                (Day, d("2024-09-30"), false),
+                (Day, d("2024-10-08"), true),
+                (Night, d("2024-10-09"), true),
+            ]
+        );
+        let scheduler = SessionScheduler::new(&[Ticker::AP], config());
+        assert!(scheduler.sessions(d("2024-09-26"), d("2024-09-27")).iter().all(|x| x.confirm_settlement));
+    }
+
+    #[test]
+    fn late_start_catches_up() {
+        let scheduler = SessionScheduler::new(&[Ticker::rb], config());
+        let mut runner = SessionRunner::new(scheduler.clone());
+        assert_eq!(
+            runner.catch_up(t("2024-09-27 10:30:00")),
+            vec![
+                SessionEvent::Login { confirm_settlement: false },
+                SessionEvent::Subscribe,
+                SessionEvent::Open
+            ]
+        );
+        assert_eq!(runner.catch_up(t("2024-09-27 11:00:00")), vec![]);
+        assert_eq!(runner.next_wake(t("2024-09-27 11:00:00")), t("2024-09-27 14:59:00"));
+        assert_eq!(
+            runner.catch_up(t("2024-09-27 21:30:00")),
+            vec![
+                SessionEvent::Logout,
+                SessionEvent::Login { confirm_settlement: true },
+                SessionEvent::Subscribe,
+                SessionEvent::Open
+            ]
+        );
+        assert_eq!(runner.catch_up(t("2024-09-28 10:00:00")), vec![SessionEvent::Logout]);
+        assert_eq!(runner.catch_up(t("2024-09-29 10:00:00")), vec![]);
+        let mut runner = SessionRunner::new(scheduler);
+        assert_eq!(
+            runner.catch_up(t("2024-09-30 14:59:30")),
+            vec![
+                SessionEvent::Login { confirm_settlement: false },
+                SessionEvent::Subscribe,
+                SessionEvent::Open,
+                SessionEvent::End(SessionEnd::Hold)
+            ]
+        );
+        runner.reset();
+        assert_eq!(runner.catch_up(t("2024-09-30 15:01:00")).len(), 4);
+        assert_eq!(runner.catch_up(t("2024-09-30 15:06:00")), vec![SessionEvent::Logout]);
+        assert_eq!(runner.state, None);
+    }
+
+    #[test]
+    fn sim_clock_does_not_go_back() {
+        let clock = SimClock::new(t("2024-09-27 10:00:00"));
+        let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        rt.block_on(clock.sleep_until(t("2024-09-27 09:00:00")));
+        assert_eq!(clock.now(), t("2024-09-27 10:00:00"));
+        rt.block_on(clock.sleep_until(t("2024-09-28 09:00:00")));
+        assert_eq!(clock.now(), t("2024-09-28 09:00:00"));
+    }
+
+    #[test]
+    fn trader_follows_the_sessions() {
+        let (start, end) = (t("2024-09-27 06:00:00"), t("2024-09-30 16:00:00"));
+        let events = run_trader(vec![], start, end);
+        assert_eq!(events, SessionScheduler::new(&[Ticker::rb], config()).events(start, end));
+    }
+
+    #[test]
+    fn failed_event_logs_out_and_retries() {
+        use SessionEvent::*;
+        let login = Login { confirm_settlement: false };
+        let events = run_trader(vec![login], t("2024-09-27 06:00:00"), t("2024-09-27 16:00:00"));
+        let mut expect = session_events("2024-09-27 09:00:00", "2024-09-27 15:00:00", false, SessionEnd::Hold);
+        expect.splice(0..0, [(t("2024-09-27 08:40:00"), login), (t("2024-09-27 08:40:00"), Logout)]);
+        expect[2].0 = t("2024-09-27 08:41:00");
+        assert_eq!(events, expect);
+
+        let events = run_trader(vec![Subscribe, Logout, Logout], t("2024-09-27 06:00:00"), t("2024-09-27 16:00:00"));
+        let expect = vec![
+            (t("2024-09-27 08:40:00"), login),
+            (t("2024-09-27 08:55:00"), Subscribe),
+            (t("2024-09-27 08:55:00"), Logout),
+            (t("2024-09-27 08:56:00"), login),
+            (t("2024-09-27 08:56:00"), Subscribe),
+            (t("2024-09-27 09:00:00"), Open),
+            (t("2024-09-27 14:59:00"), End(SessionEnd::Hold)),
+            (t("2024-09-27 15:05:00"), Logout),
+        ];
+        assert_eq!(events, expect);
+    }
+}
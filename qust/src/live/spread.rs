@@ -0,0 +1,566 @@
+use crate::loge;
+use crate::prelude::{TickData, Ticker};
+use crate::sig::prelude::ToNum;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use dyn_clone::{clone_trait_object, DynClone};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use super::algo::target_simple_action;
+use super::bt_tick::{BtTick, TickerTradeInfo};
+use super::prelude::{
+    ApiType, BtMatchBox, HoldLocal, LiveStraPool, LiveTarget, OrderAction, RetFnApi, StreamBtMatch, WithTicker,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpreadLeg {
+    pub ticker: Ticker,
+    /// The lots of the leg in one unit of the spread, negative for a short leg.
+    pub ratio: i32,
+}
+
+/// The spread is the sum of the last prices of the legs weighted by their ratios, e.g.
+/// `rb - hc` for `[(rb, 1), (hc, -1)]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegSpread {
+    pub legs: Vec<SpreadLeg>,
+}
+
+impl LegSpread {
+    pub fn new(legs: impl IntoIterator<Item = (Ticker, i32)>) -> Self {
+        let legs = legs
+            .into_iter()
+            .map(|(ticker, ratio)| {
+                assert!(ratio != 0, "spread leg with a zero ratio: {:?}", ticker);
+                SpreadLeg { ticker, ratio }
+            })
+            .collect_vec();
+        assert!(legs.len() > 1, "spread needs more than one leg");
+        Self { legs }
+    }
+
+    pub fn price(&self, tick_vec: &[TickData]) -> f32 {
+        izip!(self.legs.iter(), tick_vec.iter())
+            .map(|(leg, tick_data)| leg.ratio as f32 * tick_data.c)
+            .sum()
+    }
+
+    /// The holds of the legs for `units` of the spread.
+    pub fn leg_targets(&self, units: i32) -> Vec<i32> {
+        self.legs.iter().map(|x| x.ratio * units).collect()
+    }
+
+    /// The units of the spread held, `None` when the legs are out of ratio.
+    pub fn units(&self, hold_vec: &[i32]) -> Option<i32> {
+        let ratio = self.legs[0].ratio;
+        if hold_vec[0] % ratio != 0 {
+            return None;
+        }
+        let units = hold_vec[0] / ratio;
+        izip!(self.leg_targets(units), hold_vec.iter())
+            .all(|(target, hold)| target == *hold)
+            .then_some(units)
+    }
+}
+
+pub struct StreamSpread<'a> {
+    /// The latest tick of every leg.
+    pub tick_vec: &'a [TickData],
+    pub spread: f32,
+    pub units: Option<i32>,
+}
+
+pub type RetFnSpread = Box<dyn FnMut(&StreamSpread) -> LiveTarget + Send + 'static>;
+
+/// A strategy on the spread, its `LiveTarget` is in units of the spread.
+#[clone_trait]
+pub trait SpreadCond {
+    fn spread_cond(&self) -> RetFnSpread;
+}
+
+/// Trades the spread back to its rolling mean: goes `size` units against it when the
+/// z-score over `window` spreads passes `open`, and exits when it is back within `exit`.
+#[ta_derive]
+pub struct SpreadZScore {
+    pub window: usize,
+    pub open: f32,
+    pub exit: f32,
+    pub size: i32,
+}
+
+#[typetag::serde]
+impl SpreadCond for SpreadZScore {
+    fn spread_cond(&self) -> RetFnSpread {
+        let cond = self.clone();
+        let mut spread_vec = VecDeque::with_capacity(cond.window);
+        let mut live_target = LiveTarget::No;
+        Box::new(move |stream_spread| {
+            if spread_vec.len() == cond.window {
+                spread_vec.pop_front();
+            }
+            spread_vec.push_back(stream_spread.spread);
+            if spread_vec.len() < cond.window {
+                return live_target.clone();
+            }
+            let n = spread_vec.len() as f32;
+            let mean = spread_vec.iter().sum::<f32>() / n;
+            let std = (spread_vec.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n).sqrt();
+            if std == 0. {
+                return live_target.clone();
+            }
+            let z = (stream_spread.spread - mean) / std;
+            live_target = match live_target.clone() {
+                _ if z >= cond.open => LiveTarget::Sh(cond.size as f32),
+                _ if z <= -cond.open => LiveTarget::Lo(cond.size as f32),
+                LiveTarget::Lo(_) if z >= -cond.exit => LiveTarget::No,
+                LiveTarget::Sh(_) if z <= cond.exit => LiveTarget::No,
+                other => other,
+            };
+            live_target.clone()
+        })
+    }
+}
+
+/// How the legs get to the target. The lead leg is quoted passively, and once it fills
+/// the other legs hedge it at the opposite best price.
+#[ta_derive]
+pub struct SpreadExec {
+    /// The leg worked first, by default the one with the thinnest book when the work starts.
+    pub lead: Option<usize>,
+    /// Seconds the lead leg is worked before the try is given up, and as long again
+    /// before the next try.
+    pub work_timeout: i64,
+    /// Seconds the other legs chase the lead leg before it is unwound to them.
+    pub hedge_timeout: i64,
+}
+
+impl Default for SpreadExec {
+    fn default() -> Self {
+        Self {
+            lead: None,
+            work_timeout: 30,
+            hedge_timeout: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ExecState {
+    Idle,
+    Working { lead: usize, units: i32, since: dt },
+    GaveUp { until: dt },
+    Hedging { lead: usize, since: dt },
+}
+
+/// A strategy on several legs traded as one spread.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpreadStra {
+    pub spread: LegSpread,
+    pub cond: SpreadCondBox,
+    pub exec: SpreadExec,
+}
+
+/// Aligns the ticks of the legs, runs the strategy on the spread and works the legs.
+pub struct SpreadEngine {
+    pub spread: LegSpread,
+    pub exec: SpreadExec,
+    cond_fn: RetFnSpread,
+    tick_vec: Vec<Option<TickData>>,
+    hold_vec: Vec<HoldLocal>,
+    live_target: LiveTarget,
+    state: ExecState,
+    order_action_vec: Vec<OrderAction>,
+}
+
+impl SpreadEngine {
+    pub fn new(spread_stra: &SpreadStra) -> Self {
+        let n = spread_stra.spread.legs.len();
+        Self {
+            spread: spread_stra.spread.clone(),
+            exec: spread_stra.exec.clone(),
+            cond_fn: spread_stra.cond.spread_cond(),
+            tick_vec: vec![None; n],
+            hold_vec: vec![HoldLocal::default(); n],
+            live_target: LiveTarget::No,
+            state: ExecState::Idle,
+            order_action_vec: vec![OrderAction::No; n],
+        }
+    }
+
+    /// The order actions of the legs at the last update.
+    pub fn order_actions(&self) -> &[OrderAction] {
+        &self.order_action_vec
+    }
+
+    /// Takes a tick and the hold of a leg, and returns the order action of the leg. The
+    /// strategy runs on a tick newer than the last one of the leg, and nothing is sent
+    /// before every leg has a tick.
+    pub fn update(&mut self, leg: usize, tick_data: &TickData, hold: &HoldLocal) -> OrderAction {
+        let is_fresh = self.tick_vec[leg].as_ref().is_none_or(|x| tick_data.t > x.t);
+        self.tick_vec[leg] = Some(tick_data.clone());
+        self.hold_vec[leg] = hold.clone();
+        let Some(tick_vec) = self.tick_vec.iter().cloned().collect::<Option<Vec<_>>>() else {
+            return OrderAction::No;
+        };
+        if is_fresh {
+            let hold_vec = self.hold_vec.iter().map(|x| x.sum()).collect_vec();
+            let stream_spread = StreamSpread {
+                tick_vec: &tick_vec,
+                spread: self.spread.price(&tick_vec),
+                units: self.spread.units(&hold_vec),
+            };
+            self.live_target = (self.cond_fn)(&stream_spread);
+        }
+        let now = tick_vec.iter().map(|x| x.t).max().unwrap();
+        self.order_action_vec = self.work_legs(&tick_vec, now);
+        self.order_action_vec[leg].clone()
+    }
+
+    fn lead_leg(&self, tick_vec: &[TickData]) -> usize {
+        self.exec.lead.unwrap_or_else(|| {
+            tick_vec
+                .iter()
+                .map(|x| x.bid1_v + x.ask1_v)
+                .position_min_by(|a, b| a.total_cmp(b))
+                .unwrap()
+        })
+    }
+
+    fn work_legs(&mut self, tick_vec: &[TickData], now: dt) -> Vec<OrderAction> {
+        let target = self.live_target.to_num() as i32;
+        let ratio_vec = self.spread.legs.iter().map(|x| x.ratio).collect_vec();
+        let hold_vec = self.hold_vec.iter().map(|x| x.sum()).collect_vec();
+        let ticker_lead = |lead: usize| self.spread.legs[lead].ticker;
+        let (target_vec, is_crossing) = match self.spread.units(&hold_vec) {
+            None => {
+                let (lead, since) = match self.state {
+                    ExecState::Hedging { lead, since } => (lead, since),
+                    ExecState::Working { lead, .. } => (lead, now),
+                    _ => (self.lead_leg(tick_vec), now),
+                };
+                self.state = ExecState::Hedging { lead, since };
+                let target_vec = if (now - since).num_seconds() > self.exec.hedge_timeout {
+                    let units = izip!(hold_vec.iter(), ratio_vec.iter())
+                        .enumerate()
+                        .filter(|(i, _)| *i != lead)
+                        .map(|(_, (hold, ratio))| hold / ratio)
+                        .min_by_key(|x| x.abs())
+                        .unwrap();
+                    loge!(ticker_lead(lead), "spread hedge timeout, unwind the lead to {} units", units);
+                    self.spread.leg_targets(units)
+                } else {
+                    let hold_lead = hold_vec[lead] as f32 / ratio_vec[lead] as f32;
+                    ratio_vec
+                        .iter()
+                        .enumerate()
+                        .map(|(i, ratio)| match i == lead {
+                            true => hold_vec[lead],
+                            false => (hold_lead * *ratio as f32).round() as i32,
+                        })
+                        .collect_vec()
+                };
+                (target_vec, true)
+            }
+            Some(units) if units == target => {
+                self.state = ExecState::Idle;
+                (hold_vec, false)
+            }
+            Some(_) => match self.state {
+                ExecState::GaveUp { until } if now < until => (hold_vec, false),
+                ExecState::Working { lead, units, since } if units == target => {
+                    if (now - since).num_seconds() > self.exec.work_timeout {
+                        loge!(ticker_lead(lead), "spread lead not filled before the timeout, give up");
+                        self.state = ExecState::GaveUp { until: now + chrono::Duration::seconds(self.exec.work_timeout) };
+                        (hold_vec, false)
+                    } else {
+                        let mut target_vec = hold_vec;
+                        target_vec[lead] = ratio_vec[lead] * target;
+                        (target_vec, false)
+                    }
+                }
+                _ => {
+                    let lead = self.lead_leg(tick_vec);
+                    loge!(ticker_lead(lead), "spread work the lead to {} units", target);
+                    self.state = ExecState::Working { lead, units: target, since: now };
+                    let mut target_vec = hold_vec;
+                    target_vec[lead] = ratio_vec[lead] * target;
+                    (target_vec, false)
+                }
+            },
+        };
+        izip!(target_vec, self.hold_vec.iter(), tick_vec.iter())
+            .map(|(target, hold, tick_data)| {
+                let order_action = target_simple_action(target, hold, tick_data);
+                match is_crossing {
+                    true => cross_book(order_action, tick_data),
+                    false => order_action,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Prices the order at the opposite best, so it fills at once.
+fn cross_book(order_action: OrderAction, tick_data: &TickData) -> OrderAction {
+    use OrderAction::*;
+    match order_action {
+        LoOpen(i, _) => LoOpen(i, tick_data.ask1),
+        LoClose(i, _) => LoClose(i, tick_data.ask1),
+        LoCloseYd(i, _) => LoCloseYd(i, tick_data.ask1),
+        ShOpen(i, _) => ShOpen(i, tick_data.bid1),
+        ShClose(i, _) => ShClose(i, tick_data.bid1),
+        ShCloseYd(i, _) => ShCloseYd(i, tick_data.bid1),
+        No => No,
+    }
+}
+
+impl SpreadStra {
+    pub fn engine(&self) -> SpreadEngine {
+        SpreadEngine::new(self)
+    }
+
+    /// One strategy a leg, sharing the engine, so the spread runs live as the strategies
+    /// of its tickers. The legs are to be of different tickers.
+    pub fn to_live_stra_pool(&self) -> LiveStraPool {
+        let engine = Arc::new(Mutex::new(self.engine()));
+        let data = self
+            .spread
+            .legs
+            .iter()
+            .enumerate()
+            .map(|(leg, spread_leg)| {
+                let spread_leg_api = SpreadLegApi { leg, engine: Arc::clone(&engine) };
+                WithTicker { ticker: spread_leg.ticker, data: spread_leg_api.api_type_box() }
+            })
+            .collect_vec();
+        LiveStraPool { data }
+    }
+
+    /// Backtests on the ticks of the legs, given in the order of the legs. A leg is
+    /// matched on its own ticks, and the spread is taken on the latest tick of every leg.
+    pub fn bt_tick_legs(&self, match_box: &BtMatchBox, tick_legs: &[&[TickData]]) -> Vec<TickerTradeInfo> {
+        let n = self.spread.legs.len();
+        let mut engine = self.engine();
+        let mut match_fn_vec = (0..n).map(|_| match_box.bt_match()).collect_vec();
+        let mut hold_vec = vec![HoldLocal::default(); n];
+        let mut order_action_vec = vec![OrderAction::No; n];
+        let mut trade_info_vecs = vec![vec![]; n];
+        let mut i_vec = vec![0; n];
+        while let Some(leg) = (0..n)
+            .filter(|&leg| i_vec[leg] < tick_legs[leg].len())
+            .min_by_key(|&leg| tick_legs[leg][i_vec[leg]].t)
+        {
+            let tick_data = &tick_legs[leg][i_vec[leg]];
+            i_vec[leg] += 1;
+            let stream_bt_match = StreamBtMatch {
+                tick_data,
+                hold: &mut hold_vec[leg],
+                order_action: &order_action_vec[leg],
+            };
+            if let Some(trade_info) = match_fn_vec[leg](stream_bt_match) {
+                trade_info_vecs[leg].push(trade_info);
+            }
+            engine.update(leg, tick_data, &hold_vec[leg]);
+            order_action_vec = engine.order_actions().to_vec();
+        }
+        izip!(self.spread.legs.iter(), trade_info_vecs)
+            .map(|(leg, trade_info_vec)| TickerTradeInfo { ticker: leg.ticker, trade_info_vec })
+            .collect()
+    }
+}
+
+impl BtTick for SpreadStra {
+    type Input<'a> = (BtMatchBox, &'a hm<Ticker, Vec<TickData>>);
+    type Output = Vec<TickerTradeInfo>;
+    fn bt_tick(&self, input: Self::Input<'_>) -> Self::Output {
+        let tick_legs = self
+            .spread
+            .legs
+            .iter()
+            .map(|leg| match input.1.get(&leg.ticker) {
+                Some(tick) => tick.as_slice(),
+                None => {
+                    loge!(leg.ticker, "tick data not contains: {:?}", leg.ticker);
+                    &[]
+                }
+            })
+            .collect_vec();
+        self.bt_tick_legs(&input.0, &tick_legs)
+    }
+}
+
+/// A leg of a `SpreadStra` in the live engine. The legs run one at a time on the strategy
+/// thread and meet in the engine they share, a leg acts on what the engine decided for
+/// it at its next tick.
+#[derive(Clone)]
+pub struct SpreadLegApi {
+    pub leg: usize,
+    engine: Arc<Mutex<SpreadEngine>>,
+}
+
+impl ApiType for SpreadLegApi {
+    fn api_type(&self) -> RetFnApi<'_> {
+        let leg = self.leg;
+        let engine = Arc::clone(&self.engine);
+        Box::new(move |stream_api| {
+            engine
+                .lock()
+                .unwrap()
+                .update(leg, stream_api.tick_data, stream_api.hold)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::MatchSimple;
+
+    /// Holds as many units as the spread is.
+    #[ta_derive]
+    struct FollowSpread;
+
+    #[typetag::serde]
+    impl SpreadCond for FollowSpread {
+        fn spread_cond(&self) -> RetFnSpread {
+            Box::new(|stream_spread| match stream_spread.spread {
+                x if x >= 0. => LiveTarget::Lo(x),
+                x => LiveTarget::Sh(-x),
+            })
+        }
+    }
+
+    fn at(secs: i64) -> dt {
+        da::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(9, 0, 0).unwrap() + chrono::Duration::seconds(secs)
+    }
+
+    /// A tick at `secs` with the bid at `c`, the ask a tick above, and `book` lots on each side.
+    fn tick(secs: i64, c: f32, book: f32) -> TickData {
+        TickData { t: at(secs), c, bid1: c, ask1: c + 1., bid1_v: book, ask1_v: book, ..Default::default() }
+    }
+
+    fn hold(n: i32) -> HoldLocal {
+        match n {
+            0.. => HoldLocal { td_lo: n, ..Default::default() },
+            _ => HoldLocal { td_sh: -n, ..Default::default() },
+        }
+    }
+
+    fn spread_stra(exec: SpreadExec) -> SpreadStra {
+        SpreadStra {
+            spread: LegSpread::new([(Ticker::rb, 1), (Ticker::hc, -1)]),
+            cond: Box::new(FollowSpread),
+            exec,
+        }
+    }
+
+    /// An engine with rb worked towards 1 unit, rb having the thinner book.
+    fn working(exec: SpreadExec) -> SpreadEngine {
+        let mut engine = spread_stra(exec).engine();
+        assert_eq!(engine.update(0, &tick(0, 3501., 1.), &hold(0)), OrderAction::No);
+        engine.update(1, &tick(0, 3500., 50.), &hold(0));
+        engine
+    }
+
+    #[test]
+    fn leg_spread_units() {
+        let spread = LegSpread::new([(Ticker::rb, 2), (Ticker::hc, -3)]);
+        assert_eq!(spread.price(&[tick(0, 10., 1.), tick(0, 6., 1.)]), 2.);
+        assert_eq!(spread.leg_targets(2), vec![4, -6]);
+        assert_eq!(spread.units(&[4, -6]), Some(2));
+        assert_eq!(spread.units(&[0, 0]), Some(0));
+        assert_eq!(spread.units(&[4, -5]), None);
+        assert_eq!(spread.units(&[3, -6]), None);
+    }
+
+    #[test]
+    fn zscore_opens_holds_and_exits() {
+        let cond = SpreadZScore { window: 3, open: 1., exit: 0.5, size: 2 };
+        let mut cond_fn = cond.spread_cond();
+        let target_vec = [0., 0., 0., 3., 2.5, 1., 2.]
+            .iter()
+            .map(|spread| cond_fn(&StreamSpread { tick_vec: &[], spread: *spread, units: None }).to_num())
+            .collect_vec();
+        assert_eq!(target_vec, vec![0., 0., 0., -2., -2., 2., 0.]);
+    }
+
+    #[test]
+    fn engine_works_the_lead_then_hedges() {
+        let mut engine = working(SpreadExec::default());
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(1, 3501.), OrderAction::No]);
+        assert_eq!(engine.update(0, &tick(1, 3501., 1.), &hold(1)), OrderAction::No);
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::ShOpen(1, 3500.)]);
+        assert_eq!(engine.update(1, &tick(2, 3500., 50.), &hold(-1)), OrderAction::No);
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::No]);
+    }
+
+    #[test]
+    fn engine_works_the_lead_given() {
+        let mut engine = working(SpreadExec { lead: Some(1), ..Default::default() });
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::ShOpen(1, 3501.)]);
+        engine.update(1, &tick(1, 3500., 50.), &hold(-1));
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(1, 3502.), OrderAction::No]);
+    }
+
+    #[test]
+    fn stale_tick_keeps_the_target() {
+        let mut engine = working(SpreadExec::default());
+        engine.update(0, &tick(0, 3505., 1.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(1, 3505.), OrderAction::No]);
+        engine.update(0, &tick(1, 3505., 1.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(5, 3505.), OrderAction::No]);
+    }
+
+    #[test]
+    fn engine_gives_up_the_lead_and_tries_again() {
+        let mut engine = working(SpreadExec::default());
+        engine.update(1, &tick(30, 3500., 50.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(1, 3501.), OrderAction::No]);
+        engine.update(1, &tick(31, 3500., 50.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::No]);
+        engine.update(0, &tick(60, 3501., 1.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::No]);
+        engine.update(0, &tick(61, 3501., 1.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::LoOpen(1, 3501.), OrderAction::No]);
+    }
+
+    #[test]
+    fn engine_unwinds_the_lead_after_the_hedge_timeout() {
+        let mut engine = working(SpreadExec::default());
+        engine.update(0, &tick(1, 3501., 1.), &hold(1));
+        engine.update(1, &tick(11, 3500., 50.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::No, OrderAction::ShOpen(1, 3500.)]);
+        engine.update(1, &tick(12, 3500., 50.), &hold(0));
+        assert_eq!(engine.order_actions(), &[OrderAction::ShClose(1, 3501.), OrderAction::No]);
+    }
+
+    fn trades(ticker_trade_info: &TickerTradeInfo) -> Vec<(dt, OrderAction)> {
+        ticker_trade_info
+            .trade_info_vec
+            .iter()
+            .map(|x| (x.time, x.action.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn bt_tick_legs_fills_the_lead_then_the_hedge() {
+        let spread_stra = spread_stra(SpreadExec::default());
+        let rb = [tick(0, 3501., 1.), tick(2, 3501., 1.), tick(4, 3501., 1.)];
+        let hc = [tick(1, 3500., 50.), tick(3, 3500., 50.), tick(5, 3500., 50.)];
+        let match_box: BtMatchBox = Box::new(MatchSimple);
+        let res = spread_stra.bt_tick_legs(&match_box, &[&rb, &hc]);
+        assert_eq!(res[0].ticker, Ticker::rb);
+        assert_eq!(trades(&res[0]), vec![(at(2), OrderAction::LoOpen(1, 3501.))]);
+        assert_eq!(res[1].ticker, Ticker::hc);
+        assert_eq!(trades(&res[1]), vec![(at(3), OrderAction::ShOpen(1, 3500.))]);
+
+        let tick_map = hm::from([(Ticker::rb, rb.to_vec()), (Ticker::hc, hc.to_vec())]);
+        let res_map = spread_stra.bt_tick((match_box.clone(), &tick_map));
+        assert_eq!(res_map.iter().map(trades).collect_vec(), res.iter().map(trades).collect_vec());
+
+        let tick_map = hm::from([(Ticker::rb, rb.to_vec())]);
+        let res_map = spread_stra.bt_tick((match_box, &tick_map));
+        assert!(res_map.iter().all(|x| x.trade_info_vec.is_empty()));
+    }
+}
@@ -0,0 +1,480 @@
+use crate::idct::ta::CommSlip;
+use crate::sig::{distra::*, pnl::*, posi::*};
+use crate::trade::di::Di;
+use chrono::Datelike;
+use qust_ds::prelude::*;
+use qust_derive::*;
+
+/* #region WeightMethod */
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum WeightMethod {
+    Equal,
+    /// Inverse of the standard deviation of the daily pnl.
+    InverseVol,
+    /// Equal risk contribution of each strategy to the variance of the portfolio.
+    RiskParity,
+    /// Long only minimum variance.
+    MinVariance,
+    /// Long only maximum of the weighted volatilities over the volatility of the portfolio.
+    MaxDiversification,
+    /// Hierarchical risk parity, on a single linkage clustering of the correlations.
+    Hrp,
+}
+
+type Mat = Vec<Vec<f64>>;
+
+impl WeightMethod {
+    /// The weights of the strategies of the covariance, none for those of no variance.
+    fn weights(&self, cov: &Mat) -> Vec<f64> {
+        let active = (0..cov.len()).filter(|&i| cov[i][i] > 0.).collect_vec();
+        let cov_active = active.map(|&i| active.map(|&j| cov[i][j]));
+        let w_active = match self {
+            WeightMethod::Equal => vec![1.; active.len()],
+            WeightMethod::InverseVol => cov_active.iter().enumerate().map(|(i, x)| 1. / x[i].sqrt()).collect(),
+            WeightMethod::RiskParity => risk_parity(&cov_active),
+            WeightMethod::MinVariance => long_only(&cov_active, &vec![1.; active.len()]),
+            WeightMethod::MaxDiversification => {
+                let vol = cov_active.iter().enumerate().map(|(i, x)| x[i].sqrt()).collect_vec();
+                long_only(&cov_active, &vol)
+            }
+            WeightMethod::Hrp => hrp(&cov_active),
+        };
+        let mut res = vec![0.; cov.len()];
+        active.iter().zip(w_active).for_each(|(&i, w)| res[i] = w);
+        res
+    }
+}
+
+fn cov_matrix(data: &[&[f32]]) -> Mat {
+    let n = data.first().map(|x| x.len()).unwrap_or(0) as f64;
+    let mean = data.map(|x| x.iter().map(|&v| v as f64).sum::<f64>() / n);
+    (0..data.len())
+        .map(|i| {
+            (0..data.len())
+                .map(|j| {
+                    data[i]
+                        .iter()
+                        .zip(data[j].iter())
+                        .map(|(&x, &y)| (x as f64 - mean[i]) * (y as f64 - mean[j]))
+                        .sum::<f64>()
+                        / (n - 1.).max(1.)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Solves `a * x = b` by elimination, `a` ridged a little to stay invertible.
+fn solve(a: &Mat, b: &[f64]) -> Vec<f64> {
+    let n = b.len();
+    let ridge = (0..n).map(|i| a[i][i]).sum::<f64>() / n as f64 * 1e-8;
+    let mut m = a.iter().zip(b).enumerate().map(|(i, (row, &bi))| {
+        let mut row = row.clone();
+        row[i] += ridge;
+        row.push(bi);
+        row
+    }).collect_vec();
+    for k in 0..n {
+        let p = (k..n).max_by(|&x, &y| m[x][k].abs().total_cmp(&m[y][k].abs())).unwrap();
+        m.swap(k, p);
+        let (top, rest) = m.split_at_mut(k + 1);
+        for row in rest.iter_mut() {
+            let f = row[k] / top[k][k];
+            row.iter_mut().zip(top[k].iter()).skip(k).for_each(|(x, y)| *x -= f * y);
+        }
+    }
+    let mut x = vec![0.; n];
+    for i in (0..n).rev() {
+        x[i] = (m[i][n] - (i + 1..n).map(|j| m[i][j] * x[j]).sum::<f64>()) / m[i][i];
+    }
+    x
+}
+
+/// `cov⁻¹ * b` on the strategies left after dropping those of negative weights.
+fn long_only(cov: &Mat, b: &[f64]) -> Vec<f64> {
+    let mut active = (0..b.len()).collect_vec();
+    let mut res = vec![0.; b.len()];
+    while !active.is_empty() {
+        let cov_active = active.map(|&i| active.map(|&j| cov[i][j]));
+        let b_active = active.map(|&i| b[i]);
+        let w = solve(&cov_active, &b_active);
+        if w.iter().all(|x| *x >= 0.) {
+            active.iter().zip(w).for_each(|(&i, w)| res[i] = w);
+            break;
+        }
+        active = active.into_iter().zip(w).filter(|(_, w)| *w > 0.).map(|(i, _)| i).collect();
+    }
+    res
+}
+
+/// Cyclical coordinate descent on `w_i * (cov * w)_i = 1 / n`.
+fn risk_parity(cov: &Mat) -> Vec<f64> {
+    let n = cov.len();
+    let b = 1. / n as f64;
+    let mut w = (0..n).map(|i| 1. / cov[i][i].sqrt()).collect_vec();
+    for _ in 0..200 {
+        for i in 0..n {
+            let c = (0..n).filter(|&j| j != i).map(|j| cov[i][j] * w[j]).sum::<f64>();
+            w[i] = (-c + (c * c + 4. * cov[i][i] * b).sqrt()) / (2. * cov[i][i]);
+        }
+    }
+    w
+}
+
+fn hrp(cov: &Mat) -> Vec<f64> {
+    let n = cov.len();
+    let dist = (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| ((1. - cov[i][j] / (cov[i][i] * cov[j][j]).sqrt()) / 2.).max(0.).sqrt())
+                .collect_vec()
+        })
+        .collect_vec();
+    let mut clusters = (0..n).map(|i| vec![i]).collect_vec();
+    while clusters.len() > 1 {
+        let mut best = (f64::MAX, 0, 1);
+        for a in 0..clusters.len() {
+            for b in a + 1..clusters.len() {
+                let d = clusters[a]
+                    .iter()
+                    .flat_map(|&i| clusters[b].iter().map(move |&j| (i, j)))
+                    .map(|(i, j)| dist[i][j])
+                    .fold(f64::MAX, f64::min);
+                if d < best.0 {
+                    best = (d, a, b);
+                }
+            }
+        }
+        let mut cluster_b = clusters.remove(best.2);
+        clusters[best.1].append(&mut cluster_b);
+    }
+    let cluster_var = |c: &[usize]| {
+        let iv = c.map(|&i| 1. / cov[i][i]);
+        let iv_sum = iv.iter().sum::<f64>();
+        c.iter()
+            .zip(iv.iter())
+            .map(|(&i, wi)| c.iter().zip(iv.iter()).map(|(&j, wj)| wi * wj * cov[i][j]).sum::<f64>())
+            .sum::<f64>()
+            / (iv_sum * iv_sum)
+    };
+    let mut w = vec![1.; n];
+    let mut stack = clusters.pop().map(|x| vec![x]).unwrap_or_default();
+    while let Some(c) = stack.pop() {
+        if c.len() < 2 {
+            continue;
+        }
+        let (c1, c2) = c.split_at(c.len() / 2);
+        let (v1, v2) = (cluster_var(c1), cluster_var(c2));
+        let alpha = 1. - v1 / (v1 + v2);
+        c1.iter().for_each(|&i| w[i] *= alpha);
+        c2.iter().for_each(|&i| w[i] *= 1. - alpha);
+        stack.push(c1.to_vec());
+        stack.push(c2.to_vec());
+    }
+    w
+}
+/* #endregion */
+
+/* #region PortAlloc */
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum Rebalance {
+    /// Every number of days.
+    Days(usize),
+    /// The first day of each week.
+    Weekly,
+    /// The first day of each month.
+    Monthly,
+}
+
+impl Rebalance {
+    fn is_rebalance(&self, t: &[da], i: usize, start: usize) -> bool {
+        i == start
+            || match self {
+                Rebalance::Days(n) => (i - start).is_multiple_of(*n.max(&1)),
+                Rebalance::Weekly => t[i].iso_week() != t[i - 1].iso_week(),
+                Rebalance::Monthly => t[i].month() != t[i - 1].month(),
+            }
+    }
+}
+
+/// Weights the strategies of a `Stral` on the daily pnl of the `window` days to each
+/// rebalance day. `max_turnover` caps the share of the weights moved at a rebalance,
+/// half the sum of the changes over the sum of the weights.
+#[ta_derive]
+pub struct PortAlloc {
+    pub method: WeightMethod,
+    pub window: usize,
+    pub rebalance: Rebalance,
+    pub max_turnover: Option<f32>,
+}
+
+/// The weights of the strategies set at each rebalance day of `t`, each a row of `w`.
+/// They sum to the number of strategies, so equal weights leave each money as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortWeight {
+    pub t: vda,
+    pub w: vv32,
+}
+
+impl PortAlloc {
+    pub fn weights<T: AsRef<PnlRes<da>>>(&self, pnl: &[T]) -> PortWeight {
+        let pnl = pnl.union_pnl_res();
+        let n = pnl.len();
+        let t = pnl.first().map(|x| x.0.clone()).unwrap_or_default();
+        let window = self.window.max(2);
+        let mut res = PortWeight { t: vec![], w: vec![] };
+        let mut w_last = vec![1f32; n];
+        for i in window - 1..t.len() {
+            if !self.rebalance.is_rebalance(&t, i, window - 1) {
+                continue;
+            }
+            let data = pnl.iter().map(|x| &x.1[0][i + 1 - window..=i]).collect_vec();
+            let w = self.method.weights(&cov_matrix(&data));
+            let w_sum = w.iter().sum::<f64>();
+            if !w_sum.is_finite() || w_sum <= 0. {
+                continue;
+            }
+            let mut w_new = w.map(|x| (x / w_sum * n as f64) as f32);
+            if let Some(max_turnover) = self.max_turnover {
+                let turnover = turnover_of(&w_last, &w_new);
+                if turnover > max_turnover {
+                    let k = max_turnover / turnover;
+                    w_new = izip!(w_last.iter(), w_new.iter()).map(|(x, y)| x + (y - x) * k).collect();
+                }
+            }
+            res.t.push(t[i]);
+            res.w.push(w_new.clone());
+            w_last = w_new;
+        }
+        res
+    }
+}
+
+fn turnover_of(w_last: &[f32], w: &[f32]) -> f32 {
+    izip!(w_last.iter(), w.iter()).map(|(x, y)| (x - y).abs()).sum::<f32>() / 2. / w.len() as f32
+}
+
+impl PortWeight {
+    /// The weights of the strategy at `i` at each rebalance day.
+    pub fn weight_of(&self, i: usize) -> v32 {
+        self.w.map(|x| x[i])
+    }
+
+    /// The weights now, for a live `Stral` set with `Stral::mul_money_vec`.
+    pub fn last(&self) -> Option<&v32> {
+        self.w.last()
+    }
+
+    /// The turnover of each rebalance, from equal weights before the first.
+    pub fn turnover(&self) -> v32 {
+        self.w
+            .iter()
+            .scan(None, |last: &mut Option<&v32>, w| {
+                let res = match last {
+                    Some(w_last) => turnover_of(w_last, w),
+                    None => turnover_of(&vec![1.; w.len()], w),
+                };
+                *last = Some(w);
+                Some(res)
+            })
+            .collect()
+    }
+}
+/* #endregion */
+
+/* #region WeightMoney */
+/// The money scaled by each weight from the day after its rebalance day in `t`, and left
+/// as it is before the first.
+#[ta_derive]
+pub struct WeightMoney {
+    pub money: Box<dyn Money>,
+    pub t: vda,
+    pub w: v32,
+}
+
+#[typetag::serde]
+impl Money for WeightMoney {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let f = self.money.register(di);
+        let w = di
+            .t()
+            .iter()
+            .map(|x| match self.t.partition_point(|t| t < &x.date()) {
+                0 => 1.,
+                k => self.w[k - 1],
+            })
+            .collect_vec();
+        Box::new(move |x, i| &f(x, i) * w[i])
+    }
+    fn get_init_weight(&self) -> f32 {
+        self.money.get_init_weight()
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(WeightMoney { money: self.money.change_weight(weight), ..self.clone() })
+    }
+}
+
+impl Stral {
+    /// Each money times its weight.
+    pub fn mul_money_vec(&self, w: &[f32]) -> Self {
+        assert_eq!(self.0.len(), w.len(), "one weight for each strategy");
+        izip!(self.0.iter(), w.iter())
+            .map(|(x, w)| x.mul_money(*w))
+            .collect_vec()
+            .to_stral_bare()
+    }
+
+    /// Each money scaled by its weights through the rebalance days, as `WeightMoney`.
+    pub fn port_weight(&self, port_weight: &PortWeight) -> Self {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let money = WeightMoney {
+                    money: x.ptm.get_money_fn(),
+                    t: port_weight.t.clone(),
+                    w: port_weight.weight_of(i),
+                };
+                Stra { ptm: x.ptm.change_money(money), ..x.clone() }
+            })
+            .collect_vec()
+            .to_stral_bare()
+    }
+}
+
+impl DiStral<'_> {
+    /// The weights of the strategies on their daily pnl after `comm`.
+    pub fn port_alloc(&self, port_alloc: &PortAlloc, comm: CommSlip) -> PortWeight {
+        let pnl = self.calc(comm);
+        port_alloc.weights(&pnl)
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The covariance of the vols and the correlations, `corr[i][j]` for `i < j` by rows.
+    fn cov_of(vol: &[f64], corr: &[f64]) -> Mat {
+        let n = vol.len();
+        let mut corr_ij = corr.iter();
+        let mut res = vec![vec![0.; n]; n];
+        for i in 0..n {
+            res[i][i] = vol[i] * vol[i];
+            for j in i + 1..n {
+                let c = corr_ij.next().unwrap() * vol[i] * vol[j];
+                res[i][j] = c;
+                res[j][i] = c;
+            }
+        }
+        res
+    }
+
+    fn normed(w: &[f64]) -> Vec<f64> {
+        let sum = w.iter().sum::<f64>();
+        w.map(|x| x / sum)
+    }
+
+    fn assert_close(x: &[f64], y: &[f64]) {
+        assert!(izip!(x, y).all(|(x, y)| (x - y).abs() < 1e-6), "{:?} != {:?}", x, y);
+    }
+
+    fn risk_contrib(cov: &Mat, w: &[f64]) -> Vec<f64> {
+        (0..w.len()).map(|i| w[i] * (0..w.len()).map(|j| cov[i][j] * w[j]).sum::<f64>()).collect()
+    }
+
+    #[test]
+    fn equal_and_inverse_vol() {
+        let cov = cov_of(&[0.1, 0.2, 0.4], &[0.3, 0., 0.5]);
+        assert_close(&WeightMethod::Equal.weights(&cov), &[1., 1., 1.]);
+        assert_close(&normed(&WeightMethod::InverseVol.weights(&cov)), &[4. / 7., 2. / 7., 1. / 7.]);
+    }
+
+    #[test]
+    fn risk_parity_takes_equal_risk() {
+        // uncorrelated, the weights go inverse to the vols
+        let cov = cov_of(&[0.1, 0.2, 0.4], &[0., 0., 0.]);
+        assert_close(&normed(&WeightMethod::RiskParity.weights(&cov)), &[4. / 7., 2. / 7., 1. / 7.]);
+        let cov = cov_of(&[0.1, 0.2, 0.4], &[0.6, -0.2, 0.3]);
+        let w = normed(&WeightMethod::RiskParity.weights(&cov));
+        assert!(w.iter().all(|x| *x > 0.));
+        let rc = normed(&risk_contrib(&cov, &w));
+        assert_close(&rc, &[1. / 3.; 3]);
+    }
+
+    #[test]
+    fn min_variance_long_only() {
+        // two of them, w1 = (s2^2 - s12) / (s1^2 + s2^2 - 2 s12)
+        let cov = cov_of(&[0.2, 0.3], &[1. / 6.]);
+        assert_close(&normed(&WeightMethod::MinVariance.weights(&cov)), &[0.08 / 0.11, 0.03 / 0.11]);
+        // the second would be short, so it is dropped
+        let cov = cov_of(&[0.1, 0.3], &[0.6]);
+        assert_close(&normed(&WeightMethod::MinVariance.weights(&cov)), &[1., 0.]);
+        // of three, the one left out of the first solve is dropped and the rest solved again
+        let cov = cov_of(&[0.1, 0.3, 0.2], &[0.6, 0., 0.]);
+        assert_close(&normed(&WeightMethod::MinVariance.weights(&cov)), &[0.8, 0., 0.2]);
+    }
+
+    #[test]
+    fn max_diversification_of_uncorrelated() {
+        let cov = cov_of(&[0.1, 0.2, 0.4], &[0., 0., 0.]);
+        assert_close(&normed(&WeightMethod::MaxDiversification.weights(&cov)), &[4. / 7., 2. / 7., 1. / 7.]);
+    }
+
+    #[test]
+    fn hrp_of_two_is_inverse_variance() {
+        let cov = cov_of(&[0.1, 0.2], &[0.5]);
+        assert_close(&normed(&WeightMethod::Hrp.weights(&cov)), &[0.8, 0.2]);
+    }
+
+    #[test]
+    fn no_variance_no_weight() {
+        let mut cov = cov_of(&[0.1, 0.2, 0.4], &[0., 0., 0.]);
+        cov[1][1] = 0.;
+        for method in [
+            WeightMethod::Equal,
+            WeightMethod::InverseVol,
+            WeightMethod::RiskParity,
+            WeightMethod::MinVariance,
+            WeightMethod::MaxDiversification,
+            WeightMethod::Hrp,
+        ] {
+            let w = method.weights(&cov);
+            assert_eq!(w[1], 0., "{:?}", method);
+            assert!(w[0] > 0. && w[2] > 0., "{:?}", method);
+        }
+    }
+
+    fn pnl(scale: f32, days: &vda) -> PnlRes<da> {
+        let pnl = (0..days.len()).map(|i| if i % 2 == 0 { scale } else { -scale }).collect_vec();
+        PnlRes(days.clone(), vec![pnl])
+    }
+
+    #[test]
+    fn turnover_capped() {
+        let days = crate::test_util::days(10).map(|x| x.date());
+        let pnl = [pnl(1., &days), pnl(3., &days)];
+        let mut port_alloc = PortAlloc {
+            method: WeightMethod::InverseVol,
+            window: 4,
+            rebalance: Rebalance::Days(2),
+            max_turnover: None,
+        };
+        let port_weight = port_alloc.weights(&pnl);
+        assert_eq!(port_weight.t, [days[3], days[5], days[7], days[9]]);
+        port_weight.w.iter().for_each(|w| assert_eq!(w, &vec![1.5, 0.5]));
+
+        port_alloc.max_turnover = Some(0.1);
+        let port_weight = port_alloc.weights(&pnl);
+        let expected = [[1.2, 0.8], [1.4, 0.6], [1.5, 0.5], [1.5, 0.5]];
+        izip!(port_weight.w.iter(), expected.iter()).for_each(|(w, y)| {
+            assert!(izip!(w, y).all(|(x, y)| (x - y).abs() < 1e-5), "{:?}", port_weight.w);
+        });
+        let turnover = port_weight.turnover();
+        assert!(izip!(turnover.iter(), [0.1, 0.1, 0.05, 0.]).all(|(x, y)| (x - y).abs() < 1e-5), "{:?}", turnover);
+        assert_eq!(port_weight.weight_of(1).len(), 4);
+    }
+}
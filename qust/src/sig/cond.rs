@@ -1,4 +1,6 @@
+use crate::idct::expr::{ArithOps, Expr};
 use crate::idct::pms::Pms;
+use crate::sig::lang::{pms_exprs, TextPrec};
 use crate::sig::posi::Dire;
 use crate::trade::di::Di;
 use chrono::Timelike;
@@ -24,6 +26,14 @@ pub trait Cond {
         self.calc_da(data, di)
     }
     fn update(&mut self, _di: &Di) {}
+    /// The text of `sig::lang` parsing into this condition, `None` where there is none.
+    fn text(&self) -> Option<String> {
+        None
+    }
+    /// How tightly the `text` binds, for the condition taking it to know when to bracket it.
+    fn precedence(&self) -> TextPrec {
+        TextPrec::Call
+    }
     fn to_box(&self) -> Box<dyn Cond>
     where
         Self: Sized,
@@ -82,6 +92,27 @@ impl Cond for Msig {
         let cond2 = self.2.cond(di);
         Box::new(move |e, o| f.evaluate(cond1(e, o), cond2(e, o)))
     }
+    fn text(&self) -> Option<String> {
+        let ops = match self.0 {
+            LogicOps::And => "&",
+            LogicOps::Or => "|",
+        };
+        // the text is taken from the left, so the right side is bracketed on a tie
+        let side = |cond: &CondBox, is_right: bool| {
+            let text = cond.text()?;
+            match cond.precedence() < self.precedence() || is_right && cond.precedence() == self.precedence() {
+                true => Some(format!("({})", text)),
+                false => Some(text),
+            }
+        };
+        Some(format!("{} {} {}", side(&self.1, false)?, ops, side(&self.2, true)?))
+    }
+    fn precedence(&self) -> TextPrec {
+        match self.0 {
+            LogicOps::And => TextPrec::And,
+            LogicOps::Or => TextPrec::Or,
+        }
+    }
 }
 /* #endregion */
 
@@ -194,6 +225,20 @@ impl Cond for BandCond<Pms> {
             _ => panic!("no impelemented"),
         }
     }
+    fn text(&self) -> Option<String> {
+        let exprs = pms_exprs(&self.2)?;
+        let (diff, ops) = match (&self.1, &self.0, exprs.as_slice()) {
+            (BandState::Lieing, Dire::Lo, [diff]) => (diff, ">"),
+            (BandState::Lieing, Dire::Sh, [diff]) => (diff, "<"),
+            _ => return None,
+        };
+        match diff {
+            Expr::Bin(ArithOps::Sub, x, y) => Some(format!("{} {} {}", x, ops, y)),
+            _ => Some(format!("{} {} 0", diff, ops)),
+        }
+    }    fn precedence(&self) -> TextPrec {
+        TextPrec::Cmp
+    }
 }
 
 #[ta_derive]
@@ -214,6 +259,16 @@ impl Cond for CrossCond {
             }),
         }
     }
+    fn text(&self) -> Option<String> {
+        let name = match self.0 {
+            Dire::Lo => "cross_up",
+            Dire::Sh => "cross_down",
+        };
+        match pms_exprs(&self.1)?.as_slice() {
+            [x, y] => Some(format!("{}({}, {})", name, x, y)),
+            _ => None,
+        }
+    }
 }
 
 #[ta_derive]
@@ -227,6 +282,17 @@ impl Cond for NotCond {
         let f = self.cond.cond(di);
         Box::new(move |e, o| !f(e, o))
     }
+    fn text(&self) -> Option<String> {
+        let text = self.cond.text()?;
+        // a comparison is bracketed too, though `!` binds looser than it
+        match self.cond.precedence() {
+            TextPrec::Not | TextPrec::Call => Some(format!("!{}", text)),
+            _ => Some(format!("!({})", text)),
+        }
+    }
+    fn precedence(&self) -> TextPrec {
+        TextPrec::Not
+    }
 }
 
 impl Not for CondBox {
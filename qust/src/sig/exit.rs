@@ -0,0 +1,422 @@
+use crate::idct::prelude::*;
+use crate::live::prelude::*;
+use crate::sig::{cond::*, posi::Dire};
+use crate::trade::di::Di;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/* #region ExitRule */
+/// A distance in price from the entry or from the best price since.
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum ExitDist {
+    /// A share of the entry price.
+    Pct(f32),
+    /// A number of tick sizes of the ticker.
+    Tick(f32),
+    /// A multiple of the `Atr` of the window, at the entry bar for a stop loss, a take
+    /// profit and a break-even, and at the bar for a trailing stop.
+    Atr(usize, f32),
+}
+
+#[ta_derive]
+pub enum ExitRule {
+    /// The price gone against the entry by the distance.
+    StopLoss(ExitDist),
+    /// The price gone with the entry by the distance.
+    TakeProfit(ExitDist),
+    /// The close gone back from the best close since the entry by the distance.
+    Trailing(ExitDist),
+    /// The close gone back from the highest high, or the lowest low for a short, since the
+    /// entry by a multiple of the `Atr` of the window.
+    Chandelier(usize, f32),
+    /// The position held for the number of bars.
+    Bars(usize),
+    /// A bar at the time of day, as `Between(14:55..15:00)` for the end of the day session.
+    SessionEnd(ForCompare<tt>),
+    /// The price back to the entry once the best close has gone with it by the distance.
+    BreakEven(ExitDist),
+}
+
+/// The best prices of a position since its entry.
+#[derive(Debug, Clone)]
+pub struct ExitTrack {
+    pub dire: Dire,
+    pub entry: f32,
+    pub best: f32,
+    pub best_hl: f32,
+}
+
+impl ExitTrack {
+    pub fn new(dire: Dire, entry: f32) -> Self {
+        Self { dire, entry, best: entry, best_hl: entry }
+    }
+
+    pub fn push(&mut self, c: f32, h: f32, l: f32) {
+        match self.dire {
+            Dire::Lo => {
+                self.best = self.best.max(c);
+                self.best_hl = self.best_hl.max(h);
+            }
+            Dire::Sh => {
+                self.best = self.best.min(c);
+                self.best_hl = self.best_hl.min(l);
+            }
+        }
+    }
+
+    fn gain(&self, p: f32) -> f32 {
+        match self.dire {
+            Dire::Lo => p - self.entry,
+            Dire::Sh => self.entry - p,
+        }
+    }
+
+    fn back(&self, best: f32, p: f32) -> f32 {
+        match self.dire {
+            Dire::Lo => best - p,
+            Dire::Sh => p - best,
+        }
+    }
+}
+
+struct ExitBars {
+    t: avdt,
+    c: av32,
+    h: av32,
+    l: av32,
+    tz: f32,
+    atr: HashMap<usize, av32>,
+}
+
+impl ExitBars {
+    fn new(di: &Di, rules: &[ExitRule]) -> Self {
+        let atr = rules
+            .iter()
+            .filter_map(|x| match x {
+                ExitRule::StopLoss(ExitDist::Atr(n, _))
+                | ExitRule::TakeProfit(ExitDist::Atr(n, _))
+                | ExitRule::Trailing(ExitDist::Atr(n, _))
+                | ExitRule::BreakEven(ExitDist::Atr(n, _))
+                | ExitRule::Chandelier(n, _) => Some(*n),
+                _ => None,
+            })
+            .map(|n| (n, di.calc(Atr(n))[0].clone()))
+            .collect();
+        Self {
+            t: di.t(),
+            c: di.c(),
+            h: di.h(),
+            l: di.l(),
+            tz: di.pcon.ticker.info().tz,
+            atr,
+        }
+    }
+
+    /// The bars of `di`, built again only once a live `Di` has finished a bar past them.
+    fn of_di<'b>(bars: &'b mut Option<ExitBars>, di: &Di, rules: &[ExitRule]) -> &'b ExitBars {
+        if bars.as_ref().map(|x| x.c.len() != di.size()).unwrap_or(true) {
+            *bars = Some(ExitBars::new(di, rules));
+        }
+        bars.as_ref().unwrap()
+    }
+
+    fn dist(&self, dist: &ExitDist, entry: f32, i: usize) -> f32 {
+        match dist {
+            ExitDist::Pct(x) => entry * x,
+            ExitDist::Tick(x) => self.tz * x,
+            ExitDist::Atr(n, x) => self.atr[n][i] * x,
+        }
+    }
+
+    /// The track of the position opened at the close of `o`, brought to the bar `e`.
+    fn track(&self, dire: Dire, o: usize, e: usize, cache: &mut HashMap<usize, (usize, ExitTrack)>) -> ExitTrack {
+        let (last, track) = match cache.remove(&o) {
+            Some((last, track)) if last <= e => (last, track),
+            _ => (o, ExitTrack::new(dire, self.c[o])),
+        };
+        let track = (last + 1..=e).fold(track, |mut accu, j| {
+            accu.push(self.c[j], self.h[j], self.l[j]);
+            accu
+        });
+        cache.insert(o, (e, track.clone()));
+        track
+    }
+
+    fn hit(&self, rules: &[ExitRule], track: &ExitTrack, p: f32, t: &dt, e: usize, o: usize) -> bool {
+        rules.iter().any(|rule| match rule {
+            ExitRule::StopLoss(d) => track.gain(p) <= -self.dist(d, track.entry, o),
+            ExitRule::TakeProfit(d) => track.gain(p) >= self.dist(d, track.entry, o),
+            ExitRule::Trailing(d) => track.back(track.best, p) >= self.dist(d, track.entry, e),
+            ExitRule::Chandelier(n, k) => track.back(track.best_hl, p) >= self.atr[n][e] * k,
+            ExitRule::Bars(n) => e - o >= *n,
+            ExitRule::SessionEnd(f) => f.compare_time(t),
+            ExitRule::BreakEven(d) => {
+                track.gain(track.best) >= self.dist(d, track.entry, o) && track.gain(p) <= 0.
+            }
+        })
+    }
+}
+/* #endregion */
+
+/* #region ExitCond */
+/// Exits a position of `dire` at the first of `rules` hit on the close of a bar, the
+/// entry being the close of the open bar. In the exit slot of a `Tsig` or a `Ptm::Ptm3`,
+/// or of an `OpenExit` of a `Ktn`, and joins other exits with `|`.
+#[ta_derive]
+pub struct ExitCond {
+    pub dire: Dire,
+    pub rules: Vec<ExitRule>,
+}
+
+impl ExitCond {
+    pub fn new(dire: Dire, rules: Vec<ExitRule>) -> Self {
+        Self { dire, rules }
+    }
+}
+
+#[typetag::serde]
+impl Cond for ExitCond {
+    fn cond<'a>(&self, di: &'a Di) -> LoopSig<'a> {
+        let bars = ExitBars::new(di, &self.rules);
+        let (dire, rules) = (self.dire, self.rules.clone());
+        let cache = RefCell::new(HashMap::new());
+        Box::new(move |e, o| {
+            let track = bars.track(dire, o, e, &mut cache.borrow_mut());
+            bars.hit(&rules, &track, bars.c[e], &bars.t[e], e, o)
+        })
+    }
+}
+
+#[typetag::serde]
+impl CondType6 for ExitCond {
+    fn cond_type6(&self, _di: &Di) -> RetFnCondType6<'_> {
+        let (mut cache, mut bars) = (HashMap::new(), None);
+        Box::new(move |di_kline_o| {
+            let (e, o) = (di_kline_o.di_kline.i, di_kline_o.o);
+            let bars = ExitBars::of_di(&mut bars, di_kline_o.di_kline.di, &self.rules);
+            let track = bars.track(self.dire, o, e, &mut cache);
+            bars.hit(&self.rules, &track, bars.c[e], &bars.t[e], e, o)
+        })
+    }
+}
+/* #endregion */
+
+/* #region ExitTick */
+/// Takes the position of `data` and exits it at the first tick hitting one of `exits`,
+/// the entry being the price of the tick it opens at. It stays out until `data` turns
+/// to the other side or closes. Bars counted and the `Atr`s are of the last finished
+/// bar, the best prices of the ticks.
+#[derive(Debug, Clone)]
+pub struct ExitTick<T> {
+    pub data: T,
+    pub exits: Vec<ExitRule>,
+}
+
+impl<T: CondType1> CondType1 for ExitTick<T> {
+    fn cond_type1(&self, di: &Di) -> RetFnCondType1<'_> {
+        let mut data_fn = self.data.cond_type1(di);
+        let exits = self.exits.clone();
+        let mut open: Option<(usize, ExitTrack)> = None;
+        let mut stopped: Option<Dire> = None;
+        let mut bars = None;
+        Box::new(move |stream| {
+            let live_target = data_fn(stream);
+            let dire = match live_target {
+                LiveTarget::Lo(_) => Some(Dire::Lo),
+                LiveTarget::Sh(_) => Some(Dire::Sh),
+                _ => None,
+            };
+            if stopped.is_some() && stopped == dire {
+                return LiveTarget::No;
+            }
+            stopped = None;
+            let (tick_data, di_kline) = (stream.stream_api.tick_data, &stream.di_kline_state.di_kline);
+            let dire = match dire {
+                Some(dire) => dire,
+                None => {
+                    open = None;
+                    return live_target;
+                }
+            };
+            match &mut open {
+                Some((_, track)) if track.dire == dire => track.push(tick_data.c, tick_data.c, tick_data.c),
+                _ => open = Some((di_kline.i, ExitTrack::new(dire, tick_data.c))),
+            }
+            let (o, track) = open.as_ref().unwrap();
+            let bars = ExitBars::of_di(&mut bars, di_kline.di, &exits);
+            if bars.hit(&exits, track, tick_data.c, &tick_data.t, di_kline.i, *o) {
+                open = None;
+                stopped = Some(dire);
+                return LiveTarget::No;
+            }
+            live_target
+        })
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{TickData, Ticker, ToNum};
+    use crate::test_util::di_ohlc;
+
+    const C: [f32; 10] = [100., 101., 103., 102., 99., 104., 106., 103., 100., 97.];
+
+    fn di() -> Di {
+        let h = C.map(|x| x + 1.);
+        let l = C.map(|x| x - 1.);
+        di_ohlc(Ticker::rb, &C, &h, &l, &C)
+    }
+
+    /// The bars after `o` the rule exits at, checking the bar and the live exits agree.
+    fn hits(di: &Di, dire: Dire, rule: ExitRule, o: usize) -> Vec<usize> {
+        let exit_cond = ExitCond::new(dire, vec![rule]);
+        let f = exit_cond.cond(di);
+        let mut g = exit_cond.cond_type6(di);
+        (o + 1..di.len())
+            .filter(|e| {
+                let hit = f(*e, o);
+                assert_eq!(hit, g(&DiKlineO { di_kline: DiKline { di, i: *e }, o }), "bar {}", e);
+                hit
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stop_loss() {
+        let di = di();
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::StopLoss(ExitDist::Tick(1.)), 0), [4, 9]);
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::StopLoss(ExitDist::Pct(0.01)), 0), [4, 9]);
+        assert_eq!(hits(&di, Dire::Sh, ExitRule::StopLoss(ExitDist::Tick(1.)), 0), [1, 2, 3, 5, 6, 7]);
+        let atr = di.calc(Atr(3))[0].clone();
+        let expected = (4..10).filter(|e| C[*e] - C[3] <= -atr[3] * 0.5).collect_vec();
+        assert!(!expected.is_empty());
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::StopLoss(ExitDist::Atr(3, 0.5)), 3), expected);
+    }
+
+    #[test]
+    fn take_profit() {
+        let di = di();
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::TakeProfit(ExitDist::Pct(0.03)), 0), [2, 5, 6, 7]);
+        assert_eq!(hits(&di, Dire::Sh, ExitRule::TakeProfit(ExitDist::Tick(3.)), 6), [7, 8, 9]);
+    }
+
+    #[test]
+    fn trailing() {
+        let di = di();
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::Trailing(ExitDist::Tick(2.)), 0), [4, 7, 8, 9]);
+        // the lowest close since bar 3 is 99 at bar 4
+        assert_eq!(hits(&di, Dire::Sh, ExitRule::Trailing(ExitDist::Tick(5.)), 3), [5, 6]);
+    }
+
+    #[test]
+    fn chandelier() {
+        let di = di();
+        let atr = di.calc(Atr(3))[0].clone();
+        let best_hl = [100., 102., 104., 104., 104., 105., 107., 107., 107., 107.];
+        let expected = (1..10).filter(|e| best_hl[*e] - C[*e] >= atr[*e]).collect_vec();
+        assert!(!expected.is_empty() && expected.len() < 9);
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::Chandelier(3, 1.), 0), expected);
+    }
+
+    #[test]
+    fn bars() {
+        let di = di();
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::Bars(3), 0), [3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(hits(&di, Dire::Sh, ExitRule::Bars(1), 7), [8, 9]);
+    }
+
+    #[test]
+    fn session_end() {
+        let di = di();
+        let at = |h, m| tt::from_hms_opt(h, m, 0).unwrap();
+        let rule = ExitRule::SessionEnd(ForCompare::Between(at(14, 55)..at(15, 0)));
+        assert_eq!(hits(&di, Dire::Lo, rule, 6), [7, 8, 9]);
+        let rule = ExitRule::SessionEnd(ForCompare::Between(at(9, 0)..at(10, 0)));
+        assert!(hits(&di, Dire::Lo, rule, 0).is_empty());
+    }
+
+    #[test]
+    fn break_even() {
+        let di = di();
+        assert_eq!(hits(&di, Dire::Lo, ExitRule::BreakEven(ExitDist::Tick(2.)), 0), [4, 8, 9]);
+        assert!(hits(&di, Dire::Lo, ExitRule::BreakEven(ExitDist::Tick(7.)), 0).is_empty());
+    }
+
+    #[test]
+    fn exit_cond_takes_the_first_rule_hit() {
+        let di = di();
+        let exit_cond = ExitCond::new(Dire::Lo, vec![ExitRule::Bars(8), ExitRule::StopLoss(ExitDist::Tick(1.))]);
+        let f = exit_cond.cond(&di);
+        assert_eq!((1..10).filter(|e| f(*e, 0)).collect_vec(), [4, 8, 9]);
+    }
+
+    /// Long on a tick of volume above 0, short below and out at 0.
+    struct TickSide;
+
+    impl CondType1 for TickSide {
+        fn cond_type1(&self, _di: &Di) -> RetFnCondType1<'_> {
+            Box::new(|stream| match stream.stream_api.tick_data.v {
+                v if v > 0. => LiveTarget::Lo(1.),
+                v if v < 0. => LiveTarget::Sh(1.),
+                _ => LiveTarget::No,
+            })
+        }
+    }
+
+    /// The targets of `exit_tick` on ticks of the close and volume, at the bar given, as
+    /// numbers.
+    fn run_ticks(exit_tick: &ExitTick<TickSide>, di: &Di, ticks: &[(usize, f32, f32)]) -> v32 {
+        let mut f = exit_tick.cond_type1(di);
+        let hold = HoldLocal::default();
+        ticks
+            .iter()
+            .map(|(i, c, v)| {
+                let tick_data = TickData { c: *c, v: *v, ..Default::default() };
+                let stream_api = StreamApiType { tick_data: &tick_data, hold: &hold, target_ratio: 1., order_working: false };
+                let di_kline_state = DiKlineState { di_kline: DiKline { di, i: *i }, state: false };
+                f(&StreamCondType1 { stream_api, di_kline_state }).to_num()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exit_tick_within_the_bar() {
+        let di = di();
+        let exits = vec![ExitRule::StopLoss(ExitDist::Tick(2.)), ExitRule::TakeProfit(ExitDist::Tick(5.))];
+        let exit_tick = ExitTick { data: TickSide, exits };
+        let ticks = [
+            (9, 100., 1.),
+            (9, 99., 1.),
+            (9, 98., 1.),
+            (9, 100., 1.),
+            (9, 100., 0.),
+            (9, 100., 1.),
+            (9, 105., 1.),
+            (9, 104., -1.),
+            (9, 105., -1.),
+            (9, 106., -1.),
+        ];
+        assert_eq!(run_ticks(&exit_tick, &di, &ticks), [1., 1., 0., 0., 0., 1., 0., -1., -1., 0.]);
+    }
+
+    #[test]
+    fn exit_tick_tracks_the_best_tick() {
+        let di = di();
+        let exit_tick = ExitTick { data: TickSide, exits: vec![ExitRule::Trailing(ExitDist::Tick(3.))] };
+        let ticks = [(9, 100., 1.), (9, 104., 1.), (9, 102., 1.), (9, 101., 1.), (9, 103., 1.)];
+        assert_eq!(run_ticks(&exit_tick, &di, &ticks), [1., 1., 1., 0., 0.]);
+    }
+
+    #[test]
+    fn exit_tick_counts_the_finished_bars() {
+        let di = di();
+        let exit_tick = ExitTick { data: TickSide, exits: vec![ExitRule::Bars(2)] };
+        let ticks = [(5, 100., 1.), (6, 100., 1.), (6, 101., 1.), (7, 101., 1.), (8, 101., -1.)];
+        assert_eq!(run_ticks(&exit_tick, &di, &ticks), [1., 1., 1., 0., -1.]);
+    }
+}
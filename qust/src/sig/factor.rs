@@ -0,0 +1,563 @@
+use crate::idct::prelude::*;
+use crate::sig::{cond::CondLoop, pnl::*, posi::*};
+use crate::trade::prelude::*;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum FactorError {
+    #[error("winsorize quantile {0} out of 0 to 1")]
+    Quantile(f32),
+}
+
+pub type FactorResult<T> = Result<T, FactorError>;
+
+/* #region FactorPanel */
+/// A factor of each ticker of a `Dil` on the union of their times. A ticker takes its
+/// last value at the times it has no bar, and NaN before its first one.
+#[derive(Clone, Debug)]
+pub struct FactorPanel {
+    pub t: vdt,
+    pub tickers: Vec<Ticker>,
+    /// A series for each ticker, in the order of `tickers`.
+    pub data: vv32,
+}
+
+impl Dil {
+    /// The series `f` of each `Di`, put on the common times.
+    pub fn factor_panel_with<F>(&self, f: F) -> FactorPanel
+    where
+        F: Fn(&Di) -> v32,
+    {
+        let t: vdt = self.dil.iter().map(|x| &x.pcon.price.t[..]).collect_vec().union_vecs();
+        let data = self.panel_on(&t, f);
+        FactorPanel { t, tickers: self.dil.iter().map(|x| x.pcon.ticker).collect(), data }
+    }
+
+    /// The first output of `pms` on each `Di`, put on the common times.
+    pub fn factor_panel(&self, pms: &Pms) -> FactorPanel {
+        self.factor_panel_with(|di| di.calc(pms)[0].to_vec())
+    }
+
+    /// The value of `cond` at each bar of each `Di`, put on the common times.
+    pub fn factor_panel_loop<T: CondLoop>(&self, cond: &T) -> FactorPanel {
+        self.factor_panel_with(|di| {
+            let mut f = cond.cond(di);
+            (0..di.len()).map(&mut f).collect()
+        })
+    }
+
+    fn panel_on<F>(&self, t: &[dt], f: F) -> vv32
+    where
+        F: Fn(&Di) -> v32,
+    {
+        self.dil
+            .iter()
+            .map(|di| Reindex::new(&di.pcon.price.t[..], t).reindex(&f(di)).ffill(f32::NAN))
+            .collect()
+    }
+}
+
+impl FactorPanel {
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// The values of the tickers at the time `i`.
+    pub fn row(&self, i: usize) -> v32 {
+        self.data.iter().map(|x| x[i]).collect()
+    }
+
+    /// A panel of the same shape, each row from the row of this one.
+    pub fn map_rows<F>(&self, mut f: F) -> FactorPanel
+    where
+        F: FnMut(usize, &[f32]) -> v32,
+    {
+        let mut data = vec![Vec::with_capacity(self.len()); self.tickers.len()];
+        for i in 0..self.len() {
+            let row = f(i, &self.row(i));
+            izip!(data.iter_mut(), row).for_each(|(x, y)| x.push(y));
+        }
+        FactorPanel { t: self.t.clone(), tickers: self.tickers.clone(), data }
+    }
+
+    /// `f` across the tickers at each time, NaN staying NaN. Errs on the params of `f`
+    /// before any row is taken.
+    pub fn xs(&self, f: XsFunc) -> FactorResult<FactorPanel> {
+        f.check()?;
+        let sectors = self.tickers.iter().map(|x| sector_of(*x)).collect_vec();
+        Ok(self.map_rows(|_, row| f.apply_row(row, &sectors)))
+    }
+}
+
+/// The sector of a ticker, `None` for the products `to_section` has no sector for.
+pub fn sector_of(ticker: Ticker) -> Option<Comdty> {
+    match ticker {
+        Ticker::SH | Ticker::UR => None,
+        _ => Some(ticker.to_section()),
+    }
+}
+/* #endregion */
+
+/* #region XsFunc */
+#[ta_derive]
+#[derive(Copy)]
+pub enum XsFunc {
+    /// From 0 for the lowest to 1 for the highest, ties taking their mean rank.
+    Rank,
+    /// Less the mean, over the population standard deviation.
+    Zscore,
+    /// Clipped to the quantiles `q` and `1 - q`, the lower of them taken as the floor.
+    /// `q` is from 0 to 1.
+    Winsorize(f32),
+    /// Less the mean of the sector of the ticker, the tickers of no sector taken as one.
+    Neutralize,
+}
+
+impl XsFunc {
+    /// Errs on a param out of its range.
+    pub fn check(&self) -> FactorResult<()> {
+        match self {
+            XsFunc::Winsorize(q) if !(0. ..=1.).contains(q) => Err(FactorError::Quantile(*q)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, row: &[f32], sectors: &[Option<Comdty>]) -> FactorResult<v32> {
+        self.check()?;
+        Ok(self.apply_row(row, sectors))
+    }
+
+    fn apply_row(&self, row: &[f32], sectors: &[Option<Comdty>]) -> v32 {
+        let valid = (0..row.len()).filter(|i| !row[*i].is_nan()).collect_vec();
+        let mut res = vec![f32::NAN; row.len()];
+        if valid.is_empty() {
+            return res;
+        }
+        match self {
+            XsFunc::Rank => {
+                let mut sorted = valid.clone();
+                sorted.sort_by(|x, y| row[*x].partial_cmp(&row[*y]).unwrap());
+                let scale = (sorted.len() - 1).max(1) as f32;
+                let mut start = 0;
+                while start < sorted.len() {
+                    let mut end = start + 1;
+                    while end < sorted.len() && row[sorted[end]] == row[sorted[start]] {
+                        end += 1;
+                    }
+                    let rank = match sorted.len() {
+                        1 => 0.5,
+                        _ => (start + end - 1) as f32 / 2. / scale,
+                    };
+                    sorted[start..end].iter().for_each(|i| res[*i] = rank);
+                    start = end;
+                }
+            }
+            XsFunc::Zscore => {
+                let values = valid.iter().map(|i| row[*i]).collect_vec();
+                let mean = values.mean();
+                let std = (values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / values.len() as f32).sqrt();
+                valid.iter().for_each(|i| {
+                    res[*i] = if std == 0. { 0. } else { (row[*i] - mean) / std };
+                });
+            }
+            XsFunc::Winsorize(q) => {
+                let q = q.min(1. - q);
+                let mut values = valid.iter().map(|i| row[*i]).collect_vec();
+                values.sort_by(|x, y| x.partial_cmp(y).unwrap());
+                let at = |q: f32| values[((values.len() - 1) as f32 * q).round() as usize];
+                let (lo, hi) = (at(q), at(1. - q));
+                valid.iter().for_each(|i| res[*i] = row[*i].clamp(lo, hi));
+            }
+            XsFunc::Neutralize => {
+                let mut sector_sum: HashMap<Option<Comdty>, (f32, usize)> = HashMap::new();
+                valid.iter().for_each(|i| {
+                    let sum = sector_sum.entry(sectors[*i]).or_default();
+                    sum.0 += row[*i];
+                    sum.1 += 1;
+                });
+                valid.iter().for_each(|i| {
+                    let (sum, n) = sector_sum[&sectors[*i]];
+                    res[*i] = row[*i] - sum / n as f32;
+                });
+            }
+        }
+        res
+    }
+}
+/* #endregion */
+
+/* #region TopN */
+/// Long the `n` highest and short the `n` lowest of a factor, `1 / n` of the capital
+/// each side, chosen again every `every` times and held between. With fewer than `2n`
+/// tickers having a value, each side takes half of them.
+#[ta_derive]
+pub struct TopN {
+    pub n: usize,
+    pub every: usize,
+}
+
+impl TopN {
+    /// The weights of the tickers, positive for long and negative for short.
+    pub fn weights(&self, factor: &FactorPanel) -> FactorPanel {
+        let mut weights = vec![0f32; factor.tickers.len()];
+        factor.map_rows(|i, row| {
+            if i % self.every.max(1) == 0 {
+                let mut valid = (0..row.len()).filter(|i| !row[*i].is_nan()).collect_vec();
+                valid.sort_by(|x, y| row[*x].partial_cmp(&row[*y]).unwrap());
+                let n = self.n.min(valid.len() / 2);
+                weights.iter_mut().for_each(|x| *x = 0.);
+                if n > 0 {
+                    valid[..n].iter().for_each(|i| weights[*i] = -1. / n as f32);
+                    valid[valid.len() - n..].iter().for_each(|i| weights[*i] = 1. / n as f32);
+                }
+            }
+            weights.clone()
+        })
+    }
+}
+/* #endregion */
+
+impl Dil {
+    /// The pnl of each ticker holding `weights` of `capital` from the bar each weight is
+    /// set, the lots sized on the price of that bar and kept until the weight changes.
+    /// `pnl_sum_between_ticker` of it is the pnl of the basket.
+    pub fn basket_pnl(&self, weights: &FactorPanel, capital: f32, comm: CommSlip) -> Vec<InfoPnlRes<Ticker, dt>> {
+        izip!(self.dil.iter(), weights.data.iter())
+            .map(|(di, weight)| {
+                let ticker = di.pcon.ticker;
+                let pv = ticker.info().pv;
+                let c = di.c();
+                let weight = Reindex::new(&weights.t, &di.t()).reindex(weight).fillna(0.);
+                let mut ptm_res: PtmRes = (vec![], vec![], vec![]);
+                let (mut state, mut weight_last) = (NormHold::No, 0f32);
+                for (w, c) in izip!(weight.iter(), c.iter()) {
+                    let hold_now = if *w == weight_last {
+                        state.clone()
+                    } else {
+                        let units = w.abs() * capital / (c * pv);
+                        match w.partial_cmp(&0.) {
+                            Some(std::cmp::Ordering::Greater) => NormHold::Lo(units),
+                            Some(std::cmp::Ordering::Less) => NormHold::Sh(units),
+                            _ => NormHold::No,
+                        }
+                    };
+                    let (open_now, exit_now) = hold_now.sub_norm_hold(&state);
+                    ptm_res.0.push(hold_now.clone());
+                    ptm_res.1.push(open_now);
+                    ptm_res.2.push(exit_now);
+                    (state, weight_last) = (hold_now, *w);
+                }
+                InfoPnlRes(ticker, di.pnl_of_ptm_res(&ptm_res, comm.clone()))
+            })
+            .collect()
+    }
+}
+
+/* #region FactorEval */
+/// How a factor of a `Dil` foresees the returns of its tickers, at each time for each
+/// horizon `h` in `horizons`.
+#[derive(Clone, Debug)]
+pub struct FactorEval {
+    pub t: vdt,
+    pub horizons: Vec<usize>,
+    pub quantiles: usize,
+    /// The rank correlation of the factor with the return of the next `h` times.
+    pub ic: vv32,
+    /// The mean return of the next `h` times of each quantile, lowest first.
+    pub quantile_ret: Vec<vv32>,
+    /// The rank correlation of the factor with itself `h` times before.
+    pub autocorr: vv32,
+    /// The share of the tickers in the top and bottom quantiles that were not `h` times
+    /// before.
+    pub turnover: vv32,
+}
+
+/// The means over time of a `FactorEval`, one for each horizon. `ic` by horizon is the
+/// decay of the factor.
+#[derive(Clone, Debug)]
+pub struct FactorEvalStats {
+    pub horizons: Vec<usize>,
+    pub ic: v32,
+    pub ic_std: v32,
+    pub icir: v32,
+    pub quantile_ret: vv32,
+    pub autocorr: v32,
+    pub turnover: v32,
+}
+
+fn rank_corr(x: &[f32], y: &[f32]) -> f32 {
+    let valid = (0..x.len()).filter(|i| !x[*i].is_nan() && !y[*i].is_nan()).collect_vec();
+    if valid.len() < 3 {
+        return f32::NAN;
+    }
+    let x = XsFunc::Rank.apply_row(&valid.iter().map(|i| x[*i]).collect_vec(), &[]);
+    let y = XsFunc::Rank.apply_row(&valid.iter().map(|i| y[*i]).collect_vec(), &[]);
+    let (x_mean, y_mean) = (x.mean(), y.mean());
+    let (mut xy, mut xx, mut yy) = (0f32, 0f32, 0f32);
+    izip!(x.iter(), y.iter()).for_each(|(x, y)| {
+        xy += (x - x_mean) * (y - y_mean);
+        xx += (x - x_mean).powi(2);
+        yy += (y - y_mean).powi(2);
+    });
+    if xx == 0. || yy == 0. {
+        f32::NAN
+    } else {
+        xy / (xx * yy).sqrt()
+    }
+}
+
+fn nan_mean(data: &[f32]) -> f32 {
+    let data = data.iter().filter(|x| !x.is_nan()).cloned().collect_vec();
+    if data.is_empty() {
+        f32::NAN
+    } else {
+        data.mean()
+    }
+}
+
+fn nan_std(data: &[f32]) -> f32 {
+    let data = data.iter().filter(|x| !x.is_nan()).cloned().collect_vec();
+    if data.len() < 2 {
+        f32::NAN
+    } else {
+        data.std()
+    }
+}
+
+impl FactorPanel {
+    /// The quantile of each ticker at each time, lowest 0, by the rank of its value.
+    /// Panics on no quantiles.
+    pub fn quantile(&self, quantiles: usize) -> Vec<Vec<Option<usize>>> {
+        assert!(quantiles > 0, "factor quantiles of 0");
+        let sectors = vec![];
+        (0..self.len())
+            .map(|i| {
+                XsFunc::Rank
+                    .apply_row(&self.row(i), &sectors)
+                    .into_iter()
+                    .map(|x| match x.is_nan() {
+                        true => None,
+                        false => Some(((x * quantiles as f32) as usize).min(quantiles - 1)),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Dil {
+    /// Evaluates `factor`, a panel of this `Dil`, on the close of the tickers put on its
+    /// times. The return of the next `h` times is NaN for the last `h` of them. Panics on no
+    /// quantiles.
+    pub fn factor_eval(&self, factor: &FactorPanel, horizons: &[usize], quantiles: usize) -> FactorEval {
+        assert!(quantiles > 0, "factor quantiles of 0");
+        let c = self.panel_on(&factor.t, |di| di.c().to_vec());
+        let rows = (0..factor.len()).map(|i| factor.row(i)).collect_vec();
+        let q = factor.quantile(quantiles);
+        let len = factor.len();
+        let mut res = FactorEval {
+            t: factor.t.clone(),
+            horizons: horizons.to_vec(),
+            quantiles,
+            ic: vec![],
+            quantile_ret: vec![],
+            autocorr: vec![],
+            turnover: vec![],
+        };
+        for &h in horizons {
+            let ret = (0..len)
+                .map(|i| {
+                    c.iter()
+                        .map(|c| match i + h < len {
+                            true => c[i + h] / c[i] - 1.,
+                            false => f32::NAN,
+                        })
+                        .collect_vec()
+                })
+                .collect_vec();
+            res.ic.push(izip!(rows.iter(), ret.iter()).map(|(x, y)| rank_corr(x, y)).collect());
+            let mut quantile_ret = vec![vec![f32::NAN; len]; quantiles];
+            for i in 0..len {
+                let mut sums = vec![(0f32, 0usize); quantiles];
+                izip!(q[i].iter(), ret[i].iter()).for_each(|(q, r)| {
+                    if let (Some(q), false) = (q, r.is_nan()) {
+                        sums[*q].0 += r;
+                        sums[*q].1 += 1;
+                    }
+                });
+                izip!(quantile_ret.iter_mut(), sums).for_each(|(x, (sum, n))| {
+                    if n > 0 {
+                        x[i] = sum / n as f32;
+                    }
+                });
+            }
+            res.quantile_ret.push(quantile_ret);
+            let (mut autocorr, mut turnover) = (vec![f32::NAN; len], vec![f32::NAN; len]);
+            for i in h.max(1)..len {
+                autocorr[i] = rank_corr(&rows[i], &rows[i - h]);
+                if q[i - h].iter().all(|x| x.is_none()) {
+                    continue;
+                }
+                let changed = [0, quantiles - 1]
+                    .iter()
+                    .map(|k| {
+                        let now = (0..q[i].len()).filter(|j| q[i][*j] == Some(*k)).collect_vec();
+                        let new = now.iter().filter(|j| q[i - h][**j] != Some(*k)).count();
+                        match now.len() {
+                            0 => f32::NAN,
+                            n => new as f32 / n as f32,
+                        }
+                    })
+                    .collect_vec();
+                turnover[i] = nan_mean(&changed);
+            }
+            res.autocorr.push(autocorr);
+            res.turnover.push(turnover);
+        }
+        res
+    }
+}
+
+impl FactorEval {
+    pub fn stats(&self) -> FactorEvalStats {
+        let ic = self.ic.iter().map(|x| nan_mean(x)).collect_vec();
+        let ic_std = self.ic.iter().map(|x| nan_std(x)).collect_vec();
+        FactorEvalStats {
+            horizons: self.horizons.clone(),
+            icir: izip!(ic.iter(), ic_std.iter()).map(|(x, y)| x / y).collect(),
+            ic,
+            ic_std,
+            quantile_ret: self
+                .quantile_ret
+                .iter()
+                .map(|x| x.iter().map(|x| nan_mean(x)).collect())
+                .collect(),
+            autocorr: self.autocorr.iter().map(|x| nan_mean(x)).collect(),
+            turnover: self.turnover.iter().map(|x| nan_mean(x)).collect(),
+        }
+    }
+
+    /// The return of the top quantile less the bottom one, for each horizon.
+    pub fn spread(&self) -> vv32 {
+        self.quantile_ret
+            .iter()
+            .map(|x| {
+                izip!(x[self.quantiles - 1].iter(), x[0].iter())
+                    .map(|(x, y)| x - y)
+                    .collect()
+            })
+            .collect()
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panel(data: vv32) -> FactorPanel {
+        let t = crate::test_util::days(data[0].len());
+        FactorPanel { t, tickers: vec![Ticker::rb; data.len()], data }
+    }
+
+    #[test]
+    fn quantile_by_rank() {
+        let factor = panel(vec![vec![1.], vec![4.], vec![f32::NAN], vec![2.], vec![3.]]);
+        assert_eq!(factor.quantile(2), vec![vec![Some(0), Some(1), None, Some(0), Some(1)]]);
+        assert_eq!(factor.quantile(1), vec![vec![Some(0), Some(0), None, Some(0), Some(0)]]);
+        assert_eq!(factor.quantile(4), vec![vec![Some(0), Some(3), None, Some(1), Some(2)]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "factor quantiles of 0")]
+    fn quantile_of_none() {
+        panel(vec![vec![1.], vec![2.]]).quantile(0);
+    }
+
+    fn assert_row(res: &[f32], expected: &[f32]) {
+        assert_eq!(res.len(), expected.len());
+        izip!(res, expected).for_each(|(x, y)| {
+            assert!(x.is_nan() && y.is_nan() || (x - y).abs() < 1e-6, "{:?} != {:?}", res, expected);
+        });
+    }
+
+    #[test]
+    fn rank_ties_take_the_mean_rank() {
+        let row = [3., 1., f32::NAN, 3., 2., 3.];
+        assert_row(&XsFunc::Rank.apply(&row, &[]).unwrap(), &[0.75, 0., f32::NAN, 0.75, 0.25, 0.75]);
+        assert_row(&XsFunc::Rank.apply(&[5., 5.], &[]).unwrap(), &[0.5, 0.5]);
+        assert_row(&XsFunc::Rank.apply(&[f32::NAN, 5.], &[]).unwrap(), &[f32::NAN, 0.5]);
+        assert_row(&XsFunc::Rank.apply(&[f32::NAN; 2], &[]).unwrap(), &[f32::NAN; 2]);
+    }
+
+    #[test]
+    fn zscore_on_the_population_std() {
+        let row = [1., f32::NAN, 2., 3., 6.];
+        // mean 3, population std sqrt(14 / 4)
+        let std = 3.5f32.sqrt();
+        assert_row(&XsFunc::Zscore.apply(&row, &[]).unwrap(), &[-2. / std, f32::NAN, -1. / std, 0., 3. / std]);
+        assert_row(&XsFunc::Zscore.apply(&[4., 4., f32::NAN], &[]).unwrap(), &[0., 0., f32::NAN]);
+    }
+
+    #[test]
+    fn winsorize_clips_to_the_quantiles() {
+        let row = [9., 1., 2., 3., f32::NAN, 4., 5., 6., 7., 8., 100., -50.];
+        // 11 values, the 0.1 quantile is the 1st of them sorted and the 0.9 one the 9th
+        let expected = [9., 1., 2., 3., f32::NAN, 4., 5., 6., 7., 8., 9., 1.];
+        assert_row(&XsFunc::Winsorize(0.1).apply(&row, &[]).unwrap(), &expected);
+        assert_row(&XsFunc::Winsorize(0.9).apply(&row, &[]).unwrap(), &expected);
+        assert_row(&XsFunc::Winsorize(0.).apply(&row, &[]).unwrap(), &row);
+        assert_row(&XsFunc::Winsorize(0.5).apply(&row, &[]).unwrap(), &[5., 5., 5., 5., f32::NAN, 5., 5., 5., 5., 5., 5., 5.]);
+    }
+
+    #[test]
+    fn winsorize_out_of_range() {
+        for q in [f32::NAN, 1.5, -0.1] {
+            let err = XsFunc::Winsorize(q).apply(&[1., 2.], &[]).unwrap_err();
+            assert_eq!(err.to_string(), format!("winsorize quantile {} out of 0 to 1", q));
+            assert!(panel(vec![vec![1.], vec![2.]]).xs(XsFunc::Winsorize(q)).is_err());
+        }
+        assert!(panel(vec![vec![1.], vec![2.]]).xs(XsFunc::Winsorize(1.)).is_ok());
+    }
+
+    #[test]
+    fn neutralize_by_sector() {
+        let row = [1., 3., f32::NAN, 10., 4., 2., 6.];
+        let sectors = [Ticker::rb, Ticker::hc, Ticker::i, Ticker::c, Ticker::cs, Ticker::SH, Ticker::UR].map(sector_of);
+        assert_eq!(sectors[0], Some(Comdty::BlackMaterial));
+        assert_eq!(sectors[3], Some(Comdty::Ceral));
+        assert_eq!(sectors[5..], [None, None]);
+        assert_row(&XsFunc::Neutralize.apply(&row, &sectors).unwrap(), &[-1., 1., f32::NAN, 3., -3., -2., 2.]);
+    }
+
+    #[test]
+    fn top_n_on_few_tickers() {
+        let top_n = TopN { n: 2, every: 1 };
+        let factor = panel(vec![
+            vec![1., 1., f32::NAN],
+            vec![4., 4., f32::NAN],
+            vec![f32::NAN, 3., f32::NAN],
+            vec![2., f32::NAN, 5.],
+            vec![3., f32::NAN, f32::NAN],
+        ]);
+        let weights = top_n.weights(&factor);
+        // 4 valid take 2 each side, 3 valid take 1 each side, 1 valid takes none
+        assert_eq!(weights.row(0), vec![-0.5, 0.5, 0., -0.5, 0.5]);
+        assert_eq!(weights.row(1), vec![-1., 1., 0., 0., 0.]);
+        assert_eq!(weights.row(2), vec![0.; 5]);
+    }
+
+    #[test]
+    fn top_n_holds_between_rebalances() {
+        let top_n = TopN { n: 1, every: 2 };
+        let factor = panel(vec![vec![1., 3., 3.], vec![2., 1., 1.]]);
+        let weights = top_n.weights(&factor);
+        assert_eq!(weights.data, vec![vec![-1., -1., 1.], vec![1., 1., -1.]]);
+    }
+}
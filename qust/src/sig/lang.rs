@@ -0,0 +1,785 @@
+/*!
+Text for the conditions and the strategies, parsed into the types of `sig::cond` and
+`sig::livesig` and printed back from them.
+
+```text
+dire: lo;
+money: m1(1);
+open: cross_up(ma(c, 5), ma(c, 20)) & atr(14) > 2 * tz;
+exit: bars_since_open > 30 | c < donchian(20)[0]
+```
+
+The series are `o`, `h`, `l`, `c`, `v`, the tick size `tz`, numbers, `+ - * /`, the
+series functions `ma std max min sum ema lag diff` with the series first, and the
+indicators of `expr_ta`, with `[i]` taking one of their outputs. `a > b` and `a < b`
+are a `BandCond` lieing on `a - b`, `cross_up(a, b)` and `cross_down(a, b)` a
+`CrossCond`, `& | !` a `MsigType` or `NotCond`, and `bars_since_open` compared with a
+number a `BarsSinceOpen`. `a >= b` is `!(a < b)`, and is printed so. `#` comments to
+the end of the line.
+*/
+use crate::idct::prelude::*;
+use crate::prelude::{ono, ori};
+use crate::sig::{cond::*, distra::Stra, livesig::*, posi::*};
+use crate::trade::di::Di;
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum ExprError {
+    #[error("parse error at {0}: {1}")]
+    Parse(TextPos, String),
+    #[error("type error at {0}: {1}")]
+    Type(TextPos, String),
+    #[error("no text for {0}")]
+    Print(String),
+}
+
+pub type ExprResult<T> = Result<T, ExprError>;
+
+/// Line and column in the text, from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextPos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for TextPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.col)
+    }
+}
+
+/// How tightly the text of a condition binds, from the loosest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TextPrec {
+    Or,
+    And,
+    Not,
+    Cmp,
+    Call,
+}
+
+/* #region BarsSinceOpen */
+#[ta_derive]
+#[derive(PartialEq, Eq, Copy)]
+pub enum CmpOps {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl CmpOps {
+    pub fn compare<T: PartialOrd>(self, x: T, y: T) -> bool {
+        match self {
+            CmpOps::Gt => x > y,
+            CmpOps::Ge => x >= y,
+            CmpOps::Lt => x < y,
+            CmpOps::Le => x <= y,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            CmpOps::Gt => ">",
+            CmpOps::Ge => ">=",
+            CmpOps::Lt => "<",
+            CmpOps::Le => "<=",
+        }
+    }
+
+    /// The same comparison with the sides swapped.
+    fn flip(self) -> Self {
+        match self {
+            CmpOps::Gt => CmpOps::Lt,
+            CmpOps::Ge => CmpOps::Le,
+            CmpOps::Lt => CmpOps::Gt,
+            CmpOps::Le => CmpOps::Ge,
+        }
+    }
+}
+
+/// The bars since the open bar compared with a number, for the exit of a `Tsig`.
+#[ta_derive]
+pub struct BarsSinceOpen(pub CmpOps, pub usize);
+
+#[typetag::serde]
+impl Cond for BarsSinceOpen {
+    fn cond<'a>(&self, _di: &'a Di) -> LoopSig<'a> {
+        let (f, n) = (self.0, self.1);
+        Box::new(move |e, o| f.compare(e.saturating_sub(o), n))
+    }
+    fn text(&self) -> Option<String> {
+        Some(format!("bars_since_open {} {}", self.0.symbol(), self.1))
+    }
+    fn precedence(&self) -> TextPrec {
+        TextPrec::Cmp
+    }
+}
+/* #endregion */
+
+/* #region lexer */
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f32),
+    Ident(String),
+    Sym(&'static str),
+    End,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(x) => write!(f, "`{}`", x),
+            Token::Ident(x) => write!(f, "`{}`", x),
+            Token::Sym(x) => write!(f, "`{}`", x),
+            Token::End => write!(f, "the end"),
+        }
+    }
+}
+
+const syms: [&str; 18] = [
+    ">=", "<=", "(", ")", "[", "]", ",", ";", ":", "&", "|", "!", "+", "-", "*", "/", ">", "<",
+];
+
+fn text_pos(text: &str, at: usize) -> TextPos {
+    let before = &text[..at];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map(|x| x + 1).unwrap_or(0) + 1;
+    TextPos { line, col }
+}
+
+fn lex(text: &str) -> ExprResult<Vec<(Token, usize)>> {
+    let mut res = vec![];
+    let mut i = 0;
+    let bytes = text.as_bytes();
+    while i < bytes.len() {
+        let ch = bytes[i] as char;
+        if ch.is_whitespace() {
+            i += 1;
+        } else if ch == '#' {
+            i = text[i..].find('\n').map(|x| i + x).unwrap_or(bytes.len());
+        } else if ch.is_ascii_digit() || ch == '.' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            let num = text[start..i].parse::<f32>().map_err(|_| {
+                ExprError::Parse(text_pos(text, start), format!("bad number `{}`", &text[start..i]))
+            })?;
+            res.push((Token::Num(num), start));
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            res.push((Token::Ident(text[start..i].to_string()), start));
+        } else {
+            let sym = syms.iter().find(|x| text[i..].starts_with(*x)).ok_or_else(|| {
+                ExprError::Parse(text_pos(text, i), format!("unexpected `{}`", ch))
+            })?;
+            res.push((Token::Sym(sym), i));
+            i += sym.len();
+        }
+    }
+    res.push((Token::End, text.len()));
+    Ok(res)
+}
+/* #endregion */
+
+/* #region parser */
+#[derive(Clone, Debug)]
+enum Ast {
+    Num(f32),
+    Ident(String),
+    Call(String, Vec<Node>, Option<usize>),
+    Neg(Box<Node>),
+    Arith(ArithOps, Box<Node>, Box<Node>),
+    Cmp(CmpOps, Box<Node>, Box<Node>),
+    Logic(LogicOps, Box<Node>, Box<Node>),
+    Not(Box<Node>),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    ast: Ast,
+    at: usize,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, usize)>,
+    i: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> ExprResult<Self> {
+        Ok(Self { text, tokens: lex(text)?, i: 0 })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.i].0
+    }
+
+    fn at(&self) -> usize {
+        self.tokens[self.i].1
+    }
+
+    fn next(&mut self) -> Token {
+        let res = self.tokens[self.i].0.clone();
+        if res != Token::End {
+            self.i += 1;
+        }
+        res
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        let res = matches!(self.peek(), Token::Sym(x) if *x == sym);
+        if res {
+            self.i += 1;
+        }
+        res
+    }
+
+    fn err<T>(&self, msg: String) -> ExprResult<T> {
+        Err(ExprError::Parse(text_pos(self.text, self.at()), msg))
+    }
+
+    fn expect(&mut self, sym: &str) -> ExprResult<()> {
+        match self.eat(sym) {
+            true => Ok(()),
+            false => self.err(format!("expected `{}`, found {}", sym, self.peek())),
+        }
+    }
+
+    fn or(&mut self) -> ExprResult<Node> {
+        let mut res = self.and()?;
+        while self.eat("|") {
+            let at = res.at;
+            let rhs = self.and()?;
+            res = Node { ast: Ast::Logic(LogicOps::Or, res.into(), rhs.into()), at };
+        }
+        Ok(res)
+    }
+
+    fn and(&mut self) -> ExprResult<Node> {
+        let mut res = self.not()?;
+        while self.eat("&") {
+            let at = res.at;
+            let rhs = self.not()?;
+            res = Node { ast: Ast::Logic(LogicOps::And, res.into(), rhs.into()), at };
+        }
+        Ok(res)
+    }
+
+    fn not(&mut self) -> ExprResult<Node> {
+        let at = self.at();
+        if self.eat("!") {
+            return Ok(Node { ast: Ast::Not(self.not()?.into()), at });
+        }
+        self.cmp()
+    }
+
+    fn cmp(&mut self) -> ExprResult<Node> {
+        let res = self.sum()?;
+        let ops = match self.peek() {
+            Token::Sym(">") => CmpOps::Gt,
+            Token::Sym(">=") => CmpOps::Ge,
+            Token::Sym("<") => CmpOps::Lt,
+            Token::Sym("<=") => CmpOps::Le,
+            _ => return Ok(res),
+        };
+        self.next();
+        let at = res.at;
+        let rhs = self.sum()?;
+        Ok(Node { ast: Ast::Cmp(ops, res.into(), rhs.into()), at })
+    }
+
+    fn sum(&mut self) -> ExprResult<Node> {
+        let mut res = self.prod()?;
+        loop {
+            let ops = match self.peek() {
+                Token::Sym("+") => ArithOps::Add,
+                Token::Sym("-") => ArithOps::Sub,
+                _ => return Ok(res),
+            };
+            self.next();
+            let at = res.at;
+            let rhs = self.prod()?;
+            res = Node { ast: Ast::Arith(ops, res.into(), rhs.into()), at };
+        }
+    }
+
+    fn prod(&mut self) -> ExprResult<Node> {
+        let mut res = self.neg()?;
+        loop {
+            let ops = match self.peek() {
+                Token::Sym("*") => ArithOps::Mul,
+                Token::Sym("/") => ArithOps::Div,
+                _ => return Ok(res),
+            };
+            self.next();
+            let at = res.at;
+            let rhs = self.neg()?;
+            res = Node { ast: Ast::Arith(ops, res.into(), rhs.into()), at };
+        }
+    }
+
+    fn neg(&mut self) -> ExprResult<Node> {
+        let at = self.at();
+        if self.eat("-") {
+            return Ok(Node { ast: Ast::Neg(self.neg()?.into()), at });
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> ExprResult<Node> {
+        let at = self.at();
+        match self.next() {
+            Token::Num(x) => Ok(Node { ast: Ast::Num(x), at }),
+            Token::Sym("(") => {
+                let res = self.or()?;
+                self.expect(")")?;
+                Ok(res)
+            }
+            Token::Ident(name) => match self.eat("(") {
+                true => self.call(name, at),
+                false => Ok(Node { ast: Ast::Ident(name), at }),
+            },
+            token => {
+                self.i -= (token != Token::End) as usize;
+                self.err(format!("expected a value, found {}", token))
+            }
+        }
+    }
+
+    fn call(&mut self, name: String, at: usize) -> ExprResult<Node> {
+        let mut args = vec![];
+        if !self.eat(")") {
+            loop {
+                args.push(self.or()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let i = match self.eat("[") {
+            true => match self.next() {
+                Token::Num(x) if x.fract() == 0. => {
+                    self.expect("]")?;
+                    Some(x as usize)
+                }
+                token => {
+                    self.i -= (token != Token::End) as usize;
+                    return self.err(format!("expected an output index, found {}", token));
+                }
+            },
+            false => None,
+        };
+        Ok(Node { ast: Ast::Call(name, args, i), at })
+    }
+
+    fn finish(&self) -> ExprResult<()> {
+        match self.peek() {
+            Token::End => Ok(()),
+            token => self.err(format!("unexpected {}", token)),
+        }
+    }
+}
+/* #endregion */
+
+/* #region types */
+fn type_err<T>(text: &str, node: &Node, msg: String) -> ExprResult<T> {
+    Err(ExprError::Type(text_pos(text, node.at), msg))
+}
+
+fn to_expr(text: &str, node: &Node) -> ExprResult<Expr> {
+    let res = match &node.ast {
+        Ast::Num(x) => Expr::Num(*x),
+        Ast::Ident(x) if expr_vars.contains(&x.as_str()) => Expr::Var(x.clone()),
+        Ast::Ident(x) if x == "bars_since_open" => {
+            return type_err(text, node, "`bars_since_open` is only compared with a number".into())
+        }
+        Ast::Ident(x) => return type_err(text, node, format!("unknown series `{}`", x)),
+        Ast::Call(name, _, _) if name == "cross_up" || name == "cross_down" => {
+            return type_err(text, node, format!("expected a series, found the condition `{}`", name))
+        }
+        Ast::Call(name, args, i) if expr_series_fns.contains(&name.as_str()) => {
+            if args.len() != 2 {
+                return type_err(text, node, format!("`{}` takes a series and a number", name));
+            }
+            if i.is_some() {
+                return type_err(text, node, format!("`{}` has one output", name));
+            }
+            let n = to_num(text, &args[1])?;
+            if n < 1. || n.fract() != 0. {
+                return type_err(text, &args[1], format!("`{}` takes a whole number of bars", name));
+            }
+            Expr::Call(name.clone(), vec![to_expr(text, &args[0])?, Expr::Num(n)], None)
+        }
+        Ast::Call(name, args, i) => {
+            let nums = args.iter().map(|x| to_num(text, x)).collect::<ExprResult<Vec<_>>>()?;
+            let n_outputs = match expr_ta(name, &nums) {
+                Some(Ok((_, n_outputs, _))) => n_outputs,
+                Some(Err(i)) => {
+                    return type_err(text, &args[i], format!("`{}` takes a whole number of bars", name))
+                }
+                None => {
+                    return type_err(text, node, format!("no indicator `{}` taking {} numbers", name, nums.len()))
+                }
+            };
+            if let Some(i) = i {
+                if *i >= n_outputs {
+                    return type_err(text, node, format!("`{}` has {} outputs, no [{}]", name, n_outputs, i));
+                }
+            }
+            Expr::Call(name.clone(), nums.into_iter().map(Expr::Num).collect(), *i)
+        }
+        Ast::Neg(x) => Expr::Neg(to_expr(text, x)?.into()),
+        Ast::Arith(ops, x, y) => Expr::Bin(*ops, to_expr(text, x)?.into(), to_expr(text, y)?.into()),
+        Ast::Cmp(..) | Ast::Logic(..) | Ast::Not(..) => {
+            return type_err(text, node, "expected a series, found a condition".into())
+        }
+    };
+    Ok(res)
+}
+
+fn to_num(text: &str, node: &Node) -> ExprResult<f32> {
+    match to_expr(text, node)?.num() {
+        Some(x) => Ok(x),
+        None => type_err(text, node, "expected a number, found a series".into()),
+    }
+}
+
+fn expr_pms(exprs: Vec<Expr>) -> Pms {
+    PmsType { dcon: ori, part: ono, fore: Box::new(ExprTa(exprs)) }
+}
+
+fn to_cond(text: &str, node: &Node) -> ExprResult<CondBox> {
+    let res = match &node.ast {
+        Ast::Logic(ops, x, y) => MsigType(*ops, to_cond(text, x)?, to_cond(text, y)?).cond_box(),
+        Ast::Not(x) => !to_cond(text, x)?,
+        Ast::Cmp(ops, x, y) => {
+            let bars = |node: &Node| matches!(&node.ast, Ast::Ident(x) if x == "bars_since_open");
+            let bars_since_open = |ops: CmpOps, node: &Node| -> ExprResult<CondBox> {
+                let n = to_num(text, node)?;
+                match n >= 0. && n.fract() == 0. {
+                    true => Ok(BarsSinceOpen(ops, n as usize).cond_box()),
+                    false => type_err(text, node, "expected a whole number of bars".into()),
+                }
+            };
+            match (bars(x), bars(y)) {
+                (true, false) => bars_since_open(*ops, y)?,
+                (false, true) => bars_since_open(ops.flip(), x)?,
+                _ => {
+                    let diff = Expr::Bin(ArithOps::Sub, to_expr(text, x)?.into(), to_expr(text, y)?.into());
+                    let band = |dire| BandCond(dire, BandState::Lieing, expr_pms(vec![diff.clone()])).cond_box();
+                    match ops {
+                        CmpOps::Gt => band(Dire::Lo),
+                        CmpOps::Lt => band(Dire::Sh),
+                        CmpOps::Ge => !band(Dire::Sh),
+                        CmpOps::Le => !band(Dire::Lo),
+                    }
+                }
+            }
+        }
+        Ast::Call(name, args, _) if name == "cross_up" || name == "cross_down" => {
+            if args.len() != 2 {
+                return type_err(text, node, format!("`{}` takes two series", name));
+            }
+            let dire = if name == "cross_up" { Dire::Lo } else { Dire::Sh };
+            let exprs = vec![to_expr(text, &args[0])?, to_expr(text, &args[1])?];
+            CrossCond(dire, expr_pms(exprs)).cond_box()
+        }
+        _ => return type_err(text, node, "expected a condition, found a series".into()),
+    };
+    Ok(res)
+}
+/* #endregion */
+
+/// Parses the text of a condition.
+pub fn parse_cond(text: &str) -> ExprResult<CondBox> {
+    let mut parser = Parser::new(text)?;
+    let node = parser.or()?;
+    parser.finish()?;
+    to_cond(text, &node)
+}
+
+fn parse_money(text: &str, node: &Node) -> ExprResult<Box<dyn Money>> {
+    if let Ast::Call(name, args, None) = &node.ast {
+        if args.len() == 1 {
+            let x = to_num(text, &args[0])?;
+            match name.as_str() {
+                "m1" => return Ok(Box::new(M1(x))),
+                "m2" => return Ok(Box::new(M2(x))),
+                "m3" => return Ok(Box::new(M3(x))),
+                _ => {}
+            }
+        }
+    }
+    type_err(text, node, "expected `m1(x)`, `m2(x)` or `m3(x)`".into())
+}
+
+/// `dire` and `money` are `lo` and `m1(1)` when left out, `open` and `exit` are needed.
+impl FromStr for Ptm {
+    type Err = ExprError;
+
+    fn from_str(text: &str) -> ExprResult<Self> {
+        let mut parser = Parser::new(text)?;
+        let (mut dire, mut money, mut open, mut exit) = (None, None, None, None);
+        while *parser.peek() != Token::End {
+            let at = parser.at();
+            let key = match parser.next() {
+                Token::Ident(x) => x,
+                token => {
+                    parser.i -= (token != Token::End) as usize;
+                    return parser.err(format!("expected `dire`, `money`, `open` or `exit`, found {}", token));
+                }
+            };
+            parser.expect(":")?;
+            let node = parser.or()?;
+            let is_set = match key.as_str() {
+                "dire" => match &node.ast {
+                    Ast::Ident(x) if x == "lo" => dire.replace(Dire::Lo).is_some(),
+                    Ast::Ident(x) if x == "sh" => dire.replace(Dire::Sh).is_some(),
+                    _ => return type_err(text, &node, "expected `lo` or `sh`".into()),
+                },
+                "money" => money.replace(parse_money(text, &node)?).is_some(),
+                "open" => open.replace(to_cond(text, &node)?).is_some(),
+                "exit" => exit.replace(to_cond(text, &node)?).is_some(),
+                _ => return Err(ExprError::Parse(text_pos(text, at), format!("unknown key `{}`", key))),
+            };
+            if is_set {
+                return Err(ExprError::Parse(text_pos(text, at), format!("`{}` is given twice", key)));
+            }
+            if *parser.peek() != Token::End {
+                parser.expect(";")?;
+            }
+        }
+        let end = text_pos(text, text.len());
+        let open = open.ok_or_else(|| ExprError::Parse(end, "no `open`".into()))?;
+        let exit = exit.ok_or_else(|| ExprError::Parse(end, "no `exit`".into()))?;
+        let dire = dire.unwrap_or(Dire::Lo);
+        let money = money.unwrap_or_else(|| Box::new(M1(1.)));
+        Ok(Ptm::Ptm1(money, Stp::Stp(Tsig::Tsig(dire, !dire, open, exit))))
+    }
+}
+
+/* #region printer */
+/// The `Expr`s of a `Pms` made by the text, on the bars as they are.
+pub fn pms_exprs(pms: &Pms) -> Option<Vec<Expr>> {
+    if pms.dcon.debug_string() != ori.debug_string() || pms.part != ono {
+        return None;
+    }
+    pms.fore.exprs()
+}
+
+/// Prints the text that parses back into the same thing.
+pub trait ToText {
+    fn to_text(&self) -> ExprResult<String>;
+}
+
+impl ToText for CondBox {
+    fn to_text(&self) -> ExprResult<String> {
+        self.text().ok_or_else(|| ExprError::Print(self.debug_string()))
+    }
+}
+
+fn money_text(money: &dyn Money) -> ExprResult<String> {
+    let money_str = money.debug_string();
+    let res = ["M1(", "M2(", "M3("].iter().find_map(|x| {
+        let num = money_str.strip_prefix(x)?.strip_suffix(')')?.parse::<f32>().ok()?;
+        Some(format!("{}{})", x.to_lowercase(), num))
+    });
+    res.ok_or(ExprError::Print(money_str))
+}
+
+impl ToText for Ptm {
+    fn to_text(&self) -> ExprResult<String> {
+        let (money, dire, open, exit) = match self {
+            Ptm::Ptm1(money, Stp::Stp(Tsig::Tsig(dire, exit_dire, open, exit))) if *exit_dire == !*dire => {
+                (money, dire, open, exit)
+            }
+            _ => return Err(ExprError::Print(self.debug_string())),
+        };
+        let dire = match dire {
+            Dire::Lo => "lo",
+            Dire::Sh => "sh",
+        };
+        Ok(format!(
+            "dire: {};\nmoney: {};\nopen: {};\nexit: {}",
+            dire,
+            money_text(&**money)?,
+            open.to_text()?,
+            exit.to_text()?,
+        ))
+    }
+}
+
+impl ToText for Stra {
+    fn to_text(&self) -> ExprResult<String> {
+        self.ptm.to_text()
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_err_at(text: &str) -> (TextPos, String) {
+        match parse_cond(text) {
+            Err(ExprError::Type(pos, msg)) => (pos, msg),
+            other => panic!("{}: {:?}", text, other.map(|x| x.debug_string())),
+        }
+    }
+
+    #[test]
+    fn periods_are_whole_bars() {
+        for (text, col) in [("c > atr(0)", 9), ("c > atr(-1)", 9), ("c > atr(2.5)", 9), ("c > keltner(20, 0, 2)[0]", 17)] {
+            let (pos, msg) = type_err_at(text);
+            assert_eq!(pos, TextPos { line: 1, col }, "{}", text);
+            assert!(msg.ends_with("takes a whole number of bars"), "{}: {}", text, msg);
+        }
+        assert!(parse_cond("c > keltner(20, 10, 1.5)[0] & c > boll(20, 2.5)[2]").is_ok());
+        assert!(parse_cond("sar(0.02, 0.2) > c").is_ok());
+    }
+
+    #[test]
+    fn brackets_by_precedence() {
+        let text = |x: &str| parse_cond(x).unwrap().to_text().unwrap();
+        assert_eq!(text("(c > o | c > h) & c > l"), "(c > o | c > h) & c > l");
+        assert_eq!(text("c > o | (c > h & c > l)"), "c > o | c > h & c > l");
+        assert_eq!(text("c > o & (c > h & c > l)"), "c > o & (c > h & c > l)");
+        assert_eq!(text("(c > o & c > h) & c > l"), "c > o & c > h & c > l");
+        assert_eq!(text("!cross_up(c, o) & !(c > o | c > h)"), "!cross_up(c, o) & !(c > o | c > h)");
+        assert_eq!(text("!c < o"), "!(c < o)");
+        assert_eq!(text("c >= o"), "!(c < o)");
+        assert_eq!(text("!!(bars_since_open > 3)"), "!!(bars_since_open > 3)");
+    }
+
+    fn var(x: &str) -> Expr {
+        Expr::Var(x.into())
+    }
+
+    fn call(name: &str, args: Vec<Expr>, i: Option<usize>) -> Expr {
+        Expr::Call(name.into(), args, i)
+    }
+
+    fn bin(ops: ArithOps, x: Expr, y: Expr) -> Expr {
+        Expr::Bin(ops, x.into(), y.into())
+    }
+
+    fn cmp(dire: Dire, x: Expr, y: Expr) -> CondBox {
+        BandCond(dire, BandState::Lieing, expr_pms(vec![bin(ArithOps::Sub, x, y)])).cond_box()
+    }
+
+    /// The condition parsed from its text is the same condition.
+    fn round_trip(cond: CondBox) -> String {
+        let text = cond.to_text().unwrap();
+        assert_eq!(parse_cond(&text).unwrap().debug_string(), cond.debug_string(), "{}", text);
+        text
+    }
+
+    #[test]
+    fn exprs_round_trip() {
+        let ma = |n| call("ma", vec![var("c"), Expr::Num(n)], None);
+        let exprs = [
+            bin(ArithOps::Sub, ma(5.), ma(20.)),
+            bin(ArithOps::Mul, Expr::Num(2.), var("tz")),
+            bin(ArithOps::Div, Expr::Neg(bin(ArithOps::Sub, var("c"), var("o")).into()), bin(ArithOps::Sub, var("h"), var("l"))),
+            bin(ArithOps::Sub, var("c"), bin(ArithOps::Sub, var("o"), var("l"))),
+            bin(ArithOps::Add, call("boll", vec![Expr::Num(20.), Expr::Num(2.5)], Some(2)), call("atr", vec![Expr::Num(14.)], None)),
+            call("lag", vec![call("adx", vec![Expr::Num(14.)], Some(0)), Expr::Num(1.)], None),
+            call("sar", vec![Expr::Num(0.02), Expr::Num(0.2)], None),
+        ];
+        for expr in exprs {
+            let text = round_trip(cmp(Dire::Lo, var("c"), expr.clone()));
+            assert_eq!(text, format!("c > {}", expr));
+        }
+    }
+
+    #[test]
+    fn conds_round_trip() {
+        let (c, o, h) = (var("c"), var("o"), var("h"));
+        let cross = CrossCond(Dire::Sh, expr_pms(vec![c.clone(), call("ema", vec![c.clone(), Expr::Num(10.)], None)])).cond_box();
+        let gt = cmp(Dire::Lo, c.clone(), o.clone());
+        let lt = cmp(Dire::Sh, c.clone(), h.clone());
+        let bars = BarsSinceOpen(CmpOps::Ge, 30).cond_box();
+        let and = |x: &CondBox, y: &CondBox| MsigType(LogicOps::And, x.clone(), y.clone()).cond_box();
+        let or = |x: &CondBox, y: &CondBox| MsigType(LogicOps::Or, x.clone(), y.clone()).cond_box();
+        let not = |x: &CondBox| !x.clone();
+        assert_eq!(round_trip(cross.clone()), "cross_down(c, ema(c, 10))");
+        assert_eq!(round_trip(bars.clone()), "bars_since_open >= 30");
+        round_trip(and(&gt, &lt));
+        round_trip(or(&and(&gt, &lt), &bars));
+        round_trip(and(&or(&gt, &lt), &not(&cross)));
+        round_trip(and(&gt, &and(&lt, &bars)));
+        round_trip(or(&gt, &or(&lt, &bars)));
+        round_trip(not(&not(&gt)));
+        round_trip(not(&or(&cross, &and(&gt, &not(&lt)))));
+    }
+
+    #[test]
+    fn ptm_round_trip() {
+        let open = MsigType(LogicOps::And, cmp(Dire::Lo, var("c"), var("o")), BarsSinceOpen(CmpOps::Lt, 5).cond_box()).cond_box();
+        let exit = !cmp(Dire::Sh, var("c"), call("donchian", vec![Expr::Num(20.)], Some(0)));
+        let ptm = Ptm::Ptm1(Box::new(M2(1.5)), Stp::Stp(Tsig::Tsig(Dire::Sh, Dire::Lo, open, exit)));
+        let text = ptm.to_text().unwrap();
+        assert_eq!(text, "dire: sh;\nmoney: m2(1.5);\nopen: c > o & bars_since_open < 5;\nexit: !(c < donchian(20)[0])");
+        assert_eq!(text.parse::<Ptm>().unwrap().debug_string(), ptm.debug_string());
+        let ptm_default = "open: c > o; exit: c < o".parse::<Ptm>().unwrap();
+        assert_eq!(ptm_default.to_text().unwrap(), "dire: lo;\nmoney: m1(1);\nopen: c > o;\nexit: c < o");
+        let not_text = Ptm::Ptm1(Box::new(M1(1.)), Stp::Stp(Tsig::Tsig(Dire::Lo, Dire::Lo, cmp(Dire::Lo, var("c"), var("o")), cmp(Dire::Lo, var("c"), var("o")))));
+        assert!(matches!(not_text.to_text(), Err(ExprError::Print(_))));
+    }
+
+    fn ptm_err(text: &str) -> ExprError {
+        match text.parse::<Ptm>() {
+            Err(e) => e,
+            Ok(ptm) => panic!("{} parsed into {}", text, ptm.debug_string()),
+        }
+    }
+
+    #[test]
+    fn errors_at_the_line_and_col() {
+        let at = |line, col| TextPos { line, col };
+        let parse_err = |text: &str| match parse_cond(text) {
+            Err(ExprError::Parse(pos, msg)) => (pos, msg),
+            other => panic!("{}: {:?}", text, other.map(|x| x.debug_string())),
+        };
+        assert_eq!(parse_err("c > o $ h"), (at(1, 7), "unexpected `$`".into()));
+        assert_eq!(parse_err("c > (o"), (at(1, 7), "expected `)`, found the end".into()));
+        assert_eq!(parse_err("c >\n  & o"), (at(2, 3), "expected a value, found `&`".into()));
+        assert_eq!(parse_err("c > o o"), (at(1, 7), "unexpected `o`".into()));
+        assert_eq!(parse_err("c > 1..2"), (at(1, 5), "bad number `1..2`".into()));
+        assert_eq!(parse_err("c > boll(20, 2)[1.5]"), (at(1, 17), "expected an output index, found `1.5`".into()));
+
+        assert_eq!(type_err_at("c + 1"), (at(1, 1), "expected a condition, found a series".into()));
+        assert_eq!(type_err_at("c > foo"), (at(1, 5), "unknown series `foo`".into()));
+        assert_eq!(type_err_at("c > ma(c, 0)"), (at(1, 11), "`ma` takes a whole number of bars".into()));
+        assert_eq!(type_err_at("c > ma(c, o)"), (at(1, 11), "expected a number, found a series".into()));
+        assert_eq!(type_err_at("c > boll(20, 2)[3]"), (at(1, 5), "`boll` has 3 outputs, no [3]".into()));
+        assert_eq!(type_err_at("c > rsi(1, 2)"), (at(1, 5), "no indicator `rsi` taking 2 numbers".into()));
+        assert_eq!(type_err_at("cross_up(c, o) > c"), (at(1, 1), "expected a series, found the condition `cross_up`".into()));
+        assert_eq!(type_err_at("c > o &\n bars_since_open > 2.5"), (at(2, 20), "expected a whole number of bars".into()));
+        assert_eq!(type_err_at("c > o & c"), (at(1, 9), "expected a condition, found a series".into()));
+
+        let err = ptm_err("dire: lo;\nmoney: m1(1);\nopen: c > foo;\nexit: c < o");
+        assert_eq!(err.to_string(), "type error at line 3, col 11: unknown series `foo`");
+        let err = ptm_err("open: c > o\nexit: c < o");
+        assert_eq!(err.to_string(), "parse error at line 2, col 1: expected `;`, found `exit`");
+        let err = ptm_err("open: c > o;\nopen: c < o");
+        assert_eq!(err.to_string(), "parse error at line 2, col 1: `open` is given twice");
+        let err = ptm_err("# no exit\nopen: c > o");
+        assert_eq!(err.to_string(), "parse error at line 2, col 12: no `exit`");
+        let err = ptm_err("dire: up; open: c > o; exit: c < o");
+        assert_eq!(err.to_string(), "type error at line 1, col 7: expected `lo` or `sh`");
+        let err = ptm_err("open: c > o;\n  (c > o)");
+        assert_eq!(err.to_string(), "parse error at line 2, col 3: expected `dire`, `money`, `open` or `exit`, found `(`");
+        let err = ptm_err("open: c > o; exit: c < o; size: 1");
+        assert_eq!(err.to_string(), "parse error at line 1, col 27: unknown key `size`");
+    }
+}
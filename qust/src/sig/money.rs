@@ -0,0 +1,408 @@
+use crate::loge;
+use crate::prelude::{dayk, ono, pct, vori};
+use crate::idct::prelude::*;
+use crate::sig::{livesig::Ptm, posi::*};
+use crate::trade::{di::Di, ticker::Ticker};
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::RwLock;
+
+/* #region LotRound */
+/// Rounds a size down to whole lots, and to none below `min_lot`, as the live orders
+/// take them. Warns once for each run of bars a held position is rounded to nothing.
+struct LotRound {
+    ticker: Ticker,
+    min_lot: f32,
+    last_zero: Cell<Option<usize>>,
+}
+
+impl LotRound {
+    fn new(di: &Di, min_lot: f32) -> Self {
+        Self { ticker: di.pcon.ticker, min_lot: min_lot.max(1.), last_zero: Cell::new(None) }
+    }
+
+    fn hold(&self, x: &NormHold, lots: f32, i: usize) -> NormHold {
+        if let NormHold::No = x {
+            return NormHold::No;
+        }
+        if !lots.is_finite() || lots <= 0. {
+            return NormHold::No;
+        }
+        let lots_round = lots.floor();
+        if lots_round < self.min_lot {
+            if self.last_zero.get().map(|x| x + 1) != Some(i) {
+                loge!(
+                    level: Warn,
+                    self.ticker,
+                    "position of {:.2} lots rounded to nothing at bar {}, min lot {}",
+                    lots,
+                    i,
+                    self.min_lot
+                );
+            }
+            self.last_zero.set(Some(i));
+            return NormHold::No;
+        }
+        match x {
+            NormHold::Lo(_) => NormHold::Lo(lots_round),
+            NormHold::Sh(_) => NormHold::Sh(lots_round),
+            NormHold::No => NormHold::No,
+        }
+    }
+}
+
+fn hold_size(x: &NormHold) -> f32 {
+    match x {
+        NormHold::Lo(i) | NormHold::Sh(i) => *i,
+        NormHold::No => 0.,
+    }
+}
+
+fn unit_hold(di: &Di, ptm: &Ptm) -> Vec<NormHold> {
+    let b = di.calc(ptm);
+    let hold = b.downcast_ref::<RwLock<PtmResState>>().unwrap().read().unwrap().ptm_res.0.clone();
+    hold
+}
+
+/// The return of each bar held by `ptm` with one lot, from the close before.
+fn unit_ret(di: &Di, hold: &[NormHold]) -> v32 {
+    let c = di.c();
+    let mut res = vec![0f32; hold.len()];
+    for i in 1..hold.len() {
+        let r = c[i] / c[i - 1] - 1.;
+        res[i] = match hold[i - 1] {
+            NormHold::Lo(_) => r,
+            NormHold::Sh(_) => -r,
+            NormHold::No => 0.,
+        };
+    }
+    res
+}
+/* #endregion */
+
+/* #region VolTarget */
+/// Lots for `capital` to have the annualised volatility `risk`, on the standard deviation
+/// of the daily returns of `n` days.
+#[ta_derive]
+pub struct VolTarget {
+    pub capital: f32,
+    pub risk: f32,
+    pub n: usize,
+    pub min_lot: f32,
+}
+
+#[typetag::serde]
+impl Money for VolTarget {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let c = di.c();
+        let pv = di.pcon.ticker.info().pv;
+        let vol_pms: Pms = dayk.clone() + ono + RollTa(Box::new(pct) as Box<dyn Ta>, RollFunc::Std, RollOps::InitMiss(self.n)) + vori;
+        let vol = di.calc(vol_pms)[0].clone();
+        let lot_round = LotRound::new(di, self.min_lot);
+        Box::new(move |x, i| {
+            let vol_annu = vol[i] * 240f32.sqrt();
+            lot_round.hold(x, self.capital * self.risk / (vol_annu * c[i] * pv), i)
+        })
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(VolTarget { capital: self.capital * weight, ..self.clone() })
+    }
+}
+/* #endregion */
+
+/* #region AtrRisk */
+/// Lots losing `risk` of `capital` at a stop `k` times the `Atr` of `n` away.
+#[ta_derive]
+pub struct AtrRisk {
+    pub capital: f32,
+    pub risk: f32,
+    pub n: usize,
+    pub k: f32,
+    pub min_lot: f32,
+}
+
+#[typetag::serde]
+impl Money for AtrRisk {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let pv = di.pcon.ticker.info().pv;
+        let atr = di.calc(Atr(self.n))[0].clone();
+        let lot_round = LotRound::new(di, self.min_lot);
+        Box::new(move |x, i| {
+            lot_round.hold(x, self.capital * self.risk / (self.k * atr[i] * pv), i)
+        })
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(AtrRisk { capital: self.capital * weight, ..self.clone() })
+    }
+}
+/* #endregion */
+
+/* #region Kelly */
+/// Lots of `fraction` of the Kelly share of `capital`, from the win rate and the win to
+/// loss ratio of the last `n` trades of `ptm` with one lot. Flat before `n` trades.
+#[ta_derive]
+pub struct Kelly {
+    pub ptm: Box<Ptm>,
+    pub capital: f32,
+    pub fraction: f32,
+    pub n: usize,
+    pub min_lot: f32,
+}
+
+impl Kelly {
+    /// Sizing `ptm` on its own trades.
+    pub fn of(ptm: &Ptm, capital: f32, fraction: f32, n: usize, min_lot: f32) -> Self {
+        Self { ptm: Box::new(ptm.change_money(M1(1.))), capital, fraction, n, min_lot }
+    }
+
+    /// The Kelly share at each bar, from the trades closed by then.
+    fn share(&self, di: &Di) -> v32 {
+        let hold = unit_hold(di, &self.ptm);
+        let ret = unit_ret(di, &hold);
+        let mut trades: VecDeque<f32> = VecDeque::with_capacity(self.n + 1);
+        let (mut trade_ret, mut share) = (0f32, f32::NAN);
+        let mut res = Vec::with_capacity(hold.len());
+        for i in 0..hold.len() {
+            trade_ret += ret[i];
+            let hold_last = if i > 0 { &hold[i - 1] } else { &NormHold::No };
+            let closed = !matches!(
+                (hold_last, &hold[i]),
+                (NormHold::No, _) | (NormHold::Lo(_), NormHold::Lo(_)) | (NormHold::Sh(_), NormHold::Sh(_))
+            );
+            if closed {
+                trades.push_back(trade_ret);
+                if trades.len() > self.n {
+                    trades.pop_front();
+                }
+                if trades.len() == self.n {
+                    let win = trades.iter().filter(|x| **x > 0.).cloned().collect_vec();
+                    let loss = trades.iter().filter(|x| **x < 0.).map(|x| -x).collect_vec();
+                    let p = win.len() as f32 / self.n as f32;
+                    share = match (win.is_empty(), loss.is_empty()) {
+                        (true, _) => 0.,
+                        (false, true) => 1.,
+                        (false, false) => (p - (1. - p) / (win.mean() / loss.mean())).clamp(0., 1.),
+                    };
+                }
+            }
+            if closed || matches!(hold[i], NormHold::No) {
+                trade_ret = 0.;
+            }
+            res.push(share);
+        }
+        res
+    }
+}
+
+#[typetag::serde]
+impl Money for Kelly {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let c = di.c();
+        let pv = di.pcon.ticker.info().pv;
+        let share = self.share(di);
+        let lot_round = LotRound::new(di, self.min_lot);
+        Box::new(move |x, i| {
+            lot_round.hold(x, self.capital * self.fraction * share[i] / (c[i] * pv), i)
+        })
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(Kelly { capital: self.capital * weight, ..self.clone() })
+    }
+}
+/* #endregion */
+
+/* #region DrawdownScale */
+/// The lots of `money` scaled down with the drawdown of the returns of `ptm` with one
+/// lot, by `1 - drawdown / max_dd`, to no less than `floor`.
+#[ta_derive]
+pub struct DrawdownScale {
+    pub ptm: Box<Ptm>,
+    pub money: Box<dyn Money>,
+    pub max_dd: f32,
+    pub floor: f32,
+    pub min_lot: f32,
+}
+
+impl DrawdownScale {
+    /// Scaling `ptm`, sized by its own money, on its own drawdown.
+    pub fn of(ptm: &Ptm, max_dd: f32, floor: f32, min_lot: f32) -> Self {
+        Self {
+            ptm: Box::new(ptm.change_money(M1(1.))),
+            money: ptm.get_money_fn(),
+            max_dd,
+            floor,
+            min_lot,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Money for DrawdownScale {
+    fn register<'a>(&'a self, di: &'a Di) -> PosiFunc<'a> {
+        let f = self.money.register(di);
+        let equity = unit_ret(di, &unit_hold(di, &self.ptm)).cumsum();
+        let scale = equity
+            .iter()
+            .scan(f32::MIN, |peak, x| {
+                *peak = peak.max(*x);
+                Some((1. - (*peak - x) / self.max_dd).clamp(self.floor, 1.))
+            })
+            .collect_vec();
+        let lot_round = LotRound::new(di, self.min_lot);
+        Box::new(move |x, i| {
+            let hold = f(x, i);
+            lot_round.hold(&hold, hold_size(&hold) * scale[i], i)
+        })
+    }
+    fn change_weight(&self, weight: f32) -> Box<dyn Money> {
+        Box::new(DrawdownScale { money: self.money.change_weight(weight), ..self.clone() })
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::{m1, ori, KlineType, M1};
+    use crate::sig::cond::Iocond;
+    use crate::test_util::{di_c, di_ohlc, logs_of};
+
+    fn lots(x: &NormHold) -> Option<f32> {
+        match x {
+            NormHold::Lo(i) => Some(*i),
+            NormHold::Sh(i) => Some(-*i),
+            NormHold::No => None,
+        }
+    }
+
+    /// Long at the bars opening at 1, flat at the bars opening at 2: a trade winning 10%
+    /// from bar 0 to 2, one losing 1/11 from bar 3 to 4, and one held from bar 5.
+    fn di_trades() -> Di {
+        let o = [1., 0., 2., 1., 2., 1., 0., 0.];
+        let c = [100., 100., 110., 110., 100., 100., 110., 110.];
+        di_ohlc(Ticker::rb, &o, &c, &c, &c)
+    }
+
+    fn ptm_trades(money: Box<dyn Money>) -> Ptm {
+        let open = Iocond { pms: ori + ono + KlineType::Open, range: 0.5..1.5 };
+        let exit = Iocond { pms: ori + ono + KlineType::Open, range: 1.5..2.5 };
+        Ptm::Ptm3(money, Dire::Lo, Box::new(open), Box::new(exit))
+    }
+
+    #[test]
+    fn lot_round_floors_to_min_lot() {
+        let di = di_c(Ticker::rb, &[100.; 4]);
+        let lot_round = LotRound::new(&di, 2.);
+        assert_eq!(lots(&lot_round.hold(&NormHold::Lo(1.), 3.7, 0)), Some(3.));
+        assert_eq!(lots(&lot_round.hold(&NormHold::Sh(1.), 2., 1)), Some(-2.));
+        assert_eq!(lots(&lot_round.hold(&NormHold::Lo(1.), 1.9, 2)), None);
+        assert_eq!(lots(&lot_round.hold(&NormHold::No, 5., 3)), None);
+        for x in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 0., -3.] {
+            assert_eq!(lots(&lot_round.hold(&NormHold::Lo(1.), x, 3)), None);
+        }
+    }
+
+    #[test]
+    fn lot_round_warns_once_a_run() {
+        let di = di_c(Ticker::ag, &[100.; 8]);
+        let lot_round = LotRound::new(&di, 1.);
+        let target = Ticker::ag.to_string();
+        let warns_before = logs_of(&target).len();
+        for (i, x) in [0.5, 0.5, 0.5, 2., 0.5, 0.5, f32::NAN].into_iter().enumerate() {
+            lot_round.hold(&NormHold::Lo(1.), x, i);
+        }
+        let warns = logs_of(&target)[warns_before..].to_vec();
+        assert_eq!(warns.len(), 2, "{:?}", warns);
+        assert!(warns[0].contains("at bar 0"));
+        assert!(warns[1].contains("at bar 4"));
+        lot_round.hold(&NormHold::No, 0.5, 7);
+        assert_eq!(logs_of(&target).len(), warns_before + 2);
+    }
+
+    #[test]
+    fn vol_target_sizes_on_daily_vol() {
+        let c = (0..30)
+            .map(|i| 100. * (1. + 0.01 * ((i * 7 % 5) as f32 - 2.)))
+            .collect_vec();
+        let di = di_c(Ticker::rb, &c);
+        let money = VolTarget { capital: 1e6, risk: 0.1, n: 5, min_lot: 1. };
+        let f = money.register(&di);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 2)), None);
+        let i = c.len() - 1;
+        let vol_pms: Pms = dayk.clone() + ono + RollTa(Box::new(pct) as Box<dyn Ta>, RollFunc::Std, RollOps::InitMiss(5)) + vori;
+        let std = di.calc(vol_pms)[0][i];
+        assert!(std > 0.);
+        let pv = Ticker::rb.info().pv;
+        let expect = 1e6 * 0.1 / (std * 240f32.sqrt() * c[i] * pv);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), i)), Some(expect.floor()));
+        assert_eq!(lots(&f(&NormHold::Sh(1.), i)), Some(-expect.floor()));
+        let di = di_c(Ticker::rb, &[100.; 30]);
+        let f = money.register(&di);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 29)), None);
+    }
+
+    #[test]
+    fn atr_risk_sizes_on_atr() {
+        let c = (0..20).map(|i| 100. + (i % 3) as f32).collect_vec();
+        let h = c.map(|x| x + 2.);
+        let l = c.map(|x| x - 2.);
+        let di = di_ohlc(Ticker::rb, &c, &h, &l, &c);
+        let money = AtrRisk { capital: 1e6, risk: 0.01, n: 5, k: 2., min_lot: 1. };
+        let f = money.register(&di);
+        let atr = di.calc(Atr(5))[0].clone();
+        let pv = Ticker::rb.info().pv;
+        let expect = 1e6 * 0.01 / (2. * atr[19] * pv);
+        assert!(expect > 1.);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 19)), Some(expect.floor()));
+        let di = di_c(Ticker::rb, &[100.; 20]);
+        let f = money.register(&di);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 19)), None);
+    }
+
+    #[test]
+    fn kelly_shares_the_last_trades() {
+        let di = di_trades();
+        let kelly = Kelly::of(&ptm_trades(m1.clone()), 1e6, 0.5, 2, 1.);
+        let share = kelly.share(&di);
+        assert!(share[..4].iter().all(|x| x.is_nan()));
+        let (win, loss) = (0.1f32, 1. / 11.);
+        let expect = 0.5 - 0.5 / (win / loss);
+        assert!(share[4..].iter().all(|x| (x - expect).abs() < 1e-4), "{:?}", share);
+        let f = kelly.register(&di);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 3)), None);
+        let pv = Ticker::rb.info().pv;
+        let lots_expect = (1e6 * 0.5 * share[5] / (100. * pv)).floor();
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 5)), Some(lots_expect));
+        drop(f);
+        let ptm = ptm_trades(Box::new(kelly));
+        let hold = unit_hold(&di, &ptm);
+        assert!(matches!(hold[0], NormHold::No));
+        assert_eq!(lots(&hold[5]), Some(lots_expect));
+    }
+
+    #[test]
+    fn kelly_flat_without_a_win() {
+        let c = [100., 100., 90., 90., 80., 80., 70., 70.];
+        let di = di_ohlc(Ticker::rb, &[1., 0., 2., 1., 2., 1., 0., 0.], &c, &c, &c);
+        let kelly = Kelly::of(&ptm_trades(m1.clone()), 1e6, 1., 2, 1.);
+        assert_eq!(kelly.share(&di)[5], 0.);
+        assert_eq!(lots(&kelly.register(&di)(&NormHold::Lo(1.), 5)), None);
+    }
+
+    #[test]
+    fn drawdown_scale_scales_to_floor() {
+        let di = di_trades();
+        let ptm = ptm_trades(Box::new(M1(100.)));
+        let money = DrawdownScale::of(&ptm, 0.2, 0.5, 1.);
+        let f = money.register(&di);
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 2)), Some(100.));
+        let scale = 1. - (0.1f32 - (0.1 - 1. / 11.)) / 0.2;
+        assert_eq!(lots(&f(&NormHold::Lo(1.), 4)), Some((100. * scale).floor()));
+        assert_eq!(lots(&f(&NormHold::No, 4)), None);
+        let money = DrawdownScale::of(&ptm, 0.12, 0.5, 1.);
+        let f = money.register(&di);
+        assert_eq!(lots(&f(&NormHold::Sh(1.), 4)), Some(-50.));
+    }
+}
@@ -24,6 +24,11 @@ impl Di {
             .read()
             .unwrap()
             .ptm_res;
+        self.pnl_of_ptm_res(ptm_res, comm)
+    }
+
+    /// The pnl of holds made bar by bar outside a `Ptm`.
+    pub fn pnl_of_ptm_res(&self, ptm_res: &PtmRes, comm: CommSlip) -> PnlRes<dt> {
         let pnl_res_pre_info = PnlResPreInfo {
             c: self.c(),
             ticker: self.pcon.ticker,
@@ -0,0 +1,140 @@
+//! Small data, strategies for the live engine and a log capture shared by the tests of the crate.
+use crate::prelude::*;
+use chrono::Datelike;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// A `Di` of the bars given of `ticker`.
+pub(crate) fn di_bars(ticker: Ticker, t: vdt, ohlcv: [&[f32]; 5]) -> Di {
+    let ki = t
+        .iter()
+        .map(|x| KlineInfo { open_time: *x, pass_last: 1, pass_this: 1, contract: 0 })
+        .collect_vec();
+    let [o, h, l, c, v] = ohlcv.map(|x| x.to_vec());
+    PriceOri { t, o, h, l, c, v, ki, immut_info: vec![] }.to_di(ticker, Box::new(Box::new(Rlast) as InterBox))
+}
+
+/// The 14:55:50 close of the weekdays from 2024-01-02, `n` of them.
+pub(crate) fn days(n: usize) -> vdt {
+    let mut date = da::from_ymd_opt(2024, 1, 2).unwrap();
+    let mut t = Vec::with_capacity(n);
+    while t.len() < n {
+        if date.weekday().number_from_monday() <= 5 {
+            t.push(date.and_hms_opt(14, 55, 50).unwrap());
+        }
+        date = date.succ_opt().unwrap();
+    }
+    t
+}
+
+/// A `Di` of daily bars of `ticker` with the open, high, low and close of each bar given,
+/// and a volume of 1.
+pub(crate) fn di_ohlc(ticker: Ticker, o: &[f32], h: &[f32], l: &[f32], c: &[f32]) -> Di {
+    di_bars(ticker, days(c.len()), [o, h, l, c, &vec![1.; c.len()]])
+}
+
+/// A `Di` of daily bars closing at `c`, each bar with its open, high and low at the close.
+pub(crate) fn di_c(ticker: Ticker, c: &[f32]) -> Di {
+    di_ohlc(ticker, c, c, c, c)
+}
+
+/// A strategy that never trades.
+#[derive(Clone)]
+pub(crate) struct Stra;
+
+impl ApiType for Stra {
+    fn api_type(&self) -> RetFnApi<'_> {
+        Box::new(|_| OrderAction::No)
+    }
+}
+
+/// Always the same long target.
+pub(crate) struct Target(pub f32);
+
+impl CondType7 for Target {
+    fn cond_type7(&self) -> RetFnCondType7<'_> {
+        let target = self.0;
+        Box::new(move |_| LiveTarget::Lo(target))
+    }
+}
+
+/// The contracts of rb and hc, for the strategies of a `StraApi` in the tests.
+pub(crate) fn ticker_contract_map() -> hm<Ticker, &'static str> {
+    hm::from([(Ticker::rb, "rb2501"), (Ticker::hc, "hc2501")])
+}
+
+pub(crate) fn stra_api(stras: Vec<(Ticker, ApiTypeBox)>) -> StraApi {
+    let data = stras.into_iter().map(|(ticker, data)| WithTicker { ticker, data }).collect();
+    StraApi::new(LiveStraPool { data }, ticker_contract_map())
+}
+
+pub(crate) fn tick() -> DataReceive {
+    TickData { c: 3500., bid1: 3500., ask1: 3501., ..Default::default() }.into()
+}
+
+/// The order action the ticker sends next.
+pub(crate) async fn sent(stra_api: &StraApi, ticker: Ticker) -> OrderAction {
+    let ticker_live = stra_api.update_di.ticker_live(&ticker).unwrap();
+    let (_shutdown_send, mut shutdown) = broadcast::channel(1);
+    let order_send = ticker_live.trade_api.data_send.recv(&mut shutdown);
+    tokio::time::timeout(Duration::from_secs(1), order_send).await.unwrap().unwrap().order_action
+}
+
+pub(crate) async fn wait_mode(stra_api: &StraApi, ticker: Ticker, mode: TickerMode) {
+    let ticker_live = stra_api.update_di.ticker_live(&ticker).unwrap();
+    for _ in 0..200 {
+        if ticker_live.control.lock().unwrap().mode == mode {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("{:?} not in {:?}", ticker, mode);
+}
+
+/// Sends a tick and waits for the strategy to take it, so the commands after it close
+/// at its price.
+pub(crate) async fn first_tick(stra_api: &StraApi, ticker: Ticker) {
+    let ticker_live = stra_api.update_di.ticker_live(&ticker).unwrap();
+    ticker_live.trade_api.data_receive.send(tick());
+    for _ in 0..200 {
+        if !ticker_live.record.lock().unwrap().is_empty() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    panic!("{:?} took no tick", ticker);
+}
+
+struct Capture(Mutex<Vec<(String, String)>>);
+
+impl log::Log for Capture {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push((record.target().to_string(), record.args().to_string()));
+    }
+    fn flush(&self) {}
+}
+
+static capture: OnceLock<&'static Capture> = OnceLock::new();
+
+/// The messages logged so far to `target`, capturing the log of the process from the
+/// first call on. Tests logging to the same target should not run at the same time.
+pub(crate) fn logs_of(target: &str) -> Vec<String> {
+    let capture_now = capture.get_or_init(|| {
+        let x: &'static Capture = Box::leak(Box::new(Capture(Mutex::new(vec![]))));
+        log::set_logger(x).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        x
+    });
+    capture_now
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|x| x.0 == target)
+        .map(|x| x.1.clone())
+        .collect()
+}
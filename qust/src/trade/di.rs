@@ -350,8 +350,16 @@ impl Di {
         self.data_save.clear();
     }
 
+    /// Clears what is out of date after the price grows, but the cached `Pms` with a
+    /// `TaState`, which take the new bars at their next calc.
     pub fn clear2(&self) {
-        self.data_save.save_pms2d.write().unwrap().clear();
+        let save_ta_state = self.data_save.save_ta_state.read().unwrap();
+        self.data_save
+            .save_pms2d
+            .write()
+            .unwrap()
+            .retain(|k, _| save_ta_state.contains_key(k));
+        drop(save_ta_state);
         self.data_save.save_dcon.write().unwrap().clear();
         self.data_save.save_others.write().unwrap().clear();
     }
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
 use regex::Regex;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Deserialize, Serialize)]
@@ -335,8 +336,9 @@ lazy_static! {
     ];
 }
 
-#[derive(Debug)]
-enum Comdty {
+/// The sector of a product, grouping the tickers for cross-sectional work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Comdty {
     Soft,
     NonferrousMetals,
     Ceral,
@@ -348,7 +350,7 @@ enum Comdty {
     Oil,
 }
 
-trait ToSection {
+pub trait ToSection {
     fn to_section(self) -> Comdty;
 }
 impl ToSection for Ticker {
@@ -397,11 +399,59 @@ pub trait ExtractTicker {
 
 impl ExtractTicker for &str {
     type Output = (Ticker, i32);
+    /// A combination has no ticker, it is keyed by its `ComboContract`.
     fn extract_ticker(self) -> Option<Self::Output> {
+        if self.extract_combo().is_some() {
+            return None;
+        }
         let re = Regex::new(r"\d+").ok()?;
         let res = re.find(self)?;
         let ticker = self[0..res.start()].into_ticker()?;
         let contract_i = self[res.start()..res.end()].parse::<i32>().ok()?;
         Some((ticker, contract_i))
     }
-}
\ No newline at end of file
+}
+/// A leg of an exchange combination, bought when the combination is bought for a positive
+/// ratio and sold for a negative one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ComboLeg {
+    pub contract: String,
+    pub ticker: Ticker,
+    pub ratio: i32,
+}
+
+/// An exchange combination instrument such as `SP rb2410&rb2501`: a calendar spread for
+/// `SP`/`SPD`, and a spread of two products for `SPC`/`IPS`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ComboContract {
+    pub kind: String,
+    pub legs: Vec<ComboLeg>,
+}
+
+impl std::fmt::Display for ComboContract {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let legs = self.legs.iter().map(|x| x.contract.as_str()).collect::<Vec<_>>();
+        write!(f, "{} {}", self.kind, legs.join("&"))
+    }
+}
+
+pub trait ExtractCombo {
+    fn extract_combo(self) -> Option<ComboContract>;
+}
+
+static combo_re: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(SPD|SPC|SP|IPS) +(\w+)&(\w+)$").unwrap());
+
+impl ExtractCombo for &str {
+    fn extract_combo(self) -> Option<ComboContract> {
+        let caps = combo_re.captures(self.trim())?;
+        let legs = [(2, 1), (3, -1)]
+            .into_iter()
+            .map(|(i, ratio)| {
+                let contract = caps[i].to_string();
+                let (ticker, _) = contract.as_str().extract_ticker()?;
+                Some(ComboLeg { contract, ticker, ratio })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(ComboContract { kind: caps[1].to_string(), legs })
+    }
+}